@@ -1,3 +1,6 @@
+use crate::checksum::ContentChecksums;
+use crate::fingerprint;
+use crate::loudness::TrackLoudness;
 use crate::metadata::AudioMetadata;
 use anyhow::{bail, Context, Result};
 use lazy_static::lazy_static;
@@ -10,6 +13,54 @@ lazy_static! {
     static ref GLOBAL_CACHE: Mutex<Option<MetadataCache>> = Mutex::new(None);
 }
 
+/// Version of the metadata_json layout; entries written by older versions are re-read
+/// from the file so fields added since get filled in
+const METADATA_VERSION: i64 = 4;
+
+/// Row shape returned by a cache lookup:
+/// (metadata_json, fingerprint, recording_id, release_id, spectrum_json, integrity_json,
+/// whether the fingerprint was made with the current algorithm and length)
+type CachedRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    bool,
+);
+
+/// Canonical path, mtime and size identifying the current version of a file
+fn file_key(path: &Path) -> Option<(String, i64, i64)> {
+    let file_meta = std::fs::metadata(path).ok()?;
+    let mtime = file_meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    Some((
+        canonical_path.to_string_lossy().to_string(),
+        mtime,
+        file_meta.len() as i64,
+    ))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Content checksums stored for a file, with when they were last checked
+#[derive(Debug, Clone)]
+pub struct StoredChecksums {
+    pub checksums: ContentChecksums,
+    pub hashed_at: i64,
+}
+
 /// Thread-safe metadata cache using SQLite
 #[derive(Clone)]
 pub struct MetadataCache {
@@ -69,6 +120,61 @@ impl MetadataCache {
         let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN fingerprint TEXT", []);
         let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN musicbrainz_recording_id TEXT", []);
         let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN musicbrainz_release_id TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE metadata_cache ADD COLUMN metadata_version INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN loudness_json TEXT", []);
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN spectrum_json TEXT", []);
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN integrity_json TEXT", []);
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN file_hash TEXT", []);
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN audio_hash TEXT", []);
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN hashed_at INTEGER", []);
+        let _ = conn.execute(
+            "ALTER TABLE metadata_cache ADD COLUMN fingerprint_algorithm INTEGER",
+            [],
+        );
+        let _ = conn.execute("ALTER TABLE metadata_cache ADD COLUMN fingerprint_length INTEGER", []);
+
+        // Web service responses, so files already looked up aren't looked up again
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS acoustid_responses (
+                fingerprint_hash TEXT NOT NULL,
+                duration INTEGER NOT NULL,
+                query TEXT NOT NULL,
+                response_json TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                PRIMARY KEY (fingerprint_hash, duration)
+            );
+            CREATE TABLE IF NOT EXISTS musicbrainz_responses (
+                entity TEXT NOT NULL,
+                mbid TEXT NOT NULL,
+                query TEXT NOT NULL,
+                response_json TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                PRIMARY KEY (entity, mbid)
+            );",
+        )
+        .context("Failed to create web service response tables")?;
+
+        // Fingerprints waiting to be submitted to AcoustID, and what became of the sent ones
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS acoustid_submissions (
+                fingerprint_hash TEXT NOT NULL,
+                mbid TEXT NOT NULL,
+                fingerprint TEXT NOT NULL,
+                duration INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                status TEXT NOT NULL,
+                submission_id INTEGER,
+                error TEXT,
+                queued_at INTEGER NOT NULL,
+                sent_at INTEGER,
+                PRIMARY KEY (fingerprint_hash, mbid)
+            )",
+            [],
+        )
+        .context("Failed to create acoustid_submissions table")?;
 
         Ok(Self {
             connection: Arc::new(Mutex::new(conn)),
@@ -100,30 +206,70 @@ impl MetadataCache {
         let conn = self.connection.lock().unwrap();
 
         let mut stmt = conn.prepare(
-            "SELECT metadata_json, fingerprint, musicbrainz_recording_id, musicbrainz_release_id
+            "SELECT metadata_json, fingerprint, musicbrainz_recording_id, musicbrainz_release_id,
+                    spectrum_json, integrity_json,
+                    COALESCE(fingerprint_algorithm = ?5 AND fingerprint_length = ?6, 0)
              FROM metadata_cache
-             WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+             WHERE path = ?1 AND mtime = ?2 AND size = ?3 AND metadata_version = ?4",
         )?;
 
-        let result: Result<(String, Option<String>, Option<String>, Option<String>), rusqlite::Error> =
-            stmt.query_row(params![path_str.as_str(), mtime, size], |row| {
-                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-            });
+        let result: Result<CachedRow, rusqlite::Error> = stmt.query_row(
+            params![
+                path_str.as_str(),
+                mtime,
+                size,
+                METADATA_VERSION,
+                fingerprint::ALGORITHM,
+                fingerprint::LENGTH_SECS
+            ],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                ))
+            },
+        );
 
         match result {
-            Ok((json, fingerprint, mb_recording_id, mb_release_id)) => {
+            Ok((
+                json,
+                fingerprint,
+                mb_recording_id,
+                mb_release_id,
+                spectrum,
+                integrity,
+                fingerprint_current,
+            )) => {
                 match serde_json::from_str::<AudioMetadata>(&json) {
                     Ok(mut metadata) => {
                         // Populate from dedicated columns if not in JSON (backwards compatibility)
                         if metadata.fingerprint.is_none() && fingerprint.is_some() {
                             metadata.fingerprint = fingerprint;
                         }
+                        // Fingerprints from another algorithm (or the raw format older versions
+                        // stored) can't be compared or sent to AcoustID, so they get redone
+                        if !fingerprint_current {
+                            metadata.fingerprint = None;
+                        }
                         if metadata.musicbrainz_recording_id.is_none() && mb_recording_id.is_some() {
                             metadata.musicbrainz_recording_id = mb_recording_id;
                         }
                         if metadata.musicbrainz_release_id.is_none() && mb_release_id.is_some() {
                             metadata.musicbrainz_release_id = mb_release_id;
                         }
+                        if metadata.spectrum.is_none() {
+                            metadata.spectrum =
+                                spectrum.and_then(|json| serde_json::from_str(&json).ok());
+                        }
+                        if metadata.integrity.is_none() {
+                            metadata.integrity =
+                                integrity.and_then(|json| serde_json::from_str(&json).ok());
+                        }
                         Ok(Some(metadata))
                     }
                     Err(err) => {
@@ -170,15 +316,47 @@ impl MetadataCache {
             .as_secs() as i64;
         let metadata_json =
             serde_json::to_string(metadata).context("Failed to serialize metadata for cache")?;
+        let spectrum_json = metadata
+            .spectrum
+            .map(|spectrum| serde_json::to_string(&spectrum))
+            .transpose()
+            .context("Failed to serialize spectrum analysis for cache")?;
+        let integrity_json = metadata
+            .integrity
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .context("Failed to serialize integrity check for cache")?;
+
+        let fingerprint_algorithm = metadata
+            .fingerprint
+            .as_deref()
+            .and_then(fingerprint::algorithm_of);
 
         let conn = self.connection.lock().unwrap();
 
         // Store dedicated columns for efficient querying
-        // These fields are also in metadata_json for backwards compatibility
+        // These fields are also in metadata_json for backwards compatibility.
+        // A re-read of an outdated entry keeps the fingerprint, IDs and analyses stored for
+        // the unchanged file instead of dropping them.
         conn.execute(
             "INSERT OR REPLACE INTO metadata_cache
-             (path, mtime, size, metadata_json, cached_at, fingerprint, musicbrainz_recording_id, musicbrainz_release_id)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+             (path, mtime, size, metadata_json, cached_at, fingerprint, musicbrainz_recording_id, musicbrainz_release_id, metadata_version, loudness_json,
+              spectrum_json, integrity_json, file_hash, audio_hash, hashed_at,
+              fingerprint_algorithm, fingerprint_length)
+             SELECT ?1, ?2, ?3, ?4, ?5,
+                    COALESCE(?6, old.fingerprint),
+                    COALESCE(?7, old.musicbrainz_recording_id),
+                    COALESCE(?8, old.musicbrainz_release_id),
+                    ?9,
+                    old.loudness_json,
+                    COALESCE(?10, old.spectrum_json),
+                    COALESCE(?11, old.integrity_json),
+                    old.file_hash, old.audio_hash, old.hashed_at,
+                    CASE WHEN ?6 IS NULL THEN old.fingerprint_algorithm ELSE ?12 END,
+                    CASE WHEN ?6 IS NULL THEN old.fingerprint_length ELSE ?13 END
+             FROM (SELECT 1) LEFT JOIN metadata_cache AS old
+                  ON old.path = ?1 AND old.mtime = ?2 AND old.size = ?3",
             params![
                 path_str.as_str(),
                 mtime,
@@ -188,16 +366,314 @@ impl MetadataCache {
                 metadata.fingerprint.as_deref(),
                 metadata.musicbrainz_recording_id.as_deref(),
                 metadata.musicbrainz_release_id.as_deref(),
+                METADATA_VERSION,
+                spectrum_json,
+                integrity_json,
+                fingerprint_algorithm,
+                fingerprint::LENGTH_SECS,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the cached loudness analysis if the file hasn't changed since
+    pub fn get_loudness(&self, path: &Path) -> Result<Option<TrackLoudness>> {
+        let Some((path_str, mtime, size)) = file_key(path) else {
+            return Ok(None);
+        };
+
+        let conn = self.connection.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT loudness_json FROM metadata_cache WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+            params![path_str, mtime, size],
+            |row| row.get::<_, Option<String>>(0),
+        );
+
+        match result {
+            Ok(Some(json)) => Ok(serde_json::from_str(&json).ok()),
+            Ok(None) | Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a loudness analysis for a file (its metadata must already be cached)
+    pub fn set_loudness(&self, path: &Path, loudness: &TrackLoudness) -> Result<()> {
+        let Some((path_str, mtime, size)) = file_key(path) else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(loudness).context("Failed to serialize loudness")?;
+
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE metadata_cache SET loudness_json = ?4 WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+            params![path_str, mtime, size, json],
+        )?;
+        Ok(())
+    }
+
+    /// Get the content checksums stored for a file if it hasn't changed since
+    pub fn get_checksums(&self, path: &Path) -> Result<Option<StoredChecksums>> {
+        let Some((path_str, mtime, size)) = file_key(path) else {
+            return Ok(None);
+        };
+
+        let conn = self.connection.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT file_hash, audio_hash, hashed_at FROM metadata_cache
+             WHERE path = ?1 AND mtime = ?2 AND size = ?3 AND file_hash IS NOT NULL",
+            params![path_str, mtime, size],
+            |row| {
+                Ok(StoredChecksums {
+                    checksums: ContentChecksums {
+                        file_hash: row.get(0)?,
+                        audio_hash: row.get(1)?,
+                    },
+                    hashed_at: row.get(2)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(stored) => Ok(Some(stored)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store content checksums for a file (its metadata must already be cached)
+    pub fn set_checksums(&self, path: &Path, checksums: &ContentChecksums) -> Result<()> {
+        let Some((path_str, mtime, size)) = file_key(path) else {
+            return Ok(());
+        };
+
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE metadata_cache SET file_hash = ?4, audio_hash = ?5, hashed_at = ?6
+             WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+            params![
+                path_str,
+                mtime,
+                size,
+                checksums.file_hash,
+                checksums.audio_hash,
+                unix_now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Record that a file's checksums were checked now without changing them
+    pub fn touch_checksums(&self, path: &Path) -> Result<()> {
+        let Some((path_str, mtime, size)) = file_key(path) else {
+            return Ok(());
+        };
+
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE metadata_cache SET hashed_at = ?4 WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+            params![path_str, mtime, size, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    /// Get a cached AcoustID lookup response no older than `max_age_secs`
+    ///
+    /// `query` holds the lookup's other parameters; responses to different ones don't count.
+    pub fn get_acoustid_response(
+        &self,
+        fingerprint: &str,
+        duration: i64,
+        query: &str,
+        max_age_secs: i64,
+    ) -> Result<Option<String>> {
+        let conn = self.connection.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT response_json FROM acoustid_responses
+             WHERE fingerprint_hash = ?1 AND duration = ?2 AND query = ?3 AND fetched_at >= ?4",
+            params![
+                blake3::hash(fingerprint.as_bytes()).to_hex().as_str(),
+                duration,
+                query,
+                unix_now() - max_age_secs
+            ],
+            |row| row.get(0),
+        );
+
+        match result {
+            Ok(json) => Ok(Some(json)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store an AcoustID lookup response
+    pub fn set_acoustid_response(
+        &self,
+        fingerprint: &str,
+        duration: i64,
+        query: &str,
+        json: &str,
+    ) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO acoustid_responses
+             (fingerprint_hash, duration, query, response_json, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                blake3::hash(fingerprint.as_bytes()).to_hex().as_str(),
+                duration,
+                query,
+                json,
+                unix_now()
             ],
         )?;
+        Ok(())
+    }
 
+    /// Get a cached MusicBrainz lookup of a recording or release no older than `max_age_secs`
+    pub fn get_musicbrainz_response(
+        &self,
+        entity: &str,
+        mbid: &str,
+        query: &str,
+        max_age_secs: i64,
+    ) -> Result<Option<String>> {
+        let conn = self.connection.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT response_json FROM musicbrainz_responses
+             WHERE entity = ?1 AND mbid = ?2 AND query = ?3 AND fetched_at >= ?4",
+            params![entity, mbid, query, unix_now() - max_age_secs],
+            |row| row.get(0),
+        );
+
+        match result {
+            Ok(json) => Ok(Some(json)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a MusicBrainz lookup of a recording or release
+    pub fn set_musicbrainz_response(
+        &self,
+        entity: &str,
+        mbid: &str,
+        query: &str,
+        json: &str,
+    ) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO musicbrainz_responses
+             (entity, mbid, query, response_json, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![entity, mbid, query, json, unix_now()],
+        )?;
         Ok(())
     }
 
-    /// Clear all cached metadata
+    /// Queue a fingerprint to be submitted to AcoustID as a recording
+    ///
+    /// Returns false when that fingerprint was already queued or sent for that recording.
+    pub fn queue_acoustid_submission(
+        &self,
+        path: &Path,
+        fingerprint: &str,
+        duration: i64,
+        mbid: &str,
+    ) -> Result<bool> {
+        let conn = self.connection.lock().unwrap();
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO acoustid_submissions
+             (fingerprint_hash, mbid, fingerprint, duration, path, status, queued_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                blake3::hash(fingerprint.as_bytes()).to_hex().as_str(),
+                mbid,
+                fingerprint,
+                duration,
+                path.to_string_lossy(),
+                SubmissionStatus::Queued.as_str(),
+                unix_now()
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// Get the AcoustID submissions with a status, oldest first
+    pub fn acoustid_submissions(
+        &self,
+        status: SubmissionStatus,
+    ) -> Result<Vec<AcoustIdSubmission>> {
+        let conn = self.connection.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT fingerprint, duration, mbid, path, submission_id FROM acoustid_submissions
+             WHERE status = ?1 ORDER BY queued_at, rowid",
+        )?;
+        let rows = stmt
+            .query_map(params![status.as_str()], |row| {
+                Ok(AcoustIdSubmission {
+                    fingerprint: row.get(0)?,
+                    duration: row.get(1)?,
+                    mbid: row.get(2)?,
+                    path: PathBuf::from(row.get::<_, String>(3)?),
+                    submission_id: row.get(4)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to read AcoustID submissions")?;
+        Ok(rows)
+    }
+
+    /// Record what became of an AcoustID submission
+    ///
+    /// `submission_id` is AcoustID's ID for a sent submission; `error` says why one failed.
+    pub fn set_acoustid_submission_status(
+        &self,
+        submission: &AcoustIdSubmission,
+        status: SubmissionStatus,
+        submission_id: Option<i64>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE acoustid_submissions
+             SET status = ?3, submission_id = COALESCE(?4, submission_id), error = ?5,
+                 sent_at = COALESCE(sent_at, ?6)
+             WHERE fingerprint_hash = ?1 AND mbid = ?2",
+            params![
+                blake3::hash(submission.fingerprint.as_bytes()).to_hex().as_str(),
+                submission.mbid,
+                status.as_str(),
+                submission_id,
+                error,
+                (status != SubmissionStatus::Queued).then(unix_now)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Count AcoustID submissions by status
+    pub fn acoustid_submission_counts(&self) -> Result<Vec<(String, usize)>> {
+        let conn = self.connection.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT status, COUNT(*) FROM acoustid_submissions GROUP BY status ORDER BY status",
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to count AcoustID submissions")?;
+        Ok(rows)
+    }
+
+    /// Clear all cached metadata and web service responses
+    ///
+    /// AcoustID submissions are kept, so fingerprints already sent aren't sent again.
     pub fn clear(&self) -> Result<()> {
         let conn = self.connection.lock().unwrap();
         conn.execute("DELETE FROM metadata_cache", [])?;
+        conn.execute("DELETE FROM acoustid_responses", [])?;
+        conn.execute("DELETE FROM musicbrainz_responses", [])?;
         Ok(())
     }
 
@@ -276,6 +752,14 @@ impl MetadataCache {
 
         let total_entries: i64 =
             conn.query_row("SELECT COUNT(*) FROM metadata_cache", [], |row| row.get(0))?;
+        let api_responses: i64 = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM acoustid_responses)
+                  + (SELECT COUNT(*) FROM musicbrainz_responses)",
+            [],
+            |row| row.get(0),
+        )?;
+        // get_path() takes the connection lock itself
+        drop(conn);
 
         let db_size = if let Ok(path) = self.get_path() {
             std::fs::metadata(&path).ok().map(|m| m.len()).unwrap_or(0)
@@ -285,6 +769,7 @@ impl MetadataCache {
 
         Ok(CacheStats {
             total_entries: total_entries as usize,
+            api_responses: api_responses as usize,
             db_size_bytes: db_size,
         })
     }
@@ -374,11 +859,13 @@ impl MetadataCache {
             all_files.par_iter().for_each(|file| {
                 pb.inc(1);
 
-                // Check if already cached
-                if let Ok(Some(_)) = self.get(file) {
-                    cache_hits.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    logger::debug(&format!("Already cached: {}", file.display()), verbose);
-                    return;
+                // Check if already cached (with a current fingerprint, if those are wanted)
+                if let Ok(Some(metadata)) = self.get(file) {
+                    if !with_fingerprints || metadata.fingerprint.is_some() {
+                        cache_hits.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        logger::debug(&format!("Already cached: {}", file.display()), verbose);
+                        return;
+                    }
                 }
 
                 // Extract and cache metadata
@@ -439,9 +926,48 @@ impl MetadataCache {
     }
 }
 
+/// Where a fingerprint submission to AcoustID stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmissionStatus {
+    /// Waiting to be sent
+    Queued,
+    /// Accepted by AcoustID, which hasn't imported it yet
+    Pending,
+    /// Part of the AcoustID database
+    Imported,
+    /// Rejected by AcoustID; never sent again
+    Failed,
+}
+
+impl SubmissionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubmissionStatus::Queued => "queued",
+            SubmissionStatus::Pending => "pending",
+            SubmissionStatus::Imported => "imported",
+            SubmissionStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A fingerprint queued or sent to AcoustID as a recording
+#[derive(Debug, Clone)]
+pub struct AcoustIdSubmission {
+    pub fingerprint: String,
+    pub duration: i64,
+    /// MusicBrainz recording ID
+    pub mbid: String,
+    /// The file the fingerprint was made from
+    pub path: PathBuf,
+    /// AcoustID's ID for the submission once sent
+    pub submission_id: Option<i64>,
+}
+
 #[derive(Debug)]
 pub struct CacheStats {
     pub total_entries: usize,
+    /// Cached AcoustID and MusicBrainz responses
+    pub api_responses: usize,
     pub db_size_bytes: u64,
 }
 
@@ -452,6 +978,12 @@ impl CacheStats {
             self.total_entries,
             self.db_size_bytes as f64 / 1024.0 / 1024.0
         ));
+        if self.api_responses > 0 {
+            crate::logger::info(&format!(
+                "Cache holds {} AcoustID/MusicBrainz responses",
+                self.api_responses
+            ));
+        }
     }
 }
 
@@ -470,3 +1002,70 @@ impl CacheCleanupStats {
         ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_web_service_responses_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MetadataCache::new(dir.path().join("cache.db")).unwrap();
+
+        cache.set_acoustid_response("AQAAfp", 200, "meta", "{}").unwrap();
+        assert_eq!(
+            cache.get_acoustid_response("AQAAfp", 200, "meta", 60).unwrap().as_deref(),
+            Some("{}")
+        );
+        assert!(cache.get_acoustid_response("AQAAfp", 201, "meta", 60).unwrap().is_none());
+        assert!(cache.get_acoustid_response("AQAAfp", 200, "other", 60).unwrap().is_none());
+
+        cache.set_musicbrainz_response("release", "rel-1", "inc", "{}").unwrap();
+        assert!(cache.get_musicbrainz_response("release", "rel-1", "inc", 60).unwrap().is_some());
+        assert!(cache.get_musicbrainz_response("recording", "rel-1", "inc", 60).unwrap().is_none());
+
+        // Backdate the responses past their TTL
+        cache
+            .connection
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "UPDATE acoustid_responses SET fetched_at = fetched_at - 120;
+                 UPDATE musicbrainz_responses SET fetched_at = fetched_at - 120;",
+            )
+            .unwrap();
+        assert!(cache.get_acoustid_response("AQAAfp", 200, "meta", 60).unwrap().is_none());
+        assert!(cache.get_musicbrainz_response("release", "rel-1", "inc", 60).unwrap().is_none());
+        assert_eq!(cache.stats().unwrap().api_responses, 2);
+    }
+
+    #[test]
+    fn test_acoustid_submissions_are_queued_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MetadataCache::new(dir.path().join("cache.db")).unwrap();
+        let path = Path::new("/music/a.flac");
+
+        assert!(cache.queue_acoustid_submission(path, "AQAAfp", 200, "rec-1").unwrap());
+        assert!(!cache.queue_acoustid_submission(path, "AQAAfp", 200, "rec-1").unwrap());
+        assert!(cache.queue_acoustid_submission(path, "AQAAfp", 200, "rec-2").unwrap());
+
+        let queued = cache.acoustid_submissions(SubmissionStatus::Queued).unwrap();
+        assert_eq!(queued.len(), 2);
+        assert_eq!((queued[0].mbid.as_str(), queued[0].path.as_path()), ("rec-1", path));
+
+        let (pending, failed) = (SubmissionStatus::Pending, SubmissionStatus::Failed);
+        cache.set_acoustid_submission_status(&queued[0], pending, Some(7), None).unwrap();
+        cache.set_acoustid_submission_status(&queued[1], failed, None, Some("bad")).unwrap();
+
+        // Sent submissions stay sent, even after clearing the cache
+        cache.clear().unwrap();
+        assert!(!cache.queue_acoustid_submission(path, "AQAAfp", 200, "rec-1").unwrap());
+        assert!(cache.acoustid_submissions(SubmissionStatus::Queued).unwrap().is_empty());
+        let sent = cache.acoustid_submissions(pending).unwrap();
+        assert_eq!(sent[0].submission_id, Some(7));
+        assert_eq!(
+            cache.acoustid_submission_counts().unwrap(),
+            vec![("failed".to_string(), 1), ("pending".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+// Content checksums for bit-rot detection
+//
+// Two BLAKE3 hashes are kept per file: one over every byte, and one over just the packets
+// of the audio stream as the demuxer returns them. When the file hash changes but the audio
+// hash doesn't, the damage is confined to tags or container data.
+use crate::utils;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentChecksums {
+    /// Hash of the whole file
+    pub file_hash: String,
+    /// Hash of the audio stream's packets
+    pub audio_hash: String,
+}
+
+/// Hash a file and its audio stream
+pub fn compute(path: &Path) -> Result<ContentChecksums> {
+    let file_hash =
+        utils::hash_file(path).with_context(|| format!("Failed to hash {}", path.display()))?;
+    let audio_hash = hash_audio_stream(path)?;
+    Ok(ContentChecksums {
+        file_hash,
+        audio_hash,
+    })
+}
+
+/// Hash the packets of the default audio track without decoding them
+pub fn hash_audio_stream(path: &Path) -> Result<String> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open audio file: {}", path.display()))?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension() {
+        hint.with_extension(&ext.to_string_lossy());
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .with_context(|| format!("Failed to probe audio file: {}", path.display()))?
+        .format;
+    let track_id = format
+        .default_track()
+        .ok_or_else(|| anyhow::anyhow!("No audio track in {}", path.display()))?
+        .id;
+
+    let mut hasher = blake3::Hasher::new();
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => {
+                hasher.update(packet.buf());
+            }
+            Ok(_) => {}
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e).context("Failed to read audio packet"),
+        }
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// 16-bit mono WAV with a LIST chunk after the audio
+    fn wav(samples: &[i16], comment: &[u8]) -> Vec<u8> {
+        let data_len = samples.len() as u32 * 2;
+        let list_len = 4 + 8 + comment.len() as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(4 + 24 + 8 + data_len + 8 + list_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&16000u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&list_len.to_le_bytes());
+        bytes.extend_from_slice(b"INFOICMT");
+        bytes.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(comment);
+        bytes
+    }
+
+    fn checksums_of(bytes: &[u8]) -> ContentChecksums {
+        let mut file = tempfile::Builder::new().suffix(".wav").tempfile().unwrap();
+        file.write_all(bytes).unwrap();
+        compute(file.path()).unwrap()
+    }
+
+    #[test]
+    fn test_audio_hash_ignores_tags() {
+        let samples: Vec<i16> = (0..8000).map(|i| (i % 300) as i16).collect();
+        let original = checksums_of(&wav(&samples, b"abcd"));
+
+        // Same audio, different comment
+        let retagged = checksums_of(&wav(&samples, b"wxyz"));
+        assert_ne!(original.file_hash, retagged.file_hash);
+        assert_eq!(original.audio_hash, retagged.audio_hash);
+
+        // One flipped sample
+        let mut damaged = samples.clone();
+        damaged[4000] ^= 0x40;
+        let damaged = checksums_of(&wav(&damaged, b"abcd"));
+        assert_ne!(original.audio_hash, damaged.audio_hash);
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub general: GeneralConfig,
@@ -18,6 +18,18 @@ pub struct Config {
 
     #[serde(default)]
     pub musicbrainz: MusicBrainzConfig,
+
+    #[serde(default)]
+    pub journal: JournalConfig,
+
+    #[serde(default)]
+    pub trash: TrashConfig,
+
+    #[serde(default)]
+    pub replaygain: ReplayGainConfig,
+
+    #[serde(default)]
+    pub dedupe: DedupeConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +49,14 @@ pub struct GeneralConfig {
     /// Metadata cache database path
     #[serde(default = "default_cache_path")]
     pub cache_path: PathBuf,
+
+    /// Metadata reader for cache misses ("native" or "ffprobe")
+    #[serde(default = "default_metadata_backend")]
+    pub metadata_backend: String,
+
+    /// Retry with ffprobe when the native reader can't read a file
+    #[serde(default)]
+    pub ffprobe_fallback: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,6 +107,10 @@ pub struct QualityConfig {
     /// Lossless format score bonus
     #[serde(default = "default_lossless_bonus")]
     pub lossless_bonus: u32,
+
+    /// Score files `ferric analyze` flagged as transcodes by their source quality
+    #[serde(default = "default_penalize_transcodes")]
+    pub penalize_transcodes: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -124,6 +148,73 @@ pub struct NamingConfig {
     /// Include "Various Artists" as valid album artist
     #[serde(default)]
     pub include_va: bool,
+
+    /// Destination path template used by sort (e.g. "$albumartist/$year - $album/$track $title")
+    /// Unset keeps the default Artist/Album/NN - Title layout
+    #[serde(default)]
+    pub path_template: Option<String>,
+
+    /// Where the disc number of multi-disc albums goes without a path template:
+    /// "folder" (Album/Disc 2/), "prefix" (2-03 - Title) or "none"
+    #[serde(default = "default_disc_layout")]
+    pub disc_layout: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalConfig {
+    /// Record file operations so runs can be listed with `history` and rolled back with `undo`
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Hash files before/after each operation so undo can detect later changes
+    #[serde(default = "default_true")]
+    pub hash_files: bool,
+
+    /// Journal database path (default: journal.db next to the metadata cache)
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashConfig {
+    /// Move lower quality duplicates into the trash instead of deleting them
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Trash directory (default: trash/ next to the metadata cache)
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+
+    /// Purge trash batches older than this many days (0 = never)
+    #[serde(default = "default_trash_max_age_days")]
+    pub max_age_days: u64,
+
+    /// Purge the oldest batches once the trash grows past this size in GB (0 = unlimited)
+    #[serde(default)]
+    pub max_size_gb: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayGainConfig {
+    /// Loudness the REPLAYGAIN_* gains bring tracks to, in LUFS (R128_* tags always use -23)
+    #[serde(default = "default_reference_loudness")]
+    pub reference_loudness: f64,
+
+    /// How tracks are grouped into albums: "folder" or "tag" (album artist + album)
+    #[serde(default = "default_album_grouping")]
+    pub album_grouping: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupeConfig {
+    /// Fraction of fingerprint bits that must agree for `dedupe --by-fingerprint` to treat two
+    /// files as the same recording
+    #[serde(default = "default_fingerprint_similarity")]
+    pub fingerprint_similarity: f64,
+
+    /// Largest length difference, in seconds, between files treated as the same recording
+    #[serde(default = "default_duration_tolerance")]
+    pub duration_tolerance: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,6 +225,12 @@ pub struct MusicBrainzConfig {
     #[serde(default)]
     pub acoustid_api_key: Option<String>,
 
+    /// Your AcoustID user API key, needed to submit fingerprints
+    /// Shown on https://acoustid.org/api-key once you're signed in
+    /// Can also be set via ACOUSTID_USER_KEY environment variable
+    #[serde(default)]
+    pub acoustid_user_key: Option<String>,
+
     /// Minimum confidence threshold for auto-applying metadata (0.0-1.0)
     #[serde(default = "default_confidence_threshold")]
     pub confidence_threshold: f32,
@@ -145,6 +242,77 @@ pub struct MusicBrainzConfig {
     /// Enable MusicBrainz lookups by default
     #[serde(default = "default_true")]
     pub enabled: bool,
+
+    /// Base URL of the AcoustID web service
+    #[serde(default = "default_acoustid_url")]
+    pub acoustid_url: String,
+
+    /// Base URL of the MusicBrainz web service, e.g. a self-hosted mirror
+    #[serde(default = "default_musicbrainz_url")]
+    pub musicbrainz_url: String,
+
+    /// Web service access: "live", "record" (save every response) or "replay" (offline)
+    #[serde(default = "default_http_mode")]
+    pub http_mode: String,
+
+    /// Directory recorded responses are saved to and replayed from
+    #[serde(default)]
+    pub recordings_path: Option<PathBuf>,
+
+    /// Days a cached AcoustID lookup is reused for (0 = don't cache)
+    #[serde(default = "default_acoustid_cache_days")]
+    pub acoustid_cache_days: u64,
+
+    /// Days a cached MusicBrainz recording or release is reused for (0 = don't cache)
+    #[serde(default = "default_musicbrainz_cache_days")]
+    pub musicbrainz_cache_days: u64,
+
+    /// Most AcoustID requests sent per second
+    #[serde(default = "default_acoustid_rate_limit")]
+    pub acoustid_rate_limit: f32,
+
+    /// Most MusicBrainz requests sent per second
+    #[serde(default = "default_musicbrainz_rate_limit")]
+    pub musicbrainz_rate_limit: f32,
+
+    /// How many times a request is retried while the service answers 503 or 429
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// Fingerprints looked up per AcoustID request (1 = one request per file)
+    #[serde(default = "default_acoustid_batch_size")]
+    pub acoustid_batch_size: usize,
+
+    /// Which of a recording's releases to tag files from
+    #[serde(default)]
+    pub release_preference: ReleasePreferenceConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleasePreferenceConfig {
+    /// Release countries, most preferred first (e.g. "US", "GB", "XW" for worldwide)
+    #[serde(default)]
+    pub countries: Vec<String>,
+
+    /// Medium formats, most preferred first (e.g. "CD", "Digital Media", "Vinyl")
+    #[serde(default)]
+    pub formats: Vec<String>,
+
+    /// Release group primary types, most preferred first
+    #[serde(default = "default_primary_types")]
+    pub primary_types: Vec<String>,
+
+    /// Release group secondary types to avoid (compilations, live albums, ...)
+    #[serde(default = "default_avoided_secondary_types")]
+    pub avoided_secondary_types: Vec<String>,
+
+    /// Which date wins between otherwise equal releases: "original" (earliest) or "latest"
+    #[serde(default = "default_release_date_preference")]
+    pub date_preference: String,
+
+    /// Taken off the preference score of "Various Artists" releases (0.0-1.0)
+    #[serde(default = "default_various_artists_penalty")]
+    pub various_artists_penalty: f64,
 }
 
 // Default value functions
@@ -159,6 +327,10 @@ fn default_cache_path() -> PathBuf {
         .join("metadata_cache.db")
 }
 
+fn default_metadata_backend() -> String {
+    "native".to_string()
+}
+
 fn default_output_format() -> String {
     "opus".to_string()
 }
@@ -187,6 +359,10 @@ fn default_lossless_bonus() -> u32 {
     10000
 }
 
+fn default_penalize_transcodes() -> bool {
+    true
+}
+
 fn default_opus_mult() -> f64 {
     1.8
 }
@@ -215,6 +391,10 @@ fn default_lowercase() -> bool {
     true
 }
 
+fn default_disc_layout() -> String {
+    "none".to_string()
+}
+
 fn default_confidence_threshold() -> f32 {
     0.7
 }
@@ -223,6 +403,88 @@ fn default_user_agent() -> String {
     format!("Ferric/{}", env!("CARGO_PKG_VERSION"))
 }
 
+fn default_acoustid_url() -> String {
+    "https://api.acoustid.org/v2".to_string()
+}
+
+fn default_musicbrainz_url() -> String {
+    "https://musicbrainz.org/ws/2".to_string()
+}
+
+fn default_http_mode() -> String {
+    "live".to_string()
+}
+
+fn default_acoustid_cache_days() -> u64 {
+    30
+}
+
+fn default_musicbrainz_cache_days() -> u64 {
+    90
+}
+
+fn default_acoustid_rate_limit() -> f32 {
+    3.0
+}
+
+fn default_musicbrainz_rate_limit() -> f32 {
+    1.0
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_acoustid_batch_size() -> usize {
+    10
+}
+
+fn default_primary_types() -> Vec<String> {
+    ["Album", "EP", "Single"].map(String::from).to_vec()
+}
+
+fn default_avoided_secondary_types() -> Vec<String> {
+    [
+        "Compilation",
+        "Live",
+        "Soundtrack",
+        "Remix",
+        "DJ-mix",
+        "Mixtape/Street",
+        "Demo",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_release_date_preference() -> String {
+    "original".to_string()
+}
+
+fn default_various_artists_penalty() -> f64 {
+    0.5
+}
+
+fn default_trash_max_age_days() -> u64 {
+    30
+}
+
+fn default_reference_loudness() -> f64 {
+    -18.0
+}
+
+fn default_album_grouping() -> String {
+    "folder".to_string()
+}
+
+fn default_fingerprint_similarity() -> f64 {
+    0.85
+}
+
+fn default_duration_tolerance() -> f64 {
+    3.0
+}
+
 fn default_true() -> bool {
     true
 }
@@ -244,6 +506,8 @@ impl Default for GeneralConfig {
             verbose: false,
             log_dir: None,
             cache_path: default_cache_path(),
+            metadata_backend: default_metadata_backend(),
+            ffprobe_fallback: false,
         }
     }
 }
@@ -269,6 +533,7 @@ impl Default for QualityConfig {
         Self {
             codec_multipliers: default_codec_multipliers(),
             lossless_bonus: default_lossless_bonus(),
+            penalize_transcodes: default_penalize_transcodes(),
         }
     }
 }
@@ -280,6 +545,8 @@ impl Default for NamingConfig {
             lowercase: default_lowercase(),
             prefer_artist: false,
             include_va: false,
+            path_template: None,
+            disc_layout: default_disc_layout(),
         }
     }
 }
@@ -288,25 +555,113 @@ impl Default for MusicBrainzConfig {
     fn default() -> Self {
         Self {
             acoustid_api_key: std::env::var("ACOUSTID_API_KEY").ok(),
+            acoustid_user_key: std::env::var("ACOUSTID_USER_KEY").ok(),
             confidence_threshold: default_confidence_threshold(),
             user_agent: default_user_agent(),
             enabled: default_true(),
+            acoustid_url: default_acoustid_url(),
+            musicbrainz_url: default_musicbrainz_url(),
+            http_mode: default_http_mode(),
+            recordings_path: None,
+            acoustid_cache_days: default_acoustid_cache_days(),
+            musicbrainz_cache_days: default_musicbrainz_cache_days(),
+            acoustid_rate_limit: default_acoustid_rate_limit(),
+            musicbrainz_rate_limit: default_musicbrainz_rate_limit(),
+            max_retries: default_max_retries(),
+            acoustid_batch_size: default_acoustid_batch_size(),
+            release_preference: ReleasePreferenceConfig::default(),
         }
     }
 }
 
-impl Default for Config {
+impl Default for ReleasePreferenceConfig {
     fn default() -> Self {
         Self {
-            general: GeneralConfig::default(),
-            convert: ConvertConfig::default(),
-            quality: QualityConfig::default(),
-            naming: NamingConfig::default(),
-            musicbrainz: MusicBrainzConfig::default(),
+            countries: Vec::new(),
+            formats: Vec::new(),
+            primary_types: default_primary_types(),
+            avoided_secondary_types: default_avoided_secondary_types(),
+            date_preference: default_release_date_preference(),
+            various_artists_penalty: default_various_artists_penalty(),
         }
     }
 }
 
+impl MusicBrainzConfig {
+    /// Resolve the recorded responses directory, defaulting to the cache directory
+    pub fn resolve_recordings_path(&self, cache_path: &Path) -> PathBuf {
+        self.recordings_path.clone().unwrap_or_else(|| {
+            cache_path
+                .parent()
+                .map(|p| p.join("recordings"))
+                .unwrap_or_else(|| PathBuf::from("recordings"))
+        })
+    }
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            hash_files: default_true(),
+            path: None,
+        }
+    }
+}
+
+impl JournalConfig {
+    /// Resolve the journal database path, defaulting to the cache directory
+    pub fn resolve_path(&self, cache_path: &Path) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| {
+            cache_path
+                .parent()
+                .map(|p| p.join("journal.db"))
+                .unwrap_or_else(|| PathBuf::from("journal.db"))
+        })
+    }
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            path: None,
+            max_age_days: default_trash_max_age_days(),
+            max_size_gb: 0.0,
+        }
+    }
+}
+
+impl Default for ReplayGainConfig {
+    fn default() -> Self {
+        Self {
+            reference_loudness: default_reference_loudness(),
+            album_grouping: default_album_grouping(),
+        }
+    }
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        Self {
+            fingerprint_similarity: default_fingerprint_similarity(),
+            duration_tolerance: default_duration_tolerance(),
+        }
+    }
+}
+
+impl TrashConfig {
+    /// Resolve the trash directory, defaulting to the cache directory
+    pub fn resolve_path(&self, cache_path: &Path) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| {
+            cache_path
+                .parent()
+                .map(|p| p.join("trash"))
+                .unwrap_or_else(|| PathBuf::from("trash"))
+        })
+    }
+}
+
 impl Config {
     /// Load configuration from TOML file
     pub fn from_file(path: &Path) -> Result<Self> {
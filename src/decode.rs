@@ -0,0 +1,259 @@
+// Audio decoding for the analysis commands and fingerprinting
+//
+// Files are decoded in-process with Symphonia. Codecs Symphonia has no decoder for
+// (Opus) are decoded by piping them through ffmpeg instead.
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecType, Decoder as CodecDecoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Frames read from ffmpeg per chunk
+const FFMPEG_CHUNK_FRAMES: usize = 16 * 1024;
+
+/// Streaming decoder producing interleaved f32 samples
+pub struct Decoder {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Length of the stream according to the file header, in frames
+    pub expected_frames: Option<u64>,
+    /// Whether that length is exact (lossless codecs) rather than an estimate
+    pub exact_length: bool,
+    /// Frames decoded so far
+    pub decoded_frames: u64,
+    /// Corrupt packets skipped so far, with the first error seen
+    pub decode_errors: usize,
+    pub first_decode_error: Option<String>,
+    inner: Inner,
+}
+
+enum Inner {
+    Native {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn CodecDecoder>,
+        track_id: u32,
+        buffer: Option<SampleBuffer<f32>>,
+    },
+    Ffmpeg {
+        child: Child,
+        stdout: ChildStdout,
+        frame_bytes: usize,
+        bytes: Vec<u8>,
+        samples: Vec<f32>,
+    },
+}
+
+impl Decoder {
+    /// Open the default audio track of a file
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with(path, false)
+    }
+
+    /// Open a file with the codec's own checksum verification enabled (FLAC's STREAMINFO
+    /// MD5), see `verify_checksum`
+    pub fn open_verifying(path: &Path) -> Result<Self> {
+        Self::open_with(path, true)
+    }
+
+    fn open_with(path: &Path, verify: bool) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open audio file: {}", path.display()))?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension() {
+            hint.with_extension(&ext.to_string_lossy());
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .with_context(|| format!("Failed to probe audio file: {}", path.display()))?;
+
+        let format = probed.format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| anyhow::anyhow!("No audio track in {}", path.display()))?;
+        let params = track.codec_params.clone();
+        let track_id = track.id;
+
+        let sample_rate = params
+            .sample_rate
+            .ok_or_else(|| anyhow::anyhow!("Unknown sample rate in {}", path.display()))?;
+        let channels = params.channels.map(|c| c.count()).unwrap_or(2);
+
+        let options = DecoderOptions { verify };
+        let inner = match symphonia::default::get_codecs().make(&params, &options) {
+            Ok(decoder) => Inner::Native {
+                format,
+                decoder,
+                track_id,
+                buffer: None,
+            },
+            Err(SymphoniaError::Unsupported(_)) => spawn_ffmpeg(path, sample_rate, channels)?,
+            Err(e) => return Err(e).context("Failed to create decoder"),
+        };
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            expected_frames: params.n_frames,
+            exact_length: is_lossless(params.codec),
+            decoded_frames: 0,
+            decode_errors: 0,
+            first_decode_error: None,
+            inner,
+        })
+    }
+
+    /// Next block of interleaved samples, None at the end of the stream
+    pub fn next_samples(&mut self) -> Result<Option<&[f32]>> {
+        match &mut self.inner {
+            Inner::Native {
+                format,
+                decoder,
+                track_id,
+                buffer,
+            } => loop {
+                let packet = match format.next_packet() {
+                    Ok(packet) => packet,
+                    Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                        return Ok(None)
+                    }
+                    Err(SymphoniaError::ResetRequired) => return Ok(None),
+                    Err(e) => return Err(e).context("Failed to read audio packet"),
+                };
+                if packet.track_id() != *track_id {
+                    continue;
+                }
+
+                let decoded = match decoder.decode(&packet) {
+                    Ok(decoded) => decoded,
+                    // A corrupt frame is skipped, like players do
+                    Err(SymphoniaError::DecodeError(e)) => {
+                        self.decode_errors += 1;
+                        self.first_decode_error.get_or_insert_with(|| e.to_string());
+                        continue;
+                    }
+                    Err(e) => return Err(e).context("Failed to decode audio"),
+                };
+                if decoded.frames() == 0 {
+                    continue;
+                }
+
+                // Samples per channel can grow between packets (e.g. variable block sizes)
+                let spec = *decoded.spec();
+                let needed = decoded.capacity() * spec.channels.count();
+                if buffer.as_ref().is_none_or(|b| b.capacity() < needed) {
+                    *buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                }
+                let buffer = buffer.as_mut().unwrap();
+                self.decoded_frames += decoded.frames() as u64;
+                buffer.copy_interleaved_ref(decoded);
+                return Ok(Some(buffer.samples()));
+            },
+            Inner::Ffmpeg {
+                child,
+                stdout,
+                frame_bytes,
+                bytes,
+                samples,
+            } => {
+                bytes.resize(FFMPEG_CHUNK_FRAMES * *frame_bytes, 0);
+                let mut filled = 0;
+                // Keep reading until whole frames are available so channels stay aligned
+                while filled == 0 || filled % *frame_bytes != 0 {
+                    let n = stdout
+                        .read(&mut bytes[filled..])
+                        .context("Failed to read decoded audio from ffmpeg")?;
+                    if n == 0 {
+                        break;
+                    }
+                    filled += n;
+                }
+
+                if filled == 0 {
+                    let status = child.wait().context("Failed to wait for ffmpeg")?;
+                    if !status.success() {
+                        anyhow::bail!("ffmpeg failed to decode the file");
+                    }
+                    return Ok(None);
+                }
+
+                let whole = filled - filled % *frame_bytes;
+                self.decoded_frames += (whole / *frame_bytes) as u64;
+                samples.clear();
+                samples.extend(
+                    bytes[..whole]
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                );
+                Ok(Some(samples.as_slice()))
+            }
+        }
+    }
+
+    /// Whether the decoded audio matched the checksum stored in the file, None when the file
+    /// has none or the decoder can't check it. Call after the last samples were read.
+    pub fn verify_checksum(&mut self) -> Option<bool> {
+        match &mut self.inner {
+            Inner::Native { decoder, .. } => decoder.finalize().verify_ok,
+            Inner::Ffmpeg { .. } => None,
+        }
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        if let Inner::Ffmpeg { child, .. } = &mut self.inner {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn is_lossless(codec: CodecType) -> bool {
+    symphonia::default::get_codecs()
+        .get_codec(codec)
+        .is_some_and(|d| matches!(d.short_name, "flac" | "alac") || d.short_name.starts_with("pcm"))
+}
+
+/// Decode through ffmpeg to raw f32 at the rate and channel count Symphonia reported
+fn spawn_ffmpeg(path: &Path, sample_rate: u32, channels: usize) -> Result<Inner> {
+    let mut child = Command::new("ffmpeg")
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-f", "f32le", "-acodec", "pcm_f32le"])
+        .args([
+            "-ar",
+            &sample_rate.to_string(),
+            "-ac",
+            &channels.to_string(),
+        ])
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("No native decoder for this codec and ffmpeg could not be started")?;
+    let stdout = child.stdout.take().unwrap();
+
+    Ok(Inner::Ffmpeg {
+        child,
+        stdout,
+        frame_bytes: 4 * channels,
+        bytes: Vec::new(),
+        samples: Vec::new(),
+    })
+}
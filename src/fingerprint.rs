@@ -1,11 +1,34 @@
+use crate::decode::Decoder;
 use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use lazy_static::lazy_static;
 use std::path::Path;
 use std::process::Command;
 
-/// Generate an audio fingerprint using Chromaprint (via ffmpeg)
+/// Chromaprint algorithm fingerprints are generated with (TEST2, fpcalc's default)
+pub const ALGORITHM: u8 = 1;
+/// Seconds of audio fingerprinted from the start of each file
+pub const LENGTH_SECS: u32 = 120;
+
+lazy_static! {
+    /// Checked once, so files aren't each tried with a missing fpcalc first
+    static ref FPCALC_AVAILABLE: bool = Command::new("fpcalc")
+        .arg("--version")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+}
+
+/// Overlap (in subfingerprints, about 8 per second) needed for a comparison to count
+const MIN_OVERLAP: usize = 40;
+/// Largest value stored in the 3-bit part of a compressed fingerprint
+const MAX_NORMAL_VALUE: u8 = 7;
+
+/// Generate an audio fingerprint using Chromaprint
 ///
-/// This function uses ffmpeg to decode the audio and pipe it to chromaprint's fpcalc tool,
-/// which generates an AcoustID fingerprint. The fingerprint is a compact representation of
+/// This function uses chromaprint's fpcalc tool when it's installed, and otherwise decodes
+/// the audio itself and fingerprints it with rusty-chromaprint. Either way the result is an
+/// AcoustID fingerprint. The fingerprint is a compact representation of
 /// the audio that can be used for:
 /// - Identifying tracks via AcoustID/MusicBrainz
 /// - Detecting duplicate files with different encodings
@@ -26,19 +49,21 @@ pub fn generate_fingerprint<P: AsRef<Path>>(audio_path: P) -> Result<String> {
         anyhow::bail!("Audio file does not exist: {}", path.display());
     }
 
-    // Try to use fpcalc directly first (most efficient)
-    match generate_fingerprint_fpcalc(path) {
-        Ok(fp) => return Ok(fp),
-        Err(e) => {
-            crate::logger::warning(&format!(
-                "fpcalc not available, trying ffmpeg method: {}",
-                e
-            ));
+    // Use fpcalc (the reference implementation) when it's installed
+    if *FPCALC_AVAILABLE {
+        match generate_fingerprint_fpcalc(path) {
+            Ok(fp) => return Ok(fp),
+            Err(e) => {
+                crate::logger::warning(&format!(
+                    "fpcalc failed, trying the built-in fingerprinter: {}",
+                    e
+                ));
+            }
         }
     }
 
-    // Fallback: use ffmpeg to decode and chromaprint library
-    generate_fingerprint_ffmpeg(path)
+    // Fallback: decode in-process and use the rusty-chromaprint library
+    generate_fingerprint_native(path)
 }
 
 /// Generate fingerprint using fpcalc command-line tool (fastest method)
@@ -49,7 +74,7 @@ fn generate_fingerprint_fpcalc<P: AsRef<Path>>(audio_path: P) -> Result<String>
     // -length 120 = only analyze first 120 seconds for speed
     // Default format (compressed) is compatible with AcoustID API
     let output = Command::new("fpcalc")
-        .args(&["-length", "120"])
+        .args(["-length", &LENGTH_SECS.to_string()])
         .arg(path)
         .output()
         .context("Failed to execute fpcalc - is chromaprint installed?")?;
@@ -71,87 +96,192 @@ fn generate_fingerprint_fpcalc<P: AsRef<Path>>(audio_path: P) -> Result<String>
     anyhow::bail!("fpcalc did not output a fingerprint")
 }
 
-/// Generate fingerprint using ffmpeg + rusty-chromaprint library
+/// Generate fingerprint by decoding in-process and feeding rusty-chromaprint
 ///
-/// This method decodes audio with ffmpeg and feeds it to rusty-chromaprint (pure Rust implementation).
-/// Fallback method when fpcalc is not available.
-fn generate_fingerprint_ffmpeg<P: AsRef<Path>>(audio_path: P) -> Result<String> {
-    let path = audio_path.as_ref();
+/// Samples go to the fingerprinter at the file's own rate and channel count; it downmixes
+/// and resamples them to chromaprint's 11025 Hz itself, the same way fpcalc does.
+fn generate_fingerprint_native(path: &Path) -> Result<String> {
+    let mut decoder = Decoder::open(path)?;
 
-    // Use ffmpeg to decode audio to raw PCM that chromaprint can process
-    // -t 120 = only decode first 120 seconds for speed
-    // -f s16le = signed 16-bit little-endian PCM
-    // -ac 1 = mono (rusty-chromaprint typically expects mono)
-    // -ar 16000 = 16kHz sample rate (standard for chromaprint)
-    let output = Command::new("ffmpeg")
-        .args(&[
-            "-v",
-            "quiet",
-            "-t",
-            "120", // Only process first 120 seconds for speed
-            "-i",
-        ])
-        .arg(path)
-        .args(&[
-            "-f",
-            "s16le",
-            "-ac",
-            "1", // Mono
-            "-ar",
-            "16000", // 16kHz
-            "pipe:1", // Output to stdout
-        ])
-        .output()
-        .context("Failed to decode audio with ffmpeg")?;
+    let config = rusty_chromaprint::Configuration::preset_test2();
+    let mut fingerprinter = rusty_chromaprint::Fingerprinter::new(&config);
+    fingerprinter
+        .start(decoder.sample_rate, decoder.channels as u32)
+        .context("Failed to start rusty-chromaprint fingerprinter")?;
 
-    if !output.status.success() {
-        anyhow::bail!("ffmpeg failed to decode audio file");
+    // Only the first LENGTH_SECS seconds are fingerprinted
+    let max_samples = LENGTH_SECS as usize * decoder.sample_rate as usize * decoder.channels;
+    let mut consumed = 0;
+    let mut pcm: Vec<i16> = Vec::new();
+    while consumed < max_samples {
+        let Some(samples) = decoder.next_samples()? else {
+            break;
+        };
+        let take = samples.len().min(max_samples - consumed);
+        pcm.clear();
+        pcm.extend(
+            samples[..take]
+                .iter()
+                .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+        );
+        fingerprinter.consume(&pcm);
+        consumed += take;
     }
+    fingerprinter.finish();
 
-    let pcm_data = output.stdout;
+    let fingerprint = fingerprinter.fingerprint();
+    if fingerprint.is_empty() {
+        anyhow::bail!("Audio is too short to fingerprint");
+    }
 
-    // Convert raw bytes to i16 samples
-    let samples: Vec<i16> = pcm_data
-        .chunks_exact(2)
-        .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
-        .collect();
+    // Compress it the same way fpcalc does, which is what AcoustID expects
+    let compressed = rusty_chromaprint::FingerprintCompressor::from(&config).compress(fingerprint);
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(compressed))
+}
 
-    // Use rusty-chromaprint to generate fingerprint
-    let config = rusty_chromaprint::Configuration::preset_test2();
-    let mut fingerprinter = rusty_chromaprint::Fingerprinter::new(&config);
+/// Whether a stored fingerprint is a comma-separated list of raw values, the format older
+/// versions stored for fingerprints made without fpcalc
+fn is_raw(fingerprint: &str) -> bool {
+    fingerprint.contains(',') || fingerprint.bytes().all(|b| b.is_ascii_digit())
+}
 
-    // Start fingerprinting: 16kHz sample rate, mono (1 channel)
-    fingerprinter
-        .start(16000, 1)
-        .context("Failed to start rusty-chromaprint fingerprinter")?;
+/// Chromaprint algorithm a stored fingerprint was generated with (None for raw values)
+pub fn algorithm_of(fingerprint: &str) -> Option<u8> {
+    let fingerprint = fingerprint.trim();
+    if is_raw(fingerprint) {
+        return None;
+    }
+    let header = general_purpose::URL_SAFE_NO_PAD
+        .decode(fingerprint.get(..4)?)
+        .ok()?;
+    header.first().copied()
+}
 
-    // Feed the audio samples
-    fingerprinter.consume(&samples);
+/// Decode a stored fingerprint into its subfingerprints
+///
+/// Accepts chromaprint's compressed base64 format as well as the comma-separated list of
+/// raw values older versions stored.
+pub fn decode(fingerprint: &str) -> Result<Vec<u32>> {
+    let fingerprint = fingerprint.trim();
+    if is_raw(fingerprint) {
+        return fingerprint
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .context("Invalid raw fingerprint");
+    }
 
-    // Finalize the fingerprint
-    fingerprinter.finish();
+    let bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(fingerprint)
+        .context("Fingerprint is not valid base64")?;
+    decompress(&bytes)
+}
 
-    // Get the fingerprint (returns &[u32])
-    let fingerprint = fingerprinter.fingerprint();
+/// Decompress chromaprint's compressed fingerprint format
+///
+/// Layout: algorithm byte, 24-bit big-endian item count, then for each item the XOR with the
+/// previous one as a list of set-bit position deltas, packed in 3 bits each and terminated by
+/// 0. Deltas of 7 or more continue in a 5-bit array that follows.
+fn decompress(bytes: &[u8]) -> Result<Vec<u32>> {
+    if bytes.len() < 4 {
+        anyhow::bail!("Compressed fingerprint is too short");
+    }
+    let count = (bytes[1] as usize) << 16 | (bytes[2] as usize) << 8 | bytes[3] as usize;
+
+    let mut normal = unpack(&bytes[4..], 3);
+    let mut found = 0;
+    let mut exceptional_count = 0usize;
+    let mut end = None;
+    for (i, &value) in normal.iter().enumerate() {
+        if value == 0 {
+            found += 1;
+            if found == count {
+                end = Some(i + 1);
+                break;
+            }
+        } else if value == MAX_NORMAL_VALUE {
+            exceptional_count += 1;
+        }
+    }
+    match end {
+        Some(end) => normal.truncate(end),
+        None if count == 0 => normal.clear(),
+        None => anyhow::bail!("Compressed fingerprint is truncated"),
+    }
 
-    // Encode fingerprint as comma-separated string for storage
-    // This format can be used with AcoustID API
-    let fp_str = fingerprint
-        .iter()
-        .map(|x| x.to_string())
-        .collect::<Vec<_>>()
-        .join(",");
+    let exceptional_offset = 4 + (normal.len() * 3).div_ceil(8);
+    if bytes.len() < exceptional_offset + (exceptional_count * 5).div_ceil(8) {
+        anyhow::bail!("Compressed fingerprint is truncated");
+    }
+    let mut exceptional = unpack(&bytes[exceptional_offset..], 5).into_iter();
+    for value in normal.iter_mut().filter(|v| **v == MAX_NORMAL_VALUE) {
+        *value += exceptional.next().unwrap_or(0);
+    }
+
+    let mut items = Vec::with_capacity(count);
+    let mut value = 0u32;
+    let mut last_bit = 0u32;
+    for &delta in &normal {
+        if delta == 0 {
+            items.push(items.last().map_or(value, |previous| value ^ previous));
+            value = 0;
+            last_bit = 0;
+            continue;
+        }
+        last_bit += delta as u32;
+        if last_bit > 32 {
+            anyhow::bail!("Compressed fingerprint has an invalid bit position");
+        }
+        value |= 1 << (last_bit - 1);
+    }
 
-    Ok(fp_str)
+    Ok(items)
+}
+
+/// Unpack little-endian bit-packed integers of `width` bits
+fn unpack(bytes: &[u8], width: usize) -> Vec<u8> {
+    let total_bits = bytes.len() * 8;
+    (0..total_bits / width)
+        .map(|i| {
+            (0..width).fold(0u8, |value, bit| {
+                let pos = i * width + bit;
+                value | ((bytes[pos / 8] >> (pos % 8)) & 1) << bit
+            })
+        })
+        .collect()
+}
+
+/// Fraction of bits that agree between two fingerprints at their best alignment
+///
+/// rusty-chromaprint's matcher finds the alignment, but its segments only cover the stretches
+/// that match, so the agreement is measured over the whole overlap instead. Returns None when
+/// the two have nothing in common to align on.
+pub fn similarity(a: &[u32], b: &[u32]) -> Option<f64> {
+    let config = rusty_chromaprint::Configuration::preset_test2();
+    let segments = rusty_chromaprint::match_fingerprints(a, b, &config).ok()?;
+    let segment = segments.first()?;
+    aligned_similarity(a, b, segment.offset1 as isize - segment.offset2 as isize)
+}
+
+/// Bit agreement of `a[i + offset]` against `b[i]` over their overlap
+fn aligned_similarity(a: &[u32], b: &[u32], offset: isize) -> Option<f64> {
+    let (a, b) = if offset >= 0 {
+        (a.get(offset as usize..)?, b)
+    } else {
+        (a, b.get(offset.unsigned_abs()..)?)
+    };
+    let overlap = a.len().min(b.len());
+    if overlap < MIN_OVERLAP {
+        return None;
+    }
+
+    let errors: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+    Some(1.0 - errors as f64 / (overlap as f64 * 32.0))
 }
 
 /// Check if fingerprinting tools are available on the system
 pub fn check_fingerprint_availability() -> FingerprintAvailability {
-    let fpcalc_available = Command::new("fpcalc")
-        .arg("--version")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false);
+    let fpcalc_available = *FPCALC_AVAILABLE;
 
     let ffmpeg_available = Command::new("ffmpeg")
         .arg("-version")
@@ -168,31 +298,26 @@ pub fn check_fingerprint_availability() -> FingerprintAvailability {
 #[derive(Debug, Clone)]
 pub struct FingerprintAvailability {
     pub fpcalc_available: bool,
+    /// Needed by the built-in fingerprinter for Opus files only
     pub ffmpeg_available: bool,
 }
 
 impl FingerprintAvailability {
-    pub fn is_available(&self) -> bool {
-        self.fpcalc_available || self.ffmpeg_available
-    }
-
     pub fn print_status(&self) {
         if self.fpcalc_available {
             crate::logger::success("fpcalc (chromaprint) is available - fingerprinting enabled");
         } else {
-            crate::logger::warning("fpcalc not found - install chromaprint for better performance");
+            crate::logger::info("fpcalc not found - using the built-in fingerprinter");
         }
 
-        if self.ffmpeg_available {
-            crate::logger::success("ffmpeg is available - can decode audio for fingerprinting");
-        } else {
-            crate::logger::error("ffmpeg not found - fingerprinting will not work!");
-        }
-
-        if !self.is_available() {
-            crate::logger::error(
-                "Audio fingerprinting is disabled. Install fpcalc or ffmpeg + libchromaprint.",
-            );
+        if !self.fpcalc_available {
+            if self.ffmpeg_available {
+                crate::logger::success("ffmpeg is available - can decode Opus for fingerprinting");
+            } else {
+                crate::logger::warning(
+                    "ffmpeg not found - Opus files need fpcalc to be fingerprinted",
+                );
+            }
         }
     }
 }
@@ -252,6 +377,109 @@ pub fn generate_fingerprints_parallel(
 mod tests {
     use super::*;
 
+    /// Deterministic pseudo-random subfingerprints
+    fn noise(seed: u64, len: usize) -> Vec<u32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 32) as u32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_formats() {
+        assert_eq!(decode("1,3,4294967295").unwrap(), vec![1, 3, u32::MAX]);
+
+        // [1, 3]: deltas 1,0 then (1^3 = 2) 2,0, packed as 3-bit values
+        let compressed = general_purpose::URL_SAFE_NO_PAD.encode([1, 0, 0, 2, 0x81, 0x00]);
+        assert_eq!(decode(&compressed).unwrap(), vec![1, 3]);
+
+        // Bit 32 needs a delta of 32 = 7 + 25 from the exceptional array
+        let compressed = general_purpose::URL_SAFE_NO_PAD.encode([1, 0, 0, 1, 0x07, 0x19]);
+        assert_eq!(decode(&compressed).unwrap(), vec![1 << 31]);
+
+        assert!(decode(&general_purpose::URL_SAFE_NO_PAD.encode([1, 0, 0, 5, 0x00])).is_err());
+    }
+
+    #[test]
+    fn test_decode_chromaprint_compression() {
+        let config = rusty_chromaprint::Configuration::preset_test2();
+        let compressor = rusty_chromaprint::FingerprintCompressor::from(&config);
+        let encode = |items: &[u32]| {
+            general_purpose::URL_SAFE_NO_PAD.encode(compressor.compress(items))
+        };
+
+        let items = noise(3, 500);
+        let encoded = encode(&items);
+        assert_eq!(decode(&encoded).unwrap(), items);
+        assert_eq!(algorithm_of(&encoded), Some(ALGORITHM));
+        assert_eq!(algorithm_of("1,3,4294967295"), None);
+        assert_eq!(decode(&encode(&[])).unwrap(), Vec::<u32>::new());
+    }
+
+    /// 16-bit mono WAV at 22050 Hz of a melody of short sine tones
+    fn melody_wav(seconds: u32) -> Vec<u8> {
+        let rate = 22050u32;
+        let frames = rate * seconds;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + frames * 2).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&rate.to_le_bytes());
+        bytes.extend_from_slice(&(rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(frames * 2).to_le_bytes());
+        let notes = [220.0, 277.2, 329.6, 440.0, 392.0, 293.7, 246.9, 349.2];
+        for i in 0..frames {
+            let t = i as f32 / rate as f32;
+            let note = notes[(i / (rate / 4)) as usize % notes.len()];
+            let sample = (t * note * std::f32::consts::TAU).sin() * 12000.0;
+            bytes.extend_from_slice(&(sample as i16).to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_native_fingerprint() {
+        use std::io::Write;
+
+        let mut file = tempfile::Builder::new().suffix(".wav").tempfile().unwrap();
+        file.write_all(&melody_wav(12)).unwrap();
+
+        let fingerprint = generate_fingerprint_native(file.path()).unwrap();
+        assert_eq!(algorithm_of(&fingerprint), Some(ALGORITHM));
+        let items = decode(&fingerprint).unwrap();
+        assert!(items.len() > 50, "{}", items.len());
+        assert_eq!(similarity(&items, &items), Some(1.0));
+    }
+
+    #[test]
+    fn test_similarity() {
+        let original = noise(1, 900);
+
+        // Same recording starting 2 seconds later, with a few bits flipped by the encoder
+        let mut reencoded: Vec<u32> = original[16..].to_vec();
+        for (i, item) in reencoded.iter_mut().enumerate() {
+            if i % 3 == 0 {
+                *item ^= 1 << (i % 32);
+            }
+        }
+        let score = similarity(&original, &reencoded).unwrap();
+        assert!(score > 0.98, "{}", score);
+
+        // An unrelated recording shares nothing to align on
+        assert!(similarity(&original, &noise(2, 900)).is_none());
+    }
+
     #[test]
     fn test_fingerprint_availability() {
         let availability = check_fingerprint_availability();
@@ -0,0 +1,264 @@
+// HTTP access to the AcoustID and MusicBrainz web services
+//
+// Every request goes through `Client`, which can save each response to a directory and later
+// serve the saved responses back without touching the network, so a tagging run can be
+// reproduced exactly. Recordings are keyed by the request, leaving out the AcoustID API keys,
+// and the keys themselves are never written to disk.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Form fields that identify the caller rather than the request
+const PRIVATE_FIELDS: &[&str] = &["client", "user"];
+
+/// How the web services are reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Send requests to the network
+    Live,
+    /// Send requests to the network and save every response
+    Record,
+    /// Serve saved responses and never touch the network
+    Replay,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "live" => Ok(Mode::Live),
+            "record" => Ok(Mode::Record),
+            "replay" => Ok(Mode::Replay),
+            other => anyhow::bail!(
+                "Unknown [musicbrainz] http_mode '{}' (expected 'live', 'record' or 'replay')",
+                other
+            ),
+        }
+    }
+}
+
+/// A response, live or replayed
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+    /// How long the service asked to be left alone for, from its Retry-After header
+    pub retry_after: Option<Duration>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The service is overloaded or rate limiting us, and the request should be sent again
+    pub fn is_busy(&self) -> bool {
+        self.status == 503 || self.status == 429
+    }
+
+    pub fn json(&self) -> Result<serde_json::Value> {
+        serde_json::from_str(&self.body).context("Response is not valid JSON")
+    }
+}
+
+/// One saved request and its response
+#[derive(Serialize, Deserialize)]
+struct Recording {
+    method: String,
+    url: String,
+    form: Vec<(String, String)>,
+    status: u16,
+    body: String,
+}
+
+pub struct Client {
+    inner: reqwest::Client,
+    user_agent: String,
+    mode: Mode,
+    recordings_path: PathBuf,
+}
+
+impl Client {
+    pub fn new(user_agent: &str, mode: Mode, recordings_path: &Path) -> Self {
+        Self {
+            inner: reqwest::Client::new(),
+            user_agent: user_agent.to_string(),
+            mode,
+            recordings_path: recordings_path.to_path_buf(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn recordings_path(&self) -> &Path {
+        &self.recordings_path
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response> {
+        self.send("GET", url, &[]).await
+    }
+
+    pub async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> Result<Response> {
+        self.send("POST", url, form).await
+    }
+
+    async fn send(&self, method: &str, url: &str, form: &[(&str, &str)]) -> Result<Response> {
+        let public_form: Vec<(String, String)> = form
+            .iter()
+            .filter(|(name, _)| !PRIVATE_FIELDS.contains(name))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let path = self
+            .recordings_path
+            .join(format!("{}.json", recording_key(method, url, &public_form)));
+
+        if self.mode == Mode::Replay {
+            let contents = fs::read_to_string(&path).with_context(|| {
+                format!(
+                    "No recorded response for {} {} in {}",
+                    method,
+                    url,
+                    self.recordings_path.display()
+                )
+            })?;
+            let recording: Recording = serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse recording: {}", path.display()))?;
+            return Ok(Response {
+                status: recording.status,
+                body: recording.body,
+                retry_after: None,
+            });
+        }
+
+        let request = if method == "POST" {
+            self.inner.post(url).form(form)
+        } else {
+            self.inner.get(url)
+        };
+        let response = request
+            .header(reqwest::header::USER_AGENT, &self.user_agent)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {}", url))?;
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response
+            .text()
+            .await
+            .with_context(|| format!("Failed to read response from {}", url))?;
+
+        if self.mode == Mode::Record {
+            let recording = Recording {
+                method: method.to_string(),
+                url: url.to_string(),
+                form: public_form,
+                status,
+                body: body.clone(),
+            };
+            fs::create_dir_all(&self.recordings_path).with_context(|| {
+                format!(
+                    "Failed to create recordings directory: {}",
+                    self.recordings_path.display()
+                )
+            })?;
+            fs::write(&path, serde_json::to_string_pretty(&recording)?)
+                .with_context(|| format!("Failed to save recording: {}", path.display()))?;
+        }
+
+        Ok(Response {
+            status,
+            body,
+            retry_after,
+        })
+    }
+}
+
+/// Parse a Retry-After header, given either in seconds or as an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// File name a request's response is saved under
+fn recording_key(method: &str, url: &str, form: &[(String, String)]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(url.as_bytes());
+    for (name, value) in form {
+        hasher.update(b"\n");
+        hasher.update(name.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+    }
+    hasher.finalize().to_hex()[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A one-shot server; replaying must not need it anymore
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v2/lookup", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            let body = r#"{"status": "ok"}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let form = [("client", "secret"), ("duration", "200")];
+        let recorder = Client::new("test", Mode::Record, dir.path());
+        let live = recorder.post_form(&url, &form).await.unwrap();
+        server.join().unwrap();
+        assert_eq!(live.json().unwrap()["status"], "ok");
+
+        let saved = fs::read_dir(dir.path()).unwrap().next().unwrap().unwrap();
+        assert!(!fs::read_to_string(saved.path()).unwrap().contains("secret"));
+
+        // Another API key replays the same response
+        let replayer = Client::new("test", Mode::Replay, dir.path());
+        let form = [("client", "other"), ("duration", "200")];
+        let replayed = replayer.post_form(&url, &form).await.unwrap();
+        assert_eq!((replayed.status, replayed.body), (live.status, live.body));
+
+        assert!(replayer.get(&url).await.is_err());
+        assert!(Mode::parse("offline").is_err());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("5"), Some(Duration::from_secs(5)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
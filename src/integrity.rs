@@ -0,0 +1,202 @@
+// Audio integrity checking
+//
+// A file is decoded from start to finish and checked for corrupt packets, for audio that
+// ends before the length its header promises, and (for FLAC) for decoded audio that doesn't
+// match the MD5 stored in STREAMINFO. Decoding runs behind `catch_unwind` so a decoder that
+// panics on a broken file only fails that file.
+use crate::decode::Decoder;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+/// Audio missing before a lossy file counts as truncated; their header lengths can be
+/// estimates, while lossless formats must match to the sample
+const LOSSY_TOLERANCE_SECS: f64 = 0.5;
+
+/// Something wrong with a file's audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// The file couldn't be opened or read at all
+    Unreadable { error: String },
+    /// The decoder panicked
+    Crashed { error: String },
+    /// Corrupt packets that had to be skipped
+    DecodeErrors { count: usize, first: String },
+    /// Decoded audio is shorter than the header says (cut off, or frames dropped as corrupt)
+    Truncated {
+        expected_secs: f64,
+        decoded_secs: f64,
+    },
+    /// Decoded audio doesn't match the checksum stored in the file
+    ChecksumMismatch,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unreadable { error } => write!(f, "unreadable: {}", error),
+            Problem::Crashed { error } => write!(f, "decoder crashed: {}", error),
+            Problem::DecodeErrors { count, first } => {
+                write!(f, "{} decode error(s), first: {}", count, first)
+            }
+            Problem::Truncated {
+                expected_secs,
+                decoded_secs,
+            } => write!(
+                f,
+                "truncated or damaged: {:.2}s of {:.2}s decoded",
+                decoded_secs, expected_secs
+            ),
+            Problem::ChecksumMismatch => write!(f, "audio doesn't match the stored MD5"),
+        }
+    }
+}
+
+/// Cached result of verifying a file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntegrityCheck {
+    pub problems: Vec<Problem>,
+    /// Whether a stored checksum was compared
+    pub checksum_verified: bool,
+}
+
+impl IntegrityCheck {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Result for a file whose decoder or probe panicked
+    pub fn crashed(error: String) -> Self {
+        IntegrityCheck {
+            problems: vec![Problem::Crashed { error }],
+            checksum_verified: false,
+        }
+    }
+}
+
+/// Run `f`, turning a panic into its message so it only fails the file at hand
+pub fn isolate<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+/// Decode a file completely and report what's wrong with it
+pub fn check_file(path: &Path) -> IntegrityCheck {
+    match isolate(|| decode_and_check(path)) {
+        Ok(Ok(check)) => check,
+        Ok(Err(e)) => IntegrityCheck {
+            problems: vec![Problem::Unreadable {
+                error: format!("{:#}", e),
+            }],
+            checksum_verified: false,
+        },
+        Err(error) => IntegrityCheck::crashed(error),
+    }
+}
+
+fn decode_and_check(path: &Path) -> anyhow::Result<IntegrityCheck> {
+    let mut decoder = Decoder::open_verifying(path)?;
+    while decoder.next_samples()?.is_some() {}
+
+    let mut problems = Vec::new();
+    if decoder.decode_errors > 0 {
+        problems.push(Problem::DecodeErrors {
+            count: decoder.decode_errors,
+            first: decoder.first_decode_error.clone().unwrap_or_default(),
+        });
+    }
+
+    if let Some(expected) = decoder.expected_frames {
+        let rate = decoder.sample_rate as f64;
+        let expected_secs = expected as f64 / rate;
+        let decoded_secs = decoder.decoded_frames as f64 / rate;
+        let tolerance = if decoder.exact_length {
+            0.0
+        } else {
+            LOSSY_TOLERANCE_SECS
+        };
+        if expected_secs - decoded_secs > tolerance {
+            problems.push(Problem::Truncated {
+                expected_secs,
+                decoded_secs,
+            });
+        }
+    }
+
+    let checksum = decoder.verify_checksum();
+    if checksum == Some(false) {
+        problems.push(Problem::ChecksumMismatch);
+    }
+
+    Ok(IntegrityCheck {
+        problems,
+        checksum_verified: checksum.is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// 16-bit mono WAV of `frames` samples of a quiet ramp
+    fn wav(frames: u32) -> Vec<u8> {
+        let data_len = frames * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&16000u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for i in 0..frames {
+            bytes.extend_from_slice(&((i % 1000) as i16).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn check_bytes(bytes: &[u8]) -> IntegrityCheck {
+        let mut file = tempfile::Builder::new().suffix(".wav").tempfile().unwrap();
+        file.write_all(bytes).unwrap();
+        check_file(file.path())
+    }
+
+    #[test]
+    fn test_intact_file_passes() {
+        let check = check_bytes(&wav(16000));
+        assert!(check.passed(), "{:?}", check.problems);
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        // Cut two of the promised three seconds off
+        let bytes = wav(24000);
+        let check = check_bytes(&bytes[..bytes.len() - 32000]);
+        assert!(matches!(
+            check.problems.as_slice(),
+            [Problem::Truncated { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_garbage_is_unreadable() {
+        let check = check_bytes(b"definitely not audio");
+        assert!(matches!(
+            check.problems.as_slice(),
+            [Problem::Unreadable { .. }]
+        ));
+    }
+}
@@ -0,0 +1,766 @@
+use crate::utils;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    static ref GLOBAL_JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+}
+
+/// Kind of file operation recorded in the journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// File or directory renamed from `source` to `dest`
+    Move,
+    /// File copied from `source` to `dest`
+    Copy,
+    /// New file written at `dest` (e.g. conversion output)
+    Create,
+    /// File removed from `source`
+    Delete,
+    /// File moved from `source` into the trash at `dest`
+    Trash,
+    /// Symlink created at `dest`, target kept in details
+    Symlink,
+    /// Tags rewritten in place at `source`, old values kept in details
+    TagRewrite,
+    /// Directory created at `dest` to hold a moved or copied file
+    CreateDir,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Move => "move",
+            Action::Copy => "copy",
+            Action::Create => "create",
+            Action::Delete => "delete",
+            Action::Trash => "trash",
+            Action::Symlink => "symlink",
+            Action::TagRewrite => "tag_rewrite",
+            Action::CreateDir => "create_dir",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "move" => Some(Action::Move),
+            "copy" => Some(Action::Copy),
+            "create" => Some(Action::Create),
+            "delete" => Some(Action::Delete),
+            "trash" => Some(Action::Trash),
+            "symlink" => Some(Action::Symlink),
+            "tag_rewrite" => Some(Action::TagRewrite),
+            "create_dir" => Some(Action::CreateDir),
+            _ => None,
+        }
+    }
+}
+
+/// A single recorded file operation
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub id: i64,
+    pub session_id: i64,
+    pub action: Action,
+    pub source: Option<PathBuf>,
+    pub dest: Option<PathBuf>,
+    pub before_hash: Option<String>,
+    pub after_hash: Option<String>,
+    pub details: serde_json::Value,
+    pub undone: bool,
+}
+
+/// One ferric invocation that modified files
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: i64,
+    pub command: String,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub status: String,
+    pub entry_count: usize,
+    pub undone_count: usize,
+}
+
+/// Thread-safe operation journal using SQLite
+#[derive(Clone)]
+pub struct Journal {
+    connection: Arc<Mutex<Connection>>,
+    session_id: Option<i64>,
+    hash_files: bool,
+}
+
+/// Initialize the process-wide journal (safe to call multiple times)
+pub fn init_global_journal<P: AsRef<Path>>(db_path: P, hash_files: bool) -> Result<()> {
+    let journal = Journal::new(db_path, hash_files)?;
+    let mut global = GLOBAL_JOURNAL.lock().unwrap();
+    *global = Some(journal);
+    Ok(())
+}
+
+/// Get the initialized journal (if any)
+pub fn get_global_journal() -> Option<Journal> {
+    GLOBAL_JOURNAL
+        .lock()
+        .ok()
+        .and_then(|guard| guard.as_ref().cloned())
+}
+
+/// Start recording a session on the global journal
+pub fn begin_session(command: &str) -> Result<Option<i64>> {
+    let mut global = GLOBAL_JOURNAL.lock().unwrap();
+    match global.as_mut() {
+        Some(journal) => journal.begin_session(command).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Finish the current session on the global journal
+/// Sessions that recorded nothing are dropped so `history` only lists real changes
+pub fn finish_session(succeeded: bool) -> Result<()> {
+    let mut global = GLOBAL_JOURNAL.lock().unwrap();
+    if let Some(journal) = global.as_mut() {
+        journal.finish_session(if succeeded { "completed" } else { "failed" })?;
+    }
+    Ok(())
+}
+
+fn now_secs() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Best-effort absolute path (files may not exist yet, so canonicalize can fail)
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir()
+                .map(|cwd| cwd.join(path))
+                .unwrap_or_else(|_| path.to_path_buf())
+        }
+    })
+}
+
+fn path_to_string(path: &Option<PathBuf>) -> Option<String> {
+    path.as_ref().map(|p| p.to_string_lossy().to_string())
+}
+
+impl Journal {
+    /// Create or open a journal database
+    pub fn new<P: AsRef<Path>>(db_path: P, hash_files: bool) -> Result<Self> {
+        let path = db_path.as_ref();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create journal directory")?;
+        }
+
+        let conn = Connection::open(path).context("Failed to open journal database")?;
+
+        conn.execute_batch("PRAGMA journal_mode=WAL;")
+            .context("Failed to enable WAL mode")?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                command TEXT NOT NULL,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER,
+                status TEXT NOT NULL
+            )",
+            [],
+        )
+        .context("Failed to create sessions table")?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS entries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                source TEXT,
+                dest TEXT,
+                before_hash TEXT,
+                after_hash TEXT,
+                details TEXT,
+                created_at INTEGER NOT NULL,
+                undone INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )
+        .context("Failed to create entries table")?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_entries_session ON entries(session_id)",
+            [],
+        )
+        .context("Failed to create entries index")?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(conn)),
+            session_id: None,
+            hash_files,
+        })
+    }
+
+    /// Start a new session and make it the target for recorded operations
+    pub fn begin_session(&mut self, command: &str) -> Result<i64> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "INSERT INTO sessions (command, started_at, status) VALUES (?1, ?2, 'running')",
+            params![command, now_secs()],
+        )?;
+        let id = conn.last_insert_rowid();
+        drop(conn);
+
+        self.session_id = Some(id);
+        Ok(id)
+    }
+
+    /// Close the current session, removing it if nothing was recorded
+    pub fn finish_session(&mut self, status: &str) -> Result<()> {
+        let Some(id) = self.session_id.take() else {
+            return Ok(());
+        };
+
+        let conn = self.connection.lock().unwrap();
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM entries WHERE session_id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+
+        if count == 0 {
+            conn.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+        } else {
+            conn.execute(
+                "UPDATE sessions SET finished_at = ?1, status = ?2 WHERE id = ?3",
+                params![now_secs(), status, id],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether operations are currently being recorded
+    pub fn is_recording(&self) -> bool {
+        self.session_id.is_some()
+    }
+
+    /// Hash a file if hashing is enabled (directories and missing files yield None)
+    pub fn hash(&self, path: &Path) -> Option<String> {
+        if !self.hash_files || !path.is_file() {
+            return None;
+        }
+        utils::hash_file(path).ok()
+    }
+
+    /// Record an operation in the current session
+    pub fn record(
+        &self,
+        action: Action,
+        source: Option<&Path>,
+        dest: Option<&Path>,
+        before_hash: Option<String>,
+        after_hash: Option<String>,
+        details: serde_json::Value,
+    ) -> Result<()> {
+        let Some(session_id) = self.session_id else {
+            return Ok(());
+        };
+
+        let source = source.map(absolute);
+        let dest = dest.map(absolute);
+        let details = if details.is_null() {
+            None
+        } else {
+            Some(details.to_string())
+        };
+
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "INSERT INTO entries (session_id, action, source, dest, before_hash, after_hash, details, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                session_id,
+                action.as_str(),
+                path_to_string(&source),
+                path_to_string(&dest),
+                before_hash,
+                after_hash,
+                details,
+                now_secs(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// List the most recent sessions (newest first)
+    pub fn sessions(&self, limit: usize) -> Result<Vec<Session>> {
+        let conn = self.connection.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.command, s.started_at, s.finished_at, s.status,
+                    (SELECT COUNT(*) FROM entries e WHERE e.session_id = s.id),
+                    (SELECT COUNT(*) FROM entries e WHERE e.session_id = s.id AND e.undone = 1)
+             FROM sessions s
+             ORDER BY s.id DESC
+             LIMIT ?1",
+        )?;
+
+        let sessions = stmt
+            .query_map(params![limit as i64], Self::session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    /// Look up a single session
+    pub fn session(&self, id: i64) -> Result<Option<Session>> {
+        let conn = self.connection.lock().unwrap();
+        let session = conn
+            .query_row(
+                "SELECT s.id, s.command, s.started_at, s.finished_at, s.status,
+                        (SELECT COUNT(*) FROM entries e WHERE e.session_id = s.id),
+                        (SELECT COUNT(*) FROM entries e WHERE e.session_id = s.id AND e.undone = 1)
+                 FROM sessions s
+                 WHERE s.id = ?1",
+                params![id],
+                Self::session_from_row,
+            )
+            .optional()?;
+
+        Ok(session)
+    }
+
+    fn session_from_row(row: &rusqlite::Row) -> rusqlite::Result<Session> {
+        Ok(Session {
+            id: row.get(0)?,
+            command: row.get(1)?,
+            started_at: row.get(2)?,
+            finished_at: row.get(3)?,
+            status: row.get(4)?,
+            entry_count: row.get::<_, i64>(5)? as usize,
+            undone_count: row.get::<_, i64>(6)? as usize,
+        })
+    }
+
+    /// All entries of a session in the order they were recorded
+    pub fn entries(&self, session_id: i64) -> Result<Vec<JournalEntry>> {
+        let conn = self.connection.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, action, source, dest, before_hash, after_hash, details, undone
+             FROM entries
+             WHERE session_id = ?1
+             ORDER BY id ASC",
+        )?;
+
+        let rows = stmt
+            .query_map(params![session_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, i64>(8)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut entries = Vec::with_capacity(rows.len());
+        for (id, session_id, action, source, dest, before_hash, after_hash, details, undone) in rows
+        {
+            let action = Action::parse(&action)
+                .with_context(|| format!("Unknown journal action '{}'", action))?;
+            entries.push(JournalEntry {
+                id,
+                session_id,
+                action,
+                source: source.map(PathBuf::from),
+                dest: dest.map(PathBuf::from),
+                before_hash,
+                after_hash,
+                details: details
+                    .and_then(|d| serde_json::from_str(&d).ok())
+                    .unwrap_or(serde_json::Value::Null),
+                undone: undone != 0,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Flag an entry as rolled back
+    pub fn mark_undone(&self, entry_id: i64) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute("UPDATE entries SET undone = 1 WHERE id = ?1", params![entry_id])?;
+        Ok(())
+    }
+
+    /// Update a session's status (e.g. after undo)
+    pub fn set_session_status(&self, session_id: i64, status: &str) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET status = ?1 WHERE id = ?2",
+            params![status, session_id],
+        )?;
+        Ok(())
+    }
+}
+
+impl Session {
+    pub fn print_row(&self) {
+        let started = chrono::DateTime::from_timestamp(self.started_at, 0)
+            .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "?".to_string());
+
+        let undone = if self.undone_count > 0 {
+            format!(", {} undone", self.undone_count)
+        } else {
+            String::new()
+        };
+
+        crate::logger::plain(&format!(
+            "  #{:<5} {}  {:<18} {:<10} {} operations{}",
+            self.id, started, self.command, self.status, self.entry_count, undone
+        ));
+    }
+}
+
+impl JournalEntry {
+    pub fn describe(&self) -> String {
+        let show = |p: &Option<PathBuf>| {
+            p.as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "?".to_string())
+        };
+
+        let mut text = match self.action {
+            Action::Move | Action::Copy | Action::Trash => format!(
+                "{} {} -> {}",
+                self.action.as_str(),
+                show(&self.source),
+                show(&self.dest)
+            ),
+            Action::Symlink => format!(
+                "symlink {} -> {}",
+                show(&self.dest),
+                self.details["target"].as_str().unwrap_or("?")
+            ),
+            Action::Create => format!("create {}", show(&self.dest)),
+            Action::CreateDir => format!("create directory {}", show(&self.dest)),
+            Action::Delete | Action::TagRewrite => {
+                format!("{} {}", self.action.as_str(), show(&self.source))
+            }
+        };
+
+        if self.undone {
+            text.push_str(" (undone)");
+        }
+        text
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Journaled file operations
+//
+// Drop-in replacements for the std::fs calls used by operations. They perform the
+// operation and record it in the current session (if any). Journal failures are
+// logged but never fail the underlying operation.
+// ---------------------------------------------------------------------------
+
+fn record_or_warn(
+    journal: &Journal,
+    action: Action,
+    source: Option<&Path>,
+    dest: Option<&Path>,
+    before_hash: Option<String>,
+    after_hash: Option<String>,
+    details: serde_json::Value,
+) {
+    if let Err(e) = journal.record(action, source, dest, before_hash, after_hash, details) {
+        crate::logger::warning(&format!("Failed to record {} in journal: {}", action.as_str(), e));
+    }
+}
+
+/// Details for an operation that may overwrite an existing destination
+fn replaced_details(journal: &Journal, dest: &Path) -> serde_json::Value {
+    if dest.exists() {
+        serde_json::json!({ "replaced": true, "replaced_hash": journal.hash(dest) })
+    } else {
+        serde_json::Value::Null
+    }
+}
+
+/// Journaled `fs::rename`
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let journal = get_global_journal().filter(|j| j.is_recording());
+    let Some(journal) = journal else {
+        return fs::rename(from, to);
+    };
+
+    let source = absolute(from);
+    let hash = journal.hash(from);
+    let details = replaced_details(&journal, to);
+
+    fs::rename(from, to)?;
+    record_or_warn(
+        &journal,
+        Action::Move,
+        Some(&source),
+        Some(to),
+        hash.clone(),
+        hash,
+        details,
+    );
+    Ok(())
+}
+
+/// Journaled `fs::copy`
+pub fn copy(from: &Path, to: &Path) -> io::Result<u64> {
+    let journal = get_global_journal().filter(|j| j.is_recording());
+    let Some(journal) = journal else {
+        return fs::copy(from, to);
+    };
+
+    let hash = journal.hash(from);
+    let details = replaced_details(&journal, to);
+
+    let bytes = fs::copy(from, to)?;
+    record_or_warn(
+        &journal,
+        Action::Copy,
+        Some(from),
+        Some(to),
+        hash.clone(),
+        hash,
+        details,
+    );
+    Ok(bytes)
+}
+
+/// Journaled `fs::create_dir_all`
+/// Each directory it creates is recorded, so undo removes those and nothing above them
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    let journal = get_global_journal().filter(|j| j.is_recording());
+    let Some(journal) = journal else {
+        return fs::create_dir_all(path);
+    };
+
+    let path = absolute(path);
+    let missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|dir| !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+
+    fs::create_dir_all(&path)?;
+    for dir in missing.iter().rev() {
+        record_or_warn(
+            &journal,
+            Action::CreateDir,
+            None,
+            Some(dir),
+            None,
+            None,
+            serde_json::Value::Null,
+        );
+    }
+    Ok(())
+}
+
+/// Journaled `fs::remove_file`
+/// Removing a symlink keeps its target so undo can recreate it
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    let journal = get_global_journal().filter(|j| j.is_recording());
+    let Some(journal) = journal else {
+        return fs::remove_file(path);
+    };
+
+    let source = absolute_no_follow(path);
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    let (hash, details) = if is_symlink {
+        let target = fs::read_link(path)?;
+        (
+            None,
+            serde_json::json!({ "symlink_target": target.to_string_lossy() }),
+        )
+    } else {
+        (journal.hash(path), serde_json::Value::Null)
+    };
+
+    fs::remove_file(path)?;
+    record_or_warn(&journal, Action::Delete, Some(&source), None, hash, None, details);
+    Ok(())
+}
+
+/// Journaled `std::os::unix::fs::symlink`
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)?;
+
+    if let Some(journal) = get_global_journal().filter(|j| j.is_recording()) {
+        // Store the target as written (may be relative to the link)
+        let details = serde_json::json!({ "target": target.to_string_lossy() });
+        let link = absolute_no_follow(link);
+        record_or_warn(
+            &journal,
+            Action::Symlink,
+            None,
+            Some(&link),
+            None,
+            None,
+            details,
+        );
+    }
+    Ok(())
+}
+
+/// Record a newly written file (e.g. a conversion output) and the file it was made from
+pub fn record_create(path: &Path, from: &Path) {
+    if let Some(journal) = get_global_journal().filter(|j| j.is_recording()) {
+        let hash = journal.hash(path);
+        record_or_warn(
+            &journal,
+            Action::Create,
+            None,
+            Some(path),
+            None,
+            hash,
+            serde_json::json!({ "from": absolute(from).to_string_lossy() }),
+        );
+    }
+}
+
+/// Hash a file ahead of an operation (None when not recording)
+pub fn hash_if_recording(path: &Path) -> Option<String> {
+    get_global_journal()
+        .filter(|j| j.is_recording())
+        .and_then(|j| j.hash(path))
+}
+
+/// Record a file moved into the trash batch at `batch_dir`
+pub fn record_trash(original: &Path, trashed: &Path, batch_dir: &Path, hash: Option<String>) {
+    if let Some(journal) = get_global_journal().filter(|j| j.is_recording()) {
+        record_or_warn(
+            &journal,
+            Action::Trash,
+            Some(original),
+            Some(trashed),
+            hash.clone(),
+            hash,
+            serde_json::json!({ "batch": absolute(batch_dir).to_string_lossy() }),
+        );
+    }
+}
+
+/// Record an in-place tag rewrite with the previous and new tag values
+///
+/// Both maps are keyed by ferric's lowercase tag names, the ones `TagEdit` takes.
+pub fn record_tag_rewrite(
+    path: &Path,
+    before_hash: Option<String>,
+    before: serde_json::Map<String, serde_json::Value>,
+    after: serde_json::Map<String, serde_json::Value>,
+) {
+    if let Some(journal) = get_global_journal().filter(|j| j.is_recording()) {
+        let after_hash = journal.hash(path);
+        record_or_warn(
+            &journal,
+            Action::TagRewrite,
+            Some(path),
+            None,
+            before_hash,
+            after_hash,
+            serde_json::json!({ "before": before, "after": after }),
+        );
+    }
+}
+
+/// Absolute path without resolving a final symlink component
+fn absolute_no_follow(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => absolute(parent).join(name),
+        _ => absolute(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_session_roundtrip() {
+        let temp = TempDir::new().unwrap();
+        let mut journal = Journal::new(temp.path().join("journal.db"), true).unwrap();
+
+        let file = temp.path().join("song.flac");
+        fs::write(&file, b"audio").unwrap();
+
+        let id = journal.begin_session("sort").unwrap();
+        let hash = journal.hash(&file);
+        assert!(hash.is_some());
+        journal
+            .record(
+                Action::Move,
+                Some(&file),
+                Some(&temp.path().join("moved.flac")),
+                hash.clone(),
+                hash,
+                serde_json::Value::Null,
+            )
+            .unwrap();
+        journal.finish_session("completed").unwrap();
+
+        let sessions = journal.sessions(10).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, id);
+        assert_eq!(sessions[0].status, "completed");
+        assert_eq!(sessions[0].entry_count, 1);
+
+        let entries = journal.entries(id).unwrap();
+        assert_eq!(entries[0].action, Action::Move);
+        assert!(!entries[0].undone);
+
+        journal.mark_undone(entries[0].id).unwrap();
+        assert!(journal.entries(id).unwrap()[0].undone);
+    }
+
+    #[test]
+    fn test_empty_session_is_dropped() {
+        let temp = TempDir::new().unwrap();
+        let mut journal = Journal::new(temp.path().join("journal.db"), false).unwrap();
+
+        let id = journal.begin_session("dedupe").unwrap();
+        journal.finish_session("completed").unwrap();
+
+        assert!(journal.session(id).unwrap().is_none());
+        assert!(!journal.is_recording());
+    }
+
+    #[test]
+    fn test_action_roundtrip() {
+        for action in [
+            Action::Move,
+            Action::Copy,
+            Action::Create,
+            Action::Delete,
+            Action::Trash,
+            Action::Symlink,
+            Action::TagRewrite,
+        ] {
+            assert_eq!(Action::parse(action.as_str()), Some(action));
+        }
+        assert_eq!(Action::parse("bogus"), None);
+    }
+}
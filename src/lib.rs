@@ -1,12 +1,23 @@
 // Core library exports for ferric
 pub mod cache;
+pub mod checksum;
 pub mod config;
+pub mod decode;
 pub mod fingerprint;
+pub mod http;
+pub mod integrity;
+pub mod journal;
 pub mod logger;
+pub mod loudness;
+pub mod matching;
 pub mod metadata;
 pub mod musicbrainz;
 pub mod operations;
 pub mod quality;
+pub mod spectrum;
+pub mod tags;
+pub mod template;
+pub mod trash;
 pub mod utils;
 
 // Re-export commonly used types
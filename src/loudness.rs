@@ -0,0 +1,412 @@
+// EBU R128 / ITU-R BS.1770-4 loudness measurement
+//
+// Integrated loudness uses K-weighted 400 ms blocks with 75% overlap, an absolute gate at
+// -70 LUFS and a relative gate 10 LU below the ungated level. True peak is measured on a
+// 4x (2x above 96 kHz) oversampled signal.
+use crate::decode::Decoder;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Blocks below this loudness are ignored entirely
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks more than this far below the ungated level are ignored
+const RELATIVE_GATE: f64 = 10.0;
+/// Histogram resolution in LU, fine enough that album values match a full analysis
+const BIN_WIDTH: f64 = 0.01;
+/// Loudest block the histogram tracks, louder ones land in the top bin
+const MAX_LOUDNESS: f64 = 5.0;
+/// Taps per phase of the true peak interpolation filter
+const PEAK_TAPS: usize = 12;
+
+/// Loudness of one track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackLoudness {
+    /// Integrated loudness in LUFS, None for silence
+    pub integrated: Option<f64>,
+    /// Linear true peak (1.0 = full scale)
+    pub true_peak: f64,
+    /// Gating block histogram as (bin, count) pairs, used to gate whole albums
+    pub blocks: Vec<(u16, u32)>,
+}
+
+/// Measure a file by decoding it completely
+pub fn measure_file(path: &Path) -> Result<TrackLoudness> {
+    let mut decoder = Decoder::open(path)?;
+    let mut meter = Meter::new(decoder.sample_rate, decoder.channels);
+    while let Some(samples) = decoder.next_samples()? {
+        meter.add_samples(samples);
+    }
+    Ok(meter.finish())
+}
+
+/// Integrated loudness and peak of an album from its tracks' block histograms
+pub fn album_loudness(tracks: &[&TrackLoudness]) -> (Option<f64>, f64) {
+    let mut counts = vec![0u32; bin_count()];
+    for track in tracks {
+        for &(bin, count) in &track.blocks {
+            if let Some(slot) = counts.get_mut(bin as usize) {
+                *slot += count;
+            }
+        }
+    }
+    let peak = tracks.iter().map(|t| t.true_peak).fold(0.0, f64::max);
+    (gated_loudness(&counts), peak)
+}
+
+fn bin_count() -> usize {
+    ((MAX_LOUDNESS - ABSOLUTE_GATE) / BIN_WIDTH) as usize + 1
+}
+
+fn bin_loudness(bin: usize) -> f64 {
+    ABSOLUTE_GATE + (bin as f64 + 0.5) * BIN_WIDTH
+}
+
+fn loudness_to_energy(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Apply both gates to a block histogram
+fn gated_loudness(counts: &[u32]) -> Option<f64> {
+    let mean_energy = |min_bin: usize| {
+        let (sum, n) = counts[min_bin..]
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .fold((0.0, 0u64), |(sum, n), (i, count)| {
+                (
+                    sum + loudness_to_energy(bin_loudness(min_bin + i)) * *count as f64,
+                    n + *count as u64,
+                )
+            });
+        (n > 0).then(|| sum / n as f64)
+    };
+
+    let ungated = energy_to_loudness(mean_energy(0)?);
+    let threshold = ungated - RELATIVE_GATE;
+    let first_bin = ((threshold - ABSOLUTE_GATE) / BIN_WIDTH).ceil().max(0.0) as usize;
+    mean_energy(first_bin.min(counts.len())).map(energy_to_loudness)
+}
+
+/// Second-order IIR section (transposed direct form II)
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// K-weighting filter pair (high shelf, then high pass) for a sample rate
+///
+/// Coefficients follow BS.1770 and are re-derived for rates other than 48 kHz.
+fn k_weighting(sample_rate: u32) -> (Biquad, Biquad) {
+    let rate = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    (shelf, high_pass)
+}
+
+/// Channel weight: surround channels of a 5.1 layout count +1.5 dB and LFE is ignored
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    if channels == 6 {
+        match channel {
+            3 => 0.0,
+            4 | 5 => 1.41,
+            _ => 1.0,
+        }
+    } else {
+        1.0
+    }
+}
+
+/// Windowed-sinc polyphase filter for true peak interpolation
+fn peak_filter(factor: usize) -> Vec<Vec<f64>> {
+    let len = PEAK_TAPS * factor;
+    let center = (len - 1) as f64 / 2.0;
+    let taps: Vec<f64> = (0..len)
+        .map(|i| {
+            let x = (i as f64 - center) / factor as f64;
+            let sinc = if x.abs() < 1e-9 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * (i as f64 + 0.5) / len as f64).cos();
+            sinc * window
+        })
+        .collect();
+
+    (0..factor)
+        .map(|phase| {
+            let coeffs: Vec<f64> = (0..PEAK_TAPS).map(|k| taps[k * factor + phase]).collect();
+            // Unity gain per phase so a DC signal keeps its level
+            let sum: f64 = coeffs.iter().sum();
+            coeffs.into_iter().map(|c| c / sum).collect()
+        })
+        .collect()
+}
+
+/// Streaming loudness meter for one track
+pub struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<(Biquad, Biquad)>,
+    /// Frames per 100 ms step
+    step_frames: usize,
+    step_pos: usize,
+    step_energy: f64,
+    /// Energy of the last four steps, together one 400 ms block
+    recent_steps: Vec<f64>,
+    counts: Vec<u32>,
+    block_energies: Vec<f64>,
+    phases: Vec<Vec<f64>>,
+    history: Vec<Vec<f64>>,
+    history_pos: usize,
+    peak: f64,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let factor = if sample_rate < 96_000 {
+            4
+        } else if sample_rate < 192_000 {
+            2
+        } else {
+            1
+        };
+
+        Self {
+            channels,
+            weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            filters: (0..channels).map(|_| k_weighting(sample_rate)).collect(),
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_pos: 0,
+            step_energy: 0.0,
+            recent_steps: Vec::with_capacity(4),
+            counts: vec![0; bin_count()],
+            block_energies: Vec::new(),
+            phases: if factor > 1 {
+                peak_filter(factor)
+            } else {
+                Vec::new()
+            },
+            history: vec![vec![0.0; PEAK_TAPS]; channels],
+            history_pos: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Feed interleaved samples
+    pub fn add_samples(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            let mut energy = 0.0;
+            for (c, &sample) in frame.iter().enumerate() {
+                let x = sample as f64;
+                self.track_peak(c, x);
+
+                let (shelf, high_pass) = &mut self.filters[c];
+                let y = high_pass.process(shelf.process(x));
+                energy += self.weights[c] * y * y;
+            }
+            self.history_pos = (self.history_pos + 1) % PEAK_TAPS;
+
+            self.step_energy += energy;
+            self.step_pos += 1;
+            if self.step_pos == self.step_frames {
+                self.finish_step();
+            }
+        }
+    }
+
+    fn track_peak(&mut self, channel: usize, x: f64) {
+        self.peak = self.peak.max(x.abs());
+        if self.phases.is_empty() {
+            return;
+        }
+
+        let history = &mut self.history[channel];
+        history[self.history_pos] = x;
+        for phase in &self.phases {
+            let mut y = 0.0;
+            for (k, coeff) in phase.iter().enumerate() {
+                y += coeff * history[(self.history_pos + PEAK_TAPS - k) % PEAK_TAPS];
+            }
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+
+    fn finish_step(&mut self) {
+        if self.recent_steps.len() == 4 {
+            self.recent_steps.remove(0);
+        }
+        self.recent_steps.push(self.step_energy);
+        self.step_energy = 0.0;
+        self.step_pos = 0;
+
+        if self.recent_steps.len() == 4 {
+            let energy = self.recent_steps.iter().sum::<f64>() / (4 * self.step_frames) as f64;
+            let loudness = energy_to_loudness(energy);
+            if loudness >= ABSOLUTE_GATE {
+                self.block_energies.push(energy);
+                let bin = ((loudness - ABSOLUTE_GATE) / BIN_WIDTH) as usize;
+                let last = self.counts.len() - 1;
+                self.counts[bin.min(last)] += 1;
+            }
+        }
+    }
+
+    /// Finish measuring, computing integrated loudness from the exact block energies
+    pub fn finish(self) -> TrackLoudness {
+        let mean = |energies: &mut dyn Iterator<Item = f64>| {
+            let (sum, n) = energies.fold((0.0, 0usize), |(s, n), e| (s + e, n + 1));
+            (n > 0).then(|| sum / n as f64)
+        };
+
+        let integrated = mean(&mut self.block_energies.iter().copied()).and_then(|ungated| {
+            let threshold = loudness_to_energy(energy_to_loudness(ungated) - RELATIVE_GATE);
+            mean(
+                &mut self
+                    .block_energies
+                    .iter()
+                    .copied()
+                    .filter(|e| *e >= threshold),
+            )
+            .map(energy_to_loudness)
+        });
+
+        let blocks = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bin, count)| (bin as u16, *count))
+            .collect();
+
+        TrackLoudness {
+            integrated,
+            true_peak: self.peak,
+            blocks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, channels: usize, freq: f64, amplitude: f64, secs: f64) -> Vec<f32> {
+        let frames = (sample_rate as f64 * secs) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let x = amplitude * (2.0 * PI * freq * i as f64 / sample_rate as f64).sin();
+                std::iter::repeat_n(x as f32, channels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reference_sine() {
+        // BS.1770 calibration: a 0 dBFS 997 Hz sine in one channel reads -3.01 LUFS
+        let mut meter = Meter::new(48_000, 1);
+        meter.add_samples(&sine(48_000, 1, 997.0, 1.0, 5.0));
+        let loudness = meter.finish();
+
+        assert!((loudness.integrated.unwrap() + 3.01).abs() < 0.05);
+        assert!((loudness.true_peak - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_stereo_at_44100() {
+        // -23 dBFS 1 kHz in both channels reads -23 LUFS (EBU Tech 3341 case 1)
+        let mut meter = Meter::new(44_100, 2);
+        meter.add_samples(&sine(44_100, 2, 1000.0, 10f64.powf(-23.0 / 20.0), 5.0));
+        let loudness = meter.finish();
+
+        assert!((loudness.integrated.unwrap() + 23.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_silence_has_no_loudness() {
+        let mut meter = Meter::new(48_000, 2);
+        meter.add_samples(&vec![0.0; 48_000 * 2 * 2]);
+        let loudness = meter.finish();
+
+        assert_eq!(loudness.integrated, None);
+        assert!(loudness.blocks.is_empty());
+    }
+
+    #[test]
+    fn test_true_peak_between_samples() {
+        // fs/4 sine with a 45° phase offset never hits its peak on a sample
+        let samples: Vec<f32> = (0..48_000)
+            .map(|i| (PI / 2.0 * i as f64 + PI / 4.0).sin() as f32)
+            .collect();
+        let mut meter = Meter::new(48_000, 1);
+        meter.add_samples(&samples);
+        let loudness = meter.finish();
+
+        assert!(loudness.true_peak > 0.95);
+    }
+
+    #[test]
+    fn test_album_loudness_matches_tracks() {
+        let measure = |amplitude: f64| {
+            let mut meter = Meter::new(48_000, 2);
+            meter.add_samples(&sine(48_000, 2, 1000.0, amplitude, 10.0));
+            meter.finish()
+        };
+        let quiet = measure(0.1);
+        let loud = measure(0.2);
+
+        // Album histogram of a single track agrees with its exact value
+        let (single, _) = album_loudness(&[&quiet]);
+        assert!((single.unwrap() - quiet.integrated.unwrap()).abs() < 0.01);
+
+        // Equal-length tracks 6 dB apart: both pass the relative gate
+        let (album, peak) = album_loudness(&[&quiet, &loud]);
+        let album = album.unwrap();
+        assert!(album > quiet.integrated.unwrap() && album < loud.integrated.unwrap());
+        assert!((peak - loud.true_peak).abs() < 1e-12);
+    }
+}
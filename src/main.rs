@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
 use ferric::operations::*;
-use ferric::{cache, config::Config};
+use ferric::{cache, config::Config, journal, metadata, trash};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -35,6 +35,25 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Send fingerprints of confirmed tracks to AcoustID
+    AcoustidSubmit {
+        /// Also queue files in these directories that have a MusicBrainz recording ID but no
+        /// AcoustID match
+        #[arg(short, long, num_args = 1..)]
+        input: Vec<PathBuf>,
+    },
+
+    /// Analyze spectra to find fake lossless and upsampled lossy files
+    Analyze {
+        /// Input directory to scan
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Re-analyze files even if the cache already has a verdict for them
+        #[arg(long)]
+        force: bool,
+    },
+
     /// Convert audio files to specified format
     Convert {
         /// Input directory to scan
@@ -73,6 +92,10 @@ enum Commands {
         /// Skip generating audio fingerprints (faster but disables MusicBrainz lookups)
         #[arg(long)]
         without_fingerprints: bool,
+
+        /// Compare the native and ffprobe metadata readers on a sample of files instead of caching
+        #[arg(long, value_name = "FILES", num_args = 0..=1, default_missing_value = "500")]
+        benchmark: Option<usize>,
     },
 
     /// Find and remove duplicate files based on metadata
@@ -84,6 +107,10 @@ enum Commands {
         /// Automatically remove duplicates without confirmation
         #[arg(long)]
         auto_remove: bool,
+
+        /// Find duplicates by audio fingerprint instead of tags
+        #[arg(long)]
+        by_fingerprint: bool,
     },
 
     /// Deduplicate files across multiple libraries by replacing lower quality with symlinks
@@ -158,6 +185,10 @@ enum Commands {
         /// Overwrite existing metadata (default: false, additive-only)
         #[arg(long)]
         overwrite: bool,
+
+        /// Submit fingerprints of matches you confirm to AcoustID (needs acoustid_user_key)
+        #[arg(long)]
+        submit: bool,
     },
 
     /// Fix missing metadata manually (legacy mode - use 'fix-metadata' for MusicBrainz instead)
@@ -197,6 +228,27 @@ enum Commands {
         output: PathBuf,
     },
 
+    /// List past sessions recorded in the operation journal
+    History {
+        /// Show the operations of a single session
+        session: Option<i64>,
+
+        /// Number of sessions to list
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Refresh tags from MusicBrainz for files already tagged with MusicBrainz IDs
+    MbSync {
+        /// Directories to process (can specify multiple)
+        #[arg(short, long, num_args = 1..)]
+        input: Vec<PathBuf>,
+
+        /// Apply corrections without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+
     /// Merge an organized library into another, upgrading with better quality
     Merge {
         /// Source library directory to merge from
@@ -242,6 +294,36 @@ enum Commands {
         auto_select: bool,
     },
 
+    /// Measure loudness (EBU R128) and write ReplayGain tags (R128 gain tags for Opus)
+    Replaygain {
+        /// Input directory to scan
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Re-analyze files even if the cache already has their loudness
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Re-hash files to find content that changed without its mtime or size changing
+    Scrub {
+        /// Input directory to scan
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Stop after reading this many gigabytes (the next run resumes where this one stopped)
+        #[arg(long)]
+        max_gb: Option<f64>,
+
+        /// Stop after this many minutes (the next run resumes where this one stopped)
+        #[arg(long)]
+        max_minutes: Option<u64>,
+
+        /// Store the new checksums of changed files instead of reporting them again
+        #[arg(long)]
+        accept_changes: bool,
+    },
+
     /// Sort files by metadata into Artist/Album folder structure
     Sort {
         /// Input directory to scan
@@ -269,6 +351,18 @@ enum Commands {
         destructive: bool,
     },
 
+    /// Inspect, restore or purge files moved to the trash by destructive operations
+    Trash {
+        #[command(subcommand)]
+        action: TrashCommand,
+    },
+
+    /// Roll back the file operations of a journal session
+    Undo {
+        /// Session number (see 'ferric history')
+        session: i64,
+    },
+
     /// Run unified pipeline: sort -> optional convert -> fix naming
     Unified {
         /// Input directory to scan
@@ -307,6 +401,79 @@ enum Commands {
         #[arg(short, long)]
         yes: bool,
     },
+
+    /// Decode every file to find corrupt, truncated or checksum-failing audio
+    Verify {
+        /// Input directory to scan
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Verify files again even if the cache already has a result for them
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrashCommand {
+    /// List trash batches
+    List,
+
+    /// Move the files of a batch back to their original locations
+    Restore {
+        /// Batch name (see 'ferric trash list')
+        batch: String,
+
+        /// Only restore files originally under this path
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+
+    /// Permanently delete trash batches
+    Purge {
+        /// Batch name to purge
+        batch: Option<String>,
+
+        /// Purge batches older than this many days
+        #[arg(long)]
+        older_than: Option<u64>,
+
+        /// Purge the whole trash
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+impl Commands {
+    /// Name recorded in the operation journal, None for commands that don't modify files
+    fn journal_name(&self) -> Option<&'static str> {
+        match self {
+            Commands::Convert { .. } => Some("convert"),
+            Commands::Dedupe { .. } => Some("dedupe"),
+            Commands::DedupeLibraries { .. } => Some("dedupe-libraries"),
+            Commands::FixMetadata { .. } => Some("fix-metadata"),
+            Commands::FixMetadataManual { .. } => Some("fix-metadata-manual"),
+            Commands::FixNaming { .. } => Some("fix-naming"),
+            Commands::MbSync { .. } => Some("mb-sync"),
+            Commands::Merge { .. } => Some("merge"),
+            Commands::MergeLibraries { .. } => Some("merge-libraries"),
+            Commands::Replaygain { .. } => Some("replaygain"),
+            Commands::Sort { .. } => Some("sort"),
+            Commands::Unified { .. } => Some("unified"),
+            Commands::AcoustidSubmit { .. }
+            | Commands::Analyze { .. }
+            | Commands::Completions { .. }
+            | Commands::DatabaseClean
+            | Commands::DatabaseInit { .. }
+            | Commands::GenConfig { .. }
+            | Commands::History { .. }
+            | Commands::PlaylistImport { .. }
+            | Commands::Scrub { .. }
+            | Commands::Trash { .. }
+            | Commands::Undo { .. }
+            | Commands::Verify { .. } => None,
+        }
+    }
 }
 
 #[tokio::main]
@@ -328,6 +495,12 @@ async fn main() -> Result<()> {
     let log_path = ferric::logger::init_logger(cli.log_file)?;
     ferric::logger::info(&format!("Log file: {}", log_path.display()));
 
+    // Select the metadata reader used on cache misses
+    metadata::init_global_backend(
+        metadata::MetadataBackend::parse(&config.general.metadata_backend)?,
+        config.general.ffprobe_fallback,
+    );
+
     // Initialize metadata cache database
     cache::init_global_cache(&config.general.cache_path)?;
     ferric::logger::info(&format!(
@@ -335,6 +508,26 @@ async fn main() -> Result<()> {
         config.general.cache_path.display()
     ));
 
+    // Initialize operation journal and start a session for file-modifying commands
+    if config.journal.enabled {
+        let journal_path = config.journal.resolve_path(&config.general.cache_path);
+        journal::init_global_journal(&journal_path, config.journal.hash_files)?;
+
+        if let Some(name) = cli.command.journal_name() {
+            if !cli.dry_run {
+                if let Some(id) = journal::begin_session(name)? {
+                    ferric::logger::info(&format!("Journal session: #{}", id));
+                }
+            }
+        }
+    }
+
+    // Commands below take ownership of the config, keep what the trash auto-purge needs
+    let purge_config = (cli.command.journal_name().is_some()
+        && config.trash.enabled
+        && !cli.dry_run)
+        .then(|| config.clone());
+
     // Execute command
     let result = match cli.command {
         Commands::Convert {
@@ -452,6 +645,7 @@ async fn main() -> Result<()> {
             avoid_various_artists,
             no_avoid_various_artists,
             overwrite,
+            submit,
         } => {
             // If no fields specified, default to fixing all
             let fix_all = all || (!artist && !album && !album_artist && !title && !date && !genre);
@@ -474,16 +668,32 @@ async fn main() -> Result<()> {
                 skip_fingerprinting,
                 overwrite,
                 avoid_various_artists: avoid_various_artists && !no_avoid_various_artists,
+                submit,
             };
             fix_metadata_mb::run(opts, &config).await
         }
 
-        Commands::Dedupe { input, auto_remove } => {
+        Commands::MbSync { input, yes } => {
+            let opts = mb_sync::MbSyncOptions {
+                input_dirs: input,
+                dry_run: cli.dry_run,
+                verbose: cli.verbose,
+                yes,
+            };
+            mb_sync::run(opts, &config).await.map(|_| ())
+        }
+
+        Commands::Dedupe {
+            input,
+            auto_remove,
+            by_fingerprint,
+        } => {
             let opts = dedupe::DedupeOptions {
                 input_dir: input,
                 dry_run: cli.dry_run,
                 verbose: cli.verbose,
                 auto_remove,
+                by_fingerprint,
                 config,
             };
             dedupe::run(opts).map(|_| ())
@@ -563,6 +773,61 @@ async fn main() -> Result<()> {
             playlist::run(opts)
         }
 
+        Commands::AcoustidSubmit { input } => {
+            let opts = acoustid_submit::AcoustIdSubmitOptions {
+                input_dirs: input,
+                dry_run: cli.dry_run,
+                verbose: cli.verbose,
+            };
+            acoustid_submit::run(opts, &config).await.map(|_| ())
+        }
+
+        Commands::Analyze { input, force } => {
+            let opts = analyze::AnalyzeOptions {
+                input_dir: input,
+                verbose: cli.verbose,
+                force,
+                config,
+            };
+            analyze::run(opts).map(|_| ())
+        }
+
+        Commands::Scrub {
+            input,
+            max_gb,
+            max_minutes,
+            accept_changes,
+        } => {
+            let opts = scrub::ScrubOptions {
+                input_dir: input,
+                verbose: cli.verbose,
+                max_gb,
+                max_minutes,
+                accept_changes,
+            };
+            scrub::run(opts).map(|_| ())
+        }
+
+        Commands::Verify { input, force } => {
+            let opts = verify::VerifyOptions {
+                input_dir: input,
+                verbose: cli.verbose,
+                force,
+            };
+            verify::run(opts).map(|_| ())
+        }
+
+        Commands::Replaygain { input, force } => {
+            let opts = replaygain::ReplayGainOptions {
+                input_dir: input,
+                dry_run: cli.dry_run,
+                verbose: cli.verbose,
+                force,
+                config,
+            };
+            replaygain::run(opts).map(|_| ())
+        }
+
         Commands::DatabaseClean => {
             let cache = cache::get_global_cache()
                 .ok_or_else(|| anyhow!("Metadata cache is not initialized"))?;
@@ -571,7 +836,129 @@ async fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::DatabaseInit { input, without_fingerprints } => {
+        Commands::History { session, limit } => {
+            let journal = journal::get_global_journal()
+                .ok_or_else(|| anyhow!("Operation journal is disabled ([journal] enabled = false)"))?;
+
+            match session {
+                Some(id) => {
+                    let session = journal
+                        .session(id)?
+                        .ok_or_else(|| anyhow!("No journal session #{}", id))?;
+                    session.print_row();
+                    for entry in journal.entries(id)? {
+                        ferric::logger::plain(&format!("    {}", entry.describe()));
+                    }
+                }
+                None => {
+                    let sessions = journal.sessions(limit)?;
+                    if sessions.is_empty() {
+                        ferric::logger::info("No sessions recorded yet");
+                    }
+                    for session in sessions {
+                        session.print_row();
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        Commands::Trash { action } => {
+            let trash_root = config.trash.resolve_path(&config.general.cache_path);
+            match action {
+                TrashCommand::List => {
+                    let batches = trash::list(&trash_root)?;
+                    if batches.is_empty() {
+                        ferric::logger::info("Trash is empty");
+                    }
+                    for batch in batches {
+                        batch.print_row();
+                        if cli.verbose {
+                            for entry in &batch.entries {
+                                ferric::logger::plain(&format!(
+                                    "    {}",
+                                    entry.original.display()
+                                ));
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+                TrashCommand::Restore { batch, path } => {
+                    if cli.dry_run {
+                        trash::find(&trash_root, &batch)?;
+                        ferric::logger::info(&format!(
+                            "[DRY RUN] Would restore trash batch {}",
+                            batch
+                        ));
+                    } else {
+                        let restored = trash::restore(&trash_root, &batch, path.as_deref())?;
+                        for entry in &restored {
+                            ferric::logger::plain(&format!(
+                                "  Restored {}",
+                                entry.original.display()
+                            ));
+                        }
+                        ferric::logger::info(&format!("Restored {} file(s)", restored.len()));
+                    }
+                    Ok(())
+                }
+                TrashCommand::Purge {
+                    batch,
+                    older_than,
+                    all,
+                } => {
+                    if let Some(name) = &batch {
+                        trash::find(&trash_root, name)?;
+                    }
+                    let batches = trash::list(&trash_root)?;
+                    let selected: Vec<_> = batches
+                        .into_iter()
+                        .filter(|b| {
+                            all || batch.as_deref() == Some(b.name.as_str())
+                                || older_than
+                                    .is_some_and(|days| b.age_days().is_some_and(|age| age >= days))
+                        })
+                        .collect();
+
+                    if batch.is_none() && older_than.is_none() && !all {
+                        Err(anyhow!("Specify a batch name, --older-than DAYS or --all"))
+                    } else if cli.dry_run {
+                        for b in &selected {
+                            ferric::logger::plain(&format!("  [DRY RUN] Would purge {}", b.name));
+                        }
+                        Ok(())
+                    } else {
+                        trash::purge_batches(&selected)?.print();
+                        Ok(())
+                    }
+                }
+            }
+        }
+
+        Commands::Undo { session } => {
+            let opts = undo::UndoOptions {
+                session_id: session,
+                dry_run: cli.dry_run,
+                verbose: cli.verbose,
+            };
+            undo::run(opts).map(|_| ())
+        }
+
+        Commands::DatabaseInit {
+            input,
+            benchmark: Some(sample),
+            ..
+        } => {
+            metadata::benchmark_backends(&input, sample).print(cli.verbose);
+            Ok(())
+        }
+
+        Commands::DatabaseInit {
+            input,
+            without_fingerprints,
+            benchmark: None,
+        } => {
             let cache = cache::get_global_cache()
                 .ok_or_else(|| anyhow!("Metadata cache is not initialized"))?;
             // Default to generating fingerprints unless --without-fingerprints is specified
@@ -580,6 +967,19 @@ async fn main() -> Result<()> {
         }
     };
 
+    if let Err(e) = journal::finish_session(result.is_ok()) {
+        ferric::logger::warning(&format!("Failed to close journal session: {}", e));
+    }
+
+    // Enforce the trash age/size limits after anything that may have added to it
+    if let Some(purge_config) = purge_config {
+        match trash::auto_purge(&purge_config) {
+            Ok(stats) if stats.batches_removed > 0 => stats.print(),
+            Ok(_) => {}
+            Err(e) => ferric::logger::warning(&format!("Failed to purge old trash: {}", e)),
+        }
+    }
+
     match result {
         Ok(_) => {
             ferric::logger::success("\nOperation completed successfully!");
@@ -0,0 +1,568 @@
+// Matching files against MusicBrainz recordings and releases
+//
+// The files of an album are matched to a release as a whole: candidate releases are the
+// ones most of the files' AcoustID recordings appear on, and each candidate's tracklist is
+// scored against the files. The best release then tags every file, so an album never ends
+// up split across an original, a compilation and a deluxe edition.
+//
+// Files matched one at a time, and the tracks of a release, are scored by the same
+// `similarity` function, whether the candidate came from a fingerprint or a search.
+//
+// Where several releases fit equally well, the configured `ReleasePreference` decides:
+// it's what picks a recording's release, and the release for an album.
+use crate::config::ReleasePreferenceConfig;
+use crate::metadata::AudioMetadata;
+use crate::musicbrainz::{AcoustIdResult, MusicBrainzMetadata, Release, ReleaseInfo, ReleaseTrack};
+use crate::utils;
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Candidate releases fetched and scored per album
+pub const MAX_CANDIDATE_RELEASES: usize = 3;
+/// Share of the release score that comes from the file and track counts agreeing
+const COVERAGE_WEIGHT: f64 = 0.15;
+/// Weights of the comparisons between a file and a candidate; ones that can't be made are
+/// left out
+const RECORDING_WEIGHT: f64 = 0.4;
+const TITLE_WEIGHT: f64 = 0.3;
+const ARTIST_WEIGHT: f64 = 0.2;
+const ALBUM_WEIGHT: f64 = 0.1;
+const DURATION_WEIGHT: f64 = 0.2;
+const POSITION_WEIGHT: f64 = 0.1;
+/// Duration difference at which tracks stop counting as similar at all
+const DURATION_SCALE_SECS: f64 = 10.0;
+/// Files less similar than this to every remaining track are left unassigned
+const MIN_TRACK_SIMILARITY: f64 = 0.5;
+/// Releases scoring within this of the best album match count as fitting equally well
+const RELEASE_SCORE_TOLERANCE: f64 = 0.02;
+/// Weights of the release preferences; country and format only count when some are listed
+const STATUS_WEIGHT: f64 = 0.3;
+const PRIMARY_TYPE_WEIGHT: f64 = 0.25;
+const SECONDARY_TYPE_WEIGHT: f64 = 0.25;
+const COUNTRY_WEIGHT: f64 = 0.1;
+const FORMAT_WEIGHT: f64 = 0.1;
+
+/// What's known about one file of an album
+pub struct TrackEvidence<'a> {
+    pub metadata: &'a AudioMetadata,
+    /// Results of the file's AcoustID lookup
+    pub acoustid_results: &'a [AcoustIdResult],
+}
+
+/// How well a release fits an album's files
+#[derive(Debug, Clone)]
+pub struct ReleaseMatch {
+    /// From 0.0 to 1.0
+    pub score: f64,
+    /// Index into the release's tracks for each file, in file order
+    pub assignments: Vec<Option<usize>>,
+}
+
+/// Releases the files' AcoustID recordings appear on, most widely shared first; the release
+/// preference orders ones shared equally widely, judged by what AcoustID tells about them
+pub fn candidate_releases(files: &[TrackEvidence], preference: &ReleasePreference) -> Vec<String> {
+    let unknown = ReleaseInfo::default();
+    let mut votes: HashMap<&str, (usize, &ReleaseInfo)> = HashMap::new();
+    for file in files {
+        let mut seen: Vec<&str> = Vec::new();
+        for result in file.acoustid_results {
+            for release_id in &result.release_ids {
+                if !seen.contains(&release_id.as_str()) {
+                    seen.push(release_id);
+                    let info = result.release_info.get(release_id).unwrap_or(&unknown);
+                    votes.entry(release_id).or_insert((0, info)).0 += 1;
+                }
+            }
+        }
+    }
+
+    let mut candidates: Vec<(&str, (usize, &ReleaseInfo))> = votes.into_iter().collect();
+    candidates.sort_by(|(a_id, (a_votes, a)), (b_id, (b_votes, b))| {
+        b_votes
+            .cmp(a_votes)
+            .then_with(|| preference.compare(a, b))
+            .then(a_id.cmp(b_id))
+    });
+    candidates
+        .into_iter()
+        .take(MAX_CANDIDATE_RELEASES)
+        .map(|(id, _)| id.to_string())
+        .collect()
+}
+
+/// The best fitting of an album's candidate releases, letting the release preference
+/// decide between ones that fit about equally well
+pub fn pick_release(
+    candidates: Vec<(Release, ReleaseMatch)>,
+    preference: &ReleasePreference,
+) -> Option<(Release, ReleaseMatch)> {
+    let best_score = candidates
+        .iter()
+        .map(|(_, m)| m.score)
+        .max_by(f64::total_cmp)?;
+    candidates
+        .into_iter()
+        .filter(|(_, m)| m.score >= best_score - RELEASE_SCORE_TOLERANCE)
+        .min_by(|(a, _), (b, _)| preference.compare(&a.info, &b.info))
+}
+
+/// Score a release against an album's files and assign each file a track
+pub fn match_release(release: &Release, files: &[TrackEvidence]) -> ReleaseMatch {
+    let mut pairs: Vec<(f64, usize, usize)> = Vec::new();
+    for (file_idx, file) in files.iter().enumerate() {
+        for (track_idx, track) in release.tracks.iter().enumerate() {
+            let similarity = track_similarity(file, release, track);
+            if similarity >= MIN_TRACK_SIMILARITY {
+                pairs.push((similarity, file_idx, track_idx));
+            }
+        }
+    }
+
+    // Greedy assignment, most similar pairs first, each track used once
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    let mut assignments = vec![None; files.len()];
+    let mut used = vec![false; release.tracks.len()];
+    let mut total = 0.0;
+    for (similarity, file_idx, track_idx) in pairs {
+        if assignments[file_idx].is_none() && !used[track_idx] {
+            assignments[file_idx] = Some(track_idx);
+            used[track_idx] = true;
+            total += similarity;
+        }
+    }
+
+    let (files_len, tracks_len) = (files.len() as f64, release.tracks.len() as f64);
+    let coverage = if files.is_empty() || release.tracks.is_empty() {
+        0.0
+    } else {
+        files_len.min(tracks_len) / files_len.max(tracks_len)
+    };
+    let tracks = if files.is_empty() {
+        0.0
+    } else {
+        total / files_len
+    };
+
+    ReleaseMatch {
+        score: COVERAGE_WEIGHT * coverage + (1.0 - COVERAGE_WEIGHT) * tracks,
+        assignments,
+    }
+}
+
+/// Similarity of a file to a release track, from 0.0 to 1.0
+fn track_similarity(file: &TrackEvidence, release: &Release, track: &ReleaseTrack) -> f64 {
+    // Whether the track is one of the file's recordings decides it, not AcoustID's score
+    let recording_score = (!file.acoustid_results.is_empty()).then(|| {
+        let matched = file
+            .acoustid_results
+            .iter()
+            .any(|r| r.recording_id == track.recording_id);
+        if matched {
+            1.0
+        } else {
+            0.0
+        }
+    });
+    similarity(
+        file.metadata,
+        &release.track_metadata(track),
+        recording_score,
+    )
+}
+
+/// Similarity of a file to a candidate recording, from 0.0 to 1.0
+///
+/// `recording_score` is how sure AcoustID is that the file is the candidate's recording, or
+/// None when the candidate didn't come from a fingerprint lookup.
+pub fn similarity(
+    file: &AudioMetadata,
+    candidate: &MusicBrainzMetadata,
+    recording_score: Option<f64>,
+) -> f64 {
+    let mut weighted = 0.0;
+    let mut weights = 0.0;
+    let mut add = |weight: f64, value: f64| {
+        weighted += weight * value;
+        weights += weight;
+    };
+
+    if let Some(score) = recording_score {
+        add(RECORDING_WEIGHT, score);
+    }
+
+    let text = |value: &str, other: &str| {
+        strsim::normalized_levenshtein(
+            &utils::normalize_for_comparison(value),
+            &utils::normalize_for_comparison(other),
+        )
+    };
+    if let Some(title) = known(&file.title) {
+        add(TITLE_WEIGHT, text(title, &candidate.title));
+    }
+    if let Some(artist) = known(&file.artist) {
+        add(ARTIST_WEIGHT, text(artist, &candidate.artist));
+    }
+    if let (Some(album), Some(candidate_album)) = (known(&file.album), &candidate.album) {
+        add(ALBUM_WEIGHT, text(album, candidate_album));
+    }
+
+    if let (Some(duration), Some(length)) = (file.duration_secs, candidate.length_secs) {
+        add(
+            DURATION_WEIGHT,
+            (1.0 - (duration - length).abs() / DURATION_SCALE_SECS).max(0.0),
+        );
+    }
+
+    if let (Some(number), Some(candidate_number)) = (file.track_number, candidate.track_number) {
+        let same_disc = candidate
+            .disc_number
+            .is_none_or(|disc| disc == file.get_disc());
+        add(
+            POSITION_WEIGHT,
+            if number == candidate_number && same_disc {
+                1.0
+            } else {
+                0.0
+            },
+        );
+    }
+
+    if weights == 0.0 {
+        0.0
+    } else {
+        weighted / weights
+    }
+}
+
+/// How releases are ranked, from `[musicbrainz.release_preference]`
+#[derive(Debug, Clone)]
+pub struct ReleasePreference {
+    config: ReleasePreferenceConfig,
+    /// Earliest date first rather than latest
+    prefer_original: bool,
+}
+
+impl Default for ReleasePreference {
+    fn default() -> Self {
+        Self {
+            config: ReleasePreferenceConfig::default(),
+            prefer_original: true,
+        }
+    }
+}
+
+impl ReleasePreference {
+    pub fn from_config(config: &ReleasePreferenceConfig) -> Result<Self> {
+        let prefer_original = match config.date_preference.as_str() {
+            "original" => true,
+            "latest" => false,
+            other => anyhow::bail!(
+                "Unknown [musicbrainz.release_preference] date_preference '{}' (expected 'original' or 'latest')",
+                other
+            ),
+        };
+        Ok(Self {
+            config: config.clone(),
+            prefer_original,
+        })
+    }
+
+    /// How well a release fits the preferences, from 0.0 to 1.0 less the Various Artists
+    /// penalty
+    pub fn score(&self, info: &ReleaseInfo) -> f64 {
+        let config = &self.config;
+        let mut weighted = 0.0;
+        let mut weights = 0.0;
+        let mut add = |weight: f64, value: f64| {
+            weighted += weight * value;
+            weights += weight;
+        };
+
+        // Releases with no status listed are usually official ones entered sparsely
+        let official = info
+            .status
+            .as_deref()
+            .is_none_or(|s| s.eq_ignore_ascii_case("official"));
+        add(STATUS_WEIGHT, if official { 1.0 } else { 0.0 });
+        add(
+            PRIMARY_TYPE_WEIGHT,
+            rank(&config.primary_types, info.primary_type.iter()),
+        );
+        let avoided = info.secondary_types.iter().any(|t| {
+            config
+                .avoided_secondary_types
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(t))
+        });
+        add(SECONDARY_TYPE_WEIGHT, if avoided { 0.0 } else { 1.0 });
+        if !config.countries.is_empty() {
+            add(COUNTRY_WEIGHT, rank(&config.countries, info.country.iter()));
+        }
+        if !config.formats.is_empty() {
+            add(FORMAT_WEIGHT, rank(&config.formats, info.formats.iter()));
+        }
+
+        let penalty = if info.various_artists {
+            config.various_artists_penalty
+        } else {
+            0.0
+        };
+        weighted / weights - penalty
+    }
+
+    /// Order releases most preferred first; dates break ties, and undated releases go last
+    pub fn compare(&self, a: &ReleaseInfo, b: &ReleaseInfo) -> Ordering {
+        let by_date = match (&a.date, &b.date) {
+            (Some(a), Some(b)) if self.prefer_original => a.cmp(b),
+            (Some(a), Some(b)) => b.cmp(a),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        self.score(b).total_cmp(&self.score(a)).then(by_date)
+    }
+}
+
+/// 1.0 for the first preferred value, falling towards 0.0 down the list; values that only
+/// contain a preferred one count too, so "Vinyl" covers "12\" Vinyl"
+fn rank<'a>(preferred: &[String], values: impl Iterator<Item = &'a String>) -> f64 {
+    values
+        .filter_map(|value| {
+            let value = value.to_lowercase();
+            preferred
+                .iter()
+                .position(|p| value.contains(&p.to_lowercase()))
+        })
+        .min()
+        .map_or(0.0, |index| 1.0 - index as f64 / preferred.len() as f64)
+}
+
+/// A tag value, unless it's missing or one of the placeholders filled in for missing tags
+fn known(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|v| !v.starts_with("_unknown"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(recording_id: &str, title: &str, number: u32, length_secs: f64) -> ReleaseTrack {
+        ReleaseTrack {
+            id: format!("{}-track", recording_id),
+            recording_id: recording_id.to_string(),
+            title: title.to_string(),
+            artist: "Artist".to_string(),
+            credit: Default::default(),
+            number,
+            disc: 1,
+            track_total: 5,
+            media: None,
+            length_secs: Some(length_secs),
+        }
+    }
+
+    fn release(id: &str, tracks: Vec<ReleaseTrack>) -> Release {
+        Release {
+            id: id.to_string(),
+            title: id.to_string(),
+            artist: "Artist".to_string(),
+            date: None,
+            genres: Vec::new(),
+            info: ReleaseInfo::default(),
+            details: Default::default(),
+            disc_total: 1,
+            tracks,
+        }
+    }
+
+    fn file(title: &str, number: u32, duration_secs: f64) -> AudioMetadata {
+        AudioMetadata {
+            title: Some(title.to_string()),
+            track_number: Some(number),
+            duration_secs: Some(duration_secs),
+            ..Default::default()
+        }
+    }
+
+    fn acoustid(recording_id: &str, release_ids: &[&str]) -> Vec<AcoustIdResult> {
+        vec![AcoustIdResult {
+            recording_id: recording_id.to_string(),
+            score: 0.95,
+            title: None,
+            artists: Vec::new(),
+            release_ids: release_ids.iter().map(|r| r.to_string()).collect(),
+            release_info: HashMap::new(),
+        }]
+    }
+
+    #[test]
+    fn test_album_prefers_original_release() {
+        let metadata = [
+            file("One", 1, 200.0),
+            file("Two", 2, 180.0),
+            file("Three", 3, 240.0),
+        ];
+        let results = [
+            acoustid("r1", &["original", "deluxe", "best-of"]),
+            acoustid("r2", &["original", "deluxe"]),
+            acoustid("r3", &["original", "deluxe"]),
+        ];
+        let files: Vec<TrackEvidence> = metadata
+            .iter()
+            .zip(&results)
+            .map(|(metadata, results)| TrackEvidence {
+                metadata,
+                acoustid_results: results,
+            })
+            .collect();
+
+        let preference = ReleasePreference::from_config(&Default::default()).unwrap();
+        let candidates = candidate_releases(&files, &preference);
+        assert_eq!(candidates, vec!["deluxe", "original", "best-of"]);
+
+        let original = release(
+            "original",
+            vec![
+                track("r1", "One", 1, 201.0),
+                track("r2", "Two", 2, 180.0),
+                track("r3", "Three", 3, 239.0),
+            ],
+        );
+        let mut deluxe = original.clone();
+        deluxe.id = "deluxe".to_string();
+        deluxe.tracks.push(track("r4", "Bonus", 4, 300.0));
+        deluxe.tracks.push(track("r5", "Demo", 5, 150.0));
+        let best_of = release(
+            "best-of",
+            vec![track("x", "Hit", 1, 210.0), track("r1", "One", 2, 200.0)],
+        );
+
+        let original_match = match_release(&original, &files);
+        assert_eq!(original_match.assignments, vec![Some(0), Some(1), Some(2)]);
+        assert!(original_match.score > 0.95, "{}", original_match.score);
+        assert!(original_match.score > match_release(&deluxe, &files).score);
+
+        // The compilation only holds one of the tracks
+        let best_of_match = match_release(&best_of, &files);
+        assert_eq!(best_of_match.assignments, vec![Some(1), None, None]);
+        assert!(best_of_match.score < 0.5, "{}", best_of_match.score);
+    }
+    #[test]
+    fn test_candidate_releases_break_ties_by_preference() {
+        let info = |date: &str, secondary_types: &[&str]| ReleaseInfo {
+            primary_type: Some("Album".to_string()),
+            secondary_types: secondary_types.iter().map(|t| t.to_string()).collect(),
+            date: Some(date.to_string()),
+            ..Default::default()
+        };
+        let releases = [
+            ("a-best-of", info("2003", &["Compilation"])),
+            ("b-live", info("1980", &["Live"])),
+            ("c-remaster", info("2015", &[])),
+            ("z-original", info("1979", &[])),
+        ];
+        let mut results = acoustid("r1", &["a-best-of", "b-live", "c-remaster", "z-original"]);
+        results[0].release_info = releases
+            .iter()
+            .map(|(id, info)| (id.to_string(), info.clone()))
+            .collect();
+        let metadata = file("One", 1, 200.0);
+        let files = [TrackEvidence {
+            metadata: &metadata,
+            acoustid_results: &results,
+        }];
+
+        // Every release has the one vote, so the ID order would have dropped the original
+        let preference = ReleasePreference::from_config(&Default::default()).unwrap();
+        let candidates = candidate_releases(&files, &preference);
+        assert_eq!(candidates, vec!["z-original", "c-remaster", "b-live"]);
+    }
+
+    #[test]
+    fn test_similarity_scores_fingerprint_and_search_candidates_alike() {
+        let file = AudioMetadata {
+            artist: Some("The Band".to_string()),
+            title: Some("_unknown title".to_string()),
+            duration_secs: Some(200.0),
+            ..Default::default()
+        };
+        let candidate = |title: &str, artist: &str, length_secs: f64| MusicBrainzMetadata {
+            recording_id: "r".to_string(),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            length_secs: Some(length_secs),
+            ..Default::default()
+        };
+
+        // The placeholder title isn't compared; artist and duration are
+        let right = candidate("Song", "The Band", 201.0);
+        let wrong = candidate("Song", "Someone Else", 320.0);
+        let searched = similarity(&file, &right, None);
+        assert!(searched > 0.7, "{}", searched);
+        assert!(similarity(&file, &wrong, None) < 0.3);
+
+        // A fingerprint match counts for more than the tags
+        let fingerprinted = similarity(&file, &wrong, Some(0.95));
+        assert!(fingerprinted > similarity(&file, &wrong, None));
+        assert!(similarity(&file, &right, Some(0.95)) > searched);
+    }
+    #[test]
+    fn test_release_preference() {
+        let config = ReleasePreferenceConfig {
+            countries: vec!["GB".to_string(), "US".to_string()],
+            formats: vec!["Vinyl".to_string()],
+            ..Default::default()
+        };
+        let preference = ReleasePreference::from_config(&config).unwrap();
+        let info = |country: &str, format: &str, date: &str| ReleaseInfo {
+            status: Some("Official".to_string()),
+            country: Some(country.to_string()),
+            formats: vec![format.to_string()],
+            primary_type: Some("Album".to_string()),
+            date: Some(date.to_string()),
+            ..Default::default()
+        };
+
+        let uk_lp = info("GB", "12\" Vinyl", "1979");
+        let us_cd = info("US", "CD", "1979");
+        assert_eq!(preference.compare(&uk_lp, &us_cd), Ordering::Less);
+
+        let remaster = info("GB", "12\" Vinyl", "2015");
+        assert_eq!(preference.compare(&uk_lp, &remaster), Ordering::Less);
+        let compilation = ReleaseInfo {
+            secondary_types: vec!["Compilation".to_string()],
+            ..uk_lp.clone()
+        };
+        let various = ReleaseInfo {
+            various_artists: true,
+            ..uk_lp.clone()
+        };
+        assert!(preference.score(&compilation) < preference.score(&us_cd));
+        assert!(preference.score(&various) < preference.score(&compilation));
+
+        // Among album matches that fit about as well, the preferred release wins
+        let with = |id: &str, info: ReleaseInfo, score: f64| {
+            let mut release = release(id, Vec::new());
+            release.info = info;
+            let release_match = ReleaseMatch {
+                score,
+                assignments: Vec::new(),
+            };
+            (release, release_match)
+        };
+        let candidates = vec![
+            with("remaster", remaster.clone(), 0.97),
+            with("original", uk_lp.clone(), 0.96),
+            with("compilation", compilation, 0.5),
+        ];
+        let (picked, _) = pick_release(candidates, &preference).unwrap();
+        assert_eq!(picked.id, "original");
+
+        let unknown = ReleasePreferenceConfig {
+            date_preference: "newest".to_string(),
+            ..Default::default()
+        };
+        assert!(ReleasePreference::from_config(&unknown).is_err());
+    }
+}
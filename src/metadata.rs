@@ -1,13 +1,191 @@
-use crate::{cache, logger};
+use crate::integrity::IntegrityCheck;
+use crate::spectrum::SpectrumAnalysis;
+use crate::{cache, logger, tags};
 use anyhow::{Context, Result};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::path::Path;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use symphonia::core::codecs::{self, CodecType};
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag, Value};
 use symphonia::core::probe::Hint;
+use walkdir::WalkDir;
+
+lazy_static! {
+    static ref GLOBAL_BACKEND: Mutex<(MetadataBackend, bool)> =
+        Mutex::new((MetadataBackend::Native, false));
+}
+
+/// Reader used for metadata cache misses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataBackend {
+    /// Symphonia, in-process
+    Native,
+    /// One ffprobe process per file
+    Ffprobe,
+}
+
+impl MetadataBackend {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "native" | "symphonia" => Ok(MetadataBackend::Native),
+            "ffprobe" => Ok(MetadataBackend::Ffprobe),
+            other => anyhow::bail!(
+                "Unknown metadata backend '{}' (expected 'native' or 'ffprobe')",
+                other
+            ),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetadataBackend::Native => "native",
+            MetadataBackend::Ffprobe => "ffprobe",
+        }
+    }
+}
+
+/// Select the metadata reader (`ffprobe_fallback` retries native failures with ffprobe)
+pub fn init_global_backend(backend: MetadataBackend, ffprobe_fallback: bool) {
+    *GLOBAL_BACKEND.lock().unwrap() = (backend, ffprobe_fallback);
+}
+
+/// ffprobe-style codec name for a Symphonia codec type
+fn codec_name(codec: CodecType) -> String {
+    let name = match codec {
+        codecs::CODEC_TYPE_FLAC => "flac",
+        codecs::CODEC_TYPE_ALAC => "alac",
+        codecs::CODEC_TYPE_WAVPACK => "wavpack",
+        codecs::CODEC_TYPE_MONKEYS_AUDIO => "ape",
+        codecs::CODEC_TYPE_TTA => "tta",
+        codecs::CODEC_TYPE_MP1 => "mp1",
+        codecs::CODEC_TYPE_MP2 => "mp2",
+        codecs::CODEC_TYPE_MP3 => "mp3",
+        codecs::CODEC_TYPE_AAC => "aac",
+        codecs::CODEC_TYPE_OPUS => "opus",
+        codecs::CODEC_TYPE_VORBIS => "vorbis",
+        codecs::CODEC_TYPE_SPEEX => "speex",
+        codecs::CODEC_TYPE_WMA => "wmav2",
+        codecs::CODEC_TYPE_EAC3 => "eac3",
+        codecs::CODEC_TYPE_PCM_S16LE => "pcm_s16le",
+        codecs::CODEC_TYPE_PCM_S16BE => "pcm_s16be",
+        codecs::CODEC_TYPE_PCM_S24LE => "pcm_s24le",
+        codecs::CODEC_TYPE_PCM_S24BE => "pcm_s24be",
+        codecs::CODEC_TYPE_PCM_S32LE => "pcm_s32le",
+        codecs::CODEC_TYPE_PCM_S32BE => "pcm_s32be",
+        codecs::CODEC_TYPE_PCM_F32LE => "pcm_f32le",
+        codecs::CODEC_TYPE_PCM_F32BE => "pcm_f32be",
+        codecs::CODEC_TYPE_PCM_F64LE => "pcm_f64le",
+        codecs::CODEC_TYPE_PCM_F64BE => "pcm_f64be",
+        codecs::CODEC_TYPE_PCM_U8 => "pcm_u8",
+        codecs::CODEC_TYPE_PCM_S8 => "pcm_s8",
+        codecs::CODEC_TYPE_PCM_ALAW => "pcm_alaw",
+        codecs::CODEC_TYPE_PCM_MULAW => "pcm_mulaw",
+        _ => {
+            // Fall back to the decoder name for anything not listed
+            return match symphonia::default::get_codecs().get_codec(codec) {
+                Some(descriptor) => descriptor.short_name.to_string(),
+                None => "unknown".to_string(),
+            };
+        }
+    };
+    name.to_string()
+}
+
+/// Metadata field a tag is read into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagField {
+    Artist,
+    Album,
+    AlbumArtist,
+    Title,
+    Track,
+    TrackTotal,
+    Disc,
+    DiscTotal,
+    Date,
+    OriginalDate,
+    Genre,
+    Composer,
+    Isrc,
+    Label,
+    CatalogNumber,
+    Compilation,
+    ArtistSort,
+    AlbumArtistSort,
+    AlbumSort,
+    TitleSort,
+    ReplayGainTrackGain,
+    ReplayGainTrackPeak,
+    ReplayGainAlbumGain,
+    ReplayGainAlbumPeak,
+    R128TrackGain,
+    R128AlbumGain,
+    MusicBrainzRecordingId,
+    MusicBrainzReleaseId,
+    MusicBrainzReleaseTrackId,
+    Artists,
+    Barcode,
+    Media,
+    ReleaseStatus,
+    ReleaseType,
+    ReleaseCountry,
+    MusicBrainzArtistId,
+    MusicBrainzAlbumArtistId,
+    MusicBrainzReleaseGroupId,
+}
+
+/// Normalized tag keys (see `normalize_tag_key`) recognized for each field, in order of
+/// preference. Covers Vorbis comment names, ffprobe's names for ID3 frames and MP4 atoms,
+/// and the TXXX/freeform names Picard and foobar2000 write.
+const TAG_ALIASES: &[(TagField, &[&str])] = &[
+    (TagField::Artist, &["artist"]),
+    (TagField::Album, &["album"]),
+    (TagField::AlbumArtist, &["albumartist"]),
+    (TagField::Title, &["title"]),
+    (TagField::Track, &["track", "tracknumber"]),
+    (TagField::TrackTotal, &["tracktotal", "totaltracks"]),
+    (TagField::Disc, &["disc", "discnumber"]),
+    (TagField::DiscTotal, &["disctotal", "totaldiscs"]),
+    (TagField::Date, &["date", "year"]),
+    (TagField::OriginalDate, &["originaldate", "originalyear", "tdor"]),
+    (TagField::Genre, &["genre"]),
+    (TagField::Composer, &["composer"]),
+    (TagField::Isrc, &["isrc", "tsrc"]),
+    (TagField::Label, &["label", "publisher", "organization"]),
+    (TagField::CatalogNumber, &["catalognumber", "catalog"]),
+    (TagField::Compilation, &["compilation", "cpil", "tcmp"]),
+    (TagField::ArtistSort, &["artistsort", "sortartist"]),
+    (TagField::AlbumArtistSort, &["albumartistsort", "sortalbumartist"]),
+    (TagField::AlbumSort, &["albumsort", "sortalbum"]),
+    (TagField::TitleSort, &["titlesort", "sorttitle", "sortname"]),
+    (TagField::ReplayGainTrackGain, &["replaygaintrackgain"]),
+    (TagField::ReplayGainTrackPeak, &["replaygaintrackpeak"]),
+    (TagField::ReplayGainAlbumGain, &["replaygainalbumgain"]),
+    (TagField::ReplayGainAlbumPeak, &["replaygainalbumpeak"]),
+    (TagField::R128TrackGain, &["r128trackgain"]),
+    (TagField::R128AlbumGain, &["r128albumgain"]),
+    // Picard's MUSICBRAINZ_TRACKID is the recording, not the release track
+    (TagField::MusicBrainzRecordingId, &["musicbrainztrackid", "musicbrainzrecordingid"]),
+    (TagField::MusicBrainzReleaseId, &["musicbrainzalbumid", "musicbrainzreleaseid"]),
+    (TagField::MusicBrainzReleaseTrackId, &["musicbrainzreleasetrackid"]),
+    (TagField::Artists, &["artists"]),
+    (TagField::Barcode, &["barcode"]),
+    (TagField::Media, &["media", "tmed"]),
+    // ID3 and MP4 use the "MusicBrainz Album ..." names
+    (TagField::ReleaseStatus, &["releasestatus", "musicbrainzalbumstatus"]),
+    (TagField::ReleaseType, &["releasetype", "musicbrainzalbumtype"]),
+    (TagField::ReleaseCountry, &["releasecountry", "musicbrainzalbumreleasecountry"]),
+    (TagField::MusicBrainzArtistId, &["musicbrainzartistid"]),
+    (TagField::MusicBrainzAlbumArtistId, &["musicbrainzalbumartistid"]),
+    (TagField::MusicBrainzReleaseGroupId, &["musicbrainzreleasegroupid"]),
+];
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AudioMetadata {
@@ -24,6 +202,63 @@ pub struct AudioMetadata {
     pub channels: Option<u8>,
     pub duration_secs: Option<f64>,
 
+    // Release and credit details (absent in older cache entries)
+    #[serde(default)]
+    pub disc_number: Option<u32>,
+    #[serde(default)]
+    pub disc_total: Option<u32>,
+    #[serde(default)]
+    pub track_total: Option<u32>,
+    #[serde(default)]
+    pub composer: Option<String>,
+    #[serde(default)]
+    pub isrc: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub catalog_number: Option<String>,
+    #[serde(default)]
+    pub original_date: Option<String>,
+    #[serde(default)]
+    pub compilation: bool,
+    #[serde(default)]
+    pub barcode: Option<String>,
+    #[serde(default)]
+    pub media: Option<String>,
+    #[serde(default)]
+    pub release_status: Option<String>,
+    #[serde(default)]
+    pub release_types: Vec<String>,
+    #[serde(default)]
+    pub release_country: Option<String>,
+    /// Each credited artist (the multi-valued ARTISTS tag)
+    #[serde(default)]
+    pub artists: Vec<String>,
+
+    // Sort names
+    #[serde(default)]
+    pub artist_sort: Option<String>,
+    #[serde(default)]
+    pub album_artist_sort: Option<String>,
+    #[serde(default)]
+    pub album_sort: Option<String>,
+    #[serde(default)]
+    pub title_sort: Option<String>,
+
+    // Loudness normalization tags, as written
+    #[serde(default)]
+    pub replaygain_track_gain: Option<String>,
+    #[serde(default)]
+    pub replaygain_track_peak: Option<String>,
+    #[serde(default)]
+    pub replaygain_album_gain: Option<String>,
+    #[serde(default)]
+    pub replaygain_album_peak: Option<String>,
+    #[serde(default)]
+    pub r128_track_gain: Option<String>,
+    #[serde(default)]
+    pub r128_album_gain: Option<String>,
+
     // Audio fingerprinting and MusicBrainz integration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fingerprint: Option<String>,
@@ -31,10 +266,26 @@ pub struct AudioMetadata {
     pub musicbrainz_recording_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub musicbrainz_release_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_track_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub musicbrainz_artist_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub musicbrainz_album_artist_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_release_group_id: Option<String>,
+
+    // Spectral transcode analysis, filled in by `ferric analyze`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spectrum: Option<SpectrumAnalysis>,
+
+    // Decode check, filled in by `ferric verify`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<IntegrityCheck>,
 }
 
 impl AudioMetadata {
-    /// Extract metadata from an audio file using ffprobe (more reliable for MP3s)
+    /// Extract metadata from an audio file, using the cache when possible
     pub fn from_file(path: &Path) -> Result<Self> {
         if let Some(cache) = cache::get_global_cache() {
             match cache.get(path) {
@@ -48,9 +299,19 @@ impl AudioMetadata {
             }
         }
 
-        // Use ffprobe for better MP3/ID3 tag support
-        let metadata =
-            Self::from_file_ffprobe(path).or_else(|_| Self::from_file_symphonia(path))?;
+        let (backend, ffprobe_fallback) = *GLOBAL_BACKEND.lock().unwrap();
+        let metadata = match backend {
+            MetadataBackend::Native => Self::read_with(path, backend).or_else(|err| {
+                if ffprobe_fallback {
+                    Self::from_file_ffprobe(path)
+                } else {
+                    Err(err)
+                }
+            })?,
+            MetadataBackend::Ffprobe => {
+                Self::from_file_ffprobe(path).or_else(|_| Self::from_file_symphonia(path))?
+            }
+        };
 
         if let Some(cache) = cache::get_global_cache() {
             if let Err(err) = cache.insert(path, &metadata) {
@@ -65,6 +326,14 @@ impl AudioMetadata {
         Ok(metadata)
     }
 
+    /// Read metadata with a specific backend, bypassing the cache
+    pub fn read_with(path: &Path, backend: MetadataBackend) -> Result<Self> {
+        match backend {
+            MetadataBackend::Native => Self::from_file_symphonia(path),
+            MetadataBackend::Ffprobe => Self::from_file_ffprobe(path),
+        }
+    }
+
     /// Normalize a tag key for case-insensitive, whitespace-insensitive matching
     ///
     /// Converts to lowercase and removes all non-alphanumeric characters.
@@ -104,7 +373,7 @@ impl AudioMetadata {
     /// Extract metadata using ffprobe (fallback method, more reliable)
     fn from_file_ffprobe(path: &Path) -> Result<Self> {
         let output = Command::new("ffprobe")
-            .args(&[
+            .args([
                 "-v",
                 "quiet",
                 "-print_format",
@@ -146,22 +415,7 @@ impl AudioMetadata {
 
                     // Extract tags from stream (OPUS/OGG files store tags here)
                     if let Some(tags) = stream.get("tags").and_then(|t| t.as_object()) {
-                        metadata.artist = Self::get_tag_fuzzy(tags, "artist");
-                        metadata.album = Self::get_tag_fuzzy(tags, "album");
-                        metadata.album_artist = Self::get_tag_fuzzy(tags, "albumartist");
-                        metadata.title = Self::get_tag_fuzzy(tags, "title");
-                        metadata.genre = Self::get_tag_fuzzy(tags, "genre");
-
-                        // Date can be "date" or "year"
-                        metadata.date = Self::get_tag_fuzzy(tags, "date")
-                            .or_else(|| Self::get_tag_fuzzy(tags, "year"));
-
-                        // Track number
-                        if let Some(track_str) = Self::get_tag_fuzzy(tags, "track") {
-                            if let Some(num_str) = track_str.split('/').next() {
-                                metadata.track_number = num_str.parse().ok();
-                            }
-                        }
+                        metadata.apply_tag_map(tags);
                     }
 
                     break;
@@ -171,35 +425,9 @@ impl AudioMetadata {
 
         // Extract format/container metadata (fallback for MP3/MP4 files and primary for FLAC)
         if let Some(format) = json.get("format") {
+            // Only fills fields not already set from stream tags
             if let Some(tags) = format.get("tags").and_then(|t| t.as_object()) {
-                // Only set if not already set from stream tags
-                if metadata.artist.is_none() {
-                    metadata.artist = Self::get_tag_fuzzy(tags, "artist");
-                }
-                if metadata.album.is_none() {
-                    metadata.album = Self::get_tag_fuzzy(tags, "album");
-                }
-                if metadata.album_artist.is_none() {
-                    metadata.album_artist = Self::get_tag_fuzzy(tags, "albumartist");
-                }
-                if metadata.title.is_none() {
-                    metadata.title = Self::get_tag_fuzzy(tags, "title");
-                }
-                if metadata.date.is_none() {
-                    metadata.date = Self::get_tag_fuzzy(tags, "date")
-                        .or_else(|| Self::get_tag_fuzzy(tags, "year"));
-                }
-                if metadata.genre.is_none() {
-                    metadata.genre = Self::get_tag_fuzzy(tags, "genre");
-                }
-
-                if metadata.track_number.is_none() {
-                    if let Some(track_str) = Self::get_tag_fuzzy(tags, "track") {
-                        if let Some(num_str) = track_str.split('/').next() {
-                            metadata.track_number = num_str.parse().ok();
-                        }
-                    }
-                }
+                metadata.apply_tag_map(tags);
             }
 
             if let Some(bitrate_str) = format.get("bit_rate").and_then(|v| v.as_str()) {
@@ -211,6 +439,11 @@ impl AudioMetadata {
             }
         }
 
+        // Neither ffprobe nor Symphonia reads the UFID frame Picard keeps the recording ID in
+        if metadata.musicbrainz_recording_id.is_none() {
+            metadata.musicbrainz_recording_id = tags::read_musicbrainz_recording_id(path);
+        }
+
         // If no title, use filename
         if metadata.title.is_none() {
             if let Some(filename) = path.file_stem() {
@@ -221,10 +454,14 @@ impl AudioMetadata {
         Ok(metadata)
     }
 
-    /// Extract metadata using Symphonia (original method)
+    /// Extract metadata natively with Symphonia
+    ///
+    /// Reads tags from the container (Vorbis comments, MP4 atoms) and from tags found ahead
+    /// of the stream (ID3v2 in MP3s). Bitrate is the stream size over the duration.
     fn from_file_symphonia(path: &Path) -> Result<Self> {
         let file = File::open(path)
             .with_context(|| format!("Failed to open audio file: {}", path.display()))?;
+        let file_size = file.metadata().map(|m| m.len()).unwrap_or(0);
 
         let mss = MediaSourceStream::new(Box::new(file), Default::default());
 
@@ -248,53 +485,53 @@ impl AudioMetadata {
 
         // Extract codec information from the default track
         if let Some(track) = probed.format.default_track() {
-            metadata.codec = track.codec_params.codec.to_string();
-            // Note: bitrate is not directly available from CodecParameters in symphonia 0.5
-            // It would need to be calculated or extracted from format metadata
-            metadata.bitrate = None;
-            metadata.sample_rate = track.codec_params.sample_rate;
-            metadata.channels = track.codec_params.channels.map(|ch| ch.count() as u8);
-
-            // Calculate duration
-            if let Some(n_frames) = track.codec_params.n_frames {
-                if let Some(sample_rate) = track.codec_params.sample_rate {
-                    metadata.duration_secs = Some(n_frames as f64 / sample_rate as f64);
+            let params = &track.codec_params;
+            metadata.codec = codec_name(params.codec);
+            metadata.sample_rate = params.sample_rate;
+            metadata.channels = params.channels.map(|ch| ch.count() as u8);
+
+            metadata.duration_secs = match (params.n_frames, params.time_base, params.sample_rate) {
+                (Some(n_frames), Some(time_base), _) => {
+                    let time = time_base.calc_time(n_frames);
+                    Some(time.seconds as f64 + time.frac)
                 }
-            } else if let Some(time_base) = track.codec_params.time_base {
-                if let Some(n_frames) = track.codec_params.n_frames {
-                    metadata.duration_secs =
-                        Some(n_frames as f64 * time_base.numer as f64 / time_base.denom as f64);
+                (Some(n_frames), None, Some(sample_rate)) => {
+                    Some(n_frames as f64 / sample_rate as f64)
                 }
-            }
+                _ => None,
+            };
         }
 
-        // Extract metadata tags
-        if let Some(metadata_rev) = probed.format.metadata().current() {
-            for tag in metadata_rev.tags() {
-                let key = tag.key.to_lowercase();
-                let value = tag.value.to_string();
+        // Container tags take precedence over tags in front of the stream
+        let mut revisions = Vec::new();
+        if let Some(revision) = probed.format.metadata().current() {
+            revisions.push(revision.clone());
+        }
+        if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            revisions.push(revision.clone());
+        }
 
-                match key.as_str() {
-                    "artist" => metadata.artist = Some(value),
-                    "album" => metadata.album = Some(value),
-                    "albumartist" | "album_artist" | "album artist" => {
-                        metadata.album_artist = Some(value)
-                    }
-                    "title" => metadata.title = Some(value),
-                    "tracknumber" | "track" => {
-                        if let Ok(num) = value.parse::<u32>() {
-                            metadata.track_number = Some(num);
-                        } else if let Some(first_part) = value.split('/').next() {
-                            if let Ok(num) = first_part.parse::<u32>() {
-                                metadata.track_number = Some(num);
-                            }
-                        }
-                    }
-                    "date" | "year" => metadata.date = Some(value),
-                    "genre" => metadata.genre = Some(value),
-                    _ => {}
-                }
+        let mut picture_bytes = 0u64;
+        for revision in &revisions {
+            for tag in revision.tags() {
+                metadata.apply_tag(tag);
             }
+            picture_bytes += revision
+                .visuals()
+                .iter()
+                .map(|v| v.data.len() as u64)
+                .sum::<u64>();
+        }
+
+        // Embedded pictures aren't part of the audio stream
+        if let Some(duration) = metadata.duration_secs.filter(|d| *d > 0.0) {
+            let stream_bytes = file_size.saturating_sub(picture_bytes);
+            metadata.bitrate = Some((stream_bytes as f64 * 8.0 / duration) as u32);
+        }
+
+        // Neither ffprobe nor Symphonia reads the UFID frame Picard keeps the recording ID in
+        if metadata.musicbrainz_recording_id.is_none() {
+            metadata.musicbrainz_recording_id = tags::read_musicbrainz_recording_id(path);
         }
 
         // If no title, use filename
@@ -307,6 +544,182 @@ impl AudioMetadata {
         Ok(metadata)
     }
 
+    /// Fill fields from an ffprobe tag map, keeping values that are already set
+    fn apply_tag_map(&mut self, tags: &serde_json::Map<String, serde_json::Value>) {
+        for (field, aliases) in TAG_ALIASES {
+            for alias in aliases.iter() {
+                if let Some(value) = Self::get_tag_fuzzy(tags, alias) {
+                    self.set_tag_field(*field, &value);
+                }
+            }
+        }
+    }
+
+    /// Fill a field from a Symphonia tag unless an earlier tag already set it
+    fn apply_tag(&mut self, tag: &Tag) {
+        let field = match tag.std_key {
+            Some(key) => Self::field_for_std_key(key),
+            None => None,
+        };
+        // TXXX frames and MP4 freeform atoms carry their name after the last ':'
+        let field = field.or_else(|| {
+            let name = tag.key.rsplit(':').next().unwrap_or(&tag.key);
+            Self::field_for_key(name)
+        });
+        let Some(field) = field else {
+            return;
+        };
+
+        let value = match tag.value {
+            Value::Flag => "1".to_string(),
+            ref value => value.to_string(),
+        };
+        self.set_tag_field(field, &value);
+    }
+
+    fn field_for_std_key(key: StandardTagKey) -> Option<TagField> {
+        let field = match key {
+            StandardTagKey::Artist => TagField::Artist,
+            StandardTagKey::Album => TagField::Album,
+            StandardTagKey::AlbumArtist => TagField::AlbumArtist,
+            StandardTagKey::TrackTitle => TagField::Title,
+            StandardTagKey::TrackNumber => TagField::Track,
+            StandardTagKey::TrackTotal => TagField::TrackTotal,
+            StandardTagKey::DiscNumber => TagField::Disc,
+            StandardTagKey::DiscTotal => TagField::DiscTotal,
+            StandardTagKey::Date | StandardTagKey::ReleaseDate => TagField::Date,
+            StandardTagKey::OriginalDate => TagField::OriginalDate,
+            StandardTagKey::Genre => TagField::Genre,
+            StandardTagKey::Composer => TagField::Composer,
+            StandardTagKey::IdentIsrc => TagField::Isrc,
+            StandardTagKey::Label => TagField::Label,
+            StandardTagKey::IdentCatalogNumber => TagField::CatalogNumber,
+            StandardTagKey::Compilation => TagField::Compilation,
+            StandardTagKey::SortArtist => TagField::ArtistSort,
+            StandardTagKey::SortAlbumArtist => TagField::AlbumArtistSort,
+            StandardTagKey::SortAlbum => TagField::AlbumSort,
+            StandardTagKey::SortTrackTitle => TagField::TitleSort,
+            StandardTagKey::ReplayGainTrackGain => TagField::ReplayGainTrackGain,
+            StandardTagKey::ReplayGainTrackPeak => TagField::ReplayGainTrackPeak,
+            StandardTagKey::ReplayGainAlbumGain => TagField::ReplayGainAlbumGain,
+            StandardTagKey::ReplayGainAlbumPeak => TagField::ReplayGainAlbumPeak,
+            StandardTagKey::MusicBrainzTrackId | StandardTagKey::MusicBrainzRecordingId => {
+                TagField::MusicBrainzRecordingId
+            }
+            StandardTagKey::MusicBrainzAlbumId => TagField::MusicBrainzReleaseId,
+            StandardTagKey::MusicBrainzReleaseTrackId => TagField::MusicBrainzReleaseTrackId,
+            StandardTagKey::IdentBarcode => TagField::Barcode,
+            StandardTagKey::MediaFormat => TagField::Media,
+            StandardTagKey::MusicBrainzReleaseStatus => TagField::ReleaseStatus,
+            StandardTagKey::MusicBrainzReleaseType => TagField::ReleaseType,
+            StandardTagKey::ReleaseCountry => TagField::ReleaseCountry,
+            StandardTagKey::MusicBrainzArtistId => TagField::MusicBrainzArtistId,
+            StandardTagKey::MusicBrainzAlbumArtistId => TagField::MusicBrainzAlbumArtistId,
+            StandardTagKey::MusicBrainzReleaseGroupId => TagField::MusicBrainzReleaseGroupId,
+            _ => return None,
+        };
+        Some(field)
+    }
+
+    fn field_for_key(key: &str) -> Option<TagField> {
+        let key = Self::normalize_tag_key(key);
+        TAG_ALIASES
+            .iter()
+            .find(|(_, aliases)| aliases.contains(&key.as_str()))
+            .map(|(field, _)| *field)
+    }
+
+    /// Store a tag value in its field, keeping a value an earlier tag already set
+    ///
+    /// "N/M" track and disc values also fill the totals. A bare year is replaced by a full
+    /// date from a later tag (ID3v2.3 files often carry both).
+    fn set_tag_field(&mut self, field: TagField, value: &str) {
+        let value = value.trim();
+        if value.is_empty() {
+            return;
+        }
+
+        fn fill(slot: &mut Option<String>, value: &str) {
+            if slot.is_none() {
+                *slot = Some(value.to_string());
+            }
+        }
+        // Multi-valued tags come as repeated tags (Vorbis, MP4), NUL-separated (ID3v2.4) or
+        // joined with ';' (ffprobe)
+        fn append(slot: &mut Vec<String>, value: &str) {
+            for value in value.split([tags::SEPARATOR, ';']).map(str::trim) {
+                if !value.is_empty() && !slot.iter().any(|v| v == value) {
+                    slot.push(value.to_string());
+                }
+            }
+        }
+        fn fill_number(slot: &mut Option<u32>, value: Option<&str>) {
+            if slot.is_none() {
+                *slot = value.and_then(|n| n.trim().parse().ok());
+            }
+        }
+
+        match field {
+            TagField::Artist => fill(&mut self.artist, value),
+            TagField::Album => fill(&mut self.album, value),
+            TagField::AlbumArtist => fill(&mut self.album_artist, value),
+            TagField::Title => fill(&mut self.title, value),
+            TagField::Track | TagField::Disc => {
+                let (number, total) = if field == TagField::Track {
+                    (&mut self.track_number, &mut self.track_total)
+                } else {
+                    (&mut self.disc_number, &mut self.disc_total)
+                };
+                let mut parts = value.splitn(2, '/');
+                fill_number(number, parts.next());
+                fill_number(total, parts.next());
+            }
+            TagField::TrackTotal => fill_number(&mut self.track_total, Some(value)),
+            TagField::DiscTotal => fill_number(&mut self.disc_total, Some(value)),
+            TagField::Date => match &self.date {
+                Some(date) if date.len() == 4 && value.len() > 4 && value.starts_with(date.as_str()) => {
+                    self.date = Some(value.to_string());
+                }
+                _ => fill(&mut self.date, value),
+            },
+            TagField::OriginalDate => fill(&mut self.original_date, value),
+            TagField::Genre => fill(&mut self.genre, value),
+            TagField::Composer => fill(&mut self.composer, value),
+            TagField::Isrc => fill(&mut self.isrc, value),
+            TagField::Label => fill(&mut self.label, value),
+            TagField::CatalogNumber => fill(&mut self.catalog_number, value),
+            TagField::Compilation => {
+                self.compilation |= matches!(value.to_lowercase().as_str(), "1" | "true" | "yes");
+            }
+            TagField::ArtistSort => fill(&mut self.artist_sort, value),
+            TagField::AlbumArtistSort => fill(&mut self.album_artist_sort, value),
+            TagField::AlbumSort => fill(&mut self.album_sort, value),
+            TagField::TitleSort => fill(&mut self.title_sort, value),
+            TagField::ReplayGainTrackGain => fill(&mut self.replaygain_track_gain, value),
+            TagField::ReplayGainTrackPeak => fill(&mut self.replaygain_track_peak, value),
+            TagField::ReplayGainAlbumGain => fill(&mut self.replaygain_album_gain, value),
+            TagField::ReplayGainAlbumPeak => fill(&mut self.replaygain_album_peak, value),
+            TagField::R128TrackGain => fill(&mut self.r128_track_gain, value),
+            TagField::R128AlbumGain => fill(&mut self.r128_album_gain, value),
+            TagField::MusicBrainzRecordingId => fill(&mut self.musicbrainz_recording_id, value),
+            TagField::MusicBrainzReleaseId => fill(&mut self.musicbrainz_release_id, value),
+            TagField::MusicBrainzReleaseTrackId => fill(&mut self.musicbrainz_track_id, value),
+            TagField::Artists => append(&mut self.artists, value),
+            TagField::Barcode => fill(&mut self.barcode, value),
+            TagField::Media => fill(&mut self.media, value),
+            TagField::ReleaseStatus => fill(&mut self.release_status, value),
+            TagField::ReleaseType => append(&mut self.release_types, value),
+            TagField::ReleaseCountry => fill(&mut self.release_country, value),
+            TagField::MusicBrainzArtistId => append(&mut self.musicbrainz_artist_ids, value),
+            TagField::MusicBrainzAlbumArtistId => {
+                append(&mut self.musicbrainz_album_artist_ids, value)
+            }
+            TagField::MusicBrainzReleaseGroupId => {
+                fill(&mut self.musicbrainz_release_group_id, value)
+            }
+        }
+    }
+
     /// Get the best "artist" for organizing (album_artist preferred, then artist)
     pub fn get_organizing_artist(&self, prefer_track_artist: bool) -> String {
         if prefer_track_artist {
@@ -336,6 +749,21 @@ impl AudioMetadata {
             .unwrap_or_else(|| "_unknown title".to_string())
     }
 
+    /// Get disc number, treating untagged files as disc 1
+    pub fn get_disc(&self) -> u32 {
+        self.disc_number.unwrap_or(1)
+    }
+
+    /// Whether the album spans several discs, judging from this track's tags
+    ///
+    /// Without a disc total only discs past the first give it away.
+    pub fn is_multi_disc(&self) -> bool {
+        match self.disc_total {
+            Some(total) => total > 1,
+            None => self.get_disc() > 1,
+        }
+    }
+
     /// Check if this looks like a "Various Artists" album
     pub fn looks_like_va(&self) -> bool {
         if let Some(ref aa) = self.album_artist {
@@ -351,6 +779,67 @@ impl AudioMetadata {
         self.bitrate.map(|br| br / 1000)
     }
 
+    /// Current value of a tag by its ffmpeg metadata key (None if unset or not tracked)
+    pub fn tag_value(&self, key: &str) -> Option<String> {
+        let multi = |values: &[String]| (!values.is_empty()).then(|| tags::join_values(values));
+        match key.to_lowercase().as_str() {
+            "title" => self.title.clone(),
+            "artist" => self.artist.clone(),
+            "album" => self.album.clone(),
+            "album_artist" => self.album_artist.clone(),
+            "date" => self.date.clone(),
+            "genre" => self.genre.clone(),
+            "track" => self.track_number.map(|n| n.to_string()),
+            "disc" => self.disc_number.map(|n| n.to_string()),
+            "composer" => self.composer.clone(),
+            "isrc" => self.isrc.clone(),
+            "label" => self.label.clone(),
+            "catalognumber" => self.catalog_number.clone(),
+            "originaldate" => self.original_date.clone(),
+            "originalyear" => self.original_date.as_ref().map(|d| d.chars().take(4).collect()),
+            "artists" => multi(&self.artists),
+            "artistsort" => self.artist_sort.clone(),
+            "albumartistsort" => self.album_artist_sort.clone(),
+            "barcode" => self.barcode.clone(),
+            "media" => self.media.clone(),
+            "releasestatus" => self.release_status.clone(),
+            "releasetype" => multi(&self.release_types),
+            "releasecountry" => self.release_country.clone(),
+            "replaygain_track_gain" => self.replaygain_track_gain.clone(),
+            "replaygain_track_peak" => self.replaygain_track_peak.clone(),
+            "replaygain_album_gain" => self.replaygain_album_gain.clone(),
+            "replaygain_album_peak" => self.replaygain_album_peak.clone(),
+            "r128_track_gain" => self.r128_track_gain.clone(),
+            "r128_album_gain" => self.r128_album_gain.clone(),
+            "musicbrainz_trackid" => self.musicbrainz_recording_id.clone(),
+            "musicbrainz_albumid" => self.musicbrainz_release_id.clone(),
+            "musicbrainz_releasetrackid" => self.musicbrainz_track_id.clone(),
+            "musicbrainz_artistid" => multi(&self.musicbrainz_artist_ids),
+            "musicbrainz_albumartistid" => multi(&self.musicbrainz_album_artist_ids),
+            "musicbrainz_releasegroupid" => self.musicbrainz_release_group_id.clone(),
+            _ => None,
+        }
+    }
+
+    /// Build the before/after tag maps recorded in the journal for a tag rewrite
+    pub fn tag_changes(
+        &self,
+        updates: &[(&str, String)],
+    ) -> (
+        serde_json::Map<String, serde_json::Value>,
+        serde_json::Map<String, serde_json::Value>,
+    ) {
+        let mut before = serde_json::Map::new();
+        let mut after = serde_json::Map::new();
+
+        for (key, value) in updates {
+            before.insert(key.to_string(), self.tag_value(key).into());
+            after.insert(key.to_string(), value.clone().into());
+        }
+
+        (before, after)
+    }
+
     /// Add a fingerprint to this metadata and optionally update cache
     pub fn add_fingerprint(&mut self, fingerprint: String, path: &Path) -> Result<()> {
         self.fingerprint = Some(fingerprint);
@@ -395,15 +884,169 @@ impl AudioMetadata {
     }
 }
 
+/// Result of reading the same files with both metadata backends
+#[derive(Debug)]
+pub struct BackendBenchmark {
+    pub files: usize,
+    pub native: BackendTiming,
+    pub ffprobe: BackendTiming,
+    /// Files both backends read but disagree on, with the differing fields
+    pub mismatches: Vec<(PathBuf, Vec<&'static str>)>,
+}
+
+#[derive(Debug)]
+pub struct BackendTiming {
+    pub backend: MetadataBackend,
+    pub elapsed: Duration,
+    pub failed: usize,
+}
+
+/// Read up to `sample` files from the directories with both backends, bypassing the cache
+pub fn benchmark_backends(directories: &[PathBuf], sample: usize) -> BackendBenchmark {
+    let files: Vec<PathBuf> = directories
+        .iter()
+        .flat_map(|dir| WalkDir::new(dir).into_iter().filter_map(|e| e.ok()))
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| crate::utils::is_audio_file(p))
+        .take(sample)
+        .collect();
+
+    let run = |backend: MetadataBackend| {
+        logger::info(&format!("Reading {} files with {}...", files.len(), backend.as_str()));
+        let start = Instant::now();
+        let results: Vec<Option<AudioMetadata>> = files
+            .par_iter()
+            .map(|file| AudioMetadata::read_with(file, backend).ok())
+            .collect();
+        let timing = BackendTiming {
+            backend,
+            elapsed: start.elapsed(),
+            failed: results.iter().filter(|r| r.is_none()).count(),
+        };
+        (timing, results)
+    };
+
+    let (native, native_results) = run(MetadataBackend::Native);
+    let (ffprobe, ffprobe_results) = run(MetadataBackend::Ffprobe);
+
+    let mismatches = files
+        .iter()
+        .zip(native_results.iter().zip(ffprobe_results.iter()))
+        .filter_map(|(file, results)| match results {
+            (Some(a), Some(b)) => {
+                let fields = differing_fields(a, b);
+                (!fields.is_empty()).then(|| (file.clone(), fields))
+            }
+            _ => None,
+        })
+        .collect();
+
+    BackendBenchmark {
+        files: files.len(),
+        native,
+        ffprobe,
+        mismatches,
+    }
+}
+
+/// Fields two readings disagree on (durations within a second and bitrates within 10% match)
+fn differing_fields(a: &AudioMetadata, b: &AudioMetadata) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    let text = |x: &Option<String>| x.as_deref().map(str::trim).unwrap_or("").to_string();
+
+    if text(&a.artist) != text(&b.artist) {
+        fields.push("artist");
+    }
+    if text(&a.album) != text(&b.album) {
+        fields.push("album");
+    }
+    if text(&a.album_artist) != text(&b.album_artist) {
+        fields.push("album_artist");
+    }
+    if text(&a.title) != text(&b.title) {
+        fields.push("title");
+    }
+    if text(&a.date) != text(&b.date) {
+        fields.push("date");
+    }
+    if text(&a.genre) != text(&b.genre) {
+        fields.push("genre");
+    }
+    if a.track_number != b.track_number {
+        fields.push("track");
+    }
+    if a.disc_number != b.disc_number {
+        fields.push("disc");
+    }
+    if text(&a.composer) != text(&b.composer) {
+        fields.push("composer");
+    }
+    if text(&a.isrc) != text(&b.isrc) {
+        fields.push("isrc");
+    }
+    if a.codec != b.codec {
+        fields.push("codec");
+    }
+    match (a.duration_secs, b.duration_secs) {
+        (Some(x), Some(y)) if (x - y).abs() <= 1.0 => {}
+        (None, None) => {}
+        _ => fields.push("duration"),
+    }
+    match (a.bitrate, b.bitrate) {
+        (Some(x), Some(y)) if (x as f64 - y as f64).abs() <= 0.1 * y.max(1) as f64 => {}
+        (None, None) => {}
+        _ => fields.push("bitrate"),
+    }
+
+    fields
+}
+
+impl BackendBenchmark {
+    pub fn print(&self, verbose: bool) {
+        logger::success(&format!("\nMetadata backend benchmark ({} files)", self.files));
+        for timing in [&self.native, &self.ffprobe] {
+            let secs = timing.elapsed.as_secs_f64();
+            let rate = if secs > 0.0 {
+                (self.files - timing.failed) as f64 / secs
+            } else {
+                0.0
+            };
+            logger::info(&format!(
+                "  {:<8} {:>8.2}s  {:>8.1} files/s  {} failed",
+                timing.backend.as_str(),
+                secs,
+                rate,
+                timing.failed
+            ));
+        }
+
+        if self.mismatches.is_empty() {
+            logger::info("  Both backends agree on every file they could read");
+            return;
+        }
+
+        logger::warning(&format!(
+            "  {} files read differently",
+            self.mismatches.len()
+        ));
+        for (file, fields) in self.mismatches.iter().take(if verbose { usize::MAX } else { 10 }) {
+            logger::plain(&format!("    {}: {}", file.display(), fields.join(", ")));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_get_organizing_artist() {
-        let mut meta = AudioMetadata::default();
-        meta.artist = Some("The Beatles".to_string());
-        meta.album_artist = Some("Beatles, The".to_string());
+        let meta = AudioMetadata {
+            artist: Some("The Beatles".to_string()),
+            album_artist: Some("Beatles, The".to_string()),
+            ..Default::default()
+        };
 
         // Prefer album artist (default)
         assert_eq!(meta.get_organizing_artist(false), "Beatles, The");
@@ -414,8 +1057,10 @@ mod tests {
 
     #[test]
     fn test_looks_like_va() {
-        let mut meta = AudioMetadata::default();
-        meta.album_artist = Some("Various Artists".to_string());
+        let mut meta = AudioMetadata {
+            album_artist: Some("Various Artists".to_string()),
+            ..Default::default()
+        };
         assert!(meta.looks_like_va());
 
         meta.album_artist = Some("Compilation".to_string());
@@ -563,4 +1208,129 @@ mod tests {
 
         assert_eq!(AudioMetadata::get_tag_fuzzy(tags_map, "album"), None);
     }
+
+    #[test]
+    fn test_apply_tag_map_release_fields() {
+        use serde_json::json;
+
+        let tags = json!({
+            "TRACK": "3/12",
+            "disc": "1/2",
+            "YEAR": "1999",
+            "DATE": "1999-05-01",
+            "Composer": "Someone",
+            "TSRC": "USABC9900001",
+            "publisher": "Label Records",
+            "CATALOGNUMBER": "LR-001",
+            "compilation": "1",
+            "artist-sort": "Band, The",
+            "album_artist-sort": "Band, The",
+            "MUSICBRAINZ_TRACKID": "rec-1",
+            "MusicBrainz Album Id": "rel-1",
+            "ARTISTS": "Singer;Band",
+            "MusicBrainz Album Status": "official",
+            "BARCODE": "0123456789012"
+        });
+        let mut meta = AudioMetadata::default();
+        meta.apply_tag_map(tags.as_object().unwrap());
+
+        assert_eq!(meta.track_number, Some(3));
+        assert_eq!(meta.track_total, Some(12));
+        assert_eq!(meta.disc_number, Some(1));
+        assert_eq!(meta.disc_total, Some(2));
+        assert_eq!(meta.date.as_deref(), Some("1999-05-01"));
+        assert_eq!(meta.composer.as_deref(), Some("Someone"));
+        assert_eq!(meta.isrc.as_deref(), Some("USABC9900001"));
+        assert_eq!(meta.label.as_deref(), Some("Label Records"));
+        assert_eq!(meta.catalog_number.as_deref(), Some("LR-001"));
+        assert!(meta.compilation);
+        assert_eq!(meta.artist_sort.as_deref(), Some("Band, The"));
+        assert_eq!(meta.album_artist_sort.as_deref(), Some("Band, The"));
+        assert_eq!(meta.musicbrainz_recording_id.as_deref(), Some("rec-1"));
+        assert_eq!(meta.musicbrainz_release_id.as_deref(), Some("rel-1"));
+        assert_eq!(meta.artists, vec!["Singer", "Band"]);
+        assert_eq!(meta.release_status.as_deref(), Some("official"));
+        assert_eq!(meta.barcode.as_deref(), Some("0123456789012"));
+        assert_eq!(meta.tag_value("artists").as_deref(), Some("Singer\0Band"));
+
+        // Values already set win over later tags
+        meta.apply_tag_map(json!({"DISCNUMBER": "2", "TOTALDISCS": "3"}).as_object().unwrap());
+        assert_eq!(meta.disc_number, Some(1));
+        assert_eq!(meta.disc_total, Some(2));
+    }
+
+    #[test]
+    fn test_apply_symphonia_tags() {
+        let mut meta = AudioMetadata::default();
+        meta.apply_tag(&Tag::new(
+            Some(StandardTagKey::DiscNumber),
+            "TPOS",
+            Value::from("2/3"),
+        ));
+        meta.apply_tag(&Tag::new(None, "TXXX:ORIGINALYEAR", Value::from("1970")));
+        meta.apply_tag(&Tag::new(None, "----:com.apple.iTunes:LABEL", Value::from("Label")));
+        meta.apply_tag(&Tag::new(Some(StandardTagKey::Compilation), "cpil", Value::Flag));
+        meta.apply_tag(&Tag::new(
+            Some(StandardTagKey::MusicBrainzTrackId),
+            "MUSICBRAINZ_TRACKID",
+            Value::from("rec-1"),
+        ));
+        meta.apply_tag(&Tag::new(None, "TXXX:MusicBrainz Release Track Id", Value::from("trk-1")));
+        for id in ["art-1", "art-2"] {
+            meta.apply_tag(&Tag::new(
+                Some(StandardTagKey::MusicBrainzArtistId),
+                "MUSICBRAINZ_ARTISTID",
+                Value::from(id),
+            ));
+        }
+
+        assert_eq!(meta.disc_number, Some(2));
+        assert_eq!(meta.disc_total, Some(3));
+        assert_eq!(meta.original_date.as_deref(), Some("1970"));
+        assert_eq!(meta.label.as_deref(), Some("Label"));
+        assert!(meta.compilation);
+        assert_eq!(meta.musicbrainz_recording_id.as_deref(), Some("rec-1"));
+        assert_eq!(meta.musicbrainz_track_id.as_deref(), Some("trk-1"));
+        assert_eq!(meta.musicbrainz_artist_ids, vec!["art-1", "art-2"]);
+    }
+
+    #[test]
+    fn test_old_cache_json_still_parses() {
+        let json = r#"{"artist":"Band","album":null,"album_artist":null,"title":"Song",
+            "track_number":1,"date":null,"genre":null,"codec":"mp3","bitrate":320000,
+            "sample_rate":44100,"channels":2,"duration_secs":200.0}"#;
+        let meta: AudioMetadata = serde_json::from_str(json).unwrap();
+        assert_eq!(meta.disc_number, None);
+        assert!(!meta.compilation);
+    }
+
+    #[test]
+    fn test_backend_parse() {
+        assert_eq!(MetadataBackend::parse("Native").unwrap(), MetadataBackend::Native);
+        assert_eq!(MetadataBackend::parse("ffprobe").unwrap(), MetadataBackend::Ffprobe);
+        assert!(MetadataBackend::parse("taglib").is_err());
+    }
+
+    #[test]
+    fn test_differing_fields() {
+        let a = AudioMetadata {
+            artist: Some("Band".to_string()),
+            codec: "flac".to_string(),
+            duration_secs: Some(200.2),
+            bitrate: Some(900_000),
+            ..Default::default()
+        };
+        let mut b = AudioMetadata {
+            artist: Some("Band ".to_string()),
+            duration_secs: Some(200.9),
+            bitrate: Some(950_000),
+            ..a.clone()
+        };
+        assert!(differing_fields(&a, &b).is_empty());
+
+        b.codec = "alac".to_string();
+        b.track_number = Some(1);
+        b.bitrate = Some(500_000);
+        assert_eq!(differing_fields(&a, &b), vec!["track", "codec", "bitrate"]);
+    }
 }
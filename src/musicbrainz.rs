@@ -1,8 +1,15 @@
+use crate::cache::{self, MetadataCache, SubmissionStatus};
+use crate::config::Config;
+use crate::http;
+use crate::matching::ReleasePreference;
 use crate::metadata::AudioMetadata;
+use crate::tags::{self, TagEdit};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
 use std::path::Path;
-use std::process::Command;
+use std::time::{Duration, Instant};
 
 /// Result from AcoustID fingerprint lookup
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,10 +22,16 @@ pub struct AcoustIdResult {
     pub title: Option<String>,
     /// Artists (if available)
     pub artists: Vec<String>,
+    /// Releases the recording appears on
+    #[serde(default)]
+    pub release_ids: Vec<String>,
+    /// What the lookup tells about those releases, to rank them before fetching any
+    #[serde(default)]
+    pub release_info: HashMap<String, ReleaseInfo>,
 }
 
 /// Complete metadata from MusicBrainz
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MusicBrainzMetadata {
     /// MusicBrainz recording ID
     pub recording_id: String,
@@ -34,10 +47,409 @@ pub struct MusicBrainzMetadata {
     pub album_artist: Option<String>,
     /// Track number on release
     pub track_number: Option<u32>,
+    /// Disc (medium) number on release
+    pub disc_number: Option<u32>,
     /// Release date (YYYY or YYYY-MM-DD)
     pub date: Option<String>,
     /// Genre/tags
     pub genres: Vec<String>,
+    /// Recording length in seconds
+    #[serde(default)]
+    pub length_secs: Option<f64>,
+    /// Number of tracks on the medium
+    #[serde(default)]
+    pub track_total: Option<u32>,
+    /// Number of media in the release
+    #[serde(default)]
+    pub disc_total: Option<u32>,
+    /// MusicBrainz track ID (the recording's place on the release)
+    #[serde(default)]
+    pub track_id: Option<String>,
+    /// The track's artists, one by one
+    #[serde(default)]
+    pub credit: ArtistCredit,
+    /// Format of the medium the track is on ("CD", "Digital Media", ...)
+    #[serde(default)]
+    pub media: Option<String>,
+    #[serde(default)]
+    pub release: ReleaseDetails,
+}
+
+/// The artists of an artist credit, one by one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArtistCredit {
+    /// Names as credited
+    pub names: Vec<String>,
+    /// MusicBrainz artist IDs
+    pub ids: Vec<String>,
+    /// The whole credit spelled with sort names ("Beatles, The")
+    pub sort_name: Option<String>,
+}
+
+/// What Picard tags about a release, beyond its title, artist and date
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReleaseDetails {
+    pub release_group_id: Option<String>,
+    /// Lower case, as Picard writes it ("official")
+    pub status: Option<String>,
+    /// Release group primary type, then secondary types, lower case ("album", "live")
+    pub types: Vec<String>,
+    pub country: Option<String>,
+    pub barcode: Option<String>,
+    pub label: Option<String>,
+    pub catalog_number: Option<String>,
+    /// First release date of the release group
+    pub original_date: Option<String>,
+    /// The release's (album) artists
+    pub credit: ArtistCredit,
+}
+
+/// Metadata requested with every AcoustID lookup
+const ACOUSTID_META: &str = "recordings releasegroups releases compress";
+
+/// Wait before the first retry of a busy request without a Retry-After header, doubled on
+/// every further retry
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The AcoustID and MusicBrainz web services, as configured
+///
+/// Requests are rate limited here, each service on its own, and retried while the service is
+/// busy. Lookups are answered from the metadata cache while they're fresh. Recording and
+/// replaying runs bypass the cache so every request is captured.
+pub struct Api {
+    http: http::Client,
+    acoustid_url: String,
+    musicbrainz_url: String,
+    cache: Option<MetadataCache>,
+    /// How long cached responses are reused for, None to not cache them
+    acoustid_max_age: Option<i64>,
+    musicbrainz_max_age: Option<i64>,
+    acoustid_limiter: RateLimiter,
+    musicbrainz_limiter: RateLimiter,
+    max_retries: u32,
+    /// Fingerprints sent per AcoustID lookup
+    acoustid_batch_size: usize,
+    /// Fetch MusicBrainz entities even when the cache has a fresh response for them
+    refresh: bool,
+    /// Which of a recording's releases metadata is taken from
+    pub release_preference: ReleasePreference,
+}
+
+impl Api {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mb = &config.musicbrainz;
+        let recordings_path = mb.resolve_recordings_path(&config.general.cache_path);
+        let mode = http::Mode::parse(&mb.http_mode)?;
+        let max_age = |days: u64| (days > 0).then_some(days as i64 * 24 * 60 * 60);
+        let limiter = |name: &str, requests_per_second: f32| {
+            if requests_per_second > 0.0 {
+                Ok(RateLimiter::new(requests_per_second))
+            } else {
+                anyhow::bail!(
+                    "[musicbrainz] {} must be greater than 0 (got {})",
+                    name,
+                    requests_per_second
+                )
+            }
+        };
+        Ok(Self {
+            http: http::Client::new(&mb.user_agent, mode, &recordings_path),
+            acoustid_url: mb.acoustid_url.trim_end_matches('/').to_string(),
+            musicbrainz_url: mb.musicbrainz_url.trim_end_matches('/').to_string(),
+            cache: if mode == http::Mode::Live {
+                cache::get_global_cache()
+            } else {
+                None
+            },
+            acoustid_max_age: max_age(mb.acoustid_cache_days),
+            musicbrainz_max_age: max_age(mb.musicbrainz_cache_days),
+            acoustid_limiter: limiter("acoustid_rate_limit", mb.acoustid_rate_limit)?,
+            musicbrainz_limiter: limiter("musicbrainz_rate_limit", mb.musicbrainz_rate_limit)?,
+            max_retries: mb.max_retries,
+            acoustid_batch_size: mb.acoustid_batch_size.max(1),
+            refresh: false,
+            release_preference: ReleasePreference::from_config(&mb.release_preference)?,
+        })
+    }
+
+    pub fn mode(&self) -> http::Mode {
+        self.http.mode()
+    }
+
+    /// How many fingerprints `lookup_by_fingerprints` sends per request
+    pub fn acoustid_batch_size(&self) -> usize {
+        self.acoustid_batch_size
+    }
+
+    /// Always fetch recordings and releases, still caching the responses
+    pub fn refreshing(mut self) -> Self {
+        self.refresh = true;
+        self
+    }
+
+    /// Tell the user when responses are recorded or replayed instead of only fetched
+    pub fn log_mode(&self) {
+        let recordings = self.http.recordings_path().display();
+        match self.mode() {
+            http::Mode::Live => {}
+            http::Mode::Record => {
+                crate::logger::info(&format!("Recording web service responses to {}", recordings))
+            }
+            http::Mode::Replay => crate::logger::info(&format!(
+                "Replaying recorded web service responses from {}",
+                recordings
+            )),
+        }
+    }
+
+    /// Send a request once its service's rate limit allows it, retrying while the service is busy
+    ///
+    /// A 503 or 429 response holds back every request to that service for as long as its
+    /// Retry-After header asks, or an exponentially growing backoff without one, and then the
+    /// request is sent again. Replayed responses are neither throttled nor retried.
+    async fn send<F, Fut>(&self, limiter: &RateLimiter, request: F) -> Result<http::Response>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<http::Response>>,
+    {
+        if self.mode() == http::Mode::Replay {
+            return request().await;
+        }
+
+        let mut retries = 0;
+        loop {
+            limiter.wait().await;
+            let response = request().await?;
+            if !response.is_busy() || retries >= self.max_retries {
+                return Ok(response);
+            }
+            let backoff = RETRY_BACKOFF * 2u32.pow(retries.min(6));
+            limiter.hold(response.retry_after.unwrap_or(backoff));
+            retries += 1;
+        }
+    }
+
+    /// POST an AcoustID lookup, or reuse a cached response to it
+    async fn acoustid_lookup(
+        &self,
+        fingerprint: &str,
+        duration: i64,
+        api_key: &str,
+    ) -> Result<serde_json::Value> {
+        if let Some(json) = self.cached_acoustid_response(fingerprint, duration) {
+            return Ok(json);
+        }
+
+        let url = format!("{}/lookup", self.acoustid_url);
+        let duration_field = duration.to_string();
+        let form = [
+            ("client", api_key),
+            ("duration", duration_field.as_str()),
+            ("fingerprint", fingerprint),
+            ("meta", ACOUSTID_META),
+        ];
+        let response = self
+            .send(&self.acoustid_limiter, || self.http.post_form(&url, &form))
+            .await?;
+
+        if !response.is_success() {
+            anyhow::bail!("AcoustID API returned error {}: {}", response.status, response.body);
+        }
+
+        let json = response.json().context("Failed to parse AcoustID response")?;
+        if json["status"].as_str() == Some("ok") {
+            self.cache_acoustid_response(fingerprint, duration, &response.body);
+        }
+        Ok(json)
+    }
+
+    /// POST one AcoustID lookup for several fingerprints, reusing cached responses
+    ///
+    /// Returns each fingerprint's response in the shape of a single lookup, in the order
+    /// they were given, or None for the ones AcoustID reported an error for.
+    async fn acoustid_lookup_batch(
+        &self,
+        lookups: &[(&str, i64)],
+        api_key: &str,
+    ) -> Result<Vec<Option<serde_json::Value>>> {
+        let mut responses: Vec<Option<serde_json::Value>> = lookups
+            .iter()
+            .map(|&(fingerprint, duration)| self.cached_acoustid_response(fingerprint, duration))
+            .collect();
+        let missing: Vec<usize> = (0..lookups.len()).filter(|&i| responses[i].is_none()).collect();
+        if missing.is_empty() {
+            return Ok(responses);
+        }
+
+        // Fields are numbered by position in this request, not in `lookups`
+        let mut fields = vec![
+            ("client".to_string(), api_key.to_string()),
+            ("meta".to_string(), ACOUSTID_META.to_string()),
+        ];
+        for (n, &i) in missing.iter().enumerate() {
+            let (fingerprint, duration) = lookups[i];
+            fields.push((format!("duration.{}", n), duration.to_string()));
+            fields.push((format!("fingerprint.{}", n), fingerprint.to_string()));
+        }
+        let form: Vec<(&str, &str)> =
+            fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+        let url = format!("{}/lookup", self.acoustid_url);
+        let response = self
+            .send(&self.acoustid_limiter, || self.http.post_form(&url, &form))
+            .await?;
+        if !response.is_success() {
+            anyhow::bail!("AcoustID API returned error {}: {}", response.status, response.body);
+        }
+        let json = response.json().context("Failed to parse AcoustID response")?;
+        if json["status"].as_str() != Some("ok") {
+            anyhow::bail!("AcoustID batch lookup failed: {}", json["error"]["message"]);
+        }
+
+        for entry in json["fingerprints"].as_array().into_iter().flatten() {
+            // The index comes back as a number or a string depending on the response format
+            let index = entry["index"]
+                .as_u64()
+                .or_else(|| entry["index"].as_str().and_then(|i| i.parse().ok()));
+            let Some(&i) = index.and_then(|n| missing.get(n as usize)) else {
+                continue;
+            };
+            if entry.get("error").is_some() || entry["status"].as_str().unwrap_or("ok") != "ok" {
+                continue;
+            }
+            let single = serde_json::json!({"status": "ok", "results": entry["results"]});
+            let (fingerprint, duration) = lookups[i];
+            self.cache_acoustid_response(fingerprint, duration, &single.to_string());
+            responses[i] = Some(single);
+        }
+        Ok(responses)
+    }
+
+    /// POST to an AcoustID endpoint other than lookup, failing unless it answers "ok"
+    ///
+    /// These requests change what AcoustID knows, so they're never answered from recordings.
+    async fn acoustid_post(
+        &self,
+        endpoint: &str,
+        form: &[(&str, &str)],
+    ) -> Result<serde_json::Value> {
+        if self.mode() == http::Mode::Replay {
+            anyhow::bail!("AcoustID {} can't be replayed; set http_mode to \"live\"", endpoint);
+        }
+
+        let url = format!("{}/{}", self.acoustid_url, endpoint);
+        let response = self
+            .send(&self.acoustid_limiter, || self.http.post_form(&url, form))
+            .await?;
+        let json = response.json().ok().unwrap_or_default();
+        if !response.is_success() || json["status"].as_str() != Some("ok") {
+            let message = json["error"]["message"].as_str().unwrap_or(&response.body);
+            anyhow::bail!("AcoustID {} returned error {}: {}", endpoint, response.status, message);
+        }
+        Ok(json)
+    }
+
+    /// A fresh cached response to an AcoustID lookup
+    fn cached_acoustid_response(
+        &self,
+        fingerprint: &str,
+        duration: i64,
+    ) -> Option<serde_json::Value> {
+        let (cache, max_age) = self.cache.as_ref().zip(self.acoustid_max_age)?;
+        let cached = cache.get_acoustid_response(fingerprint, duration, ACOUSTID_META, max_age);
+        serde_json::from_str(&cached.ok()??).ok()
+    }
+
+    fn cache_acoustid_response(&self, fingerprint: &str, duration: i64, body: &str) {
+        if let Some((cache, _)) = self.cache.as_ref().zip(self.acoustid_max_age) {
+            let _ = cache.set_acoustid_response(fingerprint, duration, ACOUSTID_META, body);
+        }
+    }
+
+    /// GET a recording or release by MBID, or reuse a cached response
+    async fn musicbrainz_entity(
+        &self,
+        entity: &str,
+        mbid: &str,
+        inc: &str,
+    ) -> Result<serde_json::Value> {
+        let cache = self.cache.as_ref().zip(self.musicbrainz_max_age);
+        if let Some((cache, max_age)) = cache.filter(|_| !self.refresh) {
+            let cached = cache.get_musicbrainz_response(entity, mbid, inc, max_age);
+            if let Some(json) = cached.ok().flatten() {
+                return serde_json::from_str(&json)
+                    .context("Failed to parse cached MusicBrainz response");
+            }
+        }
+
+        let path = format!("{}/{}?inc={}&fmt=json", entity, mbid, inc);
+        let json = self
+            .musicbrainz_json(&path, &format!("{} {}", entity, mbid))
+            .await?;
+        if let Some((cache, _)) = cache {
+            let _ = cache.set_musicbrainz_response(entity, mbid, inc, &json.to_string());
+        }
+        Ok(json)
+    }
+
+    /// GET a MusicBrainz web service path and parse the JSON response
+    async fn musicbrainz_json(&self, path: &str, what: &str) -> Result<serde_json::Value> {
+        let url = format!("{}/{}", self.musicbrainz_url, path);
+        let response = self
+            .send(&self.musicbrainz_limiter, || self.http.get(&url))
+            .await?;
+
+        if !response.is_success() {
+            anyhow::bail!("MusicBrainz returned error {} for {}", response.status, what);
+        }
+
+        response
+            .json()
+            .with_context(|| format!("Failed to parse MusicBrainz {}", what))
+    }
+}
+
+impl MusicBrainzMetadata {
+    /// Track number as written to tags, with the medium's track count ("3/12")
+    pub fn track_position(&self) -> Option<String> {
+        Some(position(self.track_number?, self.track_total))
+    }
+
+    /// Disc number as written to tags, with the release's disc count ("1/2")
+    pub fn disc_position(&self) -> Option<String> {
+        Some(position(self.disc_number?, self.disc_total))
+    }
+
+    /// Picard's release-level tags (artist IDs, release group, status, barcode, ...)
+    ///
+    /// Multi-valued tags are joined with `tags::SEPARATOR`; missing values are left out.
+    pub fn release_tags(&self) -> Vec<(&'static str, String)> {
+        let release = &self.release;
+        let joined = |values: &[String]| (!values.is_empty()).then(|| tags::join_values(values));
+        let original_year = release.original_date.as_ref().map(|d| d.chars().take(4).collect());
+        [
+            ("musicbrainz_artistid", joined(&self.credit.ids)),
+            ("musicbrainz_albumartistid", joined(&release.credit.ids)),
+            ("musicbrainz_releasegroupid", release.release_group_id.clone()),
+            ("releasestatus", release.status.clone()),
+            ("releasetype", joined(&release.types)),
+            ("releasecountry", release.country.clone()),
+            ("barcode", release.barcode.clone()),
+            ("media", self.media.clone()),
+            ("originaldate", release.original_date.clone()),
+            ("originalyear", original_year),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+    }
+}
+
+fn position(number: u32, total: Option<u32>) -> String {
+    match total.filter(|t| *t > 0) {
+        Some(total) => format!("{}/{}", number, total),
+        None => number.to_string(),
+    }
 }
 
 /// Look up a recording using an AcoustID fingerprint
@@ -46,50 +458,157 @@ pub struct MusicBrainzMetadata {
 /// matching MusicBrainz recording IDs with confidence scores.
 ///
 /// # Arguments
-/// * `fingerprint` - The audio fingerprint (comma-separated u32 values)
+/// * `api` - Web services to query
+/// * `fingerprint` - The compressed audio fingerprint, as fpcalc prints it
 /// * `duration_secs` - Duration of the audio file in seconds
 /// * `api_key` - AcoustID API key
 ///
 /// # Returns
 /// Vector of results sorted by confidence score (highest first)
 pub async fn lookup_by_fingerprint(
+    api: &Api,
     fingerprint: &str,
     duration_secs: f64,
     api_key: &str,
 ) -> Result<Vec<AcoustIdResult>> {
-    let client = reqwest::Client::new();
+    // Convert duration to integer seconds (API expects integer)
+    let duration_int = duration_secs.round() as i64;
+    let json = api.acoustid_lookup(fingerprint, duration_int, api_key).await?;
 
-    // AcoustID API endpoint
-    let url = "https://api.acoustid.org/v2/lookup";
+    Ok(parse_acoustid_results(&json))
+}
 
-    // Make request
-    // Convert duration to integer seconds (API expects integer)
-    let duration_int = duration_secs.round() as i32;
+/// Look up several fingerprints, `acoustid_batch_size` per request
+///
+/// Each lookup is a fingerprint and the track's duration in seconds. Results come back in the
+/// same order; fingerprints a batch gets no answer for are looked up on their own.
+pub async fn lookup_by_fingerprints(
+    api: &Api,
+    lookups: &[(&str, f64)],
+    api_key: &str,
+) -> Vec<Result<Vec<AcoustIdResult>>> {
+    let mut results = Vec::with_capacity(lookups.len());
+    for batch in lookups.chunks(api.acoustid_batch_size) {
+        let rounded: Vec<(&str, i64)> = batch
+            .iter()
+            .map(|&(fingerprint, duration)| (fingerprint, duration.round() as i64))
+            .collect();
+        let responses = if batch.len() > 1 {
+            api.acoustid_lookup_batch(&rounded, api_key).await.unwrap_or_else(|e| {
+                crate::logger::warning(&format!("AcoustID batch lookup failed: {}", e));
+                vec![None; batch.len()]
+            })
+        } else {
+            vec![None]
+        };
 
-    let response = client
-        .post(url)
-        .form(&[
-            ("client", api_key),
-            ("duration", &duration_int.to_string()),
-            ("fingerprint", fingerprint),
-            ("meta", "recordings releasegroups compress"),
-        ])
-        .send()
-        .await
-        .context("Failed to send request to AcoustID API")?;
+        for (&(fingerprint, duration), response) in batch.iter().zip(responses) {
+            let result = match response {
+                Some(json) => Ok(parse_acoustid_results(&json)),
+                None => lookup_by_fingerprint(api, fingerprint, duration, api_key).await,
+            };
+            results.push(result);
+        }
+    }
+    results
+}
+
+/// AcoustID's answer about one submitted fingerprint
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubmissionReply {
+    /// AcoustID's ID for the submission, when it was accepted
+    pub id: Option<i64>,
+    pub status: SubmissionStatus,
+    /// Why it was rejected
+    pub error: Option<String>,
+}
+
+/// Submit fingerprints to AcoustID as the given recordings, all in one request
+///
+/// Each submission is a fingerprint, the track's duration in seconds and its MusicBrainz
+/// recording ID. Replies come back in the same order.
+pub async fn submit_fingerprints(
+    api: &Api,
+    submissions: &[(&str, i64, &str)],
+    api_key: &str,
+    user_key: &str,
+) -> Result<Vec<SubmissionReply>> {
+    let mut fields = vec![
+        ("client".to_string(), api_key.to_string()),
+        ("clientversion".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        ("user".to_string(), user_key.to_string()),
+    ];
+    for (n, (fingerprint, duration, mbid)) in submissions.iter().enumerate() {
+        fields.push((format!("duration.{}", n), duration.to_string()));
+        fields.push((format!("fingerprint.{}", n), fingerprint.to_string()));
+        fields.push((format!("mbid.{}", n), mbid.to_string()));
+    }
+    let form: Vec<(&str, &str)> = fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let json = api.acoustid_post("submit", &form).await?;
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let body = response.text().await.unwrap_or_else(|_| "Unable to read response body".to_string());
-        anyhow::bail!("AcoustID API returned error {}: {}", status, body);
+    // Anything AcoustID leaves out of its reply wasn't accepted
+    let mut replies = vec![
+        SubmissionReply {
+            id: None,
+            status: SubmissionStatus::Failed,
+            error: Some("Missing from AcoustID's reply".to_string()),
+        };
+        submissions.len()
+    ];
+    for entry in json["submissions"].as_array().into_iter().flatten() {
+        let index = entry["index"]
+            .as_u64()
+            .or_else(|| entry["index"].as_str().and_then(|i| i.parse().ok()));
+        let Some(reply) = index.and_then(|i| replies.get_mut(i as usize)) else {
+            continue;
+        };
+        *reply = match parse_submission_status(entry) {
+            Some(status) => SubmissionReply {
+                id: entry["id"].as_i64(),
+                status,
+                error: None,
+            },
+            None => SubmissionReply {
+                id: None,
+                status: SubmissionStatus::Failed,
+                error: Some(entry["error"]["message"].as_str().unwrap_or("Rejected").to_string()),
+            },
+        };
     }
+    Ok(replies)
+}
 
-    let json: serde_json::Value = response
-        .json()
-        .await
-        .context("Failed to parse AcoustID response")?;
+/// Ask AcoustID whether submissions sent earlier have been imported
+///
+/// Returns the status of every submission AcoustID knows about, by submission ID.
+pub async fn submission_status(
+    api: &Api,
+    ids: &[i64],
+    api_key: &str,
+) -> Result<Vec<(i64, SubmissionStatus)>> {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    let mut form = vec![("client", api_key)];
+    form.extend(ids.iter().map(|id| ("id", id.as_str())));
+    let json = api.acoustid_post("submission_status", &form).await?;
 
-    // Parse results
+    Ok(json["submissions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some((entry["id"].as_i64()?, parse_submission_status(entry)?)))
+        .collect())
+}
+
+fn parse_submission_status(entry: &serde_json::Value) -> Option<SubmissionStatus> {
+    match entry["status"].as_str()? {
+        "pending" => Some(SubmissionStatus::Pending),
+        "imported" => Some(SubmissionStatus::Imported),
+        _ => None,
+    }
+}
+
+/// The recordings an AcoustID lookup response matched, best first
+fn parse_acoustid_results(json: &serde_json::Value) -> Vec<AcoustIdResult> {
     let mut results = Vec::new();
 
     if let Some(results_array) = json["results"].as_array() {
@@ -119,11 +638,40 @@ pub async fn lookup_by_fingerprint(
                         })
                         .unwrap_or_default();
 
+                    // Releases are nested in their release groups when those are requested
+                    let mut release_ids: Vec<String> = Vec::new();
+                    let mut release_info = HashMap::new();
+                    let no_group = serde_json::Value::Null;
+                    let groups = recording["releasegroups"].as_array().into_iter().flatten();
+                    let releases = groups
+                        .flat_map(|group| {
+                            let releases = group["releases"].as_array().into_iter().flatten();
+                            releases.map(move |release| (group, release))
+                        })
+                        .chain(
+                            recording["releases"]
+                                .as_array()
+                                .into_iter()
+                                .flatten()
+                                .map(|release| (&no_group, release)),
+                        );
+                    for (group, release) in releases {
+                        if let Some(id) = release["id"].as_str() {
+                            if !release_ids.iter().any(|r| r == id) {
+                                release_ids.push(id.to_string());
+                                let info = parse_acoustid_release_info(group, release);
+                                release_info.insert(id.to_string(), info);
+                            }
+                        }
+                    }
+
                     results.push(AcoustIdResult {
                         recording_id,
                         score,
                         title,
                         artists,
+                        release_ids,
+                        release_info,
                     });
                 }
             }
@@ -133,102 +681,437 @@ pub async fn lookup_by_fingerprint(
     // Sort by score descending
     results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
 
-    Ok(results)
+    results
+}
+
+/// What an AcoustID lookup tells about a release and its release group; the status isn't
+/// part of it
+fn parse_acoustid_release_info(
+    group: &serde_json::Value,
+    release: &serde_json::Value,
+) -> ReleaseInfo {
+    let text = |value: &serde_json::Value| {
+        value.as_str().filter(|s| !s.is_empty()).map(String::from)
+    };
+    // Dates come as {"year": 1997, "month": 5, "day": 21}, any but the year may be missing
+    let parts = &release["date"];
+    let date = parts["year"].as_u64().map(|year| {
+        let mut date = format!("{:04}", year);
+        if let Some(month) = parts["month"].as_u64() {
+            date.push_str(&format!("-{:02}", month));
+            if let Some(day) = parts["day"].as_u64() {
+                date.push_str(&format!("-{:02}", day));
+            }
+        }
+        date
+    });
+    ReleaseInfo {
+        status: None,
+        country: text(&release["country"]),
+        formats: release["mediums"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| text(&m["format"]))
+            .collect(),
+        primary_type: text(&group["type"]),
+        secondary_types: group["secondarytypes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(text)
+            .collect(),
+        date,
+        various_artists: group["artists"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|artist| artist["id"].as_str() == Some(VARIOUS_ARTISTS_ID)),
+    }
+}
+
+/// A MusicBrainz release with its full tracklist
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    /// Release artist credit
+    pub artist: String,
+    pub date: Option<String>,
+    pub genres: Vec<String>,
+    /// What the release preference judges it by
+    pub info: ReleaseInfo,
+    pub details: ReleaseDetails,
+    /// Number of media
+    pub disc_total: u32,
+    /// Tracks of every medium, in order
+    pub tracks: Vec<ReleaseTrack>,
+}
+
+/// What a release is ranked by when choosing between releases
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReleaseInfo {
+    pub status: Option<String>,
+    pub country: Option<String>,
+    /// Format of each medium ("CD", "12\" Vinyl", ...)
+    pub formats: Vec<String>,
+    /// Release group primary type ("Album", "Single", ...)
+    pub primary_type: Option<String>,
+    /// Release group secondary types ("Compilation", "Live", ...)
+    pub secondary_types: Vec<String>,
+    pub date: Option<String>,
+    /// Credited to "Various Artists"
+    pub various_artists: bool,
+}
+
+/// MusicBrainz ID of the "Various Artists" special purpose artist
+const VARIOUS_ARTISTS_ID: &str = "89ad4ac3-39f7-470e-963a-56509c546377";
+
+/// Parse what a release is ranked by, from a release lookup or a release nested in a
+/// recording lookup or search result
+pub fn parse_release_info(json: &serde_json::Value) -> ReleaseInfo {
+    let text = |value: &serde_json::Value| {
+        value.as_str().filter(|s| !s.is_empty()).map(String::from)
+    };
+    let group = &json["release-group"];
+    ReleaseInfo {
+        status: text(&json["status"]),
+        country: text(&json["country"]),
+        formats: json["media"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| text(&m["format"]))
+            .collect(),
+        primary_type: text(&group["primary-type"]),
+        secondary_types: group["secondary-types"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(text)
+            .collect(),
+        date: text(&json["date"]),
+        various_artists: json["artist-credit"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|credit| {
+                credit["artist"]["id"].as_str() == Some(VARIOUS_ARTISTS_ID)
+                    || credit["name"].as_str() == Some("Various Artists")
+            }),
+    }
+}
+
+/// One track of a release
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseTrack {
+    /// MusicBrainz track ID
+    pub id: String,
+    pub recording_id: String,
+    pub title: String,
+    pub artist: String,
+    pub credit: ArtistCredit,
+    /// Position on its medium
+    pub number: u32,
+    /// Position of its medium in the release
+    pub disc: u32,
+    /// Number of tracks on its medium
+    pub track_total: u32,
+    /// Format of its medium
+    pub media: Option<String>,
+    pub length_secs: Option<f64>,
+}
+
+impl Release {
+    /// Metadata for one of the release's tracks
+    pub fn track_metadata(&self, track: &ReleaseTrack) -> MusicBrainzMetadata {
+        MusicBrainzMetadata {
+            recording_id: track.recording_id.clone(),
+            release_id: Some(self.id.clone()),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: Some(self.title.clone()),
+            album_artist: Some(self.artist.clone()),
+            track_number: Some(track.number),
+            disc_number: Some(track.disc),
+            date: self.date.clone(),
+            genres: self.genres.clone(),
+            length_secs: track.length_secs,
+            track_total: Some(track.track_total),
+            disc_total: Some(self.disc_total),
+            track_id: Some(track.id.clone()),
+            credit: track.credit.clone(),
+            media: track.media.clone(),
+            release: self.details.clone(),
+        }
+    }
+
+    /// Fill in where a recording sits on this release: its track and disc numbers and
+    /// totals, the track ID and the medium's format, along with the release details a
+    /// recording lookup doesn't have (label and catalog number)
+    ///
+    /// A recording on the release more than once keeps the position it already has, if
+    /// that's one of them. Returns false when the recording isn't on the release.
+    pub fn fill_position(&self, metadata: &mut MusicBrainzMetadata) -> bool {
+        let mut tracks = self
+            .tracks
+            .iter()
+            .filter(|t| t.recording_id == metadata.recording_id);
+        let Some(first) = tracks.next() else {
+            return false;
+        };
+        let track = std::iter::once(first)
+            .chain(tracks)
+            .find(|t| {
+                Some(t.number) == metadata.track_number
+                    && metadata.disc_number.is_none_or(|disc| disc == t.disc)
+            })
+            .unwrap_or(first);
+
+        metadata.track_number = Some(track.number);
+        metadata.track_total = Some(track.track_total);
+        metadata.disc_number = Some(track.disc);
+        metadata.disc_total = Some(self.disc_total);
+        metadata.track_id = Some(track.id.clone());
+        metadata.media = track.media.clone();
+        metadata.release = self.details.clone();
+        true
+    }
+}
+
+/// Fetch a release with all of its media and tracks from MusicBrainz
+pub async fn fetch_release(api: &Api, release_id: &str) -> Result<Release> {
+    let json = api
+        .musicbrainz_entity(
+            "release",
+            release_id,
+            "recordings+artist-credits+release-groups+genres+labels",
+        )
+        .await?;
+    parse_release(&json)
+}
+
+/// Parse a release lookup response
+/// (`inc=recordings+artist-credits+release-groups+genres+labels`)
+pub fn parse_release(json: &serde_json::Value) -> Result<Release> {
+    let id = json["id"]
+        .as_str()
+        .context("MusicBrainz release has no ID")?
+        .to_string();
+    let artist =
+        artist_credit(&json["artist-credit"]).unwrap_or_else(|| "Unknown Artist".to_string());
+
+    let media = json["media"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let mut tracks = Vec::new();
+    for (index, medium) in media.iter().enumerate() {
+        let disc = medium["position"].as_u64().map_or(index as u32 + 1, |p| p as u32);
+        let medium_tracks = medium["tracks"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let track_total = medium["track-count"]
+            .as_u64()
+            .map_or(medium_tracks.len() as u32, |c| c as u32);
+        let media = medium["format"].as_str().map(String::from);
+        for (position, track) in medium_tracks.iter().enumerate() {
+            let recording = &track["recording"];
+            let Some(recording_id) = recording["id"].as_str() else {
+                continue;
+            };
+            let credit = if track["artist-credit"].is_array() {
+                &track["artist-credit"]
+            } else if recording["artist-credit"].is_array() {
+                &recording["artist-credit"]
+            } else {
+                &json["artist-credit"]
+            };
+            tracks.push(ReleaseTrack {
+                id: track["id"].as_str().unwrap_or_default().to_string(),
+                recording_id: recording_id.to_string(),
+                title: track["title"]
+                    .as_str()
+                    .or(recording["title"].as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                artist: artist_credit(&track["artist-credit"])
+                    .or_else(|| artist_credit(&recording["artist-credit"]))
+                    .unwrap_or_else(|| artist.clone()),
+                credit: parse_artist_credit(credit),
+                number: track["position"].as_u64().map_or(position as u32 + 1, |p| p as u32),
+                disc,
+                track_total,
+                media: media.clone(),
+                length_secs: track["length"]
+                    .as_f64()
+                    .or(recording["length"].as_f64())
+                    .map(|ms| ms / 1000.0),
+            });
+        }
+    }
+
+    Ok(Release {
+        id,
+        title: json["title"].as_str().unwrap_or_default().to_string(),
+        artist,
+        date: json["date"].as_str().filter(|d| !d.is_empty()).map(String::from),
+        genres: json["genres"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|g| g["name"].as_str().map(String::from))
+            .collect(),
+        info: parse_release_info(json),
+        details: parse_release_details(json),
+        disc_total: media.len() as u32,
+        tracks,
+    })
+}
+
+/// Parse what Picard tags about a release, from a release lookup or a release nested in a
+/// recording lookup (which has no label information)
+pub fn parse_release_details(json: &serde_json::Value) -> ReleaseDetails {
+    let text = |value: &serde_json::Value| {
+        value.as_str().filter(|s| !s.is_empty()).map(String::from)
+    };
+    let group = &json["release-group"];
+    let label_info = json["label-info"].as_array().map(Vec::as_slice).unwrap_or_default();
+
+    ReleaseDetails {
+        release_group_id: text(&group["id"]),
+        status: text(&json["status"]).map(|s| s.to_lowercase()),
+        types: std::iter::once(&group["primary-type"])
+            .chain(group["secondary-types"].as_array().into_iter().flatten())
+            .filter_map(text)
+            .map(|t| t.to_lowercase())
+            .collect(),
+        country: text(&json["country"]),
+        barcode: text(&json["barcode"]),
+        label: label_info.iter().find_map(|info| text(&info["label"]["name"])),
+        catalog_number: label_info.iter().find_map(|info| text(&info["catalog-number"])),
+        original_date: text(&group["first-release-date"]),
+        credit: parse_artist_credit(&json["artist-credit"]),
+    }
+}
+
+/// Split an artist credit into its artists
+fn parse_artist_credit(credit: &serde_json::Value) -> ArtistCredit {
+    let parts = credit.as_array().map(Vec::as_slice).unwrap_or_default();
+    let mut result = ArtistCredit::default();
+    let mut sort_name = String::new();
+    for part in parts {
+        let artist = &part["artist"];
+        if let Some(name) = part["name"].as_str().or(artist["name"].as_str()) {
+            result.names.push(name.to_string());
+            sort_name.push_str(artist["sort-name"].as_str().unwrap_or(name));
+        }
+        if let Some(id) = artist["id"].as_str() {
+            result.ids.push(id.to_string());
+        }
+        sort_name.push_str(part["joinphrase"].as_str().unwrap_or(""));
+    }
+    result.sort_name = (!sort_name.is_empty()).then_some(sort_name);
+    result
+}
+
+/// Render an artist credit the way it's printed on the release ("A feat. B")
+fn artist_credit(credit: &serde_json::Value) -> Option<String> {
+    let parts = credit.as_array().filter(|parts| !parts.is_empty())?;
+    Some(
+        parts
+            .iter()
+            .map(|part| {
+                let name = part["name"].as_str().or(part["artist"]["name"].as_str());
+                let joinphrase = part["joinphrase"].as_str().unwrap_or("");
+                format!("{}{}", name.unwrap_or_default(), joinphrase)
+            })
+            .collect(),
+    )
 }
 
 /// Fetch complete metadata for a recording from MusicBrainz
 ///
 /// # Arguments
+/// * `api` - Web services to query
 /// * `recording_id` - MusicBrainz recording ID
-/// * `user_agent` - User agent string (required by MusicBrainz)
-///
-/// # Rate Limiting
-/// Caller is responsible for rate limiting (≤1 request/second)
 pub async fn fetch_recording_metadata(
+    api: &Api,
     recording_id: &str,
-    _user_agent: &str,
 ) -> Result<MusicBrainzMetadata> {
-    use musicbrainz_rs::entity::recording::Recording;
-    use musicbrainz_rs::prelude::*;
-
-    // Note: User agent configuration handled globally by musicbrainz_rs
-
-    // Fetch recording with includes
-    let recording = Recording::fetch()
-        .id(recording_id)
-        .with_artists()
-        .with_releases()
-        .with_tags()
-        .execute()
-        .await
-        .context("Failed to fetch recording from MusicBrainz")?;
-
-    // Extract artist names from artist credit
-    let artist = recording
-        .artist_credit
-        .as_ref()
-        .map(|ac| {
-            ac.iter()
-                .map(|a| a.name.clone())
-                .collect::<Vec<_>>()
-                .join(", ")
-        })
-        .unwrap_or_else(|| "Unknown Artist".to_string());
-
-    // Find best release (prefer official releases, then by date)
-    let best_release = recording
-        .releases
-        .as_ref()
-        .and_then(|releases| {
-            releases
-                .iter()
-                .filter(|r| {
-                    // ReleaseStatus is an enum, compare directly
-                    matches!(r.status, Some(musicbrainz_rs::entity::release::ReleaseStatus::Official))
-                })
-                .max_by(|a, b| {
-                    // DateString is a newtype wrapper, compare the inner String
-                    match (&a.date, &b.date) {
-                        (Some(a_date), Some(b_date)) => a_date.0.cmp(&b_date.0),
-                        (Some(_), None) => std::cmp::Ordering::Greater,
-                        (None, Some(_)) => std::cmp::Ordering::Less,
-                        (None, None) => std::cmp::Ordering::Equal,
-                    }
-                })
-                .or_else(|| releases.first())
-        });
+    let json = api
+        .musicbrainz_entity(
+            "recording",
+            recording_id,
+            "artist-credits+releases+release-groups+media+tags",
+        )
+        .await?;
+    parse_recording(&json, &api.release_preference)
+}
+
+/// Parse a recording from a lookup (`inc=artist-credits+releases+release-groups+media+tags`)
+/// or search response, taking the album from the release the preference ranks first
+pub fn parse_recording(
+    json: &serde_json::Value,
+    preference: &ReleasePreference,
+) -> Result<MusicBrainzMetadata> {
+    let recording_id = json["id"]
+        .as_str()
+        .context("MusicBrainz recording has no ID")?
+        .to_string();
+    let artist =
+        artist_credit(&json["artist-credit"]).unwrap_or_else(|| "Unknown Artist".to_string());
+
+    let best_release = json["releases"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .min_by(|a, b| preference.compare(&parse_release_info(a), &parse_release_info(b)));
 
-    let album = best_release.map(|r| r.title.clone());
-    let release_id = best_release.map(|r| r.id.clone());
-    let date = best_release.and_then(|r| r.date.as_ref().map(|d| d.0.clone()));
+    let album = best_release.and_then(|r| r["title"].as_str().map(String::from));
+    let release_id = best_release.and_then(|r| r["id"].as_str().map(String::from));
+    let date = best_release
+        .and_then(|r| r["date"].as_str())
+        .filter(|d| !d.is_empty())
+        .map(String::from);
+    let album_artist = best_release.and_then(|r| artist_credit(&r["artist-credit"]));
 
-    let album_artist = best_release.and_then(|r| {
-        r.artist_credit.as_ref().map(|ac| {
-            ac.iter()
-                .map(|a| a.name.clone())
-                .collect::<Vec<_>>()
-                .join(", ")
+    // The release's media only list the track of this recording
+    let (medium, track) = best_release
+        .and_then(|r| r["media"].as_array())
+        .into_iter()
+        .flatten()
+        .find_map(|medium| {
+            let tracks = medium["track"].as_array().or(medium["tracks"].as_array())?;
+            Some((medium, tracks.first()?))
         })
+        .unzip();
+    let track_number = track.and_then(|t| {
+        let number = t["number"].as_str().and_then(|n| n.parse().ok());
+        number.or(t["position"].as_u64().map(|p| p as u32))
     });
 
     // Extract genres from tags
-    let genres = recording
-        .tags
-        .unwrap_or_default()
-        .iter()
-        .map(|t| t.name.clone())
+    let genres = json["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t["name"].as_str().map(String::from))
         .collect();
 
     Ok(MusicBrainzMetadata {
-        recording_id: recording.id,
+        recording_id,
         release_id,
-        title: recording.title,
+        title: json["title"].as_str().unwrap_or_default().to_string(),
         artist,
         album,
         album_artist,
-        track_number: None, // TODO: Extract from release/medium
+        track_number,
+        disc_number: medium.and_then(|m| m["position"].as_u64()).map(|p| p as u32),
         date,
         genres,
+        length_secs: json["length"].as_f64().map(|ms| ms / 1000.0),
+        track_total: medium.and_then(|m| m["track-count"].as_u64()).map(|c| c as u32),
+        disc_total: None,
+        track_id: track.and_then(|t| t["id"].as_str()).map(String::from),
+        credit: parse_artist_credit(&json["artist-credit"]),
+        media: medium.and_then(|m| m["format"].as_str()).map(String::from),
+        release: best_release.map(parse_release_details).unwrap_or_default(),
     })
 }
 
@@ -236,25 +1119,21 @@ pub async fn fetch_recording_metadata(
 ///
 /// Useful for files that already have some metadata but might be incomplete or incorrect.
 pub async fn search_by_metadata(
+    api: &Api,
     metadata: &AudioMetadata,
-    _user_agent: &str,
 ) -> Result<Vec<MusicBrainzMetadata>> {
-    use musicbrainz_rs::entity::recording::Recording;
-    use musicbrainz_rs::prelude::*;
-
-    // Note: User agent configuration handled globally by musicbrainz_rs
-
-    // Build search query
+    // Build search query, leaving out the placeholders filled in for missing tags
     let mut query_parts = Vec::new();
-
-    if let Some(ref artist) = metadata.artist {
-        query_parts.push(format!("artist:\"{}\"", artist));
-    }
-    if let Some(ref title) = metadata.title {
-        query_parts.push(format!("recording:\"{}\"", title));
-    }
-    if let Some(ref album) = metadata.album {
-        query_parts.push(format!("release:\"{}\"", album));
+    let fields = [
+        ("artist", &metadata.artist),
+        ("recording", &metadata.title),
+        ("release", &metadata.album),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value.as_deref().filter(|v| !v.starts_with("_unknown")) {
+            let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+            query_parts.push(format!("{}:\"{}\"", field, escaped));
+        }
     }
 
     if query_parts.is_empty() {
@@ -264,51 +1143,40 @@ pub async fn search_by_metadata(
     let query = query_parts.join(" AND ");
 
     // Search recordings
-    let search_result = Recording::search(query)
-        .execute()
-        .await
-        .context("Failed to search MusicBrainz")?;
+    let path = format!(
+        "recording?query={}&limit=10&fmt=json",
+        percent_encode(&query)
+    );
+    let json = api
+        .musicbrainz_json(&path, "recording search")
+        .await?;
 
     // Convert results to our format
-    let mut results = Vec::new();
-    for recording in search_result.entities.into_iter().take(10) {
-        let artist = recording
-            .artist_credit
-            .as_ref()
-            .map(|ac| {
-                ac.iter()
-                    .map(|a| a.name.clone())
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            })
-            .unwrap_or_else(|| "Unknown Artist".to_string());
-
-        let best_release = recording.releases.as_ref().and_then(|r| r.first());
-
-        results.push(MusicBrainzMetadata {
-            recording_id: recording.id.clone(),
-            release_id: best_release.map(|r| r.id.clone()),
-            title: recording.title.clone(),
-            artist,
-            album: best_release.map(|r| r.title.clone()),
-            album_artist: None,
-            track_number: None,
-            date: best_release.and_then(|r| r.date.as_ref().map(|d| d.0.clone())),
-            genres: recording
-                .tags
-                .unwrap_or_default()
-                .iter()
-                .map(|t| t.name.clone())
-                .collect(),
-        });
-    }
+    json["recordings"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .take(10)
+        .map(|recording| parse_recording(recording, &api.release_preference))
+        .collect()
+}
 
-    Ok(results)
+/// Percent-encode a query string value
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
 }
 
 /// Apply MusicBrainz metadata to an audio file
 ///
-/// Updates the file's tags using ffmpeg and stores MusicBrainz IDs.
+/// Rewrites the file's tags in place with `tags::write` and stores MusicBrainz IDs.
 /// Only updates fields specified in `fields_to_update` for additive-only mode.
 ///
 /// # Arguments
@@ -345,80 +1213,112 @@ pub fn apply_metadata_to_file(
                 crate::logger::info(&format!("  Date: {}", date));
             }
         }
+        if fields_to_update.update_track {
+            if let Some(track) = mb_metadata.track_position() {
+                crate::logger::info(&format!("  Track: {}", track));
+            }
+        }
+        if fields_to_update.update_disc {
+            if let Some(disc) = mb_metadata.disc_position() {
+                crate::logger::info(&format!("  Disc: {}", disc));
+            }
+        }
         crate::logger::info(&format!(
             "  MusicBrainz Recording ID: {}",
             mb_metadata.recording_id
         ));
+        if let Some(ref track_id) = mb_metadata.track_id {
+            crate::logger::info(&format!("  MusicBrainz Track ID: {}", track_id));
+        }
         return Ok(());
     }
 
-    // Create temporary file
-    let temp_path = file_path.with_extension("tmp");
-
-    // Build ffmpeg command with metadata
-    let mut cmd = Command::new("ffmpeg");
-    cmd.args(&["-i"])
-        .arg(file_path)
-        .args(&["-c", "copy"]); // Copy codec, don't re-encode
-
     // Only add metadata for fields that should be updated
+    let mut updates: Vec<(&str, String)> = Vec::new();
     if fields_to_update.update_title {
-        cmd.args(&["-metadata", &format!("title={}", mb_metadata.title)]);
+        updates.push(("title", mb_metadata.title.clone()));
     }
     if fields_to_update.update_artist {
-        cmd.args(&["-metadata", &format!("artist={}", mb_metadata.artist)]);
+        updates.push(("artist", mb_metadata.artist.clone()));
     }
     if fields_to_update.update_album {
         if let Some(ref album) = mb_metadata.album {
-            cmd.args(&["-metadata", &format!("album={}", album)]);
+            updates.push(("album", album.clone()));
         }
     }
     if fields_to_update.update_album_artist {
         if let Some(ref album_artist) = mb_metadata.album_artist {
-            cmd.args(&["-metadata", &format!("album_artist={}", album_artist)]);
+            updates.push(("album_artist", album_artist.clone()));
         }
     }
     if fields_to_update.update_date {
         if let Some(ref date) = mb_metadata.date {
-            cmd.args(&["-metadata", &format!("date={}", date)]);
+            updates.push(("date", date.clone()));
         }
     }
-    if let Some(track_num) = mb_metadata.track_number {
-        cmd.args(&["-metadata", &format!("track={}", track_num)]);
+    if fields_to_update.update_track {
+        if let Some(track) = mb_metadata.track_position() {
+            updates.push(("track", track));
+        }
+    }
+    if fields_to_update.update_disc {
+        if let Some(disc) = mb_metadata.disc_position() {
+            updates.push(("disc", disc));
+        }
     }
     if fields_to_update.update_genre && !mb_metadata.genres.is_empty() {
-        cmd.args(&["-metadata", &format!("genre={}", mb_metadata.genres[0])]);
+        updates.push(("genre", mb_metadata.genres[0].clone()));
     }
 
-    // Add MusicBrainz IDs
-    cmd.args(&[
-        "-metadata",
-        &format!("MUSICBRAINZ_TRACKID={}", mb_metadata.recording_id),
-    ]);
-    if let Some(ref release_id) = mb_metadata.release_id {
-        cmd.args(&["-metadata", &format!("MUSICBRAINZ_ALBUMID={}", release_id)]);
+    // The rest of Picard's tag set: filled in where missing, and replaced along with the
+    // field they belong to
+    let release = &mb_metadata.release;
+    let joined = |values: &[String]| (!values.is_empty()).then(|| tags::join_values(values));
+    let picard_tags = [
+        ("artists", joined(&mb_metadata.credit.names), fields_to_update.update_artist),
+        ("artistsort", mb_metadata.credit.sort_name.clone(), fields_to_update.update_artist),
+        (
+            "albumartistsort",
+            release.credit.sort_name.clone(),
+            fields_to_update.update_album_artist,
+        ),
+        ("label", release.label.clone(), fields_to_update.update_album),
+        ("catalognumber", release.catalog_number.clone(), fields_to_update.update_album),
+    ];
+    for (key, value, replace) in picard_tags {
+        if let Some(value) = value {
+            if replace || current_metadata.tag_value(key).is_none() {
+                updates.push((key, value));
+            }
+        }
     }
 
-    cmd.args(&["-y"]) // Overwrite output file
-        .arg(&temp_path);
-
-    let output = cmd
-        .output()
-        .context("Failed to execute ffmpeg for metadata update")?;
+    // Add MusicBrainz IDs and release details, which only MusicBrainz knows
+    updates.push(("musicbrainz_trackid", mb_metadata.recording_id.clone()));
+    if let Some(ref release_id) = mb_metadata.release_id {
+        updates.push(("musicbrainz_albumid", release_id.clone()));
+    }
+    if let Some(ref track_id) = mb_metadata.track_id {
+        updates.push(("musicbrainz_releasetrackid", track_id.clone()));
+    }
+    updates.extend(mb_metadata.release_tags());
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("ffmpeg failed to update metadata: {}", stderr);
+    let mut edit = TagEdit::new();
+    for (key, value) in &updates {
+        edit = edit.set(key, value.clone());
     }
 
-    // Replace original with updated file
-    std::fs::rename(&temp_path, file_path)
-        .context("Failed to replace original file with updated version")?;
+    let before_hash = crate::journal::hash_if_recording(file_path);
+    tags::write(file_path, &edit)?;
+
+    let (before, after) = current_metadata.tag_changes(&updates);
+    crate::journal::record_tag_rewrite(file_path, before_hash, before, after);
 
     // Update cache
     let mut metadata = AudioMetadata::from_file(file_path)?;
     metadata.musicbrainz_recording_id = Some(mb_metadata.recording_id.clone());
     metadata.musicbrainz_release_id = mb_metadata.release_id.clone();
+    metadata.musicbrainz_track_id = mb_metadata.track_id.clone();
 
     if let Some(cache) = crate::cache::get_global_cache() {
         let _ = cache.insert(file_path, &metadata);
@@ -430,36 +1330,37 @@ pub fn apply_metadata_to_file(
 
 /// Rate limiter for API requests
 ///
-/// Ensures we don't exceed MusicBrainz/AcoustID rate limits
+/// Ensures we don't exceed MusicBrainz/AcoustID rate limits. Every caller is handed its own
+/// time slot, so requests waiting at the same time still go out one interval apart.
 pub struct RateLimiter {
-    last_request: std::sync::Mutex<std::time::Instant>,
-    min_interval: std::time::Duration,
+    /// When the next request may be sent
+    next_request: std::sync::Mutex<Instant>,
+    min_interval: Duration,
 }
 
 impl RateLimiter {
     pub fn new(requests_per_second: f32) -> Self {
-        let min_interval = std::time::Duration::from_secs_f32(1.0 / requests_per_second);
         Self {
-            last_request: std::sync::Mutex::new(
-                std::time::Instant::now() - min_interval, // Allow first request immediately
-            ),
-            min_interval,
+            next_request: std::sync::Mutex::new(Instant::now()), // Allow first request immediately
+            min_interval: Duration::from_secs_f32(1.0 / requests_per_second),
         }
     }
 
     /// Wait until it's safe to make another request (async)
     pub async fn wait(&self) {
-        let elapsed = {
-            let last = self.last_request.lock().unwrap();
-            last.elapsed()
+        let slot = {
+            let mut next = self.next_request.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.min_interval;
+            slot
         };
+        tokio::time::sleep_until(slot.into()).await;
+    }
 
-        if elapsed < self.min_interval {
-            let sleep_duration = self.min_interval - elapsed;
-            tokio::time::sleep(sleep_duration).await;
-        }
-
-        *self.last_request.lock().unwrap() = std::time::Instant::now();
+    /// Send no more requests for a while, e.g. when the service asks us to back off
+    pub fn hold(&self, duration: Duration) {
+        let mut next = self.next_request.lock().unwrap();
+        *next = (*next).max(Instant::now() + duration);
     }
 }
 
@@ -472,3 +1373,318 @@ pub fn get_acoustid_api_key(config: &crate::config::Config) -> Result<String> {
         .or_else(|| std::env::var("ACOUSTID_API_KEY").ok())
         .context("AcoustID API key not found. Set ACOUSTID_API_KEY environment variable or add to config file. Get one from https://acoustid.org/api-key")
 }
+
+/// Helper to get the AcoustID user API key submissions are sent with
+pub fn get_acoustid_user_key(config: &crate::config::Config) -> Result<String> {
+    config
+        .musicbrainz
+        .acoustid_user_key
+        .clone()
+        .or_else(|| std::env::var("ACOUSTID_USER_KEY").ok())
+        .context(
+            "AcoustID user API key not found. Set ACOUSTID_USER_KEY environment variable or add \
+             acoustid_user_key to config file. It's shown on https://acoustid.org/api-key",
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_release() {
+        let json = serde_json::json!({
+            "id": "rel-1",
+            "title": "Album",
+            "date": "1997-05-21",
+            "artist-credit": [
+                {"name": "Singer", "joinphrase": " & ",
+                 "artist": {"id": "art-1", "name": "Singer", "sort-name": "Singer, A"}},
+                {"name": "Band", "joinphrase": "",
+                 "artist": {"id": "art-2", "name": "The Band", "sort-name": "Band, The"}}
+            ],
+            "genres": [{"name": "rock"}],
+            "status": "Official",
+            "country": "GB",
+            "barcode": "0123456789012",
+            "release-group": {"id": "rg-1", "primary-type": "Album",
+                              "secondary-types": ["Live"], "first-release-date": "1997"},
+            "label-info": [{"catalog-number": "LR-001", "label": {"name": "Label Records"}}],
+            "media": [
+                {"position": 1, "format": "CD", "tracks": [
+                    {"position": 1, "title": "Intro", "length": 61000,
+                     "recording": {"id": "rec-1", "title": "Intro"}},
+                    {"id": "trk-2", "position": 2, "title": "Song", "length": null,
+                     "recording": {"id": "rec-2", "title": "Song", "length": 200500,
+                                   "artist-credit": [{"name": "Guest", "joinphrase": ""}]}}
+                ]},
+                {"position": 2, "tracks": [
+                    {"position": 1, "title": "Outro", "recording": {"id": "rec-3"}}
+                ]}
+            ]
+        });
+
+        let release = parse_release(&json).unwrap();
+        assert_eq!(release.artist, "Singer & Band");
+        assert_eq!(release.genres, vec!["rock"]);
+        assert_eq!(release.tracks.len(), 3);
+        assert_eq!(release.tracks[0].length_secs, Some(61.0));
+        assert_eq!(release.tracks[1].artist, "Guest");
+        assert_eq!(release.tracks[1].length_secs, Some(200.5));
+        assert_eq!(
+            (release.tracks[2].disc, release.tracks[2].number),
+            (2, 1)
+        );
+
+        let metadata = release.track_metadata(&release.tracks[2]);
+        assert_eq!(metadata.track_position().as_deref(), Some("1/1"));
+        assert_eq!(metadata.disc_position().as_deref(), Some("2/2"));
+        assert_eq!(metadata.album.as_deref(), Some("Album"));
+        assert_eq!(metadata.album_artist.as_deref(), Some("Singer & Band"));
+        assert_eq!((metadata.disc_number, metadata.track_number), (Some(2), Some(1)));
+
+        // Tracks credited to the release artist get its artists one by one
+        let details = &release.details;
+        assert_eq!(details.credit.names, vec!["Singer", "Band"]);
+        assert_eq!(details.credit.ids, vec!["art-1", "art-2"]);
+        assert_eq!(details.credit.sort_name.as_deref(), Some("Singer, A & Band, The"));
+        assert_eq!(metadata.credit.ids, details.credit.ids);
+        assert_eq!(details.release_group_id.as_deref(), Some("rg-1"));
+        assert_eq!(details.status.as_deref(), Some("official"));
+        assert_eq!(details.types, vec!["album", "live"]);
+        assert_eq!(details.country.as_deref(), Some("GB"));
+        assert_eq!(details.barcode.as_deref(), Some("0123456789012"));
+        assert_eq!(details.label.as_deref(), Some("Label Records"));
+        assert_eq!(details.catalog_number.as_deref(), Some("LR-001"));
+        assert_eq!(details.original_date.as_deref(), Some("1997"));
+        assert_eq!(release.tracks[1].credit.names, vec!["Guest"]);
+        assert_eq!(release.tracks[0].media.as_deref(), Some("CD"));
+        assert_eq!(metadata.media, None);
+
+        let mut found = MusicBrainzMetadata {
+            recording_id: "rec-2".to_string(),
+            ..Default::default()
+        };
+        assert!(release.fill_position(&mut found));
+        assert_eq!(found.track_position().as_deref(), Some("2/2"));
+        assert_eq!(found.disc_position().as_deref(), Some("1/2"));
+        assert_eq!(found.track_id.as_deref(), Some("trk-2"));
+        assert_eq!(found.release.label.as_deref(), Some("Label Records"));
+        found.recording_id = "elsewhere".to_string();
+        assert!(!release.fill_position(&mut found));
+    }
+
+    #[test]
+    fn test_parse_recording_takes_preferred_release() {
+        let album = serde_json::json!({"primary-type": "Album", "secondary-types": []});
+        let best_of =
+            serde_json::json!({"primary-type": "Album", "secondary-types": ["Compilation"]});
+        let json = serde_json::json!({
+            "id": "rec-1",
+            "title": "Song",
+            "length": 185000,
+            "artist-credit": [{"name": "Singer", "joinphrase": ""}],
+            "tags": [{"name": "pop", "count": 3}],
+            "releases": [
+                {"id": "bootleg", "title": "Live", "status": "Bootleg", "date": "1995",
+                 "release-group": album},
+                {"id": "hits", "title": "Hits", "status": "Official", "date": "1990",
+                 "release-group": best_of},
+                {"id": "first", "title": "Album", "status": "Official", "date": "1997-05-21",
+                 "release-group": album,
+                 "media": [{"position": 2, "track-offset": 3, "track-count": 9,
+                            "track": [{"id": "trk-4", "number": "4", "position": 4}]}]},
+                {"id": "reissue", "title": "Album (Remaster)", "status": "Official",
+                 "date": "2011", "release-group": album},
+                {"id": "undated", "title": "Album", "status": "Official", "date": "",
+                 "release-group": album}
+            ]
+        });
+
+        let metadata = parse_recording(&json, &ReleasePreference::default()).unwrap();
+        assert_eq!(metadata.artist, "Singer");
+        assert_eq!(metadata.length_secs, Some(185.0));
+        assert_eq!(metadata.release_id.as_deref(), Some("first"));
+        assert_eq!(metadata.date.as_deref(), Some("1997-05-21"));
+        assert_eq!(metadata.genres, vec!["pop"]);
+        assert_eq!(metadata.track_position().as_deref(), Some("4/9"));
+        assert_eq!(metadata.disc_number, Some(2));
+        assert_eq!(metadata.track_id.as_deref(), Some("trk-4"));
+        assert_eq!(metadata.release.types, vec!["album"]);
+        assert_eq!(metadata.release.status.as_deref(), Some("official"));
+
+        let latest = ReleasePreference::from_config(&crate::config::ReleasePreferenceConfig {
+            date_preference: "latest".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let metadata = parse_recording(&json, &latest).unwrap();
+        assert_eq!(metadata.release_id.as_deref(), Some("reissue"));
+        assert_eq!(percent_encode("artist:\"A & B\""), "artist%3A%22A%20%26%20B%22");
+    }
+
+    #[test]
+    fn test_parse_acoustid_release_info() {
+        let json = serde_json::json!({"results": [{"score": 0.9, "recordings": [{
+            "id": "rec",
+            "releasegroups": [{
+                "type": "Album",
+                "secondarytypes": ["Compilation"],
+                "artists": [{"id": VARIOUS_ARTISTS_ID, "name": "Various Artists"}],
+                "releases": [{
+                    "id": "rel",
+                    "country": "GB",
+                    "date": {"year": 1979, "month": 3},
+                    "mediums": [{"format": "12\" Vinyl"}],
+                }],
+            }],
+        }]}]});
+        let results = parse_acoustid_results(&json);
+        assert_eq!(results[0].release_ids, vec!["rel"]);
+
+        let info = &results[0].release_info["rel"];
+        assert_eq!(info.country.as_deref(), Some("GB"));
+        assert_eq!(info.date.as_deref(), Some("1979-03"));
+        assert_eq!(info.formats, vec!["12\" Vinyl"]);
+        assert_eq!(info.primary_type.as_deref(), Some("Album"));
+        assert_eq!(info.secondary_types, vec!["Compilation"]);
+        assert!(info.various_artists);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_concurrent_requests() {
+        let limiter = RateLimiter::new(20.0);
+        let start = Instant::now();
+        tokio::join!(limiter.wait(), limiter.wait(), limiter.wait());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    /// Answer one request per response with a local server, returning its URL and a handle
+    /// that yields the request bodies it received
+    fn serve(
+        responses: Vec<(&'static str, &'static str, String)>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for (status, headers, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut request = vec![0u8; length];
+                reader.read_exact(&mut request).unwrap();
+                bodies.push(String::from_utf8(request).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    headers,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_busy_responses_are_retried() {
+        // Busy once, then answers
+        let (url, server) = serve(vec![
+            ("503 Service Unavailable", "Retry-After: 0\r\n", String::new()),
+            ("200 OK", "", r#"{"id": "rel-1"}"#.to_string()),
+        ]);
+
+        let mut config = Config::default();
+        config.musicbrainz.musicbrainz_url = url;
+        config.musicbrainz.musicbrainz_cache_days = 0;
+        config.musicbrainz.musicbrainz_rate_limit = 100.0;
+        let api = Api::from_config(&config).unwrap();
+        let json = api.musicbrainz_json("release/rel-1", "release").await.unwrap();
+        server.join().unwrap();
+        assert_eq!(json["id"], "rel-1");
+
+        config.musicbrainz.acoustid_rate_limit = 0.0;
+        assert!(Api::from_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_batched_lookup_maps_results_to_fingerprints() {
+        let recording = |id: &str| serde_json::json!({"score": 0.9, "recordings": [{"id": id}]});
+        let batch = serde_json::json!({"status": "ok", "fingerprints": [
+            {"index": 1, "results": [recording("rec-b")]},
+            {"index": 0, "results": [recording("rec-a")]},
+            {"index": 2, "status": "error", "error": {"message": "invalid fingerprint"}},
+        ]});
+        let single = serde_json::json!({"status": "ok", "results": [recording("rec-c")]});
+        let (url, server) = serve(vec![
+            ("200 OK", "", batch.to_string()),
+            ("200 OK", "", single.to_string()),
+        ]);
+
+        let mut config = Config::default();
+        config.musicbrainz.acoustid_url = url;
+        config.musicbrainz.acoustid_cache_days = 0;
+        config.musicbrainz.acoustid_rate_limit = 100.0;
+        config.musicbrainz.acoustid_batch_size = 3;
+        let api = Api::from_config(&config).unwrap();
+        let lookups = [("AQAAa", 200.0), ("AQAAb", 180.4), ("AQAAc", 240.0)];
+        let results = lookup_by_fingerprints(&api, &lookups, "key").await;
+        let bodies = server.join().unwrap();
+
+        let ids: Vec<String> = results
+            .into_iter()
+            .map(|r| r.unwrap()[0].recording_id.clone())
+            .collect();
+        assert_eq!(ids, vec!["rec-a", "rec-b", "rec-c"]);
+
+        // One request for the batch, then the fingerprint it got no answer for on its own
+        assert!(bodies[0].contains("fingerprint.2=AQAAc") && bodies[0].contains("duration.1=180"));
+        assert!(bodies[1].contains("fingerprint=AQAAc"));
+    }
+
+    #[tokio::test]
+    async fn test_submissions_report_each_fingerprint() {
+        let submitted = serde_json::json!({"status": "ok", "submissions": [
+            {"index": 0, "id": 41, "status": "pending"},
+            {"index": 1, "error": {"message": "invalid fingerprint"}},
+        ]});
+        let checked = serde_json::json!({"status": "ok", "submissions": [
+            {"id": 41, "status": "imported", "result": {"id": "acoustid-1"}},
+        ]});
+        let (url, server) = serve(vec![
+            ("200 OK", "", submitted.to_string()),
+            ("200 OK", "", checked.to_string()),
+        ]);
+
+        let mut config = Config::default();
+        config.musicbrainz.acoustid_url = url;
+        config.musicbrainz.acoustid_rate_limit = 100.0;
+        let api = Api::from_config(&config).unwrap();
+        let submissions = [("AQAAa", 200, "rec-a"), ("AQAAb", 180, "rec-b")];
+        let replies = submit_fingerprints(&api, &submissions, "key", "user").await.unwrap();
+        let statuses = submission_status(&api, &[41], "key").await.unwrap();
+        let bodies = server.join().unwrap();
+
+        assert_eq!((replies[0].id, replies[0].status), (Some(41), SubmissionStatus::Pending));
+        assert_eq!(replies[1].status, SubmissionStatus::Failed);
+        assert_eq!(replies[1].error.as_deref(), Some("invalid fingerprint"));
+        assert_eq!(statuses, vec![(41, SubmissionStatus::Imported)]);
+        assert!(bodies[0].contains("user=user") && bodies[0].contains("mbid.1=rec-b"));
+    }
+}
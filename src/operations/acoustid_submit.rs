@@ -0,0 +1,271 @@
+use crate::cache::{self, MetadataCache, SubmissionStatus};
+use crate::config::Config;
+use crate::metadata::AudioMetadata;
+use crate::musicbrainz;
+use crate::operations::mb_sync::scan_tagged_files;
+use crate::operations::OperationStats;
+use crate::{fingerprint, logger};
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+pub struct AcoustIdSubmitOptions {
+    /// Directories whose MusicBrainz-tagged files are queued when AcoustID doesn't know them
+    pub input_dirs: Vec<PathBuf>,
+    pub dry_run: bool,
+    pub verbose: bool,
+}
+
+/// The user API key submissions are sent with, and the cache that tracks them
+pub(crate) struct Submitter {
+    pub user_key: String,
+    pub cache: MetadataCache,
+}
+
+impl Submitter {
+    /// Fail early when submissions can't be made
+    pub(crate) fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            user_key: musicbrainz::get_acoustid_user_key(config)?,
+            cache: cache::get_global_cache()
+                .context("The metadata cache is needed to keep track of AcoustID submissions")?,
+        })
+    }
+
+    /// Queue a file's fingerprint to be submitted as a recording
+    pub(crate) fn queue(
+        &self,
+        path: &Path,
+        metadata: &AudioMetadata,
+        fingerprint: &str,
+        mbid: &str,
+        verbose: bool,
+    ) {
+        let duration = metadata.duration_secs.unwrap_or(0.0).round() as i64;
+        match self
+            .cache
+            .queue_acoustid_submission(path, fingerprint, duration, mbid)
+        {
+            Ok(true) => logger::debug(
+                &format!("Queued {} for AcoustID as {}", path.display(), mbid),
+                verbose,
+            ),
+            Ok(false) => {}
+            Err(e) => logger::warning(&format!(
+                "Failed to queue {} for AcoustID: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+
+    /// Send every queued submission, `acoustid_batch_size` per request
+    ///
+    /// Submissions AcoustID rejects are marked failed and never sent again. A request that
+    /// fails as a whole stops sending and leaves the rest queued for the next run.
+    pub(crate) async fn send_queued(
+        &self,
+        api: &musicbrainz::Api,
+        api_key: &str,
+        dry_run: bool,
+        stats: &mut OperationStats,
+    ) -> Result<()> {
+        let queued = self.cache.acoustid_submissions(SubmissionStatus::Queued)?;
+        if queued.is_empty() {
+            logger::info("No fingerprints are waiting to be submitted");
+            return Ok(());
+        }
+        logger::info(&format!(
+            "Submitting {} fingerprints to AcoustID",
+            queued.len()
+        ));
+
+        for batch in queued.chunks(api.acoustid_batch_size()) {
+            stats.processed += batch.len();
+            if dry_run {
+                for submission in batch {
+                    logger::info(&format!(
+                        "  [DRY RUN] Would submit {} as {}",
+                        submission.path.display(),
+                        submission.mbid
+                    ));
+                }
+                stats.succeeded += batch.len();
+                continue;
+            }
+
+            let entries: Vec<(&str, i64, &str)> = batch
+                .iter()
+                .map(|s| (s.fingerprint.as_str(), s.duration, s.mbid.as_str()))
+                .collect();
+            let replies =
+                musicbrainz::submit_fingerprints(api, &entries, api_key, &self.user_key).await;
+            let replies = match replies {
+                Ok(replies) => replies,
+                Err(e) => return Err(e.context("Failed to submit fingerprints to AcoustID")),
+            };
+
+            for (submission, reply) in batch.iter().zip(replies) {
+                self.cache.set_acoustid_submission_status(
+                    submission,
+                    reply.status,
+                    reply.id,
+                    reply.error.as_deref(),
+                )?;
+                match reply.error {
+                    Some(error) => stats.add_skipped(submission.path.clone(), error),
+                    None => stats.succeeded += 1,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Log how many submissions are queued, pending, imported and failed
+    pub(crate) fn print_counts(&self) -> Result<()> {
+        let counts = self.cache.acoustid_submission_counts()?;
+        if !counts.is_empty() {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(status, count)| format!("{} {}", count, status))
+                .collect();
+            logger::info(&format!("AcoustID submissions: {}", counts.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// Submit queued fingerprints to AcoustID
+///
+/// Fingerprints are queued by `fix-metadata --submit`, or here from files in `input_dirs`
+/// tagged with a MusicBrainz recording ID that AcoustID has no match for. Submissions sent
+/// earlier that AcoustID hadn't imported yet are checked on first.
+pub async fn run(options: AcoustIdSubmitOptions, config: &Config) -> Result<OperationStats> {
+    logger::stage("AcoustID Submission");
+
+    if options.dry_run {
+        logger::warning("DRY RUN MODE - Nothing will be sent to AcoustID");
+    }
+
+    let api_key = musicbrainz::get_acoustid_api_key(config)?;
+    let submitter = Submitter::from_config(config)?;
+    let api = musicbrainz::Api::from_config(config)?;
+    api.log_mode();
+
+    if !options.input_dirs.is_empty() {
+        queue_unknown_files(&api, &submitter, &options, &api_key).await;
+    }
+
+    check_pending(&api, &submitter.cache, &api_key).await?;
+
+    let mut stats = OperationStats::new();
+    submitter
+        .send_queued(&api, &api_key, options.dry_run, &mut stats)
+        .await?;
+
+    submitter.print_counts()?;
+    stats.print_summary("AcoustID Submission");
+    Ok(stats)
+}
+
+/// Queue tagged files AcoustID returns nothing for
+async fn queue_unknown_files(
+    api: &musicbrainz::Api,
+    submitter: &Submitter,
+    options: &AcoustIdSubmitOptions,
+    api_key: &str,
+) {
+    let files = scan_tagged_files(&options.input_dirs);
+    if files.is_empty() {
+        logger::warning("No files with MusicBrainz recording IDs found");
+        return;
+    }
+    logger::info(&format!(
+        "Looking up {} files with MusicBrainz recording IDs",
+        files.len()
+    ));
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{bar:40}] {pos}/{len} | Fingerprinting...")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+    let fingerprinted: Vec<(PathBuf, AudioMetadata, String)> = files
+        .into_par_iter()
+        .filter_map(|(path, mut metadata)| {
+            pb.inc(1);
+            let fp = match metadata.fingerprint.clone() {
+                Some(fp) => fp,
+                None => match fingerprint::generate_fingerprint(&path) {
+                    Ok(fp) => {
+                        let _ = metadata.add_fingerprint(fp.clone(), &path);
+                        fp
+                    }
+                    Err(e) => {
+                        logger::warning(&format!(
+                            "Failed to fingerprint {}: {}",
+                            path.display(),
+                            e
+                        ));
+                        return None;
+                    }
+                },
+            };
+            Some((path, metadata, fp))
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    let lookups: Vec<(&str, f64)> = fingerprinted
+        .iter()
+        .map(|(_, metadata, fp)| (fp.as_str(), metadata.duration_secs.unwrap_or(0.0)))
+        .collect();
+    let results = musicbrainz::lookup_by_fingerprints(api, &lookups, api_key).await;
+
+    let mut unknown = 0;
+    for ((path, metadata, fp), result) in fingerprinted.iter().zip(results) {
+        match result {
+            Ok(matches) if matches.is_empty() => {
+                unknown += 1;
+                let Some(ref mbid) = metadata.musicbrainz_recording_id else {
+                    continue;
+                };
+                if options.dry_run {
+                    logger::info(&format!("  [DRY RUN] Would queue {}", path.display()));
+                } else {
+                    submitter.queue(path, metadata, fp, mbid, options.verbose);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => logger::warning(&format!(
+                "AcoustID lookup failed for {}: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+    logger::info(&format!("AcoustID doesn't know {} of them", unknown));
+}
+
+/// Update submissions AcoustID hadn't imported yet
+async fn check_pending(api: &musicbrainz::Api, cache: &MetadataCache, api_key: &str) -> Result<()> {
+    let pending = cache.acoustid_submissions(SubmissionStatus::Pending)?;
+    let pending: Vec<_> = pending
+        .into_iter()
+        .filter(|s| s.submission_id.is_some())
+        .collect();
+
+    for batch in pending.chunks(api.acoustid_batch_size()) {
+        let ids: Vec<i64> = batch.iter().filter_map(|s| s.submission_id).collect();
+        for (id, status) in musicbrainz::submission_status(api, &ids, api_key).await? {
+            let submission = batch.iter().find(|s| s.submission_id == Some(id));
+            if let Some(submission) = submission.filter(|_| status != SubmissionStatus::Pending) {
+                cache.set_acoustid_submission_status(submission, status, None, None)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use crate::cache;
+use crate::config::Config;
+use crate::logger;
+use crate::metadata::AudioMetadata;
+use crate::operations::OperationStats;
+use crate::spectrum::{self, SpectrumAnalysis, Verdict};
+use crate::utils;
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use walkdir::WalkDir;
+
+pub struct AnalyzeOptions {
+    pub input_dir: PathBuf,
+    pub verbose: bool,
+    /// Re-analyze files even when the cache has a verdict for them
+    pub force: bool,
+    pub config: Config,
+}
+
+/// Analyze the spectrum of every file and report suspected transcodes
+pub fn run(options: AnalyzeOptions) -> Result<OperationStats> {
+    logger::stage("Analyzing spectra for transcodes");
+    logger::info(&format!("Input directory: {}", options.input_dir.display()));
+
+    let files: Vec<PathBuf> = WalkDir::new(&options.input_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| utils::is_audio_file(p))
+        .collect();
+
+    logger::info(&format!("Found {} audio files", files.len()));
+
+    let stats_mutex = Arc::new(Mutex::new(OperationStats::new()));
+    let analyzed = Mutex::new(0_usize);
+    let cache = cache::get_global_cache();
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] [{bar:40}] {pos}/{len} ({eta}) | Analyzing spectra...")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+
+    let mut results: Vec<(PathBuf, SpectrumAnalysis)> = files
+        .par_iter()
+        .filter_map(|file| {
+            pb.inc(1);
+
+            let result = AudioMetadata::from_file(file).and_then(|mut metadata| {
+                if let (Some(analysis), false) = (metadata.spectrum, options.force) {
+                    return Ok(analysis);
+                }
+
+                let analysis = spectrum::analyze_file(file, &metadata)?;
+                metadata.spectrum = Some(analysis);
+                if let Some(cache) = &cache {
+                    if let Err(e) = cache.insert(file, &metadata) {
+                        logger::warning(&format!(
+                            "Failed to cache spectrum analysis for {}: {}",
+                            file.display(),
+                            e
+                        ));
+                    }
+                }
+                *analyzed.lock().unwrap() += 1;
+                Ok(analysis)
+            });
+
+            let mut stats = stats_mutex.lock().unwrap();
+            stats.processed += 1;
+            match result {
+                Ok(analysis) => Some((file.clone(), analysis)),
+                Err(e) => {
+                    logger::error(&format!("Failed to analyze {}: {}", file.display(), e));
+                    stats.errors += 1;
+                    None
+                }
+            }
+        })
+        .collect();
+
+    pb.finish_and_clear();
+    logger::info(&format!(
+        "Analyzed {} files ({} from cache)",
+        results.len(),
+        results.len() - *analyzed.lock().unwrap()
+    ));
+
+    let mut stats = Arc::try_unwrap(stats_mutex).unwrap().into_inner().unwrap();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut suspects = 0;
+    for (path, analysis) in &results {
+        if analysis.is_suspect() {
+            suspects += 1;
+            logger::warning(&format!("[SUSPECT] {}: {}", path.display(), analysis));
+        } else {
+            logger::debug(
+                &format!("{}: {}", path.display(), analysis),
+                options.verbose,
+            );
+        }
+
+        if analysis.verdict == Verdict::Inconclusive {
+            stats.add_skipped(path.clone(), "Too short or quiet to analyze".to_string());
+        } else {
+            stats.succeeded += 1;
+        }
+    }
+
+    let count = |verdict| results.iter().filter(|(_, a)| a.verdict == verdict).count();
+    logger::info(&format!(
+        "\nGenuine lossless: {}, likely transcodes: {}, upsampled: {}, lossy: {}",
+        count(Verdict::Genuine),
+        count(Verdict::Transcode),
+        count(Verdict::Upsampled),
+        count(Verdict::LossyCutoff)
+    ));
+
+    if suspects > 0 {
+        if options.config.quality.penalize_transcodes {
+            logger::info(
+                "Suspected transcodes are now scored by their source quality in dedupe, merge and sort",
+            );
+        }
+    } else {
+        logger::success("No suspected transcodes found");
+    }
+
+    stats.print_summary("Analysis");
+    Ok(stats)
+}
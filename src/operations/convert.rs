@@ -1,8 +1,10 @@
 use crate::config::Config;
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
 use crate::operations::OperationStats;
 use crate::quality;
+use crate::trash;
 use crate::utils;
 use anyhow::{Context, Result};
 use indicatif::{ProgressBar, ProgressStyle};
@@ -264,7 +266,7 @@ pub fn run(options: ConvertOptions) -> Result<OperationStats> {
         } else {
             // Create output directory
             if let Some(parent) = output_file.parent() {
-                let _ = std::fs::create_dir_all(parent);
+                let _ = journal::create_dir_all(parent);
             }
 
             // Convert using ffmpeg
@@ -274,12 +276,13 @@ pub fn run(options: ConvertOptions) -> Result<OperationStats> {
                         &format!("Converted: {}", output_file.display()),
                         options.verbose,
                     );
+                    journal::record_create(&output_file, file);
                     let mut stats = stats.lock().unwrap();
                     stats.succeeded += 1;
 
                     // Delete original if requested
                     if options.delete_original {
-                        if let Err(e) = std::fs::remove_file(file) {
+                        if let Err(e) = trash::discard(file, &options.input_dir, &options.config) {
                             logger::warning(&format!(
                                 "Failed to delete original {}: {}",
                                 file.display(),
@@ -1,13 +1,15 @@
 use crate::config::Config;
+use crate::fingerprint;
 use crate::logger;
 use crate::metadata::AudioMetadata;
 use crate::operations::OperationStats;
 use crate::quality;
+use crate::trash;
 use crate::utils;
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -18,24 +20,47 @@ pub struct DedupeOptions {
     pub dry_run: bool,
     pub verbose: bool,
     pub auto_remove: bool,
+    /// Group by audio fingerprint instead of tags
+    pub by_fingerprint: bool,
     pub config: Config,
 }
 
+/// Subfingerprint values shared by more files than this (silence, for instance) are not
+/// used to find candidate pairs
+const MAX_POSTINGS: usize = 32;
+/// Exact subfingerprint values two files must share to be compared at all
+const MIN_SHARED_VALUES: usize = 3;
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 struct TrackSignature {
     artist: String,
     album: String,
+    disc: u32,
     title: String,
 }
 
-/// Find and remove duplicate audio files based on metadata
+/// A candidate file for a signature: (path, metadata, quality score, mtime)
+type SignatureEntry = (PathBuf, AudioMetadata, u32, std::time::SystemTime);
+
+/// Find and remove duplicate audio files based on metadata (or audio fingerprints)
 pub fn run(options: DedupeOptions) -> Result<OperationStats> {
-    logger::stage("Starting metadata-based deduplication");
+    if options.by_fingerprint {
+        logger::stage("Starting fingerprint-based deduplication");
+    } else {
+        logger::stage("Starting metadata-based deduplication");
+    }
     logger::info(&format!(
         "Scanning directory: {}",
         options.input_dir.display()
     ));
-    logger::info("Comparing: artist, album, and title metadata");
+    if options.by_fingerprint {
+        logger::info(&format!(
+            "Comparing: audio fingerprints (at least {:.0}% similar), confirmed by duration",
+            options.config.dedupe.fingerprint_similarity * 100.0
+        ));
+    } else {
+        logger::info("Comparing: artist, album, disc, and title metadata");
+    }
 
     if options.dry_run {
         logger::warning("DRY RUN MODE - No files will be deleted");
@@ -54,9 +79,8 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
 
     logger::info(&format!("Found {} audio files to analyze", files.len()));
 
-    // Build signature map (parallelized for performance)
-    let signature_map: Arc<Mutex<HashMap<TrackSignature, Vec<(PathBuf, AudioMetadata, u32, std::time::SystemTime)>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    // Read metadata (parallelized for performance)
+    let entries: Arc<Mutex<Vec<SignatureEntry>>> = Arc::new(Mutex::new(Vec::new()));
     let stats_mutex = Arc::new(Mutex::new(stats));
 
     // Create progress bar for metadata extraction
@@ -76,14 +100,20 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
             stats.processed += 1;
         }
 
-        match AudioMetadata::from_file(file) {
-            Ok(metadata) => {
-                let signature = TrackSignature {
-                    artist: utils::normalize_for_comparison(&metadata.get_organizing_artist(false)),
-                    album: utils::normalize_for_comparison(&metadata.get_album()),
-                    title: utils::normalize_for_comparison(&metadata.get_title()),
-                };
+        let metadata = AudioMetadata::from_file(file).and_then(|metadata| {
+            if options.by_fingerprint && metadata.fingerprint.is_none() {
+                AudioMetadata::from_file_with_fingerprint(file)
+            } else {
+                Ok(metadata)
+            }
+        });
 
+        match metadata {
+            Ok(metadata) if options.by_fingerprint && metadata.fingerprint.is_none() => {
+                let mut stats = stats_mutex.lock().unwrap();
+                stats.add_skipped(file.clone(), "No fingerprint".to_string());
+            }
+            Ok(metadata) => {
                 let quality_score = quality::calculate_quality_score(&metadata, &options.config);
 
                 // Get file modification time for tiebreaking when quality is equal
@@ -91,8 +121,7 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
                     .and_then(|m| m.modified())
                     .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
 
-                let mut map = signature_map.lock().unwrap();
-                map.entry(signature).or_insert_with(Vec::new).push((
+                entries.lock().unwrap().push((
                     file.clone(),
                     metadata,
                     quality_score,
@@ -113,42 +142,53 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
 
     pb.finish_and_clear();
 
-    // Extract stats and map from Arc<Mutex<>>
+    // Extract stats and entries from Arc<Mutex<>>
     let mut stats = Arc::try_unwrap(stats_mutex).unwrap().into_inner().unwrap();
-    let signature_map = Arc::try_unwrap(signature_map)
-        .unwrap()
-        .into_inner()
-        .unwrap();
+    let entries = Arc::try_unwrap(entries).unwrap().into_inner().unwrap();
+
+    let groups = if options.by_fingerprint {
+        fingerprint_groups(entries, &options.config)
+    } else {
+        signature_groups(entries)
+    };
 
     // Find duplicates
     let mut duplicate_groups = 0;
     let mut files_to_remove = Vec::new();
 
-    for (signature, files) in signature_map.iter() {
+    for (label, files) in groups.iter() {
         if files.len() > 1 {
             duplicate_groups += 1;
 
             logger::warning(&format!(
-                "\nFound {} duplicate(s) of: {} - {} - {}",
+                "\nFound {} duplicate(s) of: {}",
                 files.len(),
-                signature.artist,
-                signature.album,
-                signature.title
+                label
             ));
 
-            // Sort by quality (highest first), then by modification time (oldest first) as tiebreaker
+            // Files that failed `ferric verify` go last, then sort by quality (highest first),
+            // then by modification time (oldest first) as tiebreaker
             let mut sorted_files = files.clone();
             sorted_files.sort_by(|a, b| {
-                // First compare by quality (descending - highest quality first)
-                match b.2.cmp(&a.2) {
-                    std::cmp::Ordering::Equal => {
-                        // If quality is equal, prefer older files (ascending time - oldest first)
-                        a.3.cmp(&b.3)
-                    }
-                    other => other,
-                }
+                failed_verification(&a.1)
+                    .cmp(&failed_verification(&b.1))
+                    // Compare by quality (descending - highest quality first)
+                    .then_with(|| b.2.cmp(&a.2))
+                    // If quality is equal, prefer older files (ascending time - oldest first)
+                    .then_with(|| a.3.cmp(&b.3))
             });
 
+            // Without a healthy copy to keep, removing any of them could lose the one that's
+            // still repairable
+            if failed_verification(&sorted_files[0].1) {
+                logger::warning("  Every copy failed verification; leaving all of them in place");
+                for (path, _, _, _) in &sorted_files {
+                    logger::info(&format!("  [DAMAGED] {}", path.display()));
+                    stats.add_skipped(path.clone(), "every copy failed verification".to_string());
+                }
+                continue;
+            }
+
             // Keep the highest quality (or oldest if quality is equal), mark others for removal
             for (idx, (path, metadata, quality, _modified_time)) in sorted_files.iter().enumerate() {
                 if idx == 0 {
@@ -160,10 +200,11 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
                     ));
                 } else {
                     logger::info(&format!(
-                        "  [REMOVE] {} (quality: {}, codec: {})",
+                        "  [REMOVE] {} (quality: {}, codec: {}{})",
                         path.display(),
                         quality,
-                        metadata.codec
+                        metadata.codec,
+                        if failed_verification(metadata) { ", failed verification" } else { "" }
                     ));
                     files_to_remove.push(path.clone());
                 }
@@ -178,7 +219,11 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
     ));
 
     if !files_to_remove.is_empty() && !options.auto_remove && !options.dry_run {
-        logger::warning("\nProceed with deletion? [y/N]: ");
+        if options.config.trash.enabled {
+            logger::warning("\nMove duplicates to the trash? [y/N]: ");
+        } else {
+            logger::warning("\nProceed with deletion? [y/N]: ");
+        }
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
         if !input.trim().eq_ignore_ascii_case("y") {
@@ -196,13 +241,13 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
             );
             stats.succeeded += 1;
         } else {
-            match fs::remove_file(&file) {
+            match trash::discard(&file, &options.input_dir, &options.config) {
                 Ok(_) => {
-                    logger::debug(&format!("Deleted: {}", file.display()), options.verbose);
+                    logger::debug(&format!("Removed: {}", file.display()), options.verbose);
                     stats.succeeded += 1;
                 }
                 Err(e) => {
-                    logger::error(&format!("Failed to delete {}: {}", file.display(), e));
+                    logger::error(&format!("Failed to remove {}: {}", file.display(), e));
                     stats.errors += 1;
                 }
             }
@@ -212,3 +257,160 @@ pub fn run(options: DedupeOptions) -> Result<OperationStats> {
     stats.print_summary("Deduplication");
     Ok(stats)
 }
+
+/// Group files by normalized artist, album, disc and title tags
+fn signature_groups(entries: Vec<SignatureEntry>) -> Vec<(String, Vec<SignatureEntry>)> {
+    let mut signature_map: HashMap<TrackSignature, Vec<SignatureEntry>> = HashMap::new();
+    for entry in entries {
+        let metadata = &entry.1;
+        let signature = TrackSignature {
+            artist: utils::normalize_for_comparison(&metadata.get_organizing_artist(false)),
+            album: utils::normalize_for_comparison(&metadata.get_album()),
+            disc: metadata.get_disc(),
+            title: utils::normalize_for_comparison(&metadata.get_title()),
+        };
+        signature_map.entry(signature).or_default().push(entry);
+    }
+
+    signature_map
+        .into_iter()
+        .map(|(signature, files)| {
+            let label = format!(
+                "{} - {} (disc {}) - {}",
+                signature.artist, signature.album, signature.disc, signature.title
+            );
+            (label, files)
+        })
+        .collect()
+}
+
+/// Group files whose fingerprints match, split so every group's durations agree
+///
+/// Candidate pairs are files sharing a few exact subfingerprint values; only those are
+/// aligned and compared bit by bit, so the whole library is never compared pairwise.
+fn fingerprint_groups(
+    entries: Vec<SignatureEntry>,
+    config: &Config,
+) -> Vec<(String, Vec<SignatureEntry>)> {
+    let fingerprints: Vec<Option<Vec<u32>>> = entries
+        .par_iter()
+        .map(|(path, metadata, _, _)| {
+            let fp = metadata.fingerprint.as_deref()?;
+            match fingerprint::decode(fp) {
+                Ok(items) => Some(items),
+                Err(e) => {
+                    logger::warning(&format!(
+                        "Unreadable fingerprint for {}: {}",
+                        path.display(),
+                        e
+                    ));
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (idx, items) in fingerprints.iter().enumerate() {
+        let unique: HashSet<u32> = items.iter().flatten().copied().collect();
+        for item in unique {
+            index.entry(item).or_default().push(idx);
+        }
+    }
+
+    let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+    for postings in index.values().filter(|p| p.len() <= MAX_POSTINGS) {
+        for (k, &a) in postings.iter().enumerate() {
+            for &b in &postings[k + 1..] {
+                *shared.entry((a, b)).or_default() += 1;
+            }
+        }
+    }
+
+    let tolerance = config.dedupe.duration_tolerance;
+    let durations_match =
+        |a: usize, b: usize| match (entries[a].1.duration_secs, entries[b].1.duration_secs) {
+            (Some(x), Some(y)) => (x - y).abs() <= tolerance,
+            _ => false,
+        };
+
+    let matches: Vec<(usize, usize)> = shared
+        .into_par_iter()
+        .filter(|&((a, b), count)| {
+            count >= MIN_SHARED_VALUES
+                && durations_match(a, b)
+                && fingerprints[a]
+                    .as_deref()
+                    .zip(fingerprints[b].as_deref())
+                    .and_then(|(x, y)| fingerprint::similarity(x, y))
+                    .is_some_and(|score| score >= config.dedupe.fingerprint_similarity)
+        })
+        .map(|(pair, _)| pair)
+        .collect();
+
+    // Union-find over matching pairs
+    let mut parent: Vec<usize> = (0..entries.len()).collect();
+    fn root(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+    for (a, b) in matches {
+        let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+        parent[ra] = rb;
+    }
+
+    let mut clusters: HashMap<usize, Vec<SignatureEntry>> = HashMap::new();
+    for (idx, entry) in entries.into_iter().enumerate() {
+        clusters
+            .entry(root(&mut parent, idx))
+            .or_default()
+            .push(entry);
+    }
+
+    // Matches chain transitively, so confirm durations again: split each cluster into runs
+    // whose durations all lie within the tolerance
+    let mut groups = Vec::new();
+    for (_, mut cluster) in clusters {
+        cluster.sort_by(|a, b| {
+            a.1.duration_secs
+                .unwrap_or(0.0)
+                .total_cmp(&b.1.duration_secs.unwrap_or(0.0))
+        });
+        let mut run: Vec<SignatureEntry> = Vec::new();
+        for entry in cluster {
+            let start = run
+                .first()
+                .and_then(|first| first.1.duration_secs)
+                .unwrap_or(0.0);
+            if !run.is_empty() && entry.1.duration_secs.unwrap_or(0.0) - start > tolerance {
+                groups.push(std::mem::take(&mut run));
+            }
+            run.push(entry);
+        }
+        groups.push(run);
+    }
+
+    groups
+        .into_iter()
+        .map(|files| {
+            let metadata = &files[0].1;
+            let label = format!(
+                "{} - {} (by fingerprint)",
+                metadata.get_organizing_artist(false),
+                metadata.get_title()
+            );
+            (label, files)
+        })
+        .collect()
+}
+
+/// Whether `ferric verify` found the file damaged
+fn failed_verification(metadata: &AudioMetadata) -> bool {
+    metadata
+        .integrity
+        .as_ref()
+        .is_some_and(|check| !check.passed())
+}
@@ -1,8 +1,10 @@
 use crate::config::Config;
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
 use crate::operations::OperationStats;
 use crate::quality;
+use crate::trash;
 use crate::utils;
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
@@ -10,7 +12,6 @@ use pathdiff::diff_paths;
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs;
-use std::os::unix::fs as unix_fs;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
@@ -150,9 +151,10 @@ pub fn run(options: DedupeLibrariesOptions) -> Result<OperationStats> {
             let title = metadata.get_title();
 
             let song_id = format!(
-                "{}__{}__{}",
+                "{}__{}__{}__{}",
                 utils::normalize_for_comparison(&artist),
                 utils::normalize_for_comparison(&album),
+                metadata.get_disc(),
                 utils::normalize_for_comparison(&title)
             );
 
@@ -179,7 +181,7 @@ pub fn run(options: DedupeLibrariesOptions) -> Result<OperationStats> {
     for file_info in file_infos {
         song_groups
             .entry(file_info.song_id.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(file_info);
     }
 
@@ -343,7 +345,15 @@ pub fn run(options: DedupeLibrariesOptions) -> Result<OperationStats> {
                     }
                 };
 
-                if let Err(e) = fs::remove_file(current_path) {
+                // Keep the replaced file's layout relative to its library in the trash
+                let library_root = options
+                    .input_dirs
+                    .iter()
+                    .find(|dir| current_path.starts_with(dir))
+                    .map(PathBuf::as_path)
+                    .unwrap_or(current_dir);
+
+                if let Err(e) = trash::discard(current_path, library_root, &options.config) {
                     logger::error(&format!(
                         "Failed to remove {}: {}",
                         current_path.display(),
@@ -354,7 +364,7 @@ pub fn run(options: DedupeLibrariesOptions) -> Result<OperationStats> {
                     continue;
                 }
 
-                if let Err(e) = unix_fs::symlink(&best_path_relative, current_path) {
+                if let Err(e) = journal::symlink(&best_path_relative, current_path) {
                     logger::error(&format!(
                         "Failed to create symlink {} -> {}: {}",
                         current_path.display(),
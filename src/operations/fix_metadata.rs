@@ -1,12 +1,12 @@
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
+use crate::tags::{self, Picture, TagEdit};
 use crate::utils;
 use anyhow::{Context, Result};
-use base64::{engine::general_purpose, Engine as _};
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use std::collections::HashMap;
-use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -34,7 +34,7 @@ struct FileMetadataInfo {
 /// Check if a file has an embedded album cover
 fn has_album_cover(path: &Path) -> Result<bool> {
     let output = Command::new("ffprobe")
-        .args(&["-v", "quiet", "-print_format", "json", "-show_streams"])
+        .args(["-v", "quiet", "-print_format", "json", "-show_streams"])
         .arg(path)
         .output()
         .context("Failed to run ffprobe")?;
@@ -113,129 +113,30 @@ fn prompt_for_file(prompt: &str) -> Result<PathBuf> {
     }
 }
 
-/// Create METADATA_BLOCK_PICTURE tag for OPUS/OGG files
-/// Reference: https://wiki.xiph.org/VorbisComment#Cover_art
-fn create_metadata_block_picture(cover_path: &Path) -> Result<String> {
-    // Read the image file
-    let image_data = fs::read(cover_path).context("Failed to read cover image")?;
-
-    // Determine MIME type from extension
-    let mime_type = match utils::get_extension(cover_path).as_deref() {
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("png") => "image/png",
-        _ => return Err(anyhow::anyhow!("Unsupported image format")),
-    };
-
-    // Get image dimensions using ffprobe
-    let probe_output = Command::new("ffprobe")
-        .args(&["-v", "quiet", "-print_format", "json", "-show_streams"])
-        .arg(cover_path)
-        .output()
-        .context("Failed to run ffprobe on cover image")?;
-
-    let probe_json: serde_json::Value = serde_json::from_slice(&probe_output.stdout)?;
-    let width = probe_json["streams"][0]["width"].as_u64().unwrap_or(0) as u32;
-    let height = probe_json["streams"][0]["height"].as_u64().unwrap_or(0) as u32;
-
-    // Build METADATA_BLOCK_PICTURE structure
-    let mut picture_data = Vec::new();
-
-    // Picture type (3 = front cover)
-    picture_data.extend_from_slice(&3u32.to_be_bytes());
-
-    // MIME type length and data
-    picture_data.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
-    picture_data.extend_from_slice(mime_type.as_bytes());
-
-    // Description (empty)
-    picture_data.extend_from_slice(&0u32.to_be_bytes());
-
-    // Width, height, color depth, colors used
-    picture_data.extend_from_slice(&width.to_be_bytes());
-    picture_data.extend_from_slice(&height.to_be_bytes());
-    picture_data.extend_from_slice(&24u32.to_be_bytes()); // 24-bit color depth
-    picture_data.extend_from_slice(&0u32.to_be_bytes()); // 0 for non-indexed
-
-    // Image data length and data
-    picture_data.extend_from_slice(&(image_data.len() as u32).to_be_bytes());
-    picture_data.extend_from_slice(&image_data);
-
-    // Base64 encode
-    Ok(general_purpose::STANDARD.encode(&picture_data))
-}
-
-/// Embed album cover into audio file using ffmpeg
+/// Embed album cover into audio file
 fn embed_cover(audio_path: &Path, cover_path: &Path, dry_run: bool) -> Result<()> {
     if dry_run {
         return Ok(());
     }
 
-    // Create temporary output file with proper extension
-    let mut temp_path = audio_path.to_path_buf();
-    let original_filename = temp_path.file_name().unwrap().to_string_lossy().to_string();
-    temp_path.set_file_name(format!("{}.tmp", original_filename));
-
-    // Get file extension to determine codec copy parameters
-    let ext = utils::get_extension(audio_path).unwrap_or_default();
-
-    // For OPUS/OGG files, we need to use METADATA_BLOCK_PICTURE
-    let result = if ext == "opus" || ext == "ogg" {
-        // OPUS/OGG: Use METADATA_BLOCK_PICTURE tag
-        let metadata_tag = create_metadata_block_picture(cover_path)?;
-
-        Command::new("ffmpeg")
-            .args(&[
-                "-i",
-                audio_path.to_str().unwrap(),
-                "-c:a",
-                "copy",
-                "-metadata",
-                &format!("METADATA_BLOCK_PICTURE={}", metadata_tag),
-                "-f",
-                &ext, // Explicitly specify output format
-                "-y",
-                temp_path.to_str().unwrap(),
-            ])
-            .output()
-    } else {
-        // For MP3, M4A, FLAC, etc.
-        Command::new("ffmpeg")
-            .args(&[
-                "-i",
-                audio_path.to_str().unwrap(),
-                "-i",
-                cover_path.to_str().unwrap(),
-                "-map",
-                "0",
-                "-map",
-                "1",
-                "-c",
-                "copy",
-                "-disposition:v:0",
-                "attached_pic",
-                "-y",
-                temp_path.to_str().unwrap(),
-            ])
-            .output()
-    };
-
-    let output = result.context("Failed to run ffmpeg for cover embedding")?;
+    let picture = Picture::from_file(cover_path)?;
+    let before_hash = journal::hash_if_recording(audio_path);
+    tags::write(audio_path, &TagEdit::new().cover(picture))?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("ffmpeg failed: {}", stderr));
-    }
-
-    // Replace original with temp file
-    fs::rename(&temp_path, audio_path)
-        .context("Failed to replace original file with updated version")?;
+    // Covers are only embedded into files that had none, so undo strips the picture again
+    let mut before = serde_json::Map::new();
+    before.insert("cover".to_string(), serde_json::Value::Null);
+    let mut after = serde_json::Map::new();
+    after.insert("cover".to_string(), cover_path.to_string_lossy().to_string().into());
+    journal::record_tag_rewrite(audio_path, before_hash, before, after);
 
     Ok(())
 }
 
-/// Update text metadata using ffmpeg
+/// Update text metadata
 fn update_metadata(
     audio_path: &Path,
+    current_metadata: &AudioMetadata,
     artist: Option<&str>,
     album: Option<&str>,
     genre: Option<&str>,
@@ -245,60 +146,28 @@ fn update_metadata(
         return Ok(());
     }
 
-    // Create temporary output file with proper extension
-    let mut temp_path = audio_path.to_path_buf();
-    let original_filename = temp_path.file_name().unwrap().to_string_lossy().to_string();
-    temp_path.set_file_name(format!("{}.tmp", original_filename));
-
-    let mut args = vec![
-        "-i".to_string(),
-        audio_path.to_str().unwrap().to_string(),
-        "-c".to_string(),
-        "copy".to_string(),
-    ];
-
-    // Add metadata arguments
+    let mut updates: Vec<(&str, String)> = Vec::new();
     if let Some(a) = artist {
-        args.push("-metadata".to_string());
-        args.push(format!("artist={}", a));
-        args.push("-metadata".to_string());
-        args.push(format!("ARTIST={}", a));
-        args.push("-metadata".to_string());
-        args.push(format!("album_artist={}", a));
-        args.push("-metadata".to_string());
-        args.push(format!("ALBUM_ARTIST={}", a));
+        updates.push(("artist", a.to_string()));
+        updates.push(("album_artist", a.to_string()));
     }
-
     if let Some(a) = album {
-        args.push("-metadata".to_string());
-        args.push(format!("album={}", a));
-        args.push("-metadata".to_string());
-        args.push(format!("ALBUM={}", a));
+        updates.push(("album", a.to_string()));
     }
-
     if let Some(g) = genre {
-        args.push("-metadata".to_string());
-        args.push(format!("genre={}", g));
-        args.push("-metadata".to_string());
-        args.push(format!("GENRE={}", g));
+        updates.push(("genre", g.to_string()));
     }
 
-    args.push("-y".to_string());
-    args.push(temp_path.to_str().unwrap().to_string());
-
-    let output = Command::new("ffmpeg")
-        .args(&args)
-        .output()
-        .context("Failed to run ffmpeg for metadata update")?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("ffmpeg failed: {}", stderr));
+    let mut edit = TagEdit::new();
+    for (key, value) in &updates {
+        edit = edit.set(key, value.clone());
     }
 
-    // Replace original with temp file
-    fs::rename(&temp_path, audio_path)
-        .context("Failed to replace original file with updated version")?;
+    let before_hash = journal::hash_if_recording(audio_path);
+    tags::write(audio_path, &edit)?;
+
+    let (before, after) = current_metadata.tag_changes(&updates);
+    journal::record_tag_rewrite(audio_path, before_hash, before, after);
 
     Ok(())
 }
@@ -421,7 +290,7 @@ pub fn run(options: FixMetadataOptions) -> Result<()> {
                 let album = info.metadata.get_album();
                 albums
                     .entry((artist, album))
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(info.clone());
             }
         }
@@ -522,7 +391,7 @@ pub fn run(options: FixMetadataOptions) -> Result<()> {
             if missing_artist || missing_album || missing_genre {
                 folders
                     .entry(info.parent_dir.clone())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(info.clone());
             }
         }
@@ -660,7 +529,7 @@ pub fn run(options: FixMetadataOptions) -> Result<()> {
                         .collect();
 
                     files_to_update.par_iter().for_each(|info| {
-                        match update_metadata(&info.path, artist_ref, album_ref, genre_ref, options.dry_run) {
+                        match update_metadata(&info.path, &info.metadata, artist_ref, album_ref, genre_ref, options.dry_run) {
                             Ok(_) => {
                                 *success_count.lock().unwrap() += 1;
                             }
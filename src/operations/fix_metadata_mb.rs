@@ -1,10 +1,15 @@
+use crate::matching::{self, TrackEvidence};
+use crate::operations::acoustid_submit::Submitter;
+use crate::operations::OperationStats;
 use crate::{config::Config, fingerprint, logger, metadata::AudioMetadata, musicbrainz, utils};
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::io::{self, Write};
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 use walkdir::WalkDir;
 
 pub struct FixMetadataOptions {
@@ -31,6 +36,10 @@ pub struct FixMetadataOptions {
 
     // Prevent Various Artists issues
     pub avoid_various_artists: bool,
+
+    /// Submit fingerprints of matches confirmed at the prompt, and of tagged files AcoustID
+    /// doesn't know, to AcoustID
+    pub submit: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -38,14 +47,36 @@ struct FileInfo {
     path: PathBuf,
     metadata: AudioMetadata,
     fingerprint: Option<String>,
-    parent_dir: PathBuf,
 }
 
 #[derive(Debug, Clone)]
 struct MatchResult {
     file: FileInfo,
     acoustid_results: Vec<musicbrainz::AcoustIdResult>,
-    selected_metadata: Option<musicbrainz::MusicBrainzMetadata>,
+    /// Fetched and scored while the lookup pipeline ran, None when still to be fetched
+    candidates: Option<Vec<Candidate>>,
+}
+
+/// AcoustID recordings fetched from MusicBrainz and scored for each file
+const MAX_FINGERPRINT_CANDIDATES: usize = 3;
+
+/// A recording a file might be, with how well it fits the file
+#[derive(Debug, Clone)]
+struct Candidate {
+    metadata: musicbrainz::MusicBrainzMetadata,
+    /// From 0.0 to 1.0
+    confidence: f64,
+    /// Found by searching for the file's tags rather than by its fingerprint
+    from_search: bool,
+}
+
+/// The files of one album, matched to a single release
+struct AlbumMatch {
+    folder: PathBuf,
+    release: musicbrainz::Release,
+    score: f64,
+    /// Each file with the index of the release track it was assigned
+    tracks: Vec<(MatchResult, usize)>,
 }
 
 /// Represents which metadata fields should be updated for a file
@@ -57,6 +88,8 @@ pub struct FieldsToUpdate {
     pub update_title: bool,
     pub update_date: bool,
     pub update_genre: bool,
+    pub update_track: bool,
+    pub update_disc: bool,
 }
 
 impl FieldsToUpdate {
@@ -93,6 +126,10 @@ impl FieldsToUpdate {
             fields.update_genre = options.overwrite || current.genre.is_none();
         }
 
+        // Positions come along with any other update, but never renumber a tagged track
+        fields.update_track = options.overwrite || current.track_number.is_none();
+        fields.update_disc = options.overwrite || current.disc_number.is_none();
+
         fields
     }
 
@@ -124,6 +161,17 @@ pub async fn run(options: FixMetadataOptions, config: &Config) -> Result<()> {
         }
     }
 
+    let api = Arc::new(musicbrainz::Api::from_config(config)?);
+    if options.use_musicbrainz {
+        api.log_mode();
+    }
+    let submitter = if options.submit && options.use_musicbrainz {
+        Some(Submitter::from_config(config)?)
+    } else {
+        None
+    };
+    let submitter = submitter.as_ref();
+
     // Step 1: Scan for audio files
     logger::info(&format!("Scanning {} directories...", options.input_dirs.len()));
     let files = scan_audio_files(&options)?;
@@ -145,32 +193,38 @@ pub async fn run(options: FixMetadataOptions, config: &Config) -> Result<()> {
 
     logger::info(&format!("{} files need metadata fixes", files_to_fix.len()));
 
-    // Step 3: Generate fingerprints if using MusicBrainz
-    let mut file_infos: Vec<FileInfo> = files_to_fix
+    let file_infos: Vec<FileInfo> = files_to_fix
         .into_iter()
         .map(|(path, metadata)| FileInfo {
-            parent_dir: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
             path,
             metadata,
             fingerprint: None,
         })
         .collect();
 
-    if options.use_musicbrainz && !options.skip_fingerprinting {
-        logger::stage("Generating Audio Fingerprints");
-        file_infos = generate_fingerprints_for_files(file_infos, &options)?;
-    }
-
-    // Step 4: Look up via MusicBrainz if enabled
-    let matches = if options.use_musicbrainz {
-        lookup_musicbrainz_batch(file_infos, config, &options).await?
-    } else {
-        vec![]
-    };
-
-    // Step 5: Interactive review and application
+    // Step 3: Fingerprint, look up and match albums as one pipeline, then review and apply
+    // the matches, whole albums first
     if options.use_musicbrainz {
-        apply_musicbrainz_matches(matches, &options).await?;
+        let api_key = musicbrainz::get_acoustid_api_key(config)?;
+        let (albums, singles) =
+            lookup_pipeline(file_infos, &api, api_key.clone(), &options, submitter).await?;
+        if let Some(mut remaining) = apply_album_matches(albums, &options, submitter)? {
+            remaining.extend(singles);
+            apply_musicbrainz_matches(remaining, &api, &options, submitter).await?;
+        }
+
+        if let Some(submitter) = submitter {
+            logger::stage("Submitting Fingerprints to AcoustID");
+            let mut stats = OperationStats::new();
+            if let Err(e) = submitter
+                .send_queued(&api, &api_key, options.dry_run, &mut stats)
+                .await
+            {
+                logger::error(&format!("{:#}", e));
+                logger::info("Queued fingerprints are sent by the next 'ferric acoustid-submit'");
+            }
+            let _ = submitter.print_counts();
+        }
     } else {
         // Fall back to manual entry mode (legacy)
         logger::warning("Manual metadata entry not implemented in MusicBrainz mode");
@@ -267,151 +321,462 @@ fn filter_files_needing_fix(
     Ok(filtered)
 }
 
-/// Generate fingerprints for files in parallel
-fn generate_fingerprints_for_files(
-    mut files: Vec<FileInfo>,
+/// Files in the same folder with the same album tag, matched to a release together
+fn album_key(file: &FileInfo) -> (PathBuf, String) {
+    let folder = file.path.parent().map(PathBuf::from).unwrap_or_default();
+    let album = utils::normalize_for_comparison(file.metadata.album.as_deref().unwrap_or(""));
+    (folder, album)
+}
+
+/// Fingerprint the files, look them up on AcoustID and match them on MusicBrainz
+///
+/// The three stages run at once: fingerprints are generated on the rayon pool and handed to
+/// AcoustID lookups a batch at a time as they're ready, and each album goes on to MusicBrainz
+/// as soon as all its files are looked up. Each web service keeps to its own rate limit.
+/// Batches are cut from the file list up front, so a recorded run replays the same requests.
+///
+/// Returns the matched albums, and the files left to be matched one at a time (files on
+/// their own, albums AcoustID found no releases for, and files no release track fit) with
+/// their candidates already fetched.
+async fn lookup_pipeline(
+    files: Vec<FileInfo>,
+    api: &Arc<musicbrainz::Api>,
+    api_key: String,
     options: &FixMetadataOptions,
-) -> Result<Vec<FileInfo>> {
-    let pb = ProgressBar::new(files.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{bar:40}] {pos}/{len} | Fingerprinting...")
-            .unwrap()
-            .progress_chars("█▓▒░"),
-    );
+    submitter: Option<&Submitter>,
+) -> Result<(Vec<AlbumMatch>, Vec<MatchResult>)> {
+    logger::stage("Looking up via AcoustID + MusicBrainz");
 
-    let files_with_fps: Vec<FileInfo> = files
-        .par_iter_mut()
-        .map(|file_info| {
-            pb.inc(1);
+    let total = files.len();
+    let mut group_sizes: HashMap<(PathBuf, String), usize> = HashMap::new();
+    for file in &files {
+        *group_sizes.entry(album_key(file)).or_default() += 1;
+    }
+    let album_count = group_sizes.values().filter(|&&size| size > 1).count();
+
+    let progress = MultiProgress::new();
+    let stage_bar = |message: &'static str| {
+        let pb = progress.add(ProgressBar::new(total as u64));
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("[{bar:40}] {pos}/{len} | {msg}")
+                .unwrap()
+                .progress_chars("█▓▒░"),
+        );
+        pb.set_message(message);
+        pb
+    };
+    let fingerprint_pb = stage_bar("Fingerprinting...");
+    let acoustid_pb = stage_bar("Querying AcoustID...");
+    let musicbrainz_pb = stage_bar("Querying MusicBrainz...");
 
-            // Check if already has fingerprint in cache
-            if file_info.metadata.fingerprint.is_some() {
-                file_info.fingerprint = file_info.metadata.fingerprint.clone();
-                logger::debug(
-                    &format!("Using cached fingerprint for {}", file_info.path.display()),
-                    options.verbose,
-                );
-                return file_info.clone();
-            }
+    let mut batches: Vec<Vec<FileInfo>> = Vec::new();
+    for file in files {
+        match batches.last_mut() {
+            Some(batch) if batch.len() < api.acoustid_batch_size() => batch.push(file),
+            _ => batches.push(vec![file]),
+        }
+    }
 
-            // Generate new fingerprint
-            match fingerprint::generate_fingerprint(&file_info.path) {
-                Ok(fp) => {
-                    file_info.fingerprint = Some(fp.clone());
+    let (fingerprinted_tx, mut fingerprinted_rx) = mpsc::unbounded_channel::<Vec<FileInfo>>();
+    let (looked_up_tx, mut looked_up_rx) = mpsc::unbounded_channel::<MatchResult>();
+
+    let fingerprint = !options.skip_fingerprinting;
+    let verbose = options.verbose;
+    let fingerprinting = tokio::task::spawn_blocking(move || {
+        batches
+            .into_par_iter()
+            .for_each_with(fingerprinted_tx, |tx, batch| {
+                let batch: Vec<FileInfo> = batch
+                    .into_iter()
+                    .map(|file| {
+                        fingerprint_pb.inc(1);
+                        if fingerprint {
+                            fingerprint_file(file, verbose)
+                        } else {
+                            file
+                        }
+                    })
+                    .collect();
+                let _ = tx.send(batch);
+            });
+        fingerprint_pb.finish_and_clear();
+    });
+
+    // Every batch waits for its own AcoustID slot, so they overlap with each other's latency
+    let acoustid = async move {
+        let mut lookups = tokio::task::JoinSet::new();
+        while let Some(batch) = fingerprinted_rx.recv().await {
+            let api = Arc::clone(api);
+            let api_key = api_key.clone();
+            let looked_up_tx = looked_up_tx.clone();
+            let acoustid_pb = acoustid_pb.clone();
+            lookups.spawn(async move {
+                let results = lookup_fingerprints(&api, &batch, &api_key).await;
+                acoustid_pb.inc(batch.len() as u64);
+                for (file, acoustid_results) in batch.into_iter().zip(results) {
+                    let _ = looked_up_tx.send(MatchResult {
+                        file,
+                        acoustid_results,
+                        candidates: None,
+                    });
+                }
+            });
+        }
+        while lookups.join_next().await.is_some() {}
+        acoustid_pb.finish_and_clear();
+    };
 
-                    // Store fingerprint in cache for future use
-                    file_info.metadata.fingerprint = Some(fp);
-                    if let Some(cache) = crate::cache::get_global_cache() {
-                        let _ = cache.insert(&file_info.path, &file_info.metadata);
-                    }
+    let matching = async {
+        let mut pending: HashMap<(PathBuf, String), Vec<MatchResult>> = HashMap::new();
+        let mut albums = Vec::new();
+        let mut singles = Vec::new();
 
-                    file_info.clone()
-                }
-                Err(e) => {
-                    logger::warning(&format!(
-                        "Failed to fingerprint {}: {}",
-                        file_info.path.display(),
-                        e
-                    ));
-                    file_info.clone()
+        while let Some(m) = looked_up_rx.recv().await {
+            // Files tagged with a recording whose fingerprint AcoustID doesn't know can teach it
+            if let Some(recording_id) = m.file.metadata.musicbrainz_recording_id.as_deref() {
+                if m.acoustid_results.is_empty() {
+                    queue_submission(submitter, &m, recording_id, options);
                 }
             }
-        })
-        .collect();
 
-    pb.finish_and_clear();
-    Ok(files_with_fps)
+            let key = album_key(&m.file);
+            let group = pending.entry(key.clone()).or_default();
+            group.push(m);
+            if group.len() < group_sizes[&key] {
+                continue;
+            }
+
+            let group = pending.remove(&key).unwrap_or_default();
+            let size = group.len() as u64;
+            let (album, unmatched) = match_album(key.0, group, api, verbose).await;
+            albums.extend(album);
+            for mut m in unmatched {
+                m.candidates = Some(prepare_candidates(api, &m, verbose).await);
+                singles.push(m);
+            }
+            musicbrainz_pb.inc(size);
+        }
+        musicbrainz_pb.finish_and_clear();
+        (albums, singles)
+    };
+
+    let (fingerprinted, (), (mut albums, mut singles)) =
+        tokio::join!(fingerprinting, acoustid, matching);
+    fingerprinted?;
+
+    albums.sort_by(|a, b| a.folder.cmp(&b.folder));
+    singles.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+
+    let matched = albums
+        .iter()
+        .flat_map(|album| album.tracks.iter().map(|(m, _)| m))
+        .chain(&singles)
+        .filter(|m| !m.acoustid_results.is_empty())
+        .count();
+    logger::info(&format!(
+        "Found matches for {} / {} files ({:.1}%)",
+        matched,
+        total,
+        (matched as f32 / total as f32) * 100.0
+    ));
+    if album_count > 0 {
+        logger::info(&format!(
+            "Matched {} of {} albums to a release",
+            albums.len(),
+            album_count
+        ));
+    }
+
+    Ok((albums, singles))
 }
 
-/// Look up files via MusicBrainz in batch
-async fn lookup_musicbrainz_batch(
-    files: Vec<FileInfo>,
-    config: &Config,
-    _options: &FixMetadataOptions,
-) -> Result<Vec<MatchResult>> {
-    logger::stage("Looking up via AcoustID + MusicBrainz");
+/// Give a file its fingerprint, from the cache or generated now
+fn fingerprint_file(mut file_info: FileInfo, verbose: bool) -> FileInfo {
+    // Check if already has fingerprint in cache
+    if file_info.metadata.fingerprint.is_some() {
+        file_info.fingerprint = file_info.metadata.fingerprint.clone();
+        logger::debug(
+            &format!("Using cached fingerprint for {}", file_info.path.display()),
+            verbose,
+        );
+        return file_info;
+    }
 
-    let api_key = musicbrainz::get_acoustid_api_key(config)?;
-    let rate_limiter = musicbrainz::RateLimiter::new(1.0); // 1 request/sec
+    // Generate new fingerprint
+    match fingerprint::generate_fingerprint(&file_info.path) {
+        Ok(fp) => {
+            file_info.fingerprint = Some(fp.clone());
 
-    let results = Arc::new(Mutex::new(Vec::new()));
-    let total = files.len();
-    let processed = Arc::new(Mutex::new(0));
+            // Store fingerprint in cache for future use
+            file_info.metadata.fingerprint = Some(fp);
+            if let Some(cache) = crate::cache::get_global_cache() {
+                let _ = cache.insert(&file_info.path, &file_info.metadata);
+            }
+        }
+        Err(e) => {
+            logger::warning(&format!(
+                "Failed to fingerprint {}: {}",
+                file_info.path.display(),
+                e
+            ));
+        }
+    }
+    file_info
+}
 
-    let pb = ProgressBar::new(total as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{bar:40}] {pos}/{len} | Querying MusicBrainz...")
-            .unwrap()
-            .progress_chars("█▓▒░"),
-    );
+/// Look a batch of files up on AcoustID, with no results for files without a fingerprint
+async fn lookup_fingerprints(
+    api: &musicbrainz::Api,
+    files: &[FileInfo],
+    api_key: &str,
+) -> Vec<Vec<musicbrainz::AcoustIdResult>> {
+    let fingerprinted: Vec<(usize, (&str, f64))> = files
+        .iter()
+        .enumerate()
+        .filter_map(|(i, file)| {
+            let fp = file.fingerprint.as_deref()?;
+            Some((i, (fp, file.metadata.duration_secs.unwrap_or(0.0))))
+        })
+        .collect();
+    let lookups: Vec<(&str, f64)> = fingerprinted.iter().map(|&(_, lookup)| lookup).collect();
+    let responses = musicbrainz::lookup_by_fingerprints(api, &lookups, api_key).await;
+
+    let mut results = vec![Vec::new(); files.len()];
+    for ((i, _), response) in fingerprinted.into_iter().zip(responses) {
+        match response {
+            Ok(r) => results[i] = r,
+            Err(e) => logger::warning(&format!(
+                "AcoustID lookup failed for {}: {}",
+                files[i].path.display(),
+                e
+            )),
+        }
+    }
+    results
+}
 
-    for file in files {
-        let api_key = api_key.clone();
-        let results = Arc::clone(&results);
-        let processed = Arc::clone(&processed);
+/// Match the files of one album to a single release
+///
+/// Returns the match, and the files left to be matched one at a time: all of them when
+/// the album is a single file or AcoustID found no releases for it, otherwise the files no
+/// release track fit.
+async fn match_album(
+    folder: PathBuf,
+    group: Vec<MatchResult>,
+    api: &musicbrainz::Api,
+    verbose: bool,
+) -> (Option<AlbumMatch>, Vec<MatchResult>) {
+    if group.len() < 2 {
+        return (None, group);
+    }
 
-        // Wait for rate limit
-        rate_limiter.wait().await;
+    let evidence: Vec<TrackEvidence> = group
+        .iter()
+        .map(|m| TrackEvidence {
+            metadata: &m.file.metadata,
+            acoustid_results: &m.acoustid_results,
+        })
+        .collect();
 
-        let acoustid_results = if let Some(ref fp) = file.fingerprint {
-            let duration = file.metadata.duration_secs.unwrap_or(0.0);
-            match musicbrainz::lookup_by_fingerprint(fp, duration, &api_key).await {
-                Ok(r) => r,
-                Err(e) => {
-                    logger::warning(&format!(
-                        "AcoustID lookup failed for {}: {}",
-                        file.path.display(),
-                        e
-                    ));
-                    vec![]
-                }
+    let mut candidates = Vec::new();
+    for release_id in matching::candidate_releases(&evidence, &api.release_preference) {
+        let release = match musicbrainz::fetch_release(api, &release_id).await {
+            Ok(release) => release,
+            Err(e) => {
+                logger::warning(&format!("Failed to fetch release {}: {}", release_id, e));
+                continue;
             }
-        } else {
-            vec![]
         };
+        let release_match = matching::match_release(&release, &evidence);
+        logger::debug(
+            &format!(
+                "{}: {} - {} scored {:.1}%",
+                folder.display(),
+                release.artist,
+                release.title,
+                release_match.score * 100.0
+            ),
+            verbose,
+        );
+        candidates.push((release, release_match));
+    }
+    drop(evidence);
+    let best = matching::pick_release(candidates, &api.release_preference);
 
-        results.lock().unwrap().push(MatchResult {
-            file,
-            acoustid_results,
-            selected_metadata: None,
-        });
+    let Some((release, release_match)) = best else {
+        return (None, group);
+    };
 
-        *processed.lock().unwrap() += 1;
-        pb.set_position(*processed.lock().unwrap() as u64);
+    let mut tracks = Vec::new();
+    let mut singles = Vec::new();
+    for (m, assignment) in group.into_iter().zip(release_match.assignments) {
+        match assignment {
+            Some(track_idx) => tracks.push((m, track_idx)),
+            None => singles.push(m),
+        }
     }
+    let album = AlbumMatch {
+        folder,
+        release,
+        score: release_match.score,
+        tracks,
+    };
+    (Some(album), singles)
+}
 
-    pb.finish_and_clear();
+/// Review and apply album matches
+///
+/// Returns the files of albums that fell back to being matched one at a time, or None when
+/// the user chose to skip everything that's left.
+fn apply_album_matches(
+    albums: Vec<AlbumMatch>,
+    options: &FixMetadataOptions,
+    submitter: Option<&Submitter>,
+) -> Result<Option<Vec<MatchResult>>> {
+    let mut fallback = Vec::new();
 
-    let final_results = results.lock().unwrap().clone();
-    let matched = final_results
-        .iter()
-        .filter(|r| !r.acoustid_results.is_empty())
-        .count();
+    for album in albums {
+        let release = &album.release;
 
-    logger::info(&format!(
-        "Found matches for {} / {} files ({:.1}%)",
-        matched,
-        total,
-        (matched as f32 / total as f32) * 100.0
-    ));
+        if options.avoid_various_artists {
+            let lower = release.artist.to_lowercase();
+            if lower.contains("various") || lower.contains("compilation") {
+                logger::warning(&format!(
+                    "Skipping '{}' - matched a Various Artists compilation",
+                    album.folder.display()
+                ));
+                println!("  Use --no-avoid-various-artists to include these\n");
+                continue;
+            }
+        }
+
+        if !options.interactive && album.score < options.confidence_threshold as f64 {
+            logger::warning(&format!(
+                "Low confidence ({:.1}%) for album {}, matching its files one by one",
+                album.score * 100.0,
+                album.folder.display()
+            ));
+            fallback.extend(album.tracks.into_iter().map(|(m, _)| m));
+            continue;
+        }
+
+        let updates: Vec<(MatchResult, usize, FieldsToUpdate)> = album
+            .tracks
+            .into_iter()
+            .map(|(m, track_idx)| {
+                let fields = FieldsToUpdate::from_metadata(&m.file.metadata, options);
+                (m, track_idx, fields)
+            })
+            .collect();
+        if !updates.iter().any(|(_, _, fields)| fields.has_updates()) {
+            logger::info(&format!(
+                "Skipping {} - all requested fields already present",
+                album.folder.display()
+            ));
+            continue;
+        }
+
+        // Display match info
+        println!("\n{}", "=".repeat(80));
+        println!("Album: {}", album.folder.display());
+        println!("{}", "-".repeat(80));
+        println!(
+            "\nMusicBrainz release ({:.1}% confidence): {} - {}{}",
+            album.score * 100.0,
+            release.artist,
+            release.title,
+            release.date.as_deref().map(|d| format!(" ({})", d)).unwrap_or_default()
+        );
+        println!("\nTracks:");
+        for (m, track_idx, _) in &updates {
+            let track = &release.tracks[*track_idx];
+            println!(
+                "  {} -> {}-{:02} {}",
+                m.file.path.file_name().unwrap_or_default().to_string_lossy(),
+                track.disc,
+                track.number,
+                track.title
+            );
+        }
+
+        // Ask user if they want to apply
+        let prompted = options.interactive || album.score < 0.9;
+        let should_apply = if prompted {
+            print!("\nApply this release to {} files? [Y/n/s(kip all)]: ", updates.len());
+            io::stdout().flush()?;
+
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)?;
+            let choice = input.trim().to_lowercase();
+
+            if choice == "s" {
+                logger::info("Skipping remaining files");
+                return Ok(None);
+            }
+
+            choice.is_empty() || choice == "y" || choice == "yes"
+        } else if options.auto_apply {
+            println!("\n✓ Auto-applying (high confidence)");
+            true
+        } else {
+            false
+        };
+
+        if !should_apply {
+            logger::info("  Skipped");
+            continue;
+        }
+
+        for (m, track_idx, fields) in &updates {
+            let track = &release.tracks[*track_idx];
+            if fields.has_updates() {
+                let mb_metadata = release.track_metadata(track);
+                if let Err(e) = musicbrainz::apply_metadata_to_file(
+                    &m.file.path,
+                    &m.file.metadata,
+                    &mb_metadata,
+                    fields,
+                    options.dry_run,
+                ) {
+                    logger::error(&format!(
+                        "  Failed to apply to {}: {}",
+                        m.file.path.display(),
+                        e
+                    ));
+                    continue;
+                }
+            }
+            // Only matches the user confirmed are worth teaching AcoustID
+            if prompted {
+                queue_submission(submitter, m, &track.recording_id, options);
+            }
+        }
+        if options.dry_run {
+            logger::info("  [DRY RUN] Would apply metadata");
+        } else {
+            logger::success("  ✓ Metadata applied");
+        }
+    }
 
-    Ok(final_results)
+    Ok(Some(fallback))
 }
 
 /// Apply MusicBrainz matches interactively
 async fn apply_musicbrainz_matches(
     mut matches: Vec<MatchResult>,
+    api: &musicbrainz::Api,
     options: &FixMetadataOptions,
+    submitter: Option<&Submitter>,
 ) -> Result<()> {
     logger::stage("Applying Metadata");
 
-    let user_agent = format!("Ferric/{}", env!("CARGO_PKG_VERSION"));
-    let rate_limiter = musicbrainz::RateLimiter::new(1.0);
-
     for match_result in &mut matches {
-        if match_result.acoustid_results.is_empty() {
+        let candidates = match match_result.candidates.take() {
+            Some(candidates) => candidates,
+            None => prepare_candidates(api, match_result, options.verbose).await,
+        };
+        let Some(best) = candidates.into_iter().next() else {
             logger::warning(&format!(
                 "No matches found for: {}",
                 match_result.file.path.display()
@@ -422,33 +787,20 @@ async fn apply_musicbrainz_matches(
             );
             println!();
             continue;
-        }
-
-        // Get top match
-        let top_match = &match_result.acoustid_results[0];
+        };
+        let confidence = best.confidence as f32;
 
         // Check confidence
-        if !options.interactive && top_match.score < options.confidence_threshold {
+        if !options.interactive && confidence < options.confidence_threshold {
             logger::warning(&format!(
                 "Low confidence ({:.1}%) for {}, skipping",
-                top_match.score * 100.0,
+                confidence * 100.0,
                 match_result.file.path.display()
             ));
             continue;
         }
 
-        // Fetch full metadata from MusicBrainz
-        rate_limiter.wait().await;
-        let mb_metadata =
-            match musicbrainz::fetch_recording_metadata(&top_match.recording_id, &user_agent)
-                .await
-            {
-                Ok(m) => m,
-                Err(e) => {
-                    logger::error(&format!("Failed to fetch metadata: {}", e));
-                    continue;
-                }
-            };
+        let mb_metadata = best.metadata;
 
         // Check for "Various Artists" and skip if user wants to avoid
         if options.avoid_various_artists {
@@ -500,7 +852,11 @@ async fn apply_musicbrainz_matches(
             println!("  Genre:  {}", genre);
         }
 
-        println!("\nMusicBrainz match ({:.1}% confidence):", top_match.score * 100.0);
+        println!(
+            "\nMusicBrainz match ({:.1}% confidence, found by {}):",
+            confidence * 100.0,
+            if best.from_search { "tag search" } else { "fingerprint" }
+        );
 
         // Show what will be added/updated
         println!("\nFields to {}:", if options.overwrite { "update" } else { "add" });
@@ -533,9 +889,20 @@ async fn apply_musicbrainz_matches(
                 match_result.file.metadata.genre.as_deref().unwrap_or("(none)"),
                 mb_metadata.genres[0]);
         }
+        if fields_to_update.update_track {
+            if let Some(track) = mb_metadata.track_position() {
+                println!("  Track:  {} -> {}", position(&match_result.file.metadata, false), track);
+            }
+        }
+        if fields_to_update.update_disc {
+            if let Some(disc) = mb_metadata.disc_position() {
+                println!("  Disc:   {} -> {}", position(&match_result.file.metadata, true), disc);
+            }
+        }
 
         // Ask user if they want to apply
-        let should_apply = if options.interactive || top_match.score < 0.9 {
+        let prompted = options.interactive || confidence < 0.9;
+        let should_apply = if prompted {
             print!("\nApply this metadata? [Y/n/s(kip all)]: ");
             io::stdout().flush()?;
 
@@ -570,6 +937,15 @@ async fn apply_musicbrainz_matches(
                     } else {
                         logger::success("  ✓ Metadata applied");
                     }
+                    // Only fingerprint matches the user confirmed are worth teaching AcoustID
+                    if prompted && !best.from_search {
+                        queue_submission(
+                            submitter,
+                            match_result,
+                            &mb_metadata.recording_id,
+                            options,
+                        );
+                    }
                 }
                 Err(e) => {
                     logger::error(&format!("  Failed to apply: {}", e));
@@ -582,3 +958,135 @@ async fn apply_musicbrainz_matches(
 
     Ok(())
 }
+
+/// Fetch and score the recordings a file might be, best first, with the best one placed on
+/// its release
+async fn prepare_candidates(
+    api: &musicbrainz::Api,
+    match_result: &MatchResult,
+    verbose: bool,
+) -> Vec<Candidate> {
+    let mut candidates = find_candidates(api, match_result, verbose).await;
+    let Some(best) = candidates.first_mut() else {
+        return candidates;
+    };
+
+    // The candidate's own release tells where on it the recording sits
+    let mb_metadata = &mut best.metadata;
+    if let Some(release_id) = mb_metadata.release_id.clone() {
+        match musicbrainz::fetch_release(api, &release_id).await {
+            Ok(release) => {
+                if !release.fill_position(mb_metadata) {
+                    logger::debug(
+                        &format!(
+                            "Recording {} isn't on release {}",
+                            mb_metadata.recording_id, release_id
+                        ),
+                        verbose,
+                    );
+                }
+            }
+            Err(e) => logger::warning(&format!("Failed to fetch release {}: {}", release_id, e)),
+        }
+    }
+    candidates
+}
+
+/// Fetch and score the recordings a file might be, best first
+///
+/// These are the file's AcoustID matches, or when it has none, or none of them can be
+/// fetched, the results of searching MusicBrainz for its tags.
+async fn find_candidates(
+    api: &musicbrainz::Api,
+    match_result: &MatchResult,
+    verbose: bool,
+) -> Vec<Candidate> {
+    let file = &match_result.file;
+    let mut candidates = Vec::new();
+
+    let mut recordings: Vec<&musicbrainz::AcoustIdResult> = Vec::new();
+    for result in &match_result.acoustid_results {
+        if !recordings.iter().any(|r| r.recording_id == result.recording_id) {
+            recordings.push(result);
+        }
+    }
+    for result in recordings.into_iter().take(MAX_FINGERPRINT_CANDIDATES) {
+        match musicbrainz::fetch_recording_metadata(api, &result.recording_id).await {
+            Ok(metadata) => candidates.push(Candidate {
+                confidence: matching::similarity(
+                    &file.metadata,
+                    &metadata,
+                    Some(result.score as f64),
+                ),
+                metadata,
+                from_search: false,
+            }),
+            Err(e) => logger::error(&format!("Failed to fetch metadata: {}", e)),
+        }
+    }
+
+    if candidates.is_empty() {
+        match musicbrainz::search_by_metadata(api, &file.metadata).await {
+            Ok(results) => {
+                logger::debug(
+                    &format!(
+                        "Searched MusicBrainz for {}: {} results",
+                        file.path.display(),
+                        results.len()
+                    ),
+                    verbose,
+                );
+                candidates.extend(results.into_iter().map(|metadata| Candidate {
+                    confidence: matching::similarity(&file.metadata, &metadata, None),
+                    metadata,
+                    from_search: true,
+                }));
+            }
+            Err(e) => logger::debug(
+                &format!("MusicBrainz search failed for {}: {}", file.path.display(), e),
+                verbose,
+            ),
+        }
+    }
+
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    candidates
+}
+
+/// Queue a file's fingerprint for AcoustID as a recording, unless AcoustID already links them
+fn queue_submission(
+    submitter: Option<&Submitter>,
+    match_result: &MatchResult,
+    recording_id: &str,
+    options: &FixMetadataOptions,
+) {
+    let Some(submitter) = submitter.filter(|_| !options.dry_run) else {
+        return;
+    };
+    let Some(ref fingerprint) = match_result.file.fingerprint else {
+        return;
+    };
+    if match_result
+        .acoustid_results
+        .iter()
+        .any(|r| r.recording_id == recording_id)
+    {
+        return;
+    }
+    let file = &match_result.file;
+    submitter.queue(&file.path, &file.metadata, fingerprint, recording_id, options.verbose);
+}
+
+/// A file's current track or disc number as shown next to the new one
+pub(crate) fn position(metadata: &AudioMetadata, disc: bool) -> String {
+    let (number, total) = if disc {
+        (metadata.disc_number, metadata.disc_total)
+    } else {
+        (metadata.track_number, metadata.track_total)
+    };
+    match (number, total) {
+        (Some(number), Some(total)) => format!("{}/{}", number, total),
+        (Some(number), None) => number.to_string(),
+        (None, _) => "(none)".to_string(),
+    }
+}
@@ -1,8 +1,10 @@
 use crate::config::Config;
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
 use crate::operations::OperationStats;
 use crate::quality;
+use crate::trash;
 use crate::utils;
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
@@ -94,7 +96,7 @@ fn fix_files(root: &Path, stats: &mut OperationStats, options: &FixNamingOptions
                     );
                     stats.succeeded += 1;
                 } else {
-                    match fs::rename(&file, &new_path) {
+                    match journal::rename(&file, &new_path) {
                         Ok(_) => {
                             logger::debug(
                                 &format!("Fixed: {} -> {}", filename_str, normalized),
@@ -129,7 +131,7 @@ fn fix_directories(
         .collect();
 
     // Sort by depth (deepest first)
-    dirs.sort_by(|a, b| b.components().count().cmp(&a.components().count()));
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
 
     let pb = ProgressBar::new(dirs.len() as u64);
     pb.set_style(
@@ -186,7 +188,7 @@ fn fix_directories(
                     );
                     stats.succeeded += 1;
                 } else {
-                    match fs::rename(&dir, &new_path) {
+                    match journal::rename(&dir, &new_path) {
                         Ok(_) => {
                             logger::debug(
                                 &format!("Fixed dir: {} -> {}", dirname_str, normalized),
@@ -224,7 +226,7 @@ fn is_same_song(file1: &Path, file2: &Path) -> bool {
     let album1 = utils::normalize_for_comparison(&m1.get_album());
     let album2 = utils::normalize_for_comparison(&m2.get_album());
 
-    title1 == title2 && artist1 == artist2 && album1 == album2
+    title1 == title2 && artist1 == artist2 && album1 == album2 && m1.get_disc() == m2.get_disc()
 }
 
 /// Find a unique filename by appending (1), (2), etc.
@@ -285,7 +287,7 @@ fn merge_directory_contents(
         // Create parent directory if needed
         if let Some(parent) = final_target_path.parent() {
             if !options.dry_run {
-                fs::create_dir_all(parent)?;
+                journal::create_dir_all(parent)?;
             }
         }
 
@@ -315,8 +317,8 @@ fn merge_directory_contents(
                                     options.verbose,
                                 );
                             } else {
-                                fs::remove_file(&final_target_path)?;
-                                fs::rename(&file, &final_target_path)?;
+                                trash::discard(&final_target_path, &options.input_dir, &options.config)?;
+                                journal::rename(&file, &final_target_path)?;
                                 logger::debug(
                                     &format!(
                                         "Replaced with higher quality: {} -> {}",
@@ -340,7 +342,7 @@ fn merge_directory_contents(
                                     options.verbose,
                                 );
                             } else {
-                                fs::remove_file(&file)?;
+                                trash::discard(&file, &options.input_dir, &options.config)?;
                                 logger::debug(
                                     &format!(
                                         "Kept existing higher quality, removed: {}",
@@ -364,7 +366,7 @@ fn merge_directory_contents(
                             options.verbose,
                         );
                     } else {
-                        fs::rename(&file, &unique_path)?;
+                        journal::rename(&file, &unique_path)?;
                         logger::debug(
                             &format!(
                                 "Renamed (different song): {} -> {}",
@@ -388,7 +390,7 @@ fn merge_directory_contents(
                         options.verbose,
                     );
                 } else {
-                    fs::rename(&file, &unique_path)?;
+                    journal::rename(&file, &unique_path)?;
                     logger::debug(
                         &format!(
                             "Renamed (conflict): {} -> {}",
@@ -411,7 +413,7 @@ fn merge_directory_contents(
                     options.verbose,
                 );
             } else {
-                fs::rename(&file, &final_target_path)?;
+                journal::rename(&file, &final_target_path)?;
                 logger::debug(
                     &format!(
                         "Moved: {} -> {}",
@@ -433,7 +435,7 @@ fn merge_directory_contents(
             .filter(|e| e.file_type().is_dir())
             .map(|e| e.path().to_path_buf())
             .collect();
-        subdirs.sort_by(|a, b| b.components().count().cmp(&a.components().count()));
+        subdirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
 
         for subdir in subdirs {
             if subdir.read_dir()?.next().is_none() {
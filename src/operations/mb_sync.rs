@@ -0,0 +1,435 @@
+use crate::config::Config;
+use crate::metadata::AudioMetadata;
+use crate::musicbrainz::{self, MusicBrainzMetadata, Release, ReleaseTrack};
+use crate::operations::fix_metadata_mb::position;
+use crate::operations::OperationStats;
+use crate::tags::{self, TagEdit};
+use crate::{cache, journal, logger, utils};
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub struct MbSyncOptions {
+    pub input_dirs: Vec<PathBuf>,
+    pub dry_run: bool,
+    pub verbose: bool,
+    /// Apply corrections without asking
+    pub yes: bool,
+}
+
+/// A tag whose value on MusicBrainz differs from the file's
+#[derive(Debug, Clone, PartialEq)]
+struct Correction {
+    /// Tag name as `tags::write` takes it
+    key: &'static str,
+    label: &'static str,
+    current: Option<String>,
+    upstream: String,
+}
+
+/// Refresh the tags of files that carry MusicBrainz IDs from MusicBrainz
+///
+/// Files are looked up by the IDs they were tagged with, so there is no fingerprinting or
+/// AcoustID lookup. Every difference is shown before anything is written.
+pub async fn run(options: MbSyncOptions, config: &Config) -> Result<OperationStats> {
+    logger::stage("MusicBrainz Sync");
+
+    if options.dry_run {
+        logger::warning("DRY RUN MODE - No files will be modified");
+    }
+
+    // Corrections are the point, so cached responses aren't trusted
+    let api = musicbrainz::Api::from_config(config)?.refreshing();
+    api.log_mode();
+
+    let files = scan_tagged_files(&options.input_dirs);
+    let mut stats = OperationStats::new();
+    if files.is_empty() {
+        logger::warning("No files with MusicBrainz recording IDs found");
+        return Ok(stats);
+    }
+    logger::success(&format!(
+        "Found {} files with MusicBrainz recording IDs",
+        files.len()
+    ));
+
+    let mut releases: HashMap<String, Option<Release>> = HashMap::new();
+    let mut apply_all = options.yes;
+
+    for (path, metadata) in &files {
+        stats.processed += 1;
+
+        let upstream = match fetch_upstream(&api, metadata, &mut releases).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                logger::error(&format!("Failed to fetch {}: {}", path.display(), e));
+                stats.errors += 1;
+                continue;
+            }
+        };
+
+        let corrections = corrections(metadata, &upstream);
+        if corrections.is_empty() {
+            logger::debug(&format!("Up to date: {}", path.display()), options.verbose);
+            stats.skipped += 1;
+            continue;
+        }
+
+        println!("\n{}", "=".repeat(80));
+        println!("File: {}", path.display());
+        println!("{}", "-".repeat(80));
+        for correction in &corrections {
+            println!(
+                "  {:<18} {} -> {}",
+                format!("{}:", correction.label),
+                correction
+                    .current
+                    .as_deref()
+                    .map_or("(none)".to_string(), display_value),
+                display_value(&correction.upstream)
+            );
+        }
+
+        if options.dry_run {
+            logger::info(&format!(
+                "  [DRY RUN] Would apply {} corrections",
+                corrections.len()
+            ));
+            stats.succeeded += 1;
+            continue;
+        }
+
+        if !apply_all {
+            print!("\nApply these corrections? [Y/n/a(ll)/s(kip all)]: ");
+            io::stdout().flush()?;
+
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)?;
+            match input.trim().to_lowercase().as_str() {
+                "" | "y" | "yes" => {}
+                "a" | "all" => apply_all = true,
+                "s" => {
+                    logger::info("Skipping remaining files");
+                    stats.add_skipped(path.clone(), "skipped by user".to_string());
+                    break;
+                }
+                _ => {
+                    logger::info("  Skipped");
+                    stats.add_skipped(path.clone(), "skipped by user".to_string());
+                    continue;
+                }
+            }
+        }
+
+        match apply_corrections(path, metadata, &upstream, &corrections) {
+            Ok(()) => {
+                logger::success("  ✓ Corrections applied");
+                stats.succeeded += 1;
+            }
+            Err(e) => {
+                logger::error(&format!("  Failed to apply: {}", e));
+                stats.errors += 1;
+            }
+        }
+    }
+
+    stats.print_summary("MusicBrainz Sync");
+    Ok(stats)
+}
+
+/// Files under the input directories that have a MusicBrainz recording ID
+pub(crate) fn scan_tagged_files(input_dirs: &[PathBuf]) -> Vec<(PathBuf, AudioMetadata)> {
+    let mut paths = Vec::new();
+    for dir in input_dirs {
+        if !dir.exists() {
+            logger::warning(&format!("Directory does not exist: {}", dir.display()));
+            continue;
+        }
+        paths.extend(
+            WalkDir::new(dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.path().to_path_buf())
+                .filter(|p| utils::is_audio_file(p)),
+        );
+    }
+
+    logger::info("Extracting metadata...");
+    let pb = ProgressBar::new(paths.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{bar:40}] {pos}/{len} | {msg}")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+
+    let mut files: Vec<(PathBuf, AudioMetadata)> = paths
+        .par_iter()
+        .filter_map(|path| {
+            pb.inc(1);
+            let metadata = AudioMetadata::from_file(path).ok()?;
+            metadata.musicbrainz_recording_id.as_ref()?;
+            Some((path.clone(), metadata))
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    // Keeps the files of a release together, so each release is fetched once
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
+
+/// The file's recording as MusicBrainz has it now
+///
+/// A file tagged with a release gets that release's track. Otherwise, or when the release can't
+/// be fetched or no longer has the recording, the recording is looked up and placed on its
+/// preferred release.
+/// Merged recordings and releases come back under their new IDs.
+async fn fetch_upstream(
+    api: &musicbrainz::Api,
+    metadata: &AudioMetadata,
+    releases: &mut HashMap<String, Option<Release>>,
+) -> Result<MusicBrainzMetadata> {
+    let recording_id = metadata
+        .musicbrainz_recording_id
+        .clone()
+        .unwrap_or_default();
+
+    if let Some(release_id) = &metadata.musicbrainz_release_id {
+        if let Some(release) = fetch_release(api, release_id, releases).await {
+            if let Some(track) = release_track(release, &recording_id, metadata) {
+                return Ok(release.track_metadata(track));
+            }
+        }
+    }
+
+    let mut upstream = musicbrainz::fetch_recording_metadata(api, &recording_id).await?;
+    if let Some(release_id) = &metadata.musicbrainz_release_id {
+        if let Some(release) = fetch_release(api, release_id, releases).await {
+            if let Some(track) = release_track(release, &upstream.recording_id, metadata) {
+                return Ok(release.track_metadata(track));
+            }
+        }
+    }
+    if let Some(release_id) = upstream.release_id.clone() {
+        if let Some(release) = fetch_release(api, &release_id, releases).await {
+            release.fill_position(&mut upstream);
+        }
+    }
+    Ok(upstream)
+}
+
+/// The file's track on a release: the one with its release track ID, or else where the
+/// recording sits, preferring the file's current position
+fn release_track<'a>(
+    release: &'a Release,
+    recording_id: &str,
+    current: &AudioMetadata,
+) -> Option<&'a ReleaseTrack> {
+    let by_id = |id: Option<&str>| release.tracks.iter().find(|t| id == Some(t.id.as_str()));
+    if let Some(track) = by_id(current.musicbrainz_track_id.as_deref()) {
+        return Some(track);
+    }
+
+    let mut position = MusicBrainzMetadata {
+        recording_id: recording_id.to_string(),
+        track_number: current.track_number,
+        disc_number: current.disc_number,
+        ..Default::default()
+    };
+    if !release.fill_position(&mut position) {
+        return None;
+    }
+    by_id(position.track_id.as_deref())
+}
+
+/// Fetch a release once per run; None when it can't be, like a release that was removed
+async fn fetch_release<'a>(
+    api: &musicbrainz::Api,
+    release_id: &str,
+    releases: &'a mut HashMap<String, Option<Release>>,
+) -> Option<&'a Release> {
+    if !releases.contains_key(release_id) {
+        let release = match musicbrainz::fetch_release(api, release_id).await {
+            Ok(release) => Some(release),
+            Err(e) => {
+                logger::warning(&format!("Failed to fetch release {}: {}", release_id, e));
+                None
+            }
+        };
+        releases.insert(release_id.to_string(), release);
+    }
+    releases[release_id].as_ref()
+}
+
+/// Tags that differ between the file and MusicBrainz, in display order
+fn corrections(current: &AudioMetadata, upstream: &MusicBrainzMetadata) -> Vec<Correction> {
+    let track = (current.track_number.is_some() || current.track_total.is_some())
+        .then(|| position(current, false));
+    let disc = (current.disc_number.is_some() || current.disc_total.is_some())
+        .then(|| position(current, true));
+    let fields = [
+        (
+            "title",
+            "Title",
+            current.title.clone(),
+            Some(upstream.title.clone()),
+        ),
+        (
+            "artist",
+            "Artist",
+            current.artist.clone(),
+            Some(upstream.artist.clone()),
+        ),
+        (
+            "album",
+            "Album",
+            current.album.clone(),
+            upstream.album.clone(),
+        ),
+        (
+            "album_artist",
+            "Album artist",
+            current.album_artist.clone(),
+            upstream.album_artist.clone(),
+        ),
+        ("date", "Date", current.date.clone(), upstream.date.clone()),
+        (
+            "genre",
+            "Genre",
+            current.genre.clone(),
+            upstream.genres.first().cloned(),
+        ),
+        ("track", "Track", track, upstream.track_position()),
+        ("disc", "Disc", disc, upstream.disc_position()),
+        (
+            "musicbrainz_trackid",
+            "Recording ID",
+            current.musicbrainz_recording_id.clone(),
+            Some(upstream.recording_id.clone()),
+        ),
+        (
+            "musicbrainz_albumid",
+            "Release ID",
+            current.musicbrainz_release_id.clone(),
+            upstream.release_id.clone(),
+        ),
+        (
+            "musicbrainz_releasetrackid",
+            "Release track ID",
+            current.musicbrainz_track_id.clone(),
+            upstream.track_id.clone(),
+        ),
+    ];
+
+    let release_tags = upstream
+        .release_tags()
+        .into_iter()
+        .map(|(key, value)| (key, key, current.tag_value(key), Some(value)));
+
+    fields
+        .into_iter()
+        .chain(release_tags)
+        .filter_map(|(key, label, current, upstream)| {
+            // Nothing upstream is never a correction; it only means MusicBrainz lacks the field
+            let upstream = upstream.filter(|v| !v.trim().is_empty())?;
+            if current.as_deref().map(str::trim) == Some(upstream.trim()) {
+                return None;
+            }
+            Some(Correction {
+                key,
+                label,
+                current,
+                upstream,
+            })
+        })
+        .collect()
+}
+
+/// Show multi-valued tags the way Picard lists them
+fn display_value(value: &str) -> String {
+    value.replace(tags::SEPARATOR, "; ")
+}
+
+/// Write the corrections, journal them and refresh the cached metadata
+fn apply_corrections(
+    path: &Path,
+    current: &AudioMetadata,
+    upstream: &MusicBrainzMetadata,
+    corrections: &[Correction],
+) -> Result<()> {
+    let updates: Vec<(&str, String)> = corrections
+        .iter()
+        .map(|c| (c.key, c.upstream.clone()))
+        .collect();
+    let mut edit = TagEdit::new();
+    for (key, value) in &updates {
+        edit = edit.set(key, value.clone());
+    }
+
+    let before_hash = journal::hash_if_recording(path);
+    tags::write(path, &edit)?;
+    let (before, after) = current.tag_changes(&updates);
+    journal::record_tag_rewrite(path, before_hash, before, after);
+
+    let mut metadata = AudioMetadata::from_file(path)?;
+    metadata.musicbrainz_recording_id = Some(upstream.recording_id.clone());
+    metadata.musicbrainz_release_id = upstream.release_id.clone();
+    metadata.musicbrainz_track_id = upstream.track_id.clone();
+    if let Some(cache) = cache::get_global_cache() {
+        let _ = cache.insert(path, &metadata);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corrections_list_only_changed_fields() {
+        let current = AudioMetadata {
+            title: Some("Song".to_string()),
+            artist: Some("Band".to_string()),
+            album: Some("Album".to_string()),
+            genre: Some("Rock".to_string()),
+            track_number: Some(3),
+            musicbrainz_recording_id: Some("rec-old".to_string()),
+            musicbrainz_release_id: Some("rel-1".to_string()),
+            release_status: Some("official".to_string()),
+            ..Default::default()
+        };
+        let mut upstream = MusicBrainzMetadata {
+            recording_id: "rec-new".to_string(),
+            release_id: Some("rel-1".to_string()),
+            title: "Song (Remastered)".to_string(),
+            artist: "Band".to_string(),
+            album: Some("Album".to_string()),
+            track_number: Some(3),
+            track_total: Some(12),
+            ..Default::default()
+        };
+        upstream.release.status = Some("official".to_string());
+        upstream.credit.ids = vec!["art-1".to_string(), "art-2".to_string()];
+
+        let corrections = corrections(&current, &upstream);
+        let changed: Vec<(&str, Option<&str>, &str)> = corrections
+            .iter()
+            .map(|c| (c.key, c.current.as_deref(), c.upstream.as_str()))
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                ("title", Some("Song"), "Song (Remastered)"),
+                ("track", Some("3"), "3/12"),
+                ("musicbrainz_trackid", Some("rec-old"), "rec-new"),
+                ("musicbrainz_artistid", None, "art-1\0art-2"),
+            ]
+        );
+    }
+}
@@ -1,15 +1,16 @@
 use crate::config::Config;
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
 use crate::operations::OperationStats;
 use crate::quality;
+use crate::trash;
 use crate::utils;
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
-use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
 
@@ -62,7 +63,6 @@ pub fn run(options: MergeOptions) -> Result<OperationStats> {
 
     struct FileInfo {
         path: PathBuf,
-        metadata: AudioMetadata,
         quality: u32,
         dest_dir: PathBuf,
         dest_path: PathBuf,
@@ -106,7 +106,6 @@ pub fn run(options: MergeOptions) -> Result<OperationStats> {
 
             Some(FileInfo {
                 path: file.clone(),
-                metadata,
                 quality,
                 dest_dir,
                 dest_path,
@@ -272,7 +271,7 @@ pub fn run(options: MergeOptions) -> Result<OperationStats> {
                 stats.succeeded += 1;
             } else {
                 // Create destination directory if needed (won't fail if exists)
-                if let Err(e) = fs::create_dir_all(&dest_dir) {
+                if let Err(e) = journal::create_dir_all(dest_dir) {
                     logger::error(&format!(
                         "Failed to create directory {}: {}",
                         dest_dir.display(),
@@ -282,13 +281,28 @@ pub fn run(options: MergeOptions) -> Result<OperationStats> {
                     continue;
                 }
 
+                // Keep the replaced file recoverable instead of overwriting it
+                if action_type == "upgrade" && options.config.trash.enabled {
+                    if let Err(e) =
+                        trash::discard(&target_path, &options.output_dir, &options.config)
+                    {
+                        logger::error(&format!(
+                            "Failed to move replaced file {} to the trash: {}",
+                            target_path.display(),
+                            e
+                        ));
+                        stats.errors += 1;
+                        continue;
+                    }
+                }
+
                 let result = if action_type == "upgrade" {
                     // Always copy when upgrading (even if do_move is true)
-                    fs::copy(&file_info.path, &target_path).map(|_| ())
+                    journal::copy(&file_info.path, &target_path).map(|_| ())
                 } else if options.do_move {
-                    fs::rename(&file_info.path, &target_path)
+                    journal::rename(&file_info.path, &target_path)
                 } else {
-                    fs::copy(&file_info.path, &target_path).map(|_| ())
+                    journal::copy(&file_info.path, &target_path).map(|_| ())
                 };
 
                 match result {
@@ -343,7 +357,12 @@ pub fn run(options: MergeOptions) -> Result<OperationStats> {
                 "Cleaning up empty directories ({} directories to check)...",
                 dirs_to_check.len()
             ));
-            let removed = cleanup_empty_directories(&options.input_dir, dirs_to_check, options.verbose);
+            let removed = cleanup_empty_directories(
+                &options.input_dir,
+                dirs_to_check,
+                &options.config,
+                options.verbose,
+            );
             if removed > 0 {
                 logger::success(&format!("Removed {} empty directories", removed));
             }
@@ -361,8 +380,9 @@ pub fn run(options: MergeOptions) -> Result<OperationStats> {
 /// Remove empty directories recursively, working from deepest to shallowest
 /// Returns the number of directories removed
 fn cleanup_empty_directories(
-    root_dir: &PathBuf,
+    root_dir: &Path,
     directories: HashSet<PathBuf>,
+    config: &Config,
     verbose: bool,
 ) -> usize {
     let mut removed_count = 0;
@@ -378,7 +398,7 @@ fn cleanup_empty_directories(
     // Clean up each directory using the shared utility function
     // The utility handles recursion up to the root automatically
     for dir in sorted_dirs {
-        removed_count += utils::cleanup_empty_directory(&dir, root_dir, verbose);
+        removed_count += utils::cleanup_empty_directory(&dir, root_dir, config, verbose);
     }
 
     removed_count
@@ -390,6 +410,13 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
 
+    /// Leftover files are deleted outright in these tests
+    fn no_trash() -> Config {
+        let mut config = Config::default();
+        config.trash.enabled = false;
+        config
+    }
+
     #[test]
     fn test_cleanup_empty_directories() {
         // Create temporary test directory
@@ -411,7 +438,7 @@ mod tests {
         dirs_to_check.insert(dir3.clone());
 
         // Run cleanup
-        let removed = cleanup_empty_directories(&root_path, dirs_to_check, false);
+        let removed = cleanup_empty_directories(&root_path, dirs_to_check, &no_trash(), false);
 
         // All empty directories should be removed, including parents
         assert_eq!(removed, 5); // album1, album2, album3, artist1, artist2
@@ -439,7 +466,7 @@ mod tests {
         dirs_to_check.insert(dir1.clone());
 
         // Run cleanup
-        let removed = cleanup_empty_directories(&root_path, dirs_to_check, false);
+        let removed = cleanup_empty_directories(&root_path, dirs_to_check, &no_trash(), false);
 
         // Directory and non-audio file should be removed
         assert!(removed >= 1);
@@ -464,7 +491,7 @@ mod tests {
         dirs_to_check.insert(dir1.clone());
 
         // Run cleanup
-        let removed = cleanup_empty_directories(&root_path, dirs_to_check, false);
+        let removed = cleanup_empty_directories(&root_path, dirs_to_check, &no_trash(), false);
 
         // Directory with audio file should NOT be removed
         assert_eq!(removed, 0);
@@ -487,7 +514,7 @@ mod tests {
         dirs_to_check.insert(dir1.clone());
 
         // Run cleanup
-        cleanup_empty_directories(&root_path, dirs_to_check, false);
+        cleanup_empty_directories(&root_path, dirs_to_check, &no_trash(), false);
 
         // Root should still exist (not removed)
         assert!(root_path.exists());
@@ -1,6 +1,8 @@
 use crate::config::Config;
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
+use crate::operations::sort::{DiscLayout, MultiDiscAlbums};
 use crate::operations::OperationStats;
 use crate::quality;
 use crate::utils;
@@ -10,7 +12,6 @@ use pathdiff::diff_paths;
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs;
-use std::os::unix::fs as unix_fs;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
@@ -53,6 +54,8 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
         logger::warning("DRY RUN MODE - No files will be modified");
     }
 
+    let disc_layout = DiscLayout::from_config(&options.config)?;
+
     let stats = OperationStats::new();
     let symlink_created = 0;
     let symlink_upgraded = 0;
@@ -116,23 +119,35 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
             .progress_chars("█▓▒░"),
     );
 
-    let file_infos: Vec<FileInfo> = all_files
+    let tracks: Vec<(PathBuf, AudioMetadata)> = all_files
         .par_iter()
         .filter_map(|file| {
             pb.inc(1);
 
-            let metadata = match AudioMetadata::from_file(file) {
-                Ok(m) => m,
+            match AudioMetadata::from_file(file) {
+                Ok(metadata) => Some((file.clone(), metadata)),
                 Err(e) => {
                     logger::error(&format!(
                         "Failed to read metadata from {}: {}",
                         file.display(),
                         e
                     ));
-                    return None;
+                    None
                 }
-            };
+            }
+        })
+        .collect();
 
+    pb.finish_and_clear();
+
+    let multi_disc = MultiDiscAlbums::collect(
+        tracks.iter().map(|(_, metadata)| metadata),
+        options.config.naming.prefer_artist,
+    );
+
+    let file_infos: Vec<FileInfo> = tracks
+        .into_par_iter()
+        .map(|(file, metadata)| {
             let quality = quality::calculate_quality_score(&metadata, &options.config);
 
             // Determine destination based on metadata
@@ -149,30 +164,35 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
                 options.config.naming.max_name_length,
             );
 
-            let dest_dir = options.output_dir.join(&artist_safe).join(&album_safe);
-            let filename = file.file_name().unwrap();
-            let dest_path = dest_dir.join(filename);
+            let is_multi_disc = multi_disc.contains(&metadata);
+            let mut dest_dir = options.output_dir.join(&artist_safe).join(&album_safe);
+            if let Some(folder) = disc_layout.folder(&metadata, is_multi_disc) {
+                dest_dir.push(folder);
+            }
+            let filename = file.file_name().unwrap().to_string_lossy();
+            let dest_path =
+                dest_dir.join(disc_layout.prefix_filename(&metadata, &filename, is_multi_disc));
 
             // Create a normalized song ID for deduplication
             let song_id = format!(
-                "{}__{}__{}",
+                "{}__{}__{}__{}",
                 utils::normalize_for_comparison(&artist),
                 utils::normalize_for_comparison(&album),
+                metadata.get_disc(),
                 utils::normalize_for_comparison(&title)
             );
 
-            Some(FileInfo {
-                path: file.clone(),
+            FileInfo {
+                path: file,
                 metadata,
                 quality,
                 dest_dir,
                 dest_path,
                 song_id,
-            })
+            }
         })
         .collect();
 
-    pb.finish_and_clear();
     logger::success(&format!(
         "Metadata extracted from {} files",
         file_infos.len()
@@ -185,7 +205,7 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
     for file_info in file_infos {
         song_groups
             .entry(file_info.song_id.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(file_info);
     }
 
@@ -212,7 +232,7 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
         }
 
         // Sort by quality (descending) and take the best
-        versions.sort_by(|a, b| b.quality.cmp(&a.quality));
+        versions.sort_by_key(|v| std::cmp::Reverse(v.quality));
         let best = versions.into_iter().next().unwrap();
 
         logger::debug(
@@ -381,7 +401,7 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
 
                     if !options.dry_run {
                         // Remove old symlink
-                        if let Err(e) = fs::remove_file(dest_path) {
+                        if let Err(e) = journal::remove_file(dest_path) {
                             logger::error(&format!(
                                 "Failed to remove old symlink {}: {}",
                                 dest_path.display(),
@@ -393,7 +413,7 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
                         }
 
                         // Create new symlink
-                        if let Err(e) = unix_fs::symlink(&source_path, dest_path) {
+                        if let Err(e) = journal::symlink(&source_path, dest_path) {
                             logger::error(&format!(
                                 "Failed to create symlink {} -> {}: {}",
                                 dest_path.display(),
@@ -467,7 +487,7 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
 
             if !options.dry_run {
                 // Create destination directory if needed
-                if let Err(e) = fs::create_dir_all(dest_dir) {
+                if let Err(e) = journal::create_dir_all(dest_dir) {
                     logger::error(&format!(
                         "Failed to create directory {}: {}",
                         dest_dir.display(),
@@ -479,7 +499,7 @@ pub fn run(options: MergeLibrariesOptions) -> Result<OperationStats> {
                 }
 
                 // Create symlink
-                if let Err(e) = unix_fs::symlink(&source_path, dest_path) {
+                if let Err(e) = journal::symlink(&source_path, dest_path) {
                     logger::error(&format!(
                         "Failed to create symlink {} -> {}: {}",
                         dest_path.display(),
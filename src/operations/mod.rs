@@ -1,14 +1,21 @@
+pub mod acoustid_submit;
+pub mod analyze;
 pub mod convert;
 pub mod dedupe;
 pub mod dedupe_libraries;
 pub mod fix_metadata;
 pub mod fix_metadata_mb;
 pub mod fix_naming;
+pub mod mb_sync;
 pub mod merge;
 pub mod merge_libraries;
 pub mod playlist;
+pub mod replaygain;
+pub mod scrub;
 pub mod sort;
+pub mod undo;
 pub mod unified;
+pub mod verify;
 
 // Common structures for all operations
 use std::path::PathBuf;
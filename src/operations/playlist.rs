@@ -66,7 +66,7 @@ pub fn run(options: PlaylistImportOptions) -> Result<()> {
     let mut missing = Vec::new();
 
     for entry in &entries {
-        let candidates = find_matches(&entry, &library_tracks, &exact_match_index, options.verbose);
+        let candidates = find_matches(entry, &library_tracks, &exact_match_index, options.verbose);
 
         if candidates.is_empty() {
             missing.push(format!("{} - {}", entry.artist_raw, entry.title_raw));
@@ -721,7 +721,7 @@ fn build_exact_match_index(library: &[LibraryTrack]) -> HashMap<(String, String)
         for artist in &track.artist_variants {
             for title in &track.title_variants {
                 let key = (artist.clone(), title.clone());
-                index.entry(key).or_insert_with(Vec::new).push(track_idx);
+                index.entry(key).or_default().push(track_idx);
             }
         }
     }
@@ -0,0 +1,418 @@
+use crate::cache;
+use crate::config::Config;
+use crate::journal;
+use crate::logger;
+use crate::loudness::{self, TrackLoudness};
+use crate::metadata::AudioMetadata;
+use crate::operations::OperationStats;
+use crate::tags::{self, TagEdit};
+use crate::utils;
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use walkdir::WalkDir;
+
+/// Loudness the R128_* gains of Opus files are relative to (RFC 7845)
+const R128_REFERENCE: f64 = -23.0;
+
+pub struct ReplayGainOptions {
+    pub input_dir: PathBuf,
+    pub dry_run: bool,
+    pub verbose: bool,
+    /// Re-analyze files even when the cache has a result for them
+    pub force: bool,
+    pub config: Config,
+}
+
+struct Track {
+    path: PathBuf,
+    metadata: AudioMetadata,
+    loudness: TrackLoudness,
+}
+
+/// Tags ferric writes, in the order they're applied
+const REPLAYGAIN_KEYS: &[&str] = &[
+    "replaygain_track_gain",
+    "replaygain_track_peak",
+    "replaygain_album_gain",
+    "replaygain_album_peak",
+];
+const R128_KEYS: &[&str] = &["r128_track_gain", "r128_album_gain"];
+
+/// Scan loudness and write ReplayGain tags (R128 gain tags for Opus)
+pub fn run(options: ReplayGainOptions) -> Result<OperationStats> {
+    logger::stage("Scanning loudness (EBU R128) and writing ReplayGain tags");
+    logger::info(&format!("Input directory: {}", options.input_dir.display()));
+    logger::info(&format!(
+        "Reference loudness: {} LUFS, albums grouped by {}",
+        options.config.replaygain.reference_loudness, options.config.replaygain.album_grouping
+    ));
+
+    let group_by_tag = match options
+        .config
+        .replaygain
+        .album_grouping
+        .to_lowercase()
+        .as_str()
+    {
+        "folder" => false,
+        "tag" => true,
+        other => anyhow::bail!(
+            "Unknown [replaygain] album_grouping '{}' (expected 'folder' or 'tag')",
+            other
+        ),
+    };
+
+    if options.dry_run {
+        logger::warning("DRY RUN MODE - No files will be modified");
+    }
+
+    let files: Vec<PathBuf> = WalkDir::new(&options.input_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| utils::is_audio_file(p))
+        .collect();
+
+    logger::info(&format!("Found {} audio files", files.len()));
+
+    let stats_mutex = Arc::new(Mutex::new(OperationStats::new()));
+    let analyzed = Mutex::new(0_usize);
+    let cache = cache::get_global_cache();
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] [{bar:40}] {pos}/{len} ({eta}) | Measuring loudness...")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+
+    let tracks: Vec<Track> = files
+        .par_iter()
+        .filter_map(|file| {
+            pb.inc(1);
+
+            let result = AudioMetadata::from_file(file).and_then(|metadata| {
+                let cached = match (&cache, options.force) {
+                    (Some(cache), false) => cache.get_loudness(file).unwrap_or(None),
+                    _ => None,
+                };
+
+                let loudness = match cached {
+                    Some(loudness) => loudness,
+                    None => {
+                        let loudness = loudness::measure_file(file)?;
+                        if let Some(cache) = &cache {
+                            if let Err(e) = cache.set_loudness(file, &loudness) {
+                                logger::warning(&format!(
+                                    "Failed to cache loudness for {}: {}",
+                                    file.display(),
+                                    e
+                                ));
+                            }
+                        }
+                        *analyzed.lock().unwrap() += 1;
+                        loudness
+                    }
+                };
+
+                Ok(Track {
+                    path: file.clone(),
+                    metadata,
+                    loudness,
+                })
+            });
+
+            match result {
+                Ok(track) => Some(track),
+                Err(e) => {
+                    logger::error(&format!("Failed to analyze {}: {}", file.display(), e));
+                    let mut stats = stats_mutex.lock().unwrap();
+                    stats.processed += 1;
+                    stats.errors += 1;
+                    None
+                }
+            }
+        })
+        .collect();
+
+    pb.finish_and_clear();
+    logger::info(&format!(
+        "Analyzed {} files ({} from cache)",
+        tracks.len(),
+        tracks.len() - *analyzed.lock().unwrap()
+    ));
+
+    // Group tracks into albums
+    let mut albums: HashMap<String, Vec<Track>> = HashMap::new();
+    for track in tracks {
+        albums
+            .entry(album_key(&track, group_by_tag))
+            .or_default()
+            .push(track);
+    }
+    logger::info(&format!("Found {} albums", albums.len()));
+
+    let up_to_date = Mutex::new(0_usize);
+    albums.par_iter().for_each(|(_, tracks)| {
+        let measured: Vec<&TrackLoudness> = tracks.iter().map(|t| &t.loudness).collect();
+        let (album_loudness, album_peak) = loudness::album_loudness(&measured);
+
+        for track in tracks {
+            let mut stats = stats_mutex.lock().unwrap();
+            stats.processed += 1;
+
+            let Some(track_loudness) = track.loudness.integrated else {
+                stats.add_skipped(
+                    track.path.clone(),
+                    "silent, no loudness to normalize".to_string(),
+                );
+                continue;
+            };
+            drop(stats);
+
+            let album = album_loudness.map(|l| (l, album_peak));
+            let updates = gain_tags(track, track_loudness, album, &options.config);
+
+            logger::debug(
+                &format!(
+                    "{}: {:.1} LUFS, peak {:.6}",
+                    track.path.display(),
+                    track_loudness,
+                    track.loudness.true_peak
+                ),
+                options.verbose,
+            );
+
+            if updates
+                .iter()
+                .all(|(key, value)| track.metadata.tag_value(key) == *value)
+            {
+                *up_to_date.lock().unwrap() += 1;
+                stats_mutex.lock().unwrap().succeeded += 1;
+                continue;
+            }
+
+            if options.dry_run {
+                for (key, value) in &updates {
+                    logger::debug(
+                        &format!(
+                            "  Would set {} = {}",
+                            key,
+                            value.as_deref().unwrap_or("(removed)")
+                        ),
+                        options.verbose,
+                    );
+                }
+                stats_mutex.lock().unwrap().succeeded += 1;
+                continue;
+            }
+
+            match write_gain_tags(track, &updates) {
+                Ok(()) => stats_mutex.lock().unwrap().succeeded += 1,
+                Err(e) => {
+                    logger::error(&format!(
+                        "Failed to write ReplayGain tags to {}: {}",
+                        track.path.display(),
+                        e
+                    ));
+                    stats_mutex.lock().unwrap().errors += 1;
+                }
+            }
+        }
+    });
+
+    let up_to_date = *up_to_date.lock().unwrap();
+    if up_to_date > 0 {
+        logger::info(&format!("{} files already had up-to-date tags", up_to_date));
+    }
+
+    let stats = Arc::try_unwrap(stats_mutex).unwrap().into_inner().unwrap();
+    stats.print_summary("ReplayGain");
+    Ok(stats)
+}
+
+/// Album a track belongs to, falling back to its folder when the album tag is missing
+fn album_key(track: &Track, group_by_tag: bool) -> String {
+    let folder = || {
+        track
+            .path
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    if group_by_tag {
+        if let Some(album) = &track.metadata.album {
+            return format!(
+                "{}__{}",
+                utils::normalize_for_comparison(&track.metadata.get_organizing_artist(false)),
+                utils::normalize_for_comparison(album)
+            );
+        }
+    }
+    folder()
+}
+
+/// Tag values for a track; Opus gets R128 gains and loses any ReplayGain tags
+fn gain_tags(
+    track: &Track,
+    track_loudness: f64,
+    album: Option<(f64, f64)>,
+    config: &Config,
+) -> Vec<(&'static str, Option<String>)> {
+    let is_opus = track.metadata.codec == "opus"
+        || utils::get_extension(&track.path).as_deref() == Some("opus");
+
+    if is_opus {
+        let q78 = |loudness: f64| {
+            let gain = ((R128_REFERENCE - loudness) * 256.0).round();
+            (gain.clamp(i16::MIN as f64, i16::MAX as f64) as i16).to_string()
+        };
+        let mut updates = vec![
+            ("r128_track_gain", Some(q78(track_loudness))),
+            ("r128_album_gain", album.map(|(l, _)| q78(l))),
+        ];
+        updates.extend(REPLAYGAIN_KEYS.iter().map(|key| (*key, None)));
+        return updates;
+    }
+
+    let reference = config.replaygain.reference_loudness;
+    let gain = |loudness: f64| format!("{:.2} dB", reference - loudness);
+    let peak = |peak: f64| format!("{:.6}", peak);
+
+    let mut updates = vec![
+        ("replaygain_track_gain", Some(gain(track_loudness))),
+        (
+            "replaygain_track_peak",
+            Some(peak(track.loudness.true_peak)),
+        ),
+        ("replaygain_album_gain", album.map(|(l, _)| gain(l))),
+        ("replaygain_album_peak", album.map(|(_, p)| peak(p))),
+    ];
+    updates.extend(R128_KEYS.iter().map(|key| (*key, None)));
+    updates
+}
+
+/// Write the tags, record them in the journal and refresh the cache entry
+fn write_gain_tags(track: &Track, updates: &[(&'static str, Option<String>)]) -> Result<()> {
+    let path = &track.path;
+
+    // Only touch tags that change, removing ones the file doesn't have is a no-op
+    let changes: Vec<&(&str, Option<String>)> = updates
+        .iter()
+        .filter(|(key, value)| track.metadata.tag_value(key) != *value)
+        .collect();
+
+    let mut edit = TagEdit::new();
+    let mut before = serde_json::Map::new();
+    let mut after = serde_json::Map::new();
+    for (key, value) in &changes {
+        edit = match value {
+            Some(value) => edit.set(key, value.clone()),
+            None => edit.remove(key),
+        };
+        before.insert(key.to_string(), track.metadata.tag_value(key).into());
+        after.insert(key.to_string(), value.clone().into());
+    }
+
+    let before_hash = journal::hash_if_recording(path);
+    tags::write(path, &edit)?;
+    journal::record_tag_rewrite(path, before_hash, before, after);
+
+    // The write changed the file, so re-key the cache entry and keep the analysis
+    if let Some(cache) = cache::get_global_cache() {
+        let mut metadata = track.metadata.clone();
+        for (key, value) in &changes {
+            set_gain_field(&mut metadata, key, value.clone());
+        }
+        cache.insert(path, &metadata)?;
+        cache.set_loudness(path, &track.loudness)?;
+    }
+
+    Ok(())
+}
+
+fn set_gain_field(metadata: &mut AudioMetadata, key: &str, value: Option<String>) {
+    match key {
+        "replaygain_track_gain" => metadata.replaygain_track_gain = value,
+        "replaygain_track_peak" => metadata.replaygain_track_peak = value,
+        "replaygain_album_gain" => metadata.replaygain_album_gain = value,
+        "replaygain_album_peak" => metadata.replaygain_album_peak = value,
+        "r128_track_gain" => metadata.r128_track_gain = value,
+        "r128_album_gain" => metadata.r128_album_gain = value,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(path: &str, codec: &str, integrated: f64) -> Track {
+        Track {
+            path: PathBuf::from(path),
+            metadata: AudioMetadata {
+                codec: codec.to_string(),
+                album: Some("Record".to_string()),
+                artist: Some("Band".to_string()),
+                ..Default::default()
+            },
+            loudness: TrackLoudness {
+                integrated: Some(integrated),
+                true_peak: 0.5,
+                blocks: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_gain_tags() {
+        let config = Config::default();
+
+        let flac = track("/music/a/01.flac", "flac", -9.5);
+        let updates = gain_tags(&flac, -9.5, Some((-10.0, 0.9)), &config);
+        assert_eq!(
+            updates[0],
+            ("replaygain_track_gain", Some("-8.50 dB".to_string()))
+        );
+        assert_eq!(
+            updates[1],
+            ("replaygain_track_peak", Some("0.500000".to_string()))
+        );
+        assert_eq!(
+            updates[2],
+            ("replaygain_album_gain", Some("-8.00 dB".to_string()))
+        );
+        assert_eq!(
+            updates[3],
+            ("replaygain_album_peak", Some("0.900000".to_string()))
+        );
+        assert!(updates[4..].iter().all(|(_, v)| v.is_none()));
+
+        // Opus: Q7.8 relative to -23 LUFS
+        let opus = track("/music/a/01.opus", "opus", -13.0);
+        let updates = gain_tags(&opus, -13.0, None, &config);
+        assert_eq!(updates[0], ("r128_track_gain", Some("-2560".to_string())));
+        assert_eq!(updates[1], ("r128_album_gain", None));
+        assert!(updates[2..].iter().all(|(_, v)| v.is_none()));
+    }
+
+    #[test]
+    fn test_album_key() {
+        let a = track("/music/a/01.flac", "flac", -10.0);
+        let mut b = track("/music/b/02.flac", "flac", -10.0);
+
+        assert_ne!(album_key(&a, false), album_key(&b, false));
+        assert_eq!(album_key(&a, true), album_key(&b, true));
+
+        // Untagged albums fall back to the folder
+        b.metadata.album = None;
+        assert_eq!(album_key(&b, true), "/music/b");
+    }
+}
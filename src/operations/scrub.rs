@@ -0,0 +1,216 @@
+use crate::cache::{self, MetadataCache, StoredChecksums};
+use crate::checksum::{self, ContentChecksums};
+use crate::logger;
+use crate::metadata::AudioMetadata;
+use crate::operations::OperationStats;
+use crate::utils;
+use anyhow::{anyhow, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+pub struct ScrubOptions {
+    pub input_dir: PathBuf,
+    pub verbose: bool,
+    /// Stop starting new files after reading this many gigabytes
+    pub max_gb: Option<f64>,
+    /// Stop starting new files after this many minutes
+    pub max_minutes: Option<u64>,
+    /// Store the new checksums of files whose content changed
+    pub accept_changes: bool,
+}
+
+/// What happened to one file
+enum Outcome {
+    /// No checksums yet; they were recorded
+    Baseline,
+    /// Content matches the stored checksums
+    Intact,
+    /// Content changed although mtime and size didn't
+    Changed { audio_changed: bool },
+}
+
+/// Hash one file and compare it with its stored checksums. Files whose whole-file hash
+/// still matches don't need their audio stream hashed again.
+fn scrub_file(
+    cache: &MetadataCache,
+    file: &Path,
+    stored: Option<&StoredChecksums>,
+    accept_changes: bool,
+) -> Result<Outcome> {
+    let Some(stored) = stored else {
+        // Make sure the file has a cache row to hold its checksums
+        AudioMetadata::from_file(file)?;
+        cache.set_checksums(file, &checksum::compute(file)?)?;
+        return Ok(Outcome::Baseline);
+    };
+
+    let file_hash = utils::hash_file(file)?;
+    if file_hash == stored.checksums.file_hash {
+        cache.touch_checksums(file)?;
+        return Ok(Outcome::Intact);
+    }
+
+    // A file damaged badly enough may not parse at all
+    let audio_hash = checksum::hash_audio_stream(file).ok();
+    match (&audio_hash, accept_changes) {
+        (Some(audio_hash), true) => cache.set_checksums(
+            file,
+            &ContentChecksums {
+                file_hash,
+                audio_hash: audio_hash.clone(),
+            },
+        )?,
+        // Keep the old checksums so the file is reported until it's dealt with
+        _ => cache.touch_checksums(file)?,
+    }
+
+    Ok(Outcome::Changed {
+        audio_changed: audio_hash.as_ref() != Some(&stored.checksums.audio_hash),
+    })
+}
+
+/// Re-hash files and report the ones whose content changed behind the cache's back
+pub fn run(options: ScrubOptions) -> Result<OperationStats> {
+    logger::stage("Scrubbing library for silent corruption");
+    logger::info(&format!("Input directory: {}", options.input_dir.display()));
+
+    let cache = cache::get_global_cache()
+        .ok_or_else(|| anyhow!("Scrub needs the metadata cache to store checksums"))?;
+
+    let max_bytes = options
+        .max_gb
+        .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64);
+    let max_time = options.max_minutes.map(|m| Duration::from_secs(m * 60));
+    if let Some(gb) = options.max_gb {
+        logger::info(&format!("Budget: {} GB", gb));
+    }
+    if let Some(minutes) = options.max_minutes {
+        logger::info(&format!("Budget: {} minutes", minutes));
+    }
+
+    let files: Vec<PathBuf> = WalkDir::new(&options.input_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| utils::is_audio_file(p))
+        .collect();
+
+    logger::info(&format!("Found {} audio files", files.len()));
+
+    // Files never hashed come first, then the ones checked longest ago, so a run cut short
+    // by its budget is picked up by the next one
+    let mut queue: Vec<(PathBuf, Option<StoredChecksums>)> = files
+        .into_par_iter()
+        .map(|file| {
+            let stored = cache.get_checksums(&file).unwrap_or(None);
+            (file, stored)
+        })
+        .collect();
+    queue.sort_by_key(|(path, stored)| (stored.as_ref().map_or(0, |s| s.hashed_at), path.clone()));
+
+    let started = Instant::now();
+    let bytes_read = AtomicU64::new(0);
+    let stats_mutex = Mutex::new(OperationStats::new());
+    let changed = Mutex::new(Vec::new());
+    let baselines = AtomicU64::new(0);
+
+    let pb = ProgressBar::new(queue.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] [{bar:40}] {pos}/{len} ({eta}) | Hashing...")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+
+    queue.par_iter().for_each(|(file, stored)| {
+        pb.inc(1);
+
+        let over_budget = max_bytes.is_some_and(|max| bytes_read.load(Ordering::Relaxed) >= max)
+            || max_time.is_some_and(|max| started.elapsed() >= max);
+        if over_budget {
+            stats_mutex.lock().unwrap().skipped += 1;
+            return;
+        }
+
+        let size = fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+        bytes_read.fetch_add(size, Ordering::Relaxed);
+
+        let result = scrub_file(&cache, file, stored.as_ref(), options.accept_changes);
+
+        let mut stats = stats_mutex.lock().unwrap();
+        stats.processed += 1;
+        match result {
+            Ok(Outcome::Baseline) => {
+                baselines.fetch_add(1, Ordering::Relaxed);
+                stats.succeeded += 1;
+                logger::debug(&format!("New: {}", file.display()), options.verbose);
+            }
+            Ok(Outcome::Intact) => {
+                stats.succeeded += 1;
+                logger::debug(&format!("OK: {}", file.display()), options.verbose);
+            }
+            Ok(Outcome::Changed { audio_changed }) => {
+                stats.errors += 1;
+                changed.lock().unwrap().push((file.clone(), audio_changed));
+            }
+            Err(e) => {
+                stats.errors += 1;
+                logger::error(&format!("Failed to hash {}: {}", file.display(), e));
+            }
+        }
+    });
+
+    pb.finish_and_clear();
+
+    let stats = stats_mutex.into_inner().unwrap();
+    let mut changed = changed.into_inner().unwrap();
+    changed.sort();
+
+    logger::info(&format!(
+        "Hashed {} files ({:.2} GB) in {:.0?}, {} of them for the first time",
+        stats.processed,
+        bytes_read.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0 * 1024.0),
+        started.elapsed(),
+        baselines.load(Ordering::Relaxed)
+    ));
+    if stats.skipped > 0 {
+        logger::info(&format!(
+            "Budget reached; {} files are left for the next run",
+            stats.skipped
+        ));
+    }
+
+    if changed.is_empty() {
+        logger::success("No silent changes found");
+    } else {
+        logger::error(&format!(
+            "\n{} file(s) changed without their modification time or size changing:",
+            changed.len()
+        ));
+        for (path, audio_changed) in &changed {
+            let what = if *audio_changed {
+                "audio changed"
+            } else {
+                "audio intact, tags or container changed"
+            };
+            logger::error(&format!("  [CHANGED] {} ({})", path.display(), what));
+        }
+        if options.accept_changes {
+            logger::info("New checksums were stored for the ones that could still be read");
+        } else {
+            logger::info(
+                "Restore them from a backup, or run with --accept-changes to store their new checksums",
+            );
+        }
+    }
+
+    stats.print_summary("Scrub");
+    Ok(stats)
+}
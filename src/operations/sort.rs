@@ -1,15 +1,18 @@
 use crate::config::Config;
+use crate::journal;
 use crate::logger;
 use crate::metadata::AudioMetadata;
 use crate::operations::OperationStats;
 use crate::quality;
+use crate::template::PathTemplate;
+use crate::trash;
 use crate::utils;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
 
@@ -27,75 +30,211 @@ pub struct SortOptions {
 
 struct FileInfo {
     path: PathBuf,
-    metadata: AudioMetadata,
     quality: u32,
     dest_path: PathBuf,
 }
 
+/// Where the disc number of multi-disc albums goes in the default Artist/Album layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscLayout {
+    /// Album/Disc 2/03 - Title
+    Folder,
+    /// Album/2-03 - Title
+    Prefix,
+    /// Disc number is left out
+    None,
+}
+
+impl DiscLayout {
+    /// Read `[naming] disc_layout`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        match config.naming.disc_layout.to_lowercase().as_str() {
+            "folder" => Ok(DiscLayout::Folder),
+            "prefix" => Ok(DiscLayout::Prefix),
+            "none" => Ok(DiscLayout::None),
+            other => anyhow::bail!(
+                "Unknown [naming] disc_layout '{}' (expected 'folder', 'prefix' or 'none')",
+                other
+            ),
+        }
+    }
+
+    /// `Disc N` folder to put the track in (only for multi-disc albums)
+    pub fn folder(&self, metadata: &AudioMetadata, multi_disc: bool) -> Option<String> {
+        if *self == DiscLayout::Folder && multi_disc {
+            Some(format!("Disc {}", metadata.get_disc()))
+        } else {
+            None
+        }
+    }
+
+    /// File name with the disc number in front (only for multi-disc albums)
+    ///
+    /// Names that already carry the prefix are left alone so re-sorting doesn't stack them.
+    pub fn prefix_filename(
+        &self,
+        metadata: &AudioMetadata,
+        filename: &str,
+        multi_disc: bool,
+    ) -> String {
+        if *self == DiscLayout::Prefix && multi_disc {
+            let prefix = format!("{}-", metadata.get_disc());
+            if !filename.starts_with(&prefix) {
+                return format!("{}{}", prefix, filename);
+            }
+        }
+        filename.to_string()
+    }
+}
+
+/// Albums that span several discs, by organizing artist and album
+///
+/// Decided per album rather than per track: without a disc total only the tracks past the
+/// first disc give it away, and disc 1 still has to end up next to them.
+pub struct MultiDiscAlbums {
+    albums: HashSet<(String, String)>,
+    prefer_artist: bool,
+}
+
+impl MultiDiscAlbums {
+    pub fn collect<'a>(
+        tracks: impl IntoIterator<Item = &'a AudioMetadata>,
+        prefer_artist: bool,
+    ) -> Self {
+        let albums = tracks
+            .into_iter()
+            .filter(|m| m.is_multi_disc())
+            .map(|m| Self::key(m, prefer_artist))
+            .collect();
+        Self {
+            albums,
+            prefer_artist,
+        }
+    }
+
+    fn key(metadata: &AudioMetadata, prefer_artist: bool) -> (String, String) {
+        (
+            metadata.get_organizing_artist(prefer_artist).to_lowercase(),
+            metadata.get_album().to_lowercase(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.albums.is_empty()
+    }
+
+    /// Whether the album this track belongs to spans several discs
+    pub fn contains(&self, metadata: &AudioMetadata) -> bool {
+        self.albums.contains(&Self::key(metadata, self.prefer_artist))
+    }
+}
+
 /// Recursively remove empty parent directories up to (but not including) the root directory
 /// Also removes directories that only contain non-audio files (like leftover cover art)
-fn cleanup_empty_dirs(file_path: &PathBuf, root_dir: &PathBuf) {
+fn cleanup_empty_dirs(file_path: &Path, root_dir: &Path, config: &Config) {
     if let Some(parent) = file_path.parent() {
         // Use the shared utility function
-        utils::cleanup_empty_directory(parent, root_dir, false);
+        utils::cleanup_empty_directory(parent, root_dir, config, false);
     }
 }
 
+/// Normalize (if fix_naming is enabled), sanitize and clamp a single path component
+fn clean_component(name: &str, options: &SortOptions) -> String {
+    let name = if options.fix_naming {
+        utils::normalize_name(name, options.config.naming.lowercase)
+    } else {
+        name.to_string()
+    };
+
+    utils::clamp_component(&utils::sanitize(&name), options.config.naming.max_name_length)
+}
+
+/// Build the destination path for a file, relative to the output directory
+///
+/// With a path template the whole path (including the file name) comes from the template.
+/// Without one, files go to Artist/Album/ and keep their name unless fix_naming is enabled,
+/// with the disc number of multi-disc albums placed according to `disc_layout`.
+fn relative_destination(
+    file_path: &Path,
+    metadata: &AudioMetadata,
+    template: Option<&PathTemplate>,
+    disc_layout: DiscLayout,
+    multi_disc: bool,
+    options: &SortOptions,
+) -> PathBuf {
+    let ext = utils::get_extension(file_path).unwrap_or_else(|| "mp3".to_string());
+
+    if let Some(template) = template {
+        let mut components = template.render(metadata);
+        let stem = components.pop().unwrap_or_default();
+        let stem = if stem.trim().is_empty() {
+            metadata.get_title()
+        } else {
+            stem
+        };
+
+        // Directory components that render empty are dropped instead of becoming "_unknown"
+        let mut dest: PathBuf = components
+            .iter()
+            .filter(|c| !c.trim().is_empty())
+            .map(|c| clean_component(c, options))
+            .collect();
+        dest.push(format!("{}.{}", clean_component(&stem, options), ext));
+        return dest;
+    }
 
-/// Check if a file is already organized in the correct Artist/Album structure
-/// Returns true if the file's current path matches the expected path based on metadata
-fn is_already_organized(file_path: &PathBuf, metadata: &AudioMetadata, options: &SortOptions) -> bool {
-    // Get the expected artist and album from metadata
     let artist = metadata.get_organizing_artist(options.config.naming.prefer_artist);
     let album = metadata.get_album();
 
-    // Apply normalization if fix_naming is enabled
-    let (artist_expected, album_expected) = if options.fix_naming {
-        (utils::normalize_name(&artist, options.config.naming.lowercase), utils::normalize_name(&album, options.config.naming.lowercase))
+    let filename = if options.fix_naming {
+        let title = utils::normalize_name(&metadata.get_title(), options.config.naming.lowercase);
+        let title_safe = utils::sanitize(&title);
+
+        if let Some(track_num) = metadata.track_number {
+            format!("{:02} - {}.{}", track_num, title_safe, ext)
+        } else {
+            format!("{}.{}", title_safe, ext)
+        }
     } else {
-        (artist.clone(), album.clone())
+        file_path.file_name().unwrap().to_string_lossy().to_string()
     };
+    let filename = disc_layout.prefix_filename(metadata, &filename, multi_disc);
 
-    // Sanitize and clamp the names
-    let artist_expected = utils::clamp_component(
-        &utils::sanitize(&artist_expected),
-        options.config.naming.max_name_length,
-    );
-    let album_expected = utils::clamp_component(
-        &utils::sanitize(&album_expected),
-        options.config.naming.max_name_length,
-    );
-
-    // Get the current file's parent directories
-    let parent_album = match file_path.parent() {
-        Some(p) => p,
-        None => return false,
-    };
+    let mut dest =
+        PathBuf::from(clean_component(&artist, options)).join(clean_component(&album, options));
+    if let Some(folder) = disc_layout.folder(metadata, multi_disc) {
+        dest.push(folder);
+    }
+    dest.join(filename)
+}
 
-    let parent_artist = match parent_album.parent() {
-        Some(p) => p,
-        None => return false,
-    };
+/// Check if a file is already organized according to its expected destination
+/// Compares the file's parent folders (and, with a path template, its file name)
+/// case-insensitively against the expected relative destination
+fn is_already_organized(file_path: &Path, relative_dest: &Path, compare_filename: bool) -> bool {
+    let expected: Vec<String> = relative_dest
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+    let current: Vec<String> = file_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
 
-    // Extract the folder names
-    let current_album = match parent_album.file_name() {
-        Some(name) => name.to_string_lossy().to_string(),
-        None => return false,
-    };
+    if expected.is_empty() || current.len() < expected.len() {
+        return false;
+    }
 
-    let current_artist = match parent_artist.file_name() {
-        Some(name) => name.to_string_lossy().to_string(),
-        None => return false,
-    };
+    let current = &current[current.len() - expected.len()..];
+    let dir_count = expected.len() - 1;
 
-    // Compare case-insensitively
-    current_artist.to_lowercase() == artist_expected.to_lowercase()
-        && current_album.to_lowercase() == album_expected.to_lowercase()
+    current[..dir_count] == expected[..dir_count]
+        && (!compare_filename || current[dir_count] == expected[dir_count])
 }
 
-/// Sort files into Artist/Album folder structure based on metadata
+/// Sort files into Artist/Album folder structure (or the configured path template) based on metadata
 pub fn run(options: SortOptions) -> Result<OperationStats> {
-    logger::stage("Sorting files by metadata into Artist/Album structure");
+    logger::stage("Sorting files by metadata");
     logger::info(&format!("Input directory: {}", options.input_dir.display()));
     logger::info(&format!("Output directory: {}", options.output_dir.display()));
 
@@ -106,13 +245,32 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
     }
 
     if options.destructive {
-        logger::warning("DESTRUCTIVE MODE - Will delete lower quality duplicate files");
+        if options.config.trash.enabled {
+            logger::warning("DESTRUCTIVE MODE - Will move lower quality duplicate files to the trash");
+        } else {
+            logger::warning("DESTRUCTIVE MODE - Will delete lower quality duplicate files");
+        }
     }
 
     if options.fix_naming {
         logger::info("Will normalize file and folder names");
     }
 
+    let template = options
+        .config
+        .naming
+        .path_template
+        .as_deref()
+        .map(PathTemplate::parse)
+        .transpose()
+        .context("Invalid [naming] path_template")?;
+
+    if let Some(ref raw) = options.config.naming.path_template {
+        logger::info(&format!("Using path template: {}", raw));
+    }
+
+    let disc_layout = DiscLayout::from_config(&options.config)?;
+
     if options.dry_run {
         logger::warning("DRY RUN MODE - No files will be modified");
     }
@@ -139,14 +297,14 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
             .progress_chars("█▓▒░"),
     );
 
-    // Build all file info in parallel
-    let file_infos: Vec<FileInfo> = files
+    // Read all metadata in parallel
+    let tracks: Vec<(PathBuf, AudioMetadata)> = files
         .par_iter()
         .filter_map(|file| {
             pb.inc(1);
 
-            let metadata = match AudioMetadata::from_file(file) {
-                Ok(m) => m,
+            match AudioMetadata::from_file(file) {
+                Ok(metadata) => Some((file.clone(), metadata)),
                 Err(e) => {
                     logger::error(&format!(
                         "Failed to read metadata from {}: {}",
@@ -155,12 +313,41 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
                     ));
                     let mut stats = stats_mutex.lock().unwrap();
                     stats.errors += 1;
-                    return None;
+                    None
                 }
-            };
+            }
+        })
+        .collect();
+
+    pb.finish_and_clear();
+
+    let multi_disc = MultiDiscAlbums::collect(
+        tracks.iter().map(|(_, metadata)| metadata),
+        options.config.naming.prefer_artist,
+    );
+    let template_lacks_disc = template.as_ref().is_some_and(|t| !t.uses_field("disc"));
+    if template_lacks_disc && !multi_disc.is_empty() {
+        logger::warning(
+            "Found multi-disc albums but the path template doesn't use $disc - \
+             tracks from different discs may end up at the same path",
+        );
+    }
+
+    // Build all file info in parallel
+    let file_infos: Vec<FileInfo> = tracks
+        .par_iter()
+        .filter_map(|(file, metadata)| {
+            let relative_dest = relative_destination(
+                file,
+                metadata,
+                template.as_ref(),
+                disc_layout,
+                multi_disc.contains(metadata),
+                &options,
+            );
 
             // Skip files that are already organized unless force is enabled
-            if !options.force && is_already_organized(file, &metadata, &options) {
+            if !options.force && is_already_organized(file, &relative_dest, template.is_some()) {
                 logger::debug(
                     &format!("File already organized, skipping: {}", file.display()),
                     options.verbose,
@@ -170,65 +357,24 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
                 return None;
             }
 
-            let quality = quality::calculate_quality_score(&metadata, &options.config);
+            let quality = quality::calculate_quality_score(metadata, &options.config);
 
-            let artist = metadata.get_organizing_artist(options.config.naming.prefer_artist);
-            let album = metadata.get_album();
-            let title = metadata.get_title();
-
-            // Determine destination path
-            let (artist_final, album_final, title_final) = if options.fix_naming {
-                (
-                    utils::normalize_name(&artist, options.config.naming.lowercase),
-                    utils::normalize_name(&album, options.config.naming.lowercase),
-                    utils::normalize_name(&title, options.config.naming.lowercase),
-                )
-            } else {
-                (artist.clone(), album.clone(), title.clone())
-            };
-
-            let artist_safe = utils::clamp_component(
-                &utils::sanitize(&artist_final),
-                options.config.naming.max_name_length,
-            );
-            let album_safe = utils::clamp_component(
-                &utils::sanitize(&album_final),
-                options.config.naming.max_name_length,
-            );
-
-            let filename = if options.fix_naming {
-                let title_safe = utils::sanitize(&title_final);
-                let ext = utils::get_extension(file).unwrap_or_else(|| "mp3".to_string());
-
-                if let Some(track_num) = metadata.track_number {
-                    format!("{:02} - {}.{}", track_num, title_safe, ext)
-                } else {
-                    format!("{}.{}", title_safe, ext)
-                }
-            } else {
-                file.file_name().unwrap().to_string_lossy().to_string()
-            };
-
-            let dest_dir = options.output_dir.join(&artist_safe).join(&album_safe);
-            let dest_path = dest_dir.join(&filename);
+            let dest_path = options.output_dir.join(&relative_dest);
 
             Some(FileInfo {
                 path: file.clone(),
-                metadata,
                 quality,
                 dest_path,
             })
         })
         .collect();
 
-    pb.finish_and_clear();
-
     // Group files by destination to detect duplicates
     let mut dest_map: HashMap<PathBuf, Vec<FileInfo>> = HashMap::new();
     for file_info in file_infos {
         dest_map
             .entry(file_info.dest_path.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(file_info);
     }
 
@@ -276,19 +422,19 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
             if options.destructive && !options.dry_run {
                 for file in files.iter() {
                     if file.path != best_file.path {
-                        if let Err(e) = fs::remove_file(&file.path) {
+                        if let Err(e) = trash::discard(&file.path, &options.input_dir, &options.config) {
                             logger::error(&format!(
-                                "Failed to delete duplicate file {}: {}",
+                                "Failed to remove duplicate file {}: {}",
                                 file.path.display(),
                                 e
                             ));
                         } else {
                             logger::debug(
-                                &format!("Deleted duplicate file: {}", file.path.display()),
+                                &format!("Removed duplicate file: {}", file.path.display()),
                                 options.verbose,
                             );
                             // Clean up any empty directories left behind
-                            cleanup_empty_dirs(&file.path, &options.input_dir);
+                            cleanup_empty_dirs(&file.path, &options.input_dir, &options.config);
                         }
                     }
                 }
@@ -328,6 +474,19 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
                         ),
                         options.verbose,
                     );
+
+                    // Keep the replaced file recoverable instead of overwriting it
+                    if options.config.trash.enabled && !options.dry_run {
+                        if let Err(e) = trash::discard(dest_path, &options.output_dir, &options.config) {
+                            logger::error(&format!(
+                                "Failed to move replaced file {} to the trash: {}",
+                                dest_path.display(),
+                                e
+                            ));
+                            stats.errors += 1;
+                            return;
+                        }
+                    }
                 } else if file_to_use.quality < existing_quality {
                     logger::debug(
                         &format!(
@@ -345,19 +504,19 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
 
                     // Delete lower quality file if destructive mode is enabled
                     if options.destructive && !options.dry_run {
-                        if let Err(e) = fs::remove_file(&file_to_use.path) {
+                        if let Err(e) = trash::discard(&file_to_use.path, &options.input_dir, &options.config) {
                             logger::error(&format!(
-                                "Failed to delete lower quality file {}: {}",
+                                "Failed to remove lower quality file {}: {}",
                                 file_to_use.path.display(),
                                 e
                             ));
                         } else {
                             logger::debug(
-                                &format!("Deleted lower quality file: {}", file_to_use.path.display()),
+                                &format!("Removed lower quality file: {}", file_to_use.path.display()),
                                 options.verbose,
                             );
                             // Clean up any empty directories left behind
-                            cleanup_empty_dirs(&file_to_use.path, &options.input_dir);
+                            cleanup_empty_dirs(&file_to_use.path, &options.input_dir, &options.config);
                         }
                     }
 
@@ -380,7 +539,7 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
         } else {
             // Create destination directory
             if let Some(parent) = dest_path.parent() {
-                if let Err(e) = fs::create_dir_all(parent) {
+                if let Err(e) = journal::create_dir_all(parent) {
                     logger::error(&format!(
                         "Failed to create directory {}: {}",
                         parent.display(),
@@ -393,9 +552,9 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
 
             // Perform copy or move
             let result = if options.do_move {
-                fs::rename(&file_to_use.path, dest_path)
+                journal::rename(&file_to_use.path, dest_path)
             } else {
-                fs::copy(&file_to_use.path, dest_path).map(|_| ())
+                journal::copy(&file_to_use.path, dest_path).map(|_| ())
             };
 
             match result {
@@ -413,7 +572,7 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
 
                     // If we moved the file, clean up any empty directories left behind
                     if options.do_move {
-                        cleanup_empty_dirs(&file_to_use.path, &options.input_dir);
+                        cleanup_empty_dirs(&file_to_use.path, &options.input_dir, &options.config);
                     }
                 }
                 Err(e) => {
@@ -447,3 +606,154 @@ pub fn run(options: SortOptions) -> Result<OperationStats> {
     stats.print_summary("Sort");
     Ok(stats)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options_with(fix_naming: bool, template: Option<&str>) -> SortOptions {
+        let mut config = Config::default();
+        config.naming.lowercase = false;
+        config.naming.path_template = template.map(String::from);
+
+        SortOptions {
+            input_dir: PathBuf::from("/in"),
+            output_dir: PathBuf::from("/out"),
+            do_move: false,
+            fix_naming,
+            dry_run: true,
+            verbose: false,
+            force: false,
+            destructive: false,
+            config,
+        }
+    }
+
+    fn sample() -> AudioMetadata {
+        AudioMetadata {
+            artist: Some("AC/DC".to_string()),
+            album: Some("Back in Black".to_string()),
+            title: Some("Hells Bells".to_string()),
+            track_number: Some(1),
+            date: Some("1980".to_string()),
+            codec: "flac".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_relative_destination_legacy() {
+        let file = Path::new("/in/misc/01.flac");
+
+        let options = options_with(false, None);
+        assert_eq!(
+            relative_destination(file, &sample(), None, DiscLayout::Folder, false, &options),
+            PathBuf::from("AC–DC/Back in Black/01.flac")
+        );
+
+        let options = options_with(true, None);
+        assert_eq!(
+            relative_destination(file, &sample(), None, DiscLayout::Folder, false, &options),
+            PathBuf::from("AC–DC/Back in Black/01 - Hells Bells.flac")
+        );
+    }
+
+    #[test]
+    fn test_relative_destination_template() {
+        let raw = "${albumartist|artist}/[$genre/]$year - $album/[$disc-]$track $title";
+        let options = options_with(false, Some(raw));
+        let template = PathTemplate::parse(raw).unwrap();
+        let file = Path::new("/in/misc/01.flac");
+
+        assert_eq!(
+            relative_destination(file, &sample(), Some(&template), DiscLayout::Folder, false, &options),
+            PathBuf::from("AC–DC/1980 - Back in Black/01 Hells Bells.flac")
+        );
+    }
+
+    #[test]
+    fn test_relative_destination_multi_disc() {
+        let file = Path::new("/in/misc/01.flac");
+        let mut metadata = sample();
+        metadata.disc_number = Some(2);
+        metadata.disc_total = Some(2);
+
+        let options = options_with(true, None);
+        assert_eq!(
+            relative_destination(file, &metadata, None, DiscLayout::Folder, true, &options),
+            PathBuf::from("AC–DC/Back in Black/Disc 2/01 - Hells Bells.flac")
+        );
+        assert_eq!(
+            relative_destination(file, &metadata, None, DiscLayout::Prefix, true, &options),
+            PathBuf::from("AC–DC/Back in Black/2-01 - Hells Bells.flac")
+        );
+        assert_eq!(
+            relative_destination(file, &metadata, None, DiscLayout::None, true, &options),
+            PathBuf::from("AC–DC/Back in Black/01 - Hells Bells.flac")
+        );
+
+        // Kept file names get the prefix once
+        let options = options_with(false, None);
+        assert_eq!(
+            relative_destination(
+                Path::new("/in/2-01.flac"),
+                &metadata,
+                None,
+                DiscLayout::Prefix,
+                true,
+                &options
+            ),
+            PathBuf::from("AC–DC/Back in Black/2-01.flac")
+        );
+
+        // Single-disc albums are left alone
+        assert_eq!(
+            relative_destination(file, &sample(), None, DiscLayout::Folder, false, &options),
+            PathBuf::from("AC–DC/Back in Black/01.flac")
+        );
+    }
+
+    #[test]
+    fn test_multi_disc_albums() {
+        // No disc totals: only the disc 2 track tells the album spans several discs
+        let mut disc1 = sample();
+        disc1.disc_number = Some(1);
+        let mut disc2 = sample();
+        disc2.disc_number = Some(2);
+        let mut other = sample();
+        other.album = Some("Highway to Hell".to_string());
+        other.disc_number = Some(1);
+
+        let albums = MultiDiscAlbums::collect([&disc1, &disc2, &other], false);
+        assert!(albums.contains(&disc1));
+        assert!(albums.contains(&disc2));
+        assert!(!albums.contains(&other));
+
+        let mut total = sample();
+        total.disc_number = Some(1);
+        total.disc_total = Some(1);
+        assert!(MultiDiscAlbums::collect([&total], false).is_empty());
+    }
+
+    #[test]
+    fn test_is_already_organized() {
+        let dest = Path::new("AC–DC/Back in Black/01 - Hells Bells.flac");
+
+        assert!(is_already_organized(
+            Path::new("/music/ac–dc/back in black/hells bells.flac"),
+            dest,
+            false
+        ));
+        assert!(!is_already_organized(
+            Path::new("/music/ac–dc/back in black/hells bells.flac"),
+            dest,
+            true
+        ));
+        assert!(is_already_organized(
+            Path::new("/music/AC–DC/Back in Black/01 - Hells Bells.flac"),
+            dest,
+            true
+        ));
+        assert!(!is_already_organized(Path::new("/music/Back in Black/01.flac"), dest, false));
+    }
+}
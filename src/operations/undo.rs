@@ -0,0 +1,442 @@
+use crate::journal::{self, Action, JournalEntry};
+use crate::logger;
+use crate::operations::OperationStats;
+use crate::tags::{self, TagEdit};
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+
+pub struct UndoOptions {
+    pub session_id: i64,
+    pub dry_run: bool,
+    pub verbose: bool,
+}
+
+/// Outcome of rolling back a single journal entry
+enum Outcome {
+    Undone,
+    /// Could not be rolled back, with the reason
+    Skipped(String),
+}
+
+/// Roll back every operation recorded in a journal session, newest first
+pub fn run(options: UndoOptions) -> Result<OperationStats> {
+    let journal = journal::get_global_journal()
+        .ok_or_else(|| anyhow!("Operation journal is not enabled"))?;
+
+    let session = journal
+        .session(options.session_id)?
+        .ok_or_else(|| anyhow!("No journal session #{}", options.session_id))?;
+
+    logger::stage(&format!(
+        "Undoing session #{} ({}, {} operations)",
+        session.id, session.command, session.entry_count
+    ));
+
+    if options.dry_run {
+        logger::warning("DRY RUN MODE - No files will be modified");
+    }
+
+    let mut stats = OperationStats::new();
+    let entries = journal.entries(session.id)?;
+
+    for entry in entries.iter().rev().filter(|e| !e.undone) {
+        stats.processed += 1;
+
+        if options.dry_run {
+            logger::info(&format!("Would undo: {}", entry.describe()));
+            stats.succeeded += 1;
+            continue;
+        }
+
+        match undo_entry(entry, options.verbose) {
+            Ok(Outcome::Undone) => {
+                logger::debug(&format!("Undone: {}", entry.describe()), options.verbose);
+                journal.mark_undone(entry.id)?;
+                stats.succeeded += 1;
+            }
+            Ok(Outcome::Skipped(reason)) => {
+                let path = entry.source.clone().or_else(|| entry.dest.clone()).unwrap_or_default();
+                stats.add_skipped(path, reason);
+            }
+            Err(e) => {
+                logger::error(&format!("Failed to undo {}: {}", entry.describe(), e));
+                stats.errors += 1;
+            }
+        }
+    }
+
+    if !options.dry_run {
+        let status = if stats.skipped == 0 && stats.errors == 0 {
+            "undone"
+        } else {
+            "partially undone"
+        };
+        journal.set_session_status(session.id, status)?;
+    }
+
+    stats.print_summary("Undo");
+    Ok(stats)
+}
+
+/// True if the file still has the content recorded in the journal
+fn unchanged(path: &Path, expected_hash: &Option<String>) -> bool {
+    match expected_hash {
+        Some(expected) => utils::hash_file(path).map(|h| &h == expected).unwrap_or(false),
+        None => true,
+    }
+}
+
+fn replaced_warning(entry: &JournalEntry) {
+    if entry.details["replaced"].as_bool().unwrap_or(false) {
+        logger::warning(&format!(
+            "{} overwrote a file that wasn't kept in the trash, which cannot be restored",
+            entry.describe()
+        ));
+    }
+}
+
+fn undo_entry(entry: &JournalEntry, verbose: bool) -> Result<Outcome> {
+    match entry.action {
+        // Trashed files are moved back like any other move
+        Action::Move | Action::Trash => {
+            let (source, dest) = match (&entry.source, &entry.dest) {
+                (Some(s), Some(d)) => (s, d),
+                _ => return Ok(Outcome::Skipped("incomplete journal entry".to_string())),
+            };
+
+            if !dest.exists() {
+                return Ok(Outcome::Skipped(format!("{} no longer exists", dest.display())));
+            }
+            if source.exists() {
+                return Ok(Outcome::Skipped(format!(
+                    "original location {} is occupied",
+                    source.display()
+                )));
+            }
+            if !unchanged(dest, &entry.after_hash) {
+                return Ok(Outcome::Skipped("file changed since the session".to_string()));
+            }
+
+            if let Some(parent) = source.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            fs::rename(dest, source)?;
+            // Folders the session created are removed by their own entries; inside the trash
+            // the batch's folders are cleaned up here
+            if let Some(batch) = entry.details["batch"].as_str() {
+                utils::remove_empty_dirs(dest.parent(), Path::new(batch));
+            }
+            replaced_warning(entry);
+            Ok(Outcome::Undone)
+        }
+
+        Action::Copy | Action::Create => {
+            let Some(dest) = &entry.dest else {
+                return Ok(Outcome::Skipped("incomplete journal entry".to_string()));
+            };
+
+            if !dest.exists() {
+                return Ok(Outcome::Undone);
+            }
+            let expected = if entry.action == Action::Copy {
+                &entry.before_hash
+            } else {
+                &entry.after_hash
+            };
+            if !unchanged(dest, expected) {
+                return Ok(Outcome::Skipped("file changed since the session".to_string()));
+            }
+            // A conversion output is all that's left when its original was deleted for good
+            if let Some(from) = entry.details["from"].as_str() {
+                if !Path::new(from).exists() {
+                    return Ok(Outcome::Skipped(format!(
+                        "{} was made from {}, which cannot be restored",
+                        dest.display(),
+                        from
+                    )));
+                }
+            }
+
+            fs::remove_file(dest)?;
+            replaced_warning(entry);
+            Ok(Outcome::Undone)
+        }
+
+        Action::Delete => {
+            let Some(source) = &entry.source else {
+                return Ok(Outcome::Skipped("incomplete journal entry".to_string()));
+            };
+
+            // Deleted symlinks can be recreated from the recorded target
+            if let Some(target) = entry.details["symlink_target"].as_str() {
+                if fs::symlink_metadata(source).is_ok() {
+                    return Ok(Outcome::Skipped(format!("{} already exists", source.display())));
+                }
+                std::os::unix::fs::symlink(target, source)?;
+                return Ok(Outcome::Undone);
+            }
+
+            Ok(Outcome::Skipped("deleted files cannot be restored".to_string()))
+        }
+
+        Action::Symlink => {
+            let Some(link) = &entry.dest else {
+                return Ok(Outcome::Skipped("incomplete journal entry".to_string()));
+            };
+
+            match fs::symlink_metadata(link) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    fs::remove_file(link)?;
+                    Ok(Outcome::Undone)
+                }
+                Ok(_) => Ok(Outcome::Skipped(format!(
+                    "{} is no longer a symlink",
+                    link.display()
+                ))),
+                Err(_) => Ok(Outcome::Undone),
+            }
+        }
+
+        Action::CreateDir => {
+            let Some(dir) = &entry.dest else {
+                return Ok(Outcome::Skipped("incomplete journal entry".to_string()));
+            };
+
+            if !dir.exists() || fs::remove_dir(dir).is_ok() {
+                Ok(Outcome::Undone)
+            } else {
+                Ok(Outcome::Skipped(format!("{} is not empty", dir.display())))
+            }
+        }
+
+        Action::TagRewrite => {
+            let Some(path) = &entry.source else {
+                return Ok(Outcome::Skipped("incomplete journal entry".to_string()));
+            };
+
+            if !path.exists() {
+                return Ok(Outcome::Skipped(format!("{} no longer exists", path.display())));
+            }
+            if !unchanged(path, &entry.after_hash) {
+                return Ok(Outcome::Skipped("file changed since the session".to_string()));
+            }
+
+            let Some(before) = entry.details["before"].as_object() else {
+                return Ok(Outcome::Skipped("no previous tag values recorded".to_string()));
+            };
+
+            restore_tags(path, before, verbose)?;
+            Ok(Outcome::Undone)
+        }
+    }
+}
+
+/// Write previous tag values back
+/// Tags that did not exist before are cleared; a recorded cover embed is stripped
+fn restore_tags(
+    path: &Path,
+    before: &serde_json::Map<String, serde_json::Value>,
+    verbose: bool,
+) -> Result<()> {
+    tags::write(path, &TagEdit::from_journal(before))?;
+    logger::debug(&format!("Restored tags: {}", path.display()), verbose);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn entry(action: Action, source: Option<&Path>, dest: Option<&Path>) -> JournalEntry {
+        JournalEntry {
+            id: 1,
+            session_id: 1,
+            action,
+            source: source.map(Path::to_path_buf),
+            dest: dest.map(Path::to_path_buf),
+            before_hash: None,
+            after_hash: None,
+            details: serde_json::Value::Null,
+            undone: false,
+        }
+    }
+
+    #[test]
+    fn test_undo_move() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("a/song.flac");
+        let dest = temp.path().join("b/song.flac");
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&dest, b"audio").unwrap();
+
+        let mut e = entry(Action::Move, Some(&source), Some(&dest));
+        e.after_hash = Some(utils::hash_file(&dest).unwrap());
+
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Undone));
+        assert!(source.exists());
+        assert!(!dest.exists());
+        // Folders the session didn't create are kept
+        assert!(temp.path().join("b").exists());
+    }
+
+    #[test]
+    fn test_undo_move_skips_changed_file() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("song.flac");
+        let dest = temp.path().join("moved.flac");
+        fs::write(&dest, b"changed").unwrap();
+
+        let mut e = entry(Action::Move, Some(&source), Some(&dest));
+        e.after_hash = Some("not-the-hash".to_string());
+
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Skipped(_)));
+        assert!(dest.exists());
+    }
+
+    #[test]
+    fn test_undo_copy_and_symlink() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("song.flac");
+        let copy = temp.path().join("copy.flac");
+        let link = temp.path().join("link.flac");
+        fs::write(&source, b"audio").unwrap();
+        fs::write(&copy, b"audio").unwrap();
+        std::os::unix::fs::symlink(&source, &link).unwrap();
+
+        let mut e = entry(Action::Copy, Some(&source), Some(&copy));
+        e.before_hash = Some(utils::hash_file(&source).unwrap());
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Undone));
+        assert!(!copy.exists());
+        assert!(source.exists());
+
+        let e = entry(Action::Symlink, None, Some(&link));
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Undone));
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn test_undo_delete() {
+        let temp = TempDir::new().unwrap();
+        let file = temp.path().join("gone.flac");
+
+        let e = entry(Action::Delete, Some(&file), None);
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Skipped(_)));
+
+        let link = temp.path().join("link.flac");
+        let mut e = entry(Action::Delete, Some(&link), None);
+        e.details = json!({ "symlink_target": "target.flac" });
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Undone));
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("target.flac"));
+    }
+
+    #[test]
+    fn test_journaled_session_undo() {
+        let temp = TempDir::new().unwrap();
+        let mut journal = Journal::new(temp.path().join("journal.db"), true).unwrap();
+        let session = journal.begin_session("sort").unwrap();
+
+        let source = temp.path().join("in/song.flac");
+        let output = temp.path().join("out");
+        let dest = output.join("Artist/song.flac");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        fs::write(&source, b"audio").unwrap();
+
+        // The session creates the artist folder inside an existing output root
+        fs::create_dir(dest.parent().unwrap()).unwrap();
+        journal
+            .record(Action::CreateDir, None, dest.parent(), None, None, serde_json::Value::Null)
+            .unwrap();
+        let hash = journal.hash(&source);
+        fs::rename(&source, &dest).unwrap();
+        journal
+            .record(Action::Move, Some(&source), Some(&dest), hash.clone(), hash, serde_json::Value::Null)
+            .unwrap();
+        journal.finish_session("completed").unwrap();
+
+        for e in journal.entries(session).unwrap().iter().rev() {
+            assert!(matches!(undo_entry(e, false).unwrap(), Outcome::Undone));
+        }
+        assert!(source.exists());
+        assert!(!dest.exists());
+        assert!(!dest.parent().unwrap().exists());
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_undo_convert_with_delete_original() {
+        let temp = TempDir::new().unwrap();
+        let mut journal = Journal::new(temp.path().join("journal.db"), true).unwrap();
+        let original = temp.path().join("in/song.flac");
+        let output = temp.path().join("out/song.opus");
+        let batch = temp.path().join("trash/batch");
+        let trashed = batch.join("song.flac");
+        fs::create_dir_all(original.parent().unwrap()).unwrap();
+        fs::create_dir_all(output.parent().unwrap()).unwrap();
+        fs::create_dir_all(&batch).unwrap();
+
+        let convert = |journal: &mut Journal, trash: bool| {
+            let session = journal.begin_session("convert").unwrap();
+            fs::write(&original, b"lossless").unwrap();
+            fs::write(&output, b"lossy").unwrap();
+            let from = json!({ "from": original.to_string_lossy() });
+            let hash = journal.hash(&output);
+            journal
+                .record(Action::Create, None, Some(&output), None, hash, from)
+                .unwrap();
+            let hash = journal.hash(&original);
+            if trash {
+                fs::rename(&original, &trashed).unwrap();
+                let details = json!({ "batch": batch.to_string_lossy() });
+                journal
+                    .record(Action::Trash, Some(&original), Some(&trashed), hash.clone(), hash, details)
+                    .unwrap();
+            } else {
+                fs::remove_file(&original).unwrap();
+                journal
+                    .record(Action::Delete, Some(&original), None, hash, None, serde_json::Value::Null)
+                    .unwrap();
+            }
+            journal.finish_session("completed").unwrap();
+            journal.entries(session).unwrap()
+        };
+
+        // The original comes back from the trash, so the output can go
+        for e in convert(&mut journal, true).iter().rev() {
+            assert!(matches!(undo_entry(e, false).unwrap(), Outcome::Undone));
+        }
+        assert!(original.exists());
+        assert!(!output.exists());
+
+        // Without the trash the output is the only copy left, so it's kept
+        for e in convert(&mut journal, false).iter().rev() {
+            assert!(matches!(undo_entry(e, false).unwrap(), Outcome::Skipped(_)));
+        }
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_undo_trash_keeps_batch() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("library/song.flac");
+        let batch = temp.path().join("trash/batch");
+        let trashed = batch.join("Artist/Album/song.flac");
+        fs::create_dir_all(trashed.parent().unwrap()).unwrap();
+        fs::write(&trashed, b"audio").unwrap();
+
+        let mut e = entry(Action::Trash, Some(&source), Some(&trashed));
+        e.details = json!({ "batch": batch.to_string_lossy() });
+
+        assert!(matches!(undo_entry(&e, false).unwrap(), Outcome::Undone));
+        assert!(source.exists());
+        assert!(!batch.join("Artist").exists());
+        assert!(batch.exists());
+    }
+}
@@ -55,7 +55,7 @@ pub fn run(options: UnifiedOptions) -> Result<()> {
     // Confirm with user
     if !options.dry_run && !options.yes {
         logger::warning("\nYou are about to run the unified pipeline:");
-        logger::info("  1. Sort into Artist/Album structure (quality-aware)");
+        logger::info("  1. Sort by metadata (quality-aware)");
         if should_convert {
             let format = options.output_format.as_ref().unwrap().to_uppercase();
             logger::info(&format!("  2. Convert to {} (only higher quality)", format));
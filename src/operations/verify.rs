@@ -0,0 +1,129 @@
+use crate::cache;
+use crate::integrity::{self, IntegrityCheck};
+use crate::logger;
+use crate::metadata::AudioMetadata;
+use crate::operations::OperationStats;
+use crate::utils;
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+pub struct VerifyOptions {
+    pub input_dir: PathBuf,
+    pub verbose: bool,
+    /// Verify files again even when the cache has a result for them
+    pub force: bool,
+}
+
+/// Decode every file completely and report the ones that are damaged
+pub fn run(options: VerifyOptions) -> Result<OperationStats> {
+    logger::stage("Verifying audio integrity");
+    logger::info(&format!("Input directory: {}", options.input_dir.display()));
+
+    let files: Vec<PathBuf> = WalkDir::new(&options.input_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| utils::is_audio_file(p))
+        .collect();
+
+    logger::info(&format!("Found {} audio files", files.len()));
+
+    let checked = Mutex::new(0_usize);
+    let cache = cache::get_global_cache();
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] [{bar:40}] {pos}/{len} ({eta}) | Decoding...")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+
+    let mut results: Vec<(PathBuf, IntegrityCheck)> = files
+        .par_iter()
+        .map(|file| {
+            pb.inc(1);
+
+            // A file whose tags can't be read is still decoded; only its result isn't cached.
+            // Probing a malformed file can panic just like decoding it.
+            let metadata = match integrity::isolate(|| AudioMetadata::from_file(file)) {
+                Ok(metadata) => metadata.ok(),
+                Err(error) => {
+                    *checked.lock().unwrap() += 1;
+                    return (file.clone(), IntegrityCheck::crashed(error));
+                }
+            };
+            if let Some(check) = metadata.as_ref().and_then(|m| m.integrity.clone()) {
+                if !options.force {
+                    return (file.clone(), check);
+                }
+            }
+
+            let check = integrity::check_file(file);
+            *checked.lock().unwrap() += 1;
+
+            if let (Some(cache), Some(mut metadata)) = (&cache, metadata) {
+                metadata.integrity = Some(check.clone());
+                if let Err(e) = cache.insert(file, &metadata) {
+                    logger::warning(&format!(
+                        "Failed to cache integrity check for {}: {}",
+                        file.display(),
+                        e
+                    ));
+                }
+            }
+
+            (file.clone(), check)
+        })
+        .collect();
+
+    pb.finish_and_clear();
+    logger::info(&format!(
+        "Verified {} files ({} from cache)",
+        results.len(),
+        results.len() - *checked.lock().unwrap()
+    ));
+
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut stats = OperationStats::new();
+    let mut checksums = 0;
+    for (path, check) in &results {
+        stats.processed += 1;
+        if check.checksum_verified {
+            checksums += 1;
+        }
+
+        if check.passed() {
+            stats.succeeded += 1;
+            logger::debug(&format!("OK: {}", path.display()), options.verbose);
+        } else {
+            stats.errors += 1;
+            logger::error(&format!("[DAMAGED] {}", path.display()));
+            for problem in &check.problems {
+                logger::plain(&format!("    - {}", problem));
+            }
+        }
+    }
+
+    logger::info(&format!(
+        "\n{} files had a stored checksum to compare against",
+        checksums
+    ));
+    if stats.errors == 0 {
+        logger::success("All files decoded cleanly");
+    } else {
+        logger::warning(&format!(
+            "{} damaged file(s) found; dedupe will not keep them over a healthy copy",
+            stats.errors
+        ));
+    }
+
+    stats.print_summary("Verification");
+    Ok(stats)
+}
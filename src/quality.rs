@@ -1,8 +1,12 @@
 use crate::config::Config;
 use crate::metadata::AudioMetadata;
+use crate::spectrum::Verdict;
 use crate::utils;
 use std::path::Path;
 
+/// Bitrate of 16-bit/44.1 kHz stereo PCM
+const CD_KBPS: u32 = 1411;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AudioFormat {
     Lossless,
@@ -23,11 +27,14 @@ pub enum AudioFormat {
 /// - Vorbis/OGG: 1.2×
 /// - MP3: 1.0× (baseline)
 /// - WMA: 0.9× (suboptimal)
+///
+/// Files `ferric analyze` flagged as transcodes are scored as the MP3 their cutoff
+/// suggests they came from, and upsampled files as CD quality lossless.
 pub fn calculate_quality_score(metadata: &AudioMetadata, config: &Config) -> u32 {
     let format = get_audio_format(&metadata.codec);
     let bitrate_kbps = metadata.get_bitrate_kbps().unwrap_or(0);
 
-    match format {
+    let score = match format {
         AudioFormat::Lossless => {
             // Lossless always wins - base score + bitrate bonus
             config.quality.lossless_bonus + bitrate_kbps
@@ -41,6 +48,18 @@ pub fn calculate_quality_score(metadata: &AudioMetadata, config: &Config) -> u32
             // Conservative estimate for unknown formats
             bitrate_kbps
         }
+    };
+
+    match metadata.spectrum {
+        Some(spectrum) if config.quality.penalize_transcodes => match spectrum.verdict {
+            Verdict::Transcode => {
+                let source_kbps = spectrum.source_bitrate_kbps() as f64;
+                score.min((source_kbps * config.quality.codec_multipliers.mp3) as u32)
+            }
+            Verdict::Upsampled => score.min(config.quality.lossless_bonus + CD_KBPS),
+            _ => score,
+        },
+        _ => score,
     }
 }
 
@@ -200,7 +219,7 @@ mod tests {
         let config = Config::default();
 
         // Lossless should always score higher
-        let mut lossless = AudioMetadata {
+        let lossless = AudioMetadata {
             codec: "flac".to_string(),
             bitrate: Some(1411000), // 1411 kbps
             ..Default::default()
@@ -209,7 +228,7 @@ mod tests {
         assert!(lossless_score > 10000); // Should have lossless bonus
 
         // High-bitrate lossy
-        let mut lossy = AudioMetadata {
+        let lossy = AudioMetadata {
             codec: "opus".to_string(),
             bitrate: Some(320000), // 320 kbps
             ..Default::default()
@@ -227,13 +246,13 @@ mod tests {
     fn test_opus_vs_mp3() {
         let config = Config::default();
 
-        let mut opus = AudioMetadata {
+        let opus = AudioMetadata {
             codec: "opus".to_string(),
             bitrate: Some(192000), // 192 kbps
             ..Default::default()
         };
 
-        let mut mp3 = AudioMetadata {
+        let mp3 = AudioMetadata {
             codec: "mp3".to_string(),
             bitrate: Some(320000), // 320 kbps
             ..Default::default()
@@ -247,4 +266,33 @@ mod tests {
         // OPUS should win despite lower bitrate!
         assert!(opus_score > mp3_score);
     }
+
+    #[test]
+    fn test_transcode_penalty() {
+        use crate::spectrum::SpectrumAnalysis;
+
+        let mut config = Config::default();
+
+        // FLAC made from a 128k MP3
+        let fake = AudioMetadata {
+            codec: "flac".to_string(),
+            bitrate: Some(900000),
+            spectrum: Some(SpectrumAnalysis {
+                verdict: Verdict::Transcode,
+                cutoff_hz: 16000,
+            }),
+            ..Default::default()
+        };
+        let mp3 = AudioMetadata {
+            codec: "mp3".to_string(),
+            bitrate: Some(320000),
+            ..Default::default()
+        };
+
+        assert_eq!(calculate_quality_score(&fake, &config), 128);
+        assert!(calculate_quality_score(&fake, &config) < calculate_quality_score(&mp3, &config));
+
+        config.quality.penalize_transcodes = false;
+        assert_eq!(calculate_quality_score(&fake, &config), 10900);
+    }
 }
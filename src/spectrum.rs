@@ -0,0 +1,372 @@
+// Spectral analysis for transcode detection
+//
+// Lossy encoders low-pass the signal, so a file decoded from MP3/AAC has almost no energy
+// above a fixed frequency (16 kHz for 128 kbps MP3, around 19-20 kHz for 320 kbps). The
+// average spectrum of the whole track is scanned for the highest band that still carries
+// energy, and a sharp drop right above it marks an encoder cutoff rather than a recording
+// that is naturally dull.
+use crate::decode::Decoder;
+use crate::metadata::AudioMetadata;
+use crate::quality::{self, AudioFormat};
+use anyhow::Result;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// FFT size; about 5 Hz per bin at 44.1 kHz
+const FFT_SIZE: usize = 8192;
+/// Width of the bands the averaged spectrum is grouped into
+const BAND_HZ: f64 = 100.0;
+/// Bands this far below the midrange level count as empty
+const FLOOR_DB: f64 = 60.0;
+/// Drop across the cutoff (500 Hz either side) that marks an encoder low-pass
+const CLIFF_DB: f64 = 25.0;
+/// Windows quieter than this (RMS, -80 dBFS) are skipped so silence doesn't dilute the average
+const SILENCE_RMS: f32 = 1e-4;
+/// Windows needed for a verdict
+const MIN_WINDOWS: usize = 8;
+/// Lossless files with content up to here are considered genuine
+const LOSSLESS_CUTOFF_HZ: u32 = 20_500;
+/// Hi-res files with nothing above here were made from a 44.1/48 kHz source
+const UPSAMPLED_CUTOFF_HZ: u32 = 24_500;
+
+/// What the spectrum says about where a file came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// Lossless with content up to the top of the spectrum
+    Genuine,
+    /// Lossless at a high sample rate but with nothing above what a CD can hold
+    Upsampled,
+    /// Lossless (or high bitrate lossy) file with the cutoff of a lower quality lossy encode
+    Transcode,
+    /// Lossy file with the cutoff expected for its bitrate
+    LossyCutoff,
+    /// Too short or too quiet to tell
+    Inconclusive,
+}
+
+/// Cached result of analyzing a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpectrumAnalysis {
+    pub verdict: Verdict,
+    /// Highest frequency with content, in Hz
+    pub cutoff_hz: u32,
+}
+
+impl SpectrumAnalysis {
+    /// Whether the file should be reported and penalized
+    pub fn is_suspect(&self) -> bool {
+        matches!(self.verdict, Verdict::Transcode | Verdict::Upsampled)
+    }
+
+    /// Bitrate (kbps) of an MP3 that would have this cutoff, following LAME's low-pass table
+    pub fn source_bitrate_kbps(&self) -> u32 {
+        match self.cutoff_hz {
+            0..=15_499 => 96,
+            15_500..=16_999 => 128,
+            17_000..=17_999 => 160,
+            18_000..=18_999 => 192,
+            19_000..=19_999 => 256,
+            _ => 320,
+        }
+    }
+}
+
+impl fmt::Display for SpectrumAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let khz = self.cutoff_hz as f64 / 1000.0;
+        match self.verdict {
+            Verdict::Genuine => write!(f, "genuine lossless"),
+            Verdict::Upsampled => write!(f, "upsampled (no content above {:.1} kHz)", khz),
+            Verdict::Transcode => write!(
+                f,
+                "likely transcode (cutoff at {:.1} kHz, like a {} kbps MP3)",
+                khz,
+                self.source_bitrate_kbps()
+            ),
+            Verdict::LossyCutoff => write!(f, "lossy cutoff at {:.1} kHz", khz),
+            Verdict::Inconclusive => write!(f, "inconclusive"),
+        }
+    }
+}
+
+/// Where the content of a spectrum ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cutoff {
+    pub hz: u32,
+    /// Whether the spectrum falls off a cliff there (an encoder low-pass)
+    pub sharp: bool,
+}
+
+/// Decode a file completely and classify it
+pub fn analyze_file(path: &Path, metadata: &AudioMetadata) -> Result<SpectrumAnalysis> {
+    let mut decoder = Decoder::open(path)?;
+    let mut analyzer = Analyzer::new(decoder.sample_rate, decoder.channels);
+    while let Some(samples) = decoder.next_samples()? {
+        analyzer.add_samples(samples);
+    }
+    Ok(classify(analyzer.finish(), metadata, decoder.sample_rate))
+}
+
+/// Turn a measured cutoff into a verdict for a file of this codec, bitrate and sample rate
+pub fn classify(
+    cutoff: Option<Cutoff>,
+    metadata: &AudioMetadata,
+    sample_rate: u32,
+) -> SpectrumAnalysis {
+    let Some(cutoff) = cutoff else {
+        return SpectrumAnalysis {
+            verdict: Verdict::Inconclusive,
+            cutoff_hz: 0,
+        };
+    };
+
+    let verdict = match quality::get_audio_format(&metadata.codec) {
+        AudioFormat::Lossy => {
+            let kbps = metadata.get_bitrate_kbps().unwrap_or(0);
+            if cutoff.sharp && cutoff.hz + 1_500 < expected_lossy_cutoff(kbps) {
+                Verdict::Transcode
+            } else {
+                Verdict::LossyCutoff
+            }
+        }
+        _ if !cutoff.sharp => Verdict::Genuine,
+        _ if cutoff.hz < LOSSLESS_CUTOFF_HZ => Verdict::Transcode,
+        _ if sample_rate > 48_000 && cutoff.hz < UPSAMPLED_CUTOFF_HZ => Verdict::Upsampled,
+        _ => Verdict::Genuine,
+    };
+
+    SpectrumAnalysis {
+        verdict,
+        cutoff_hz: cutoff.hz,
+    }
+}
+
+/// Lowest cutoff a straight encode at this bitrate normally has
+fn expected_lossy_cutoff(kbps: u32) -> u32 {
+    match kbps {
+        256.. => 19_500,
+        192..=255 => 18_500,
+        160..=191 => 17_500,
+        128..=159 => 16_000,
+        _ => 0,
+    }
+}
+
+/// Accumulates the average power spectrum of a stream
+pub struct Analyzer {
+    sample_rate: u32,
+    channels: usize,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    pending: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    power: Vec<f64>,
+    windows: usize,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let x = std::f32::consts::PI * 2.0 * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * x.cos()
+            })
+            .collect();
+
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            pending: Vec::with_capacity(FFT_SIZE),
+            buffer: vec![Complex::default(); FFT_SIZE],
+            power: vec![0.0; FFT_SIZE / 2],
+            windows: 0,
+        }
+    }
+
+    /// Feed interleaved samples
+    pub fn add_samples(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.pending
+                .push(frame.iter().sum::<f32>() / self.channels as f32);
+            if self.pending.len() == FFT_SIZE {
+                self.process_window();
+                self.pending.clear();
+            }
+        }
+    }
+
+    fn process_window(&mut self) {
+        let mean_square = self.pending.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32;
+        if mean_square.sqrt() < SILENCE_RMS {
+            return;
+        }
+
+        for (slot, (sample, weight)) in self
+            .buffer
+            .iter_mut()
+            .zip(self.pending.iter().zip(&self.window))
+        {
+            *slot = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        for (power, bin) in self.power.iter_mut().zip(&self.buffer) {
+            *power += bin.norm_sqr() as f64;
+        }
+        self.windows += 1;
+    }
+
+    /// Find where the content ends, None if there wasn't enough audio to tell
+    pub fn finish(self) -> Option<Cutoff> {
+        if self.windows < MIN_WINDOWS {
+            return None;
+        }
+
+        // Group bins into bands, in dB
+        let bin_hz = self.sample_rate as f64 / FFT_SIZE as f64;
+        let band_count = ((self.sample_rate as f64 / 2.0) / BAND_HZ) as usize;
+        let mut sums = vec![(0.0f64, 0usize); band_count];
+        for (bin, power) in self.power.iter().enumerate().skip(1) {
+            let band = ((bin as f64 * bin_hz) / BAND_HZ) as usize;
+            if let Some(slot) = sums.get_mut(band) {
+                slot.0 += power;
+                slot.1 += 1;
+            }
+        }
+        let levels: Vec<f64> = sums
+            .iter()
+            .map(|&(sum, count)| {
+                let mean = sum / count.max(1) as f64 / self.windows as f64;
+                10.0 * (mean + 1e-20).log10()
+            })
+            .collect();
+
+        // Midrange (500 Hz - 5 kHz) level as the reference
+        let mid = &levels[5..50.min(levels.len())];
+        let reference = 10.0
+            * (mid.iter().map(|l| 10f64.powf(l / 10.0)).sum::<f64>() / mid.len() as f64).log10();
+        let floor = reference - FLOOR_DB;
+
+        let top = levels.iter().rposition(|&l| l > floor)?;
+        let below = levels[top.saturating_sub(5)];
+        let above = levels.get(top + 5).copied();
+        let sharp = above.is_some_and(|above| below - above >= CLIFF_DB);
+
+        Some(Cutoff {
+            hz: ((top + 1) as f64 * BAND_HZ) as u32,
+            sharp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two seconds of tones every 50 Hz up to `max_hz`, with scattered phases
+    fn tones(max_hz: u32) -> Vec<f32> {
+        let rate = 44_100;
+        let freqs: Vec<f64> = (1..)
+            .map(|i| i as f64 * 50.0)
+            .take_while(|f| *f <= max_hz as f64)
+            .collect();
+        (0..rate * 2)
+            .map(|n| {
+                let t = n as f64 / rate as f64;
+                let sum: f64 = freqs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, f)| (2.0 * std::f64::consts::PI * f * t + i as f64 * 1.7).sin())
+                    .sum();
+                (sum / freqs.len() as f64) as f32
+            })
+            .collect()
+    }
+
+    fn cutoff_of(samples: &[f32]) -> Cutoff {
+        let mut analyzer = Analyzer::new(44_100, 1);
+        analyzer.add_samples(samples);
+        analyzer.finish().unwrap()
+    }
+
+    fn flac() -> AudioMetadata {
+        AudioMetadata {
+            codec: "flac".to_string(),
+            bitrate: Some(900_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_full_band_is_genuine() {
+        let cutoff = cutoff_of(&tones(22_000));
+        assert!(cutoff.hz > 21_500, "{:?}", cutoff);
+        assert_eq!(
+            classify(Some(cutoff), &flac(), 44_100).verdict,
+            Verdict::Genuine
+        );
+    }
+
+    #[test]
+    fn test_lowpassed_flac_is_transcode() {
+        let cutoff = cutoff_of(&tones(16_000));
+        assert!(cutoff.sharp);
+        assert!((15_900..=16_200).contains(&cutoff.hz), "{:?}", cutoff);
+
+        let analysis = classify(Some(cutoff), &flac(), 44_100);
+        assert_eq!(analysis.verdict, Verdict::Transcode);
+        assert_eq!(analysis.source_bitrate_kbps(), 128);
+    }
+
+    #[test]
+    fn test_classify() {
+        let sharp = |hz| Some(Cutoff { hz, sharp: true });
+        let mp3 = |kbps: u32| AudioMetadata {
+            codec: "mp3".to_string(),
+            bitrate: Some(kbps * 1000),
+            ..Default::default()
+        };
+
+        // A 320k MP3 with a 128k cutoff was upsampled from the 128k file
+        assert_eq!(
+            classify(sharp(16_000), &mp3(320), 44_100).verdict,
+            Verdict::Transcode
+        );
+        assert_eq!(
+            classify(sharp(16_000), &mp3(128), 44_100).verdict,
+            Verdict::LossyCutoff
+        );
+        assert_eq!(
+            classify(sharp(20_000), &mp3(320), 44_100).verdict,
+            Verdict::LossyCutoff
+        );
+
+        // Hi-res file holding only CD bandwidth
+        assert_eq!(
+            classify(sharp(22_000), &flac(), 96_000).verdict,
+            Verdict::Upsampled
+        );
+        assert_eq!(
+            classify(sharp(22_000), &flac(), 44_100).verdict,
+            Verdict::Genuine
+        );
+
+        // A gentle roll-off is a dull recording, not an encoder
+        let dull = Some(Cutoff {
+            hz: 12_000,
+            sharp: false,
+        });
+        assert_eq!(classify(dull, &flac(), 44_100).verdict, Verdict::Genuine);
+        assert_eq!(
+            classify(None, &flac(), 44_100).verdict,
+            Verdict::Inconclusive
+        );
+    }
+}
@@ -0,0 +1,71 @@
+// Fallback for formats without a native writer: remux through ffmpeg
+use super::TagEdit;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub fn write(path: &Path, edit: &TagEdit) -> Result<()> {
+    // Keep the extension last so ffmpeg picks the right muxer
+    let ext = crate::utils::get_extension(path).unwrap_or_default();
+    let temp_path = path.with_extension(format!("tmp.{}", ext));
+
+    // ffmpeg needs the cover as a file of its own
+    let cover_path = match &edit.cover {
+        Some(Some(picture)) => {
+            let ext = if picture.mime_type == "image/png" {
+                "png"
+            } else {
+                "jpg"
+            };
+            let cover_path = path.with_extension(format!("cover.{}", ext));
+            fs::write(&cover_path, &picture.data).context("Failed to write cover image")?;
+            Some(cover_path)
+        }
+        _ => None,
+    };
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-i").arg(path);
+    if let Some(cover_path) = &cover_path {
+        cmd.arg("-i").arg(cover_path);
+        cmd.args([
+            "-map",
+            "0:a",
+            "-map",
+            "1",
+            "-disposition:v:0",
+            "attached_pic",
+        ]);
+    } else if edit.cover.is_some() {
+        // Drop the attached picture stream
+        cmd.args(["-map", "0:a"]);
+    } else {
+        cmd.args(["-map", "0"]);
+    }
+    cmd.args(["-c", "copy"]);
+
+    for (key, value) in &edit.text {
+        // Arguments can't hold NUL; ffmpeg itself joins multiple values with ';'
+        let value = value
+            .as_deref()
+            .unwrap_or("")
+            .replace(super::SEPARATOR, ";");
+        cmd.args(["-metadata", &format!("{}={}", key, value)]);
+    }
+    cmd.arg("-y").arg(&temp_path);
+
+    let output = cmd.output();
+    if let Some(cover_path) = &cover_path {
+        let _ = fs::remove_file(cover_path);
+    }
+    let output = output.context("Failed to run ffmpeg for metadata update")?;
+
+    if !output.status.success() {
+        let _ = fs::remove_file(&temp_path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg failed: {}", stderr);
+    }
+
+    fs::rename(&temp_path, path).context("Failed to replace original file with updated version")
+}
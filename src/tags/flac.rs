@@ -0,0 +1,257 @@
+// FLAC metadata blocks
+// Reference: https://xiph.org/flac/format.html#metadata_block
+use super::vorbis::Comments;
+use super::TagEdit;
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+/// Padding added when the metadata has to grow
+const NEW_PADDING: usize = 4096;
+
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+pub fn write(path: &Path, edit: &TagEdit) -> Result<()> {
+    let file = fs::read(path).context("Failed to read FLAC file")?;
+    let start = metadata_start(&file)?;
+    let (blocks, audio_start) = parse_blocks(&file, start)?;
+
+    let old_size = audio_start - start - 4;
+    let blocks = apply(blocks, edit)?;
+    let needed: usize = blocks.iter().map(|b| 4 + b.data.len()).sum();
+
+    // Fit the new blocks into the old metadata area, turning the rest into padding
+    if needed == old_size || needed + 4 <= old_size {
+        let padding = old_size.checked_sub(needed + 4);
+        let metadata = serialize(&blocks, padding);
+
+        let mut out = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .context("Failed to open FLAC file for writing")?;
+        out.seek(SeekFrom::Start((start + 4) as u64))?;
+        out.write_all(&metadata)?;
+        return Ok(());
+    }
+
+    let metadata = serialize(&blocks, Some(NEW_PADDING));
+    let mut data = Vec::with_capacity(file.len() + metadata.len());
+    data.extend_from_slice(&file[..start + 4]);
+    data.extend_from_slice(&metadata);
+    data.extend_from_slice(&file[audio_start..]);
+    super::replace_file(path, &data)
+}
+
+/// Offset of the "fLaC" marker, skipping an ID3v2 tag some encoders put in front
+fn metadata_start(file: &[u8]) -> Result<usize> {
+    let mut start = 0;
+    if file.starts_with(b"ID3") && file.len() >= 10 {
+        let size = file[6..10]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 7) | (*b as usize & 0x7F));
+        start = 10 + size;
+    }
+
+    if file.get(start..start + 4) != Some(b"fLaC") {
+        anyhow::bail!("Not a FLAC file");
+    }
+    Ok(start)
+}
+
+fn parse_blocks(file: &[u8], start: usize) -> Result<(Vec<Block>, usize)> {
+    let mut blocks = Vec::new();
+    let mut pos = start + 4;
+
+    loop {
+        let header = file
+            .get(pos..pos + 4)
+            .ok_or_else(|| anyhow::anyhow!("Truncated FLAC metadata"))?;
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let data = file
+            .get(pos + 4..pos + 4 + len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated FLAC metadata"))?;
+
+        if kind != PADDING {
+            blocks.push(Block {
+                kind,
+                data: data.to_vec(),
+            });
+        }
+        pos += 4 + len;
+
+        if last {
+            break;
+        }
+    }
+
+    if blocks.first().map(|b| b.kind) != Some(STREAMINFO) {
+        anyhow::bail!("FLAC file does not start with STREAMINFO");
+    }
+    Ok((blocks, pos))
+}
+
+fn apply(mut blocks: Vec<Block>, edit: &TagEdit) -> Result<Vec<Block>> {
+    let mut comments = match blocks.iter().position(|b| b.kind == VORBIS_COMMENT) {
+        Some(index) => Comments::parse(&blocks.remove(index).data)?,
+        None => Comments {
+            vendor: format!("ferric {}", env!("CARGO_PKG_VERSION")),
+            fields: Vec::new(),
+        },
+    };
+    comments.apply(edit, false);
+
+    // Comments go right after STREAMINFO like most taggers write them
+    blocks.insert(
+        1,
+        Block {
+            kind: VORBIS_COMMENT,
+            data: comments.to_bytes(),
+        },
+    );
+
+    if let Some(cover) = &edit.cover {
+        blocks.retain(|b| b.kind != PICTURE);
+        if let Some(picture) = cover {
+            blocks.push(Block {
+                kind: PICTURE,
+                data: picture.to_flac_block(),
+            });
+        }
+    }
+
+    if let Some(block) = blocks.iter().find(|b| b.data.len() >= 1 << 24) {
+        anyhow::bail!("FLAC metadata block of type {} is too large", block.kind);
+    }
+    Ok(blocks)
+}
+
+/// Serialize blocks, ending with a padding block of `padding` bytes if given
+fn serialize(blocks: &[Block], padding: Option<usize>) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut push = |kind: u8, data: &[u8], last: bool| {
+        let len = (data.len() as u32).to_be_bytes();
+        out.push(if last { kind | 0x80 } else { kind });
+        out.extend_from_slice(&len[1..]);
+        out.extend_from_slice(data);
+    };
+
+    for (i, block) in blocks.iter().enumerate() {
+        push(
+            block.kind,
+            &block.data,
+            padding.is_none() && i == blocks.len() - 1,
+        );
+    }
+    if let Some(padding) = padding {
+        push(PADDING, &vec![0; padding], true);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn flac_file(comments: &Comments, padding: usize) -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&serialize(
+            &[
+                Block {
+                    kind: STREAMINFO,
+                    data: vec![0; 34],
+                },
+                Block {
+                    kind: VORBIS_COMMENT,
+                    data: comments.to_bytes(),
+                },
+            ],
+            Some(padding),
+        ));
+        data.extend_from_slice(b"AUDIOFRAMES");
+        data
+    }
+
+    fn read_comments(data: &[u8]) -> Comments {
+        let (blocks, _) = parse_blocks(data, 0).unwrap();
+        let block = blocks.iter().find(|b| b.kind == VORBIS_COMMENT).unwrap();
+        Comments::parse(&block.data).unwrap()
+    }
+
+    #[test]
+    fn test_edit_in_place_uses_padding() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.flac");
+        let comments = Comments {
+            vendor: "test".to_string(),
+            fields: vec![("TITLE".to_string(), "Old".to_string())],
+        };
+        fs::write(&path, flac_file(&comments, 100)).unwrap();
+        let original_len = fs::metadata(&path).unwrap().len();
+
+        write(
+            &path,
+            &TagEdit::new().set("title", "New").set("artist", "Band"),
+        )
+        .unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len() as u64, original_len);
+        assert!(data.ends_with(b"AUDIOFRAMES"));
+        assert_eq!(
+            read_comments(&data).fields,
+            vec![
+                ("TITLE".to_string(), "New".to_string()),
+                ("ARTIST".to_string(), "Band".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_grows_file_without_padding() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.flac");
+        let comments = Comments {
+            vendor: "test".to_string(),
+            fields: Vec::new(),
+        };
+        fs::write(&path, flac_file(&comments, 0)).unwrap();
+
+        let picture = super::super::Picture {
+            mime_type: "image/png".to_string(),
+            data: vec![1, 2, 3],
+            width: 1,
+            height: 1,
+        };
+        write(&path, &TagEdit::new().set("album", "LP").cover(picture)).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert!(data.ends_with(b"AUDIOFRAMES"));
+        let (blocks, _) = parse_blocks(&data, 0).unwrap();
+        assert!(blocks.iter().any(|b| b.kind == PICTURE));
+        assert_eq!(
+            read_comments(&data).fields,
+            vec![("ALBUM".to_string(), "LP".to_string())]
+        );
+
+        // Removing the cover again fits in place
+        let edit = TagEdit {
+            cover: Some(None),
+            ..TagEdit::default()
+        };
+        write(&path, &edit).unwrap();
+        let (blocks, _) = parse_blocks(&fs::read(&path).unwrap(), 0).unwrap();
+        assert!(!blocks.iter().any(|b| b.kind == PICTURE));
+    }
+}
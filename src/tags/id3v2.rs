@@ -0,0 +1,221 @@
+// ID3v2 tags in MP3 files
+use super::TagEdit;
+use anyhow::{Context, Result};
+use id3::frame::{ExtendedText, Picture, PictureType, UniqueFileIdentifier};
+use id3::{Tag, TagLike, Version};
+use std::path::Path;
+
+/// Owner of the UFID frame holding the MusicBrainz recording ID (as written by Picard)
+const MUSICBRAINZ_UFID_OWNER: &str = "http://musicbrainz.org";
+
+/// Where a ferric tag lives in an ID3v2 tag
+enum Field {
+    Text(&'static str),
+    ExtendedText(String),
+    UniqueFileId,
+}
+
+fn field_for(key: &str, version: Version) -> Field {
+    match key {
+        "title" => Field::Text("TIT2"),
+        "artist" => Field::Text("TPE1"),
+        "album" => Field::Text("TALB"),
+        "album_artist" => Field::Text("TPE2"),
+        // ID3v2.3 keeps the year in TYER, with the day and time in TDAT and TIME
+        "date" if version == Version::Id3v23 => Field::Text("TYER"),
+        "date" => Field::Text("TDRC"),
+        "genre" => Field::Text("TCON"),
+        "track" => Field::Text("TRCK"),
+        "disc" => Field::Text("TPOS"),
+        "musicbrainz_trackid" => Field::UniqueFileId,
+        "musicbrainz_albumid" => Field::ExtendedText("MusicBrainz Album Id".to_string()),
+        "musicbrainz_releasetrackid" => {
+            Field::ExtendedText("MusicBrainz Release Track Id".to_string())
+        }
+        "musicbrainz_artistid" => Field::ExtendedText("MusicBrainz Artist Id".to_string()),
+        "musicbrainz_albumartistid" => {
+            Field::ExtendedText("MusicBrainz Album Artist Id".to_string())
+        }
+        "musicbrainz_releasegroupid" => {
+            Field::ExtendedText("MusicBrainz Release Group Id".to_string())
+        }
+        "releasestatus" => Field::ExtendedText("MusicBrainz Album Status".to_string()),
+        "releasetype" => Field::ExtendedText("MusicBrainz Album Type".to_string()),
+        "releasecountry" => Field::ExtendedText("MusicBrainz Album Release Country".to_string()),
+        "artistsort" => Field::Text("TSOP"),
+        "albumartistsort" => Field::Text("TSO2"),
+        "label" => Field::Text("TPUB"),
+        "media" => Field::Text("TMED"),
+        // ID3v2.3 only has the original release year
+        "originaldate" if version == Version::Id3v23 => Field::Text("TORY"),
+        "originaldate" => Field::Text("TDOR"),
+        "artists" | "barcode" | "catalognumber" => Field::ExtendedText(key.to_uppercase()),
+        // Upper case like foobar2000 and most scanners write them
+        other if other.starts_with("replaygain_") => Field::ExtendedText(other.to_uppercase()),
+        other => Field::ExtendedText(other.to_string()),
+    }
+}
+
+pub fn write(path: &Path, edit: &TagEdit) -> Result<()> {
+    let mut tag = id3::no_tag_ok(Tag::read_from_path(path))
+        .context("Failed to read ID3 tag")?
+        .unwrap_or_default();
+
+    // Keep v2.3 tags at v2.3 for older players, upgrade anything else
+    let version = match tag.version() {
+        Version::Id3v23 => Version::Id3v23,
+        _ => Version::Id3v24,
+    };
+
+    apply(&mut tag, edit, version);
+
+    tag.write_to_path(path, version)
+        .context("Failed to write ID3 tag")
+}
+
+/// Recording ID from the MusicBrainz UFID frame
+pub fn read_recording_id(path: &Path) -> Option<String> {
+    let tag = Tag::read_from_path(path).ok()?;
+    let frame = tag
+        .unique_file_identifiers()
+        .find(|frame| frame.owner_identifier == MUSICBRAINZ_UFID_OWNER)?;
+    String::from_utf8(frame.identifier.clone()).ok()
+}
+
+fn apply(tag: &mut Tag, edit: &TagEdit, version: Version) {
+    for (key, value) in &edit.text {
+        let field = field_for(key, version);
+        let value = value.as_ref().map(|value| match field {
+            Field::Text("TORY" | "TYER") => value.chars().take(4).collect(),
+            // ID3v2.3 has no multi-valued frames, Picard joins the values with '/'
+            _ if version == Version::Id3v23 => value.replace(super::SEPARATOR, "/"),
+            _ => value.clone(),
+        });
+        if let Field::Text("TYER") = field {
+            // Whatever the old date was split into goes with it
+            for id in ["TDAT", "TIME", "TDRC"] {
+                tag.remove(id);
+            }
+        }
+        match field {
+            Field::Text(id) => match value {
+                Some(value) => tag.set_text(id, value),
+                None => {
+                    tag.remove(id);
+                }
+            },
+            Field::ExtendedText(description) => {
+                // Descriptions are matched case-insensitively so other taggers' spellings go too
+                let existing: Vec<String> = tag
+                    .extended_texts()
+                    .filter(|frame| frame.description.eq_ignore_ascii_case(&description))
+                    .map(|frame| frame.description.clone())
+                    .collect();
+                for existing in existing {
+                    tag.remove_extended_text(Some(&existing), None);
+                }
+                if let Some(value) = value {
+                    tag.add_frame(ExtendedText { description, value });
+                }
+            }
+            Field::UniqueFileId => {
+                tag.remove_unique_file_identifier_by_owner_identifier(MUSICBRAINZ_UFID_OWNER);
+                if let Some(value) = value {
+                    tag.add_frame(UniqueFileIdentifier {
+                        owner_identifier: MUSICBRAINZ_UFID_OWNER.to_string(),
+                        identifier: value.as_bytes().to_vec(),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(cover) = &edit.cover {
+        tag.remove_all_pictures();
+        if let Some(picture) = cover {
+            tag.add_frame(Picture {
+                mime_type: picture.mime_type.clone(),
+                picture_type: PictureType::CoverFront,
+                description: String::new(),
+                data: picture.data.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_keeps_audio_and_removes_tags() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.mp3");
+        fs::write(&path, b"\xFF\xFBaudio").unwrap();
+
+        let edit = TagEdit::new()
+            .set("title", "Song")
+            .set("album_artist", "Band")
+            .set("musicbrainz_trackid", "abc-123")
+            .set("musicbrainz_albumid", "def-456");
+        write(&path, &edit).unwrap();
+
+        let tag = Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.title(), Some("Song"));
+        assert_eq!(tag.album_artist(), Some("Band"));
+        assert_eq!(
+            tag.unique_file_identifiers().next().unwrap().identifier,
+            b"abc-123"
+        );
+        assert_eq!(tag.extended_texts().next().unwrap().value, "def-456");
+        assert_eq!(read_recording_id(&path).as_deref(), Some("abc-123"));
+
+        let ids = ["a1".to_string(), "a2".to_string()];
+        let edit = TagEdit::new()
+            .set_values("musicbrainz_artistid", &ids)
+            .set("label", "Label")
+            .set("originaldate", "1997-05-21")
+            .set("date", "2001-03-04");
+        let mut v23 = Tag::new();
+        v23.set_text("TYER", "1999");
+        v23.set_text("TDAT", "0101");
+        apply(&mut v23, &edit, Version::Id3v23);
+        assert_eq!(v23.get("TPUB").unwrap().content().text(), Some("Label"));
+        assert_eq!(v23.get("TORY").unwrap().content().text(), Some("1997"));
+        assert_eq!(v23.get("TYER").unwrap().content().text(), Some("2001"));
+        assert!(v23.get("TDAT").is_none() && v23.get("TDRC").is_none());
+        apply(&mut v23, &TagEdit::new().remove("date"), Version::Id3v23);
+        assert!(v23.get("TYER").is_none());
+        let frame = v23.extended_texts().next().unwrap();
+        assert_eq!(frame.description, "MusicBrainz Artist Id");
+        assert_eq!(frame.value, "a1/a2");
+        assert!(fs::read(&path).unwrap().ends_with(b"\xFF\xFBaudio"));
+
+        write(&path, &TagEdit::new().remove("title")).unwrap();
+        let tag = Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.title(), None);
+        assert_eq!(tag.album_artist(), Some("Band"));
+    }
+
+    #[test]
+    fn test_replaygain_replaces_any_case() {
+        let mut tag = Tag::new();
+        tag.add_frame(ExtendedText {
+            description: "replaygain_track_gain".to_string(),
+            value: "+1.00 dB".to_string(),
+        });
+
+        apply(
+            &mut tag,
+            &TagEdit::new().set("replaygain_track_gain", "-3.20 dB"),
+            Version::Id3v24,
+        );
+
+        let frames: Vec<_> = tag.extended_texts().collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].description, "REPLAYGAIN_TRACK_GAIN");
+        assert_eq!(frames[0].value, "-3.20 dB");
+    }
+}
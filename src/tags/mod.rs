@@ -0,0 +1,264 @@
+// Native tag writing
+//
+// MP3 (ID3v2), FLAC and Ogg (Vorbis comments) and MP4 (iTunes atoms) are edited
+// directly; anything else falls back to an ffmpeg remux.
+mod ffmpeg;
+mod flac;
+mod id3v2;
+mod mp4;
+mod ogg;
+mod vorbis;
+
+use crate::utils;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Separates the values of a multi-valued tag (NUL, as in ID3v2.4 text frames)
+pub const SEPARATOR: char = '\0';
+
+/// Tag changes to apply to a file in a single write
+///
+/// Keys use ferric's tag names (`title`, `artist`, `album`, `album_artist`, `date`,
+/// `genre`, `track`, `disc`), or Picard's internal names for the rest of its tag set
+/// (`artists`, `artistsort`, `albumartistsort`, `label`, `catalognumber`, `barcode`,
+/// `media`, `originaldate`, `originalyear`, `releasestatus`, `releasetype`,
+/// `releasecountry`, `musicbrainz_trackid`, `musicbrainz_albumid`,
+/// `musicbrainz_releasetrackid`, `musicbrainz_artistid`, `musicbrainz_albumartistid`,
+/// `musicbrainz_releasegroupid`); any other key is written as a custom text tag. `track` and
+/// `disc` may carry a total ("3/12"). Values joined with `SEPARATOR` are written as
+/// multiple values where the format has them.
+#[derive(Debug, Clone, Default)]
+pub struct TagEdit {
+    /// Text tags to set, `None` removes the tag
+    pub text: Vec<(String, Option<String>)>,
+    /// `Some(Some(_))` replaces the embedded cover, `Some(None)` removes all pictures
+    pub cover: Option<Option<Picture>>,
+}
+
+impl TagEdit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a text tag
+    pub fn set(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.text.push((key.to_string(), Some(value.into())));
+        self
+    }
+
+    /// Set a multi-valued text tag
+    pub fn set_values(self, key: &str, values: &[String]) -> Self {
+        self.set(key, join_values(values))
+    }
+
+    /// Remove a text tag
+    pub fn remove(mut self, key: &str) -> Self {
+        self.text.push((key.to_string(), None));
+        self
+    }
+
+    /// Embed a front cover, replacing existing pictures
+    pub fn cover(mut self, picture: Picture) -> Self {
+        self.cover = Some(Some(picture));
+        self
+    }
+
+    /// Build the edit that puts back the "before" values recorded in the journal
+    ///
+    /// Null values are tags the file didn't have; a `cover` key means the cover was added.
+    pub fn from_journal(before: &serde_json::Map<String, serde_json::Value>) -> Self {
+        let mut edit = Self::new();
+        for (key, value) in before {
+            if key == "cover" {
+                edit.cover = Some(None);
+            } else {
+                edit.text.push((key.clone(), value.as_str().map(String::from)));
+            }
+        }
+        edit
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.cover.is_none()
+    }
+}
+
+/// Embedded picture (always written as the front cover)
+#[derive(Debug, Clone)]
+pub struct Picture {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Picture {
+    /// Load a JPEG or PNG image
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = fs::read(path).context("Failed to read cover image")?;
+        let mime_type = match utils::get_extension(path).as_deref() {
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("png") => "image/png",
+            _ => anyhow::bail!("Unsupported image format"),
+        };
+        let (width, height) = image_dimensions(&data).unwrap_or((0, 0));
+
+        Ok(Self {
+            mime_type: mime_type.to_string(),
+            data,
+            width,
+            height,
+        })
+    }
+
+    /// FLAC METADATA_BLOCK_PICTURE body, also used base64-encoded in Vorbis comments
+    /// Reference: https://xiph.org/flac/format.html#metadata_block_picture
+    pub(crate) fn to_flac_block(&self) -> Vec<u8> {
+        let mut block = Vec::with_capacity(self.data.len() + 64);
+        block.extend_from_slice(&3u32.to_be_bytes()); // Front cover
+        block.extend_from_slice(&(self.mime_type.len() as u32).to_be_bytes());
+        block.extend_from_slice(self.mime_type.as_bytes());
+        block.extend_from_slice(&0u32.to_be_bytes()); // Empty description
+        block.extend_from_slice(&self.width.to_be_bytes());
+        block.extend_from_slice(&self.height.to_be_bytes());
+        block.extend_from_slice(&24u32.to_be_bytes()); // Color depth
+        block.extend_from_slice(&0u32.to_be_bytes()); // Not indexed
+        block.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        block.extend_from_slice(&self.data);
+        block
+    }
+}
+
+/// Read width and height from a PNG or JPEG header
+fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.len() >= 24 {
+        let width = u32::from_be_bytes(data[16..20].try_into().ok()?);
+        let height = u32::from_be_bytes(data[20..24].try_into().ok()?);
+        return Some((width, height));
+    }
+
+    if data.starts_with(&[0xFF, 0xD8]) {
+        let mut pos = 2;
+        while pos + 9 < data.len() {
+            if data[pos] != 0xFF {
+                return None;
+            }
+            let marker = data[pos + 1];
+            let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            // SOF0..SOF15, excluding DHT (C4), JPG (C8) and DAC (CC)
+            if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+                let height = u16::from_be_bytes([data[pos + 5], data[pos + 6]]) as u32;
+                let width = u16::from_be_bytes([data[pos + 7], data[pos + 8]]) as u32;
+                return Some((width, height));
+            }
+            pos += 2 + length;
+        }
+    }
+
+    None
+}
+
+/// Write tag changes to an audio file
+///
+/// Tags are edited in place when the existing tag has room for the changes; otherwise the
+/// file is rewritten next to the original and renamed over it.
+pub fn write(path: &Path, edit: &TagEdit) -> Result<()> {
+    if edit.is_empty() {
+        return Ok(());
+    }
+
+    let ext = utils::get_extension(path).unwrap_or_default();
+    let result = match ext.as_str() {
+        "mp3" => id3v2::write(path, edit),
+        "flac" => flac::write(path, edit),
+        "ogg" | "oga" | "opus" => ogg::write(path, edit),
+        "m4a" | "m4b" | "mp4" | "alac" => mp4::write(path, edit),
+        _ => ffmpeg::write(path, edit),
+    };
+
+    result.with_context(|| format!("Failed to write tags to {}", path.display()))
+}
+
+/// MusicBrainz recording ID from an MP3's UFID frame
+///
+/// Other formats keep it in a text tag the metadata readers already see.
+pub fn read_musicbrainz_recording_id(path: &Path) -> Option<String> {
+    match utils::get_extension(path).as_deref() {
+        Some("mp3") => id3v2::read_recording_id(path),
+        _ => None,
+    }
+}
+
+/// Temporary path next to `path` used when a file has to be rewritten
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.to_path_buf();
+    let original_filename = temp_path.file_name().unwrap().to_string_lossy().to_string();
+    temp_path.set_file_name(format!("{}.tmp", original_filename));
+    temp_path
+}
+
+/// Write `data` next to `path` and rename it over the original
+fn replace_file(path: &Path, data: &[u8]) -> Result<()> {
+    let temp = temp_path(path);
+    if let Err(e) = fs::write(&temp, data) {
+        let _ = fs::remove_file(&temp);
+        return Err(e).context("Failed to write temporary file");
+    }
+    fs::rename(&temp, path).context("Failed to replace original file with updated version")
+}
+
+/// Join the values of a multi-valued tag with `SEPARATOR`
+pub fn join_values(values: &[String]) -> String {
+    values.join(&SEPARATOR.to_string())
+}
+
+/// Parse a `track` value ("3" or "3/12") into number and total
+fn parse_track(value: &str) -> (Option<u32>, Option<u32>) {
+    let mut parts = value.splitn(2, '/');
+    let number = parts.next().and_then(|n| n.trim().parse().ok());
+    let total = parts.next().and_then(|n| n.trim().parse().ok());
+    (number, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_dimensions_png() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some((640, 480)));
+    }
+
+    #[test]
+    fn test_image_dimensions_jpeg() {
+        let jpeg = [
+            0xFF, 0xD8, // SOI
+            0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, // APP0
+            0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xF4, 0x02, 0x58, 0x03, 0x00, 0x00,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((600, 500)));
+    }
+
+    #[test]
+    fn test_from_journal() {
+        let before = serde_json::json!({"artist": "Old", "genre": null, "cover": null});
+        let edit = TagEdit::from_journal(before.as_object().unwrap());
+
+        assert!(edit
+            .text
+            .contains(&("artist".to_string(), Some("Old".to_string()))));
+        assert!(edit.text.contains(&("genre".to_string(), None)));
+        assert!(matches!(edit.cover, Some(None)));
+    }
+
+    #[test]
+    fn test_parse_track() {
+        assert_eq!(parse_track("3"), (Some(3), None));
+        assert_eq!(parse_track("3/12"), (Some(3), Some(12)));
+        assert_eq!(parse_track("x"), (None, None));
+    }
+}
@@ -0,0 +1,545 @@
+// iTunes-style metadata atoms in MP4/M4A files (moov/udta/meta/ilst)
+use super::TagEdit;
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Atoms whose payload is a list of child atoms
+const CONTAINERS: &[&[u8; 4]] = &[
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"meta", b"ilst",
+];
+
+const FREEFORM_MEAN: &str = "com.apple.iTunes";
+
+/// Data atom type indicators
+const TYPE_IMPLICIT: u32 = 0;
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+
+#[derive(Debug, Clone)]
+struct Atom {
+    kind: [u8; 4],
+    /// Version/flags of full boxes (`meta`), written before the children
+    prefix: Vec<u8>,
+    body: Body,
+}
+
+#[derive(Debug, Clone)]
+enum Body {
+    Leaf(Vec<u8>),
+    Container(Vec<Atom>),
+}
+
+/// Identity of an item in `ilst`
+#[derive(Debug, Clone, PartialEq)]
+enum ItemKey {
+    Atom([u8; 4]),
+    Freeform(String),
+}
+
+fn item_key(key: &str) -> ItemKey {
+    match key {
+        "title" => ItemKey::Atom(*b"\xa9nam"),
+        "artist" => ItemKey::Atom(*b"\xa9ART"),
+        "album" => ItemKey::Atom(*b"\xa9alb"),
+        "album_artist" => ItemKey::Atom(*b"aART"),
+        "date" => ItemKey::Atom(*b"\xa9day"),
+        "genre" => ItemKey::Atom(*b"\xa9gen"),
+        "track" => ItemKey::Atom(*b"trkn"),
+        "disc" => ItemKey::Atom(*b"disk"),
+        "musicbrainz_trackid" => ItemKey::Freeform("MusicBrainz Track Id".to_string()),
+        "musicbrainz_albumid" => ItemKey::Freeform("MusicBrainz Album Id".to_string()),
+        "musicbrainz_releasetrackid" => {
+            ItemKey::Freeform("MusicBrainz Release Track Id".to_string())
+        }
+        "musicbrainz_artistid" => ItemKey::Freeform("MusicBrainz Artist Id".to_string()),
+        "musicbrainz_albumartistid" => ItemKey::Freeform("MusicBrainz Album Artist Id".to_string()),
+        "musicbrainz_releasegroupid" => {
+            ItemKey::Freeform("MusicBrainz Release Group Id".to_string())
+        }
+        "releasestatus" => ItemKey::Freeform("MusicBrainz Album Status".to_string()),
+        "releasetype" => ItemKey::Freeform("MusicBrainz Album Type".to_string()),
+        "releasecountry" => ItemKey::Freeform("MusicBrainz Album Release Country".to_string()),
+        "artistsort" => ItemKey::Atom(*b"soar"),
+        "albumartistsort" => ItemKey::Atom(*b"soaa"),
+        // Picard's upper case freeform names
+        "artists" | "label" | "catalognumber" | "barcode" | "media" | "originaldate"
+        | "originalyear" => ItemKey::Freeform(key.to_uppercase()),
+        other => ItemKey::Freeform(other.to_string()),
+    }
+}
+
+impl Atom {
+    fn leaf(kind: &[u8; 4], data: Vec<u8>) -> Self {
+        Self {
+            kind: *kind,
+            prefix: Vec::new(),
+            body: Body::Leaf(data),
+        }
+    }
+
+    fn container(kind: &[u8; 4], children: Vec<Atom>) -> Self {
+        Self {
+            kind: *kind,
+            prefix: Vec::new(),
+            body: Body::Container(children),
+        }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Atom>> {
+        match &mut self.body {
+            Body::Container(children) => Some(children),
+            Body::Leaf(_) => None,
+        }
+    }
+
+    /// Child container of the given kind, created if missing
+    fn child_mut(&mut self, kind: &[u8; 4], create: impl FnOnce() -> Atom) -> &mut Atom {
+        let children = self.children_mut().expect("container atom");
+        let index = match children.iter().position(|c| &c.kind == kind) {
+            Some(index) => index,
+            None => {
+                children.push(create());
+                children.len() - 1
+            }
+        };
+        &mut children[index]
+    }
+
+    fn size(&self) -> usize {
+        8 + self.prefix.len()
+            + match &self.body {
+                Body::Leaf(data) => data.len(),
+                Body::Container(children) => children.iter().map(Atom::size).sum(),
+            }
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.size() as u32).to_be_bytes());
+        out.extend_from_slice(&self.kind);
+        out.extend_from_slice(&self.prefix);
+        match &self.body {
+            Body::Leaf(data) => out.extend_from_slice(data),
+            Body::Container(children) => {
+                for child in children {
+                    child.write_to(out);
+                }
+            }
+        }
+    }
+
+    /// Identity of an `ilst` item
+    fn item_key(&self) -> ItemKey {
+        if &self.kind != b"----" {
+            return ItemKey::Atom(self.kind);
+        }
+
+        // Freeform items hold "mean", "name" and "data" children
+        let children = match &self.body {
+            Body::Leaf(data) => parse_atoms(data).unwrap_or_default(),
+            Body::Container(children) => children.clone(),
+        };
+        let name = children
+            .into_iter()
+            .find(|c| &c.kind == b"name")
+            .and_then(|c| match c.body {
+                Body::Leaf(data) if data.len() >= 4 => {
+                    Some(String::from_utf8_lossy(&data[4..]).to_string())
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        ItemKey::Freeform(name)
+    }
+
+    /// Shift chunk offsets that point past `after` by `delta`
+    fn shift_chunk_offsets(&mut self, after: u64, delta: i64) -> Result<()> {
+        match &mut self.body {
+            Body::Container(children) => {
+                for child in children {
+                    child.shift_chunk_offsets(after, delta)?;
+                }
+            }
+            Body::Leaf(data) if &self.kind == b"stco" || &self.kind == b"co64" => {
+                let width = if &self.kind == b"stco" { 4 } else { 8 };
+                let count = data
+                    .get(4..8)
+                    .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+                    .unwrap_or(0);
+                for i in 0..count {
+                    let start = 8 + i * width;
+                    let Some(entry) = data.get_mut(start..start + width) else {
+                        anyhow::bail!("Truncated chunk offset table");
+                    };
+                    let offset = if width == 4 {
+                        u32::from_be_bytes(entry[..].try_into().unwrap()) as u64
+                    } else {
+                        u64::from_be_bytes(entry[..].try_into().unwrap())
+                    };
+                    if offset < after {
+                        continue;
+                    }
+                    let shifted = (offset as i64 + delta) as u64;
+                    if width == 4 {
+                        let shifted = u32::try_from(shifted)
+                            .context("Chunk offset no longer fits in stco")?;
+                        entry.copy_from_slice(&shifted.to_be_bytes());
+                    } else {
+                        entry.copy_from_slice(&shifted.to_be_bytes());
+                    }
+                }
+            }
+            Body::Leaf(_) => {}
+        }
+        Ok(())
+    }
+}
+
+fn parse_atoms(data: &[u8]) -> Result<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+
+    while pos + 8 <= data.len() {
+        let (header_len, size) = atom_size(data, pos)?;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let payload = &data[pos + header_len..pos + size];
+
+        let atom = if CONTAINERS.contains(&&kind) {
+            // `meta` is a full box, except in some QuickTime files where hdlr follows directly
+            let prefix_len = if &kind == b"meta" && payload.get(4..8) != Some(b"hdlr") {
+                4
+            } else {
+                0
+            };
+            let prefix_len = prefix_len.min(payload.len());
+            Atom {
+                kind,
+                prefix: payload[..prefix_len].to_vec(),
+                body: Body::Container(parse_atoms(&payload[prefix_len..])?),
+            }
+        } else {
+            Atom::leaf(&kind, payload.to_vec())
+        };
+
+        atoms.push(atom);
+        pos += size;
+    }
+
+    Ok(atoms)
+}
+
+/// Header length and total size of the atom at `pos`
+fn atom_size(data: &[u8], pos: usize) -> Result<(usize, usize)> {
+    let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+    let (header_len, size) = match size {
+        0 => (8, data.len() - pos),
+        1 => {
+            let large = data
+                .get(pos + 8..pos + 16)
+                .ok_or_else(|| anyhow::anyhow!("Truncated MP4 atom"))?;
+            (16, u64::from_be_bytes(large.try_into().unwrap()) as usize)
+        }
+        size => (8, size),
+    };
+
+    if size < header_len || pos + size > data.len() {
+        anyhow::bail!("Invalid MP4 atom size at offset {}", pos);
+    }
+    Ok((header_len, size))
+}
+
+/// Item atom holding a data atom for each value
+fn data_item(key: &ItemKey, data_type: u32, values: &[&[u8]]) -> Atom {
+    let data_atoms = values.iter().map(|value| {
+        let mut data = data_type.to_be_bytes().to_vec();
+        data.extend_from_slice(&[0; 4]); // Locale
+        data.extend_from_slice(value);
+        Atom::leaf(b"data", data)
+    });
+
+    match key {
+        ItemKey::Atom(kind) => Atom::container(kind, data_atoms.collect()),
+        ItemKey::Freeform(name) => {
+            let mut mean = vec![0; 4];
+            mean.extend_from_slice(FREEFORM_MEAN.as_bytes());
+            let mut name_data = vec![0; 4];
+            name_data.extend_from_slice(name.as_bytes());
+            let mut children = vec![Atom::leaf(b"mean", mean), Atom::leaf(b"name", name_data)];
+            children.extend(data_atoms);
+            Atom::container(b"----", children)
+        }
+    }
+}
+
+fn handler_atom() -> Atom {
+    let mut data = vec![0; 8];
+    data.extend_from_slice(b"mdirappl");
+    data.extend_from_slice(&[0; 9]);
+    Atom::leaf(b"hdlr", data)
+}
+
+fn apply(moov: &mut Atom, edit: &TagEdit) {
+    let meta = moov
+        .child_mut(b"udta", || Atom::container(b"udta", Vec::new()))
+        .child_mut(b"meta", || Atom {
+            kind: *b"meta",
+            prefix: vec![0; 4],
+            body: Body::Container(vec![handler_atom()]),
+        });
+    let items = meta
+        .child_mut(b"ilst", || Atom::container(b"ilst", Vec::new()))
+        .children_mut()
+        .expect("ilst is a container");
+
+    for (key, value) in &edit.text {
+        let item = item_key(key);
+        let matches = |atom: &Atom| match (&item, atom.item_key()) {
+            (ItemKey::Freeform(a), ItemKey::Freeform(b)) => a.eq_ignore_ascii_case(&b),
+            (a, b) => *a == b,
+        };
+        items.retain(|atom| !matches(atom));
+        if key == "genre" {
+            // Drop the ID3v1 genre index so it doesn't shadow the text genre
+            items.retain(|atom| &atom.kind != b"gnre");
+        }
+
+        let Some(value) = value else { continue };
+        let atom = if key == "track" || key == "disc" {
+            let (number, total) = super::parse_track(value);
+            let mut data = vec![0, 0];
+            data.extend_from_slice(&(number.unwrap_or(0) as u16).to_be_bytes());
+            data.extend_from_slice(&(total.unwrap_or(0) as u16).to_be_bytes());
+            // trkn has two trailing padding bytes, disk doesn't
+            if key == "track" {
+                data.extend_from_slice(&[0, 0]);
+            }
+            data_item(&item, TYPE_IMPLICIT, &[&data])
+        } else {
+            let values: Vec<&[u8]> = value.split(super::SEPARATOR).map(str::as_bytes).collect();
+            data_item(&item, TYPE_UTF8, &values)
+        };
+        items.push(atom);
+    }
+
+    if let Some(cover) = &edit.cover {
+        items.retain(|atom| &atom.kind != b"covr");
+        if let Some(picture) = cover {
+            let data_type = if picture.mime_type == "image/png" {
+                TYPE_PNG
+            } else {
+                TYPE_JPEG
+            };
+            items.push(data_item(
+                &ItemKey::Atom(*b"covr"),
+                data_type,
+                &[&picture.data],
+            ));
+        }
+    }
+}
+
+pub fn write(path: &Path, edit: &TagEdit) -> Result<()> {
+    let file = fs::read(path).context("Failed to read MP4 file")?;
+
+    // Locate moov and whatever follows it at the top level
+    let mut pos = 0;
+    let mut moov = None;
+    let mut next = None;
+    while pos + 8 <= file.len() {
+        let (header_len, size) = atom_size(&file, pos)?;
+        let kind = &file[pos + 4..pos + 8];
+        if moov.is_some() && next.is_none() {
+            next = Some((kind.to_vec(), pos, size));
+        }
+        if kind == b"moov" {
+            moov = Some((pos, header_len, size));
+        }
+        pos += size;
+    }
+    let (moov_start, header_len, old_size) =
+        moov.ok_or_else(|| anyhow::anyhow!("No moov atom found"))?;
+    let moov_end = moov_start + old_size;
+
+    let mut atom = Atom::container(
+        b"moov",
+        parse_atoms(&file[moov_start + header_len..moov_end])?,
+    );
+    apply(&mut atom, edit);
+    let new_size = atom.size();
+
+    // Reuse a free atom after moov (or leave one behind) so media data doesn't move
+    let free_after = match &next {
+        Some((kind, _, size)) if kind == b"free" || kind == b"skip" => *size,
+        _ => 0,
+    };
+    let available = old_size + free_after;
+    if new_size == available || new_size + 8 <= available {
+        let mut out = Vec::with_capacity(available);
+        atom.write_to(&mut out);
+        if available > new_size {
+            out.extend_from_slice(&((available - new_size) as u32).to_be_bytes());
+            out.extend_from_slice(b"free");
+            out.resize(available, 0);
+        }
+
+        let mut f = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .context("Failed to open MP4 file for writing")?;
+        f.seek(SeekFrom::Start(moov_start as u64))?;
+        f.write_all(&out)?;
+        return Ok(());
+    }
+
+    // Media data after moov moves, so chunk offsets pointing there move with it
+    let delta = new_size as i64 - old_size as i64;
+    atom.shift_chunk_offsets(moov_end as u64, delta)?;
+
+    let mut data = Vec::with_capacity(file.len() + delta.max(0) as usize);
+    data.extend_from_slice(&file[..moov_start]);
+    atom.write_to(&mut data);
+    data.extend_from_slice(&file[moov_end..]);
+    super::replace_file(path, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// ftyp, moov (one stco pointing into mdat), mdat
+    fn mp4_file(moov_first: bool) -> Vec<u8> {
+        let ftyp = Atom::leaf(b"ftyp", b"M4A \0\0\0\0".to_vec());
+        let mdat = Atom::leaf(b"mdat", b"AUDIO".to_vec());
+
+        let build = |chunk_offset: u32| {
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend_from_slice(&chunk_offset.to_be_bytes());
+            let stbl = Atom::container(b"stbl", vec![Atom::leaf(b"stco", stco)]);
+            let minf = Atom::container(b"minf", vec![stbl]);
+            let mdia = Atom::container(b"mdia", vec![minf]);
+            let trak = Atom::container(b"trak", vec![mdia]);
+            Atom::container(b"moov", vec![trak])
+        };
+
+        let mut out = Vec::new();
+        ftyp.write_to(&mut out);
+        if moov_first {
+            let moov_size = build(0).size();
+            let offset = out.len() + moov_size + 8;
+            build(offset as u32).write_to(&mut out);
+            mdat.write_to(&mut out);
+        } else {
+            let offset = out.len() + 8;
+            mdat.write_to(&mut out);
+            build(offset as u32).write_to(&mut out);
+        }
+        out
+    }
+
+    fn read_moov(file: &[u8]) -> Atom {
+        let atoms = parse_atoms(file).unwrap();
+        atoms.into_iter().find(|a| &a.kind == b"moov").unwrap()
+    }
+
+    fn chunk_offset(moov: &Atom) -> usize {
+        fn find(atom: &Atom) -> Option<Vec<u8>> {
+            match &atom.body {
+                Body::Leaf(data) if &atom.kind == b"stco" => Some(data.clone()),
+                Body::Container(children) => children.iter().find_map(find),
+                _ => None,
+            }
+        }
+        let stco = find(moov).unwrap();
+        u32::from_be_bytes(stco[8..12].try_into().unwrap()) as usize
+    }
+
+    fn items(moov: &mut Atom) -> Vec<Atom> {
+        let meta = moov
+            .child_mut(b"udta", || unreachable!())
+            .child_mut(b"meta", || unreachable!());
+        meta.child_mut(b"ilst", || unreachable!())
+            .children_mut()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_write_moves_chunk_offsets() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.m4a");
+        fs::write(&path, mp4_file(true)).unwrap();
+
+        let edit = TagEdit::new()
+            .set("title", "Song")
+            .set("track", "3/12")
+            .set("musicbrainz_trackid", "abc")
+            .set_values(
+                "musicbrainz_artistid",
+                &["a1".to_string(), "a2".to_string()],
+            );
+        write(&path, &edit).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let mut moov = read_moov(&data);
+        let offset = chunk_offset(&moov);
+        assert_eq!(&data[offset..offset + 5], b"AUDIO");
+
+        let items = items(&mut moov);
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].item_key(), ItemKey::Atom(*b"\xa9nam"));
+        assert_eq!(
+            items[2].item_key(),
+            ItemKey::Freeform("MusicBrainz Track Id".to_string())
+        );
+        assert_eq!(
+            items[3].item_key(),
+            ItemKey::Freeform("MusicBrainz Artist Id".to_string())
+        );
+        let Body::Leaf(freeform) = &items[3].body else {
+            panic!("items are read as leaves");
+        };
+        let children = parse_atoms(freeform).unwrap();
+        assert_eq!(children.iter().filter(|a| &a.kind == b"data").count(), 2);
+    }
+
+    #[test]
+    fn test_shrinking_edit_stays_in_place() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.m4a");
+        fs::write(&path, mp4_file(true)).unwrap();
+
+        write(
+            &path,
+            &TagEdit::new().set("title", "A long title to remove"),
+        )
+        .unwrap();
+        let grown = fs::read(&path).unwrap();
+
+        write(&path, &TagEdit::new().set("title", "Short")).unwrap();
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), grown.len());
+
+        let mut moov = read_moov(&data);
+        let offset = chunk_offset(&moov);
+        assert_eq!(&data[offset..offset + 5], b"AUDIO");
+        assert_eq!(items(&mut moov).len(), 1);
+    }
+
+    #[test]
+    fn test_moov_after_mdat_keeps_offsets() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.m4a");
+        fs::write(&path, mp4_file(false)).unwrap();
+
+        write(&path, &TagEdit::new().set("album", "LP").remove("genre")).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let mut moov = read_moov(&data);
+        let offset = chunk_offset(&moov);
+        assert_eq!(&data[offset..offset + 5], b"AUDIO");
+        assert_eq!(items(&mut moov).len(), 1);
+    }
+}
@@ -0,0 +1,337 @@
+// Comment headers of Ogg Vorbis and Ogg Opus streams
+// References: https://xiph.org/ogg/doc/framing.html, RFC 7845 section 5.2
+use super::vorbis::Comments;
+use super::TagEdit;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+const MAX_SEGMENTS: usize = 255;
+
+struct Page {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    /// Number of header packets before audio starts
+    fn header_packets(self) -> usize {
+        match self {
+            Codec::Vorbis => 3,
+            Codec::Opus => 2,
+        }
+    }
+
+    /// Bytes before the comment block in the comment packet
+    fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus => b"OpusTags",
+        }
+    }
+}
+
+pub fn write(path: &Path, edit: &TagEdit) -> Result<()> {
+    let file = fs::read(path).context("Failed to read Ogg file")?;
+    let pages = parse_pages(&file)?;
+
+    let serial = pages[0].serial;
+    if pages.iter().any(|p| p.serial != serial) {
+        anyhow::bail!("Multiplexed or chained Ogg streams are not supported");
+    }
+
+    // Collect header packets; they end on a page boundary before the first audio page
+    let first_packet = pages[0].data.as_slice();
+    let codec = if first_packet.starts_with(b"\x01vorbis") {
+        Codec::Vorbis
+    } else if first_packet.starts_with(b"OpusHead") {
+        Codec::Opus
+    } else {
+        anyhow::bail!("Unsupported Ogg codec");
+    };
+
+    // The first page holds nothing but the identification header
+    let first_page = &pages[0].segments;
+    if first_page.iter().filter(|lace| **lace < 255).count() != 1
+        || first_page.last().is_none_or(|lace| *lace == 255)
+    {
+        anyhow::bail!("Unexpected Ogg header layout");
+    }
+
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut current = Vec::new();
+    let mut header_pages = 0;
+    for page in &pages {
+        header_pages += 1;
+        let mut offset = 0;
+        for &lace in &page.segments {
+            current.extend_from_slice(&page.data[offset..offset + lace as usize]);
+            offset += lace as usize;
+            if lace < 255 {
+                packets.push(std::mem::take(&mut current));
+            }
+        }
+        if packets.len() >= codec.header_packets() {
+            break;
+        }
+    }
+    if packets.len() != codec.header_packets() || !current.is_empty() {
+        anyhow::bail!("Unexpected Ogg header layout");
+    }
+
+    // Rewrite the comment packet
+    let magic = codec.comment_magic();
+    let comment_packet = &packets[1];
+    if !comment_packet.starts_with(magic) {
+        anyhow::bail!("Missing Ogg comment header");
+    }
+    let mut comments = Comments::parse(&comment_packet[magic.len()..])?;
+    comments.apply(edit, true);
+
+    let mut new_comment = magic.to_vec();
+    new_comment.extend_from_slice(&comments.to_bytes());
+    if codec == Codec::Vorbis {
+        new_comment.push(1); // Framing bit
+    }
+    packets[1] = new_comment;
+
+    // Page 0 keeps the identification header, the other headers are paginated again
+    let mut new_pages = vec![Page {
+        segments: pages[0].segments.clone(),
+        data: pages[0].data.clone(),
+        ..pages[0]
+    }];
+    new_pages.extend(paginate(&packets[1..], serial));
+
+    let mut out = Vec::with_capacity(file.len() + 1024);
+    for (sequence, page) in new_pages.iter_mut().enumerate() {
+        page.sequence = sequence as u32;
+        write_page(&mut out, page);
+    }
+
+    let shift = new_pages.len() as i64 - header_pages as i64;
+    for page in &pages[header_pages..] {
+        let page = Page {
+            sequence: (page.sequence as i64 + shift) as u32,
+            segments: page.segments.clone(),
+            data: page.data.clone(),
+            ..*page
+        };
+        write_page(&mut out, &page);
+    }
+
+    super::replace_file(path, &out)
+}
+
+fn parse_pages(file: &[u8]) -> Result<Vec<Page>> {
+    let mut pages = Vec::new();
+    let mut pos = 0;
+
+    while pos < file.len() {
+        let header = file
+            .get(pos..pos + 27)
+            .filter(|h| h.starts_with(b"OggS"))
+            .ok_or_else(|| anyhow::anyhow!("Invalid Ogg page at offset {}", pos))?;
+        let segment_count = header[26] as usize;
+        let segments = file
+            .get(pos + 27..pos + 27 + segment_count)
+            .ok_or_else(|| anyhow::anyhow!("Truncated Ogg page"))?
+            .to_vec();
+        let data_len: usize = segments.iter().map(|s| *s as usize).sum();
+        let data_start = pos + 27 + segment_count;
+        let data = file
+            .get(data_start..data_start + data_len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated Ogg page"))?
+            .to_vec();
+
+        pages.push(Page {
+            header_type: header[5],
+            granule: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+            serial: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+            sequence: u32::from_le_bytes(header[18..22].try_into().unwrap()),
+            segments,
+            data,
+        });
+        pos = data_start + data_len;
+    }
+
+    if pages.is_empty() {
+        anyhow::bail!("Empty Ogg file");
+    }
+    Ok(pages)
+}
+
+/// Lay header packets out on pages, the last page ending with the last packet
+fn paginate(packets: &[Vec<u8>], serial: u32) -> Vec<Page> {
+    let mut pages = Vec::new();
+    let mut page = Page {
+        header_type: 0,
+        granule: 0,
+        serial,
+        sequence: 0,
+        segments: Vec::new(),
+        data: Vec::new(),
+    };
+
+    for packet in packets {
+        let mut laces = vec![255u8; packet.len() / 255];
+        laces.push((packet.len() % 255) as u8);
+
+        let mut offset = 0;
+        for lace in laces {
+            if page.segments.len() == MAX_SEGMENTS {
+                // No packet finishes on this page, mark its granule position as unset
+                let continued = page.segments.last() == Some(&255);
+                if page.segments.iter().all(|s| *s == 255) {
+                    page.granule = u64::MAX;
+                }
+                pages.push(std::mem::replace(
+                    &mut page,
+                    Page {
+                        header_type: if continued { 0x01 } else { 0 },
+                        granule: 0,
+                        serial,
+                        sequence: 0,
+                        segments: Vec::new(),
+                        data: Vec::new(),
+                    },
+                ));
+            }
+            page.segments.push(lace);
+            page.data
+                .extend_from_slice(&packet[offset..offset + lace as usize]);
+            offset += lace as usize;
+        }
+    }
+
+    pages.push(page);
+    pages
+}
+
+fn write_page(out: &mut Vec<u8>, page: &Page) {
+    let start = out.len();
+    out.extend_from_slice(b"OggS");
+    out.push(0);
+    out.push(page.header_type);
+    out.extend_from_slice(&page.granule.to_le_bytes());
+    out.extend_from_slice(&page.serial.to_le_bytes());
+    out.extend_from_slice(&page.sequence.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.push(page.segments.len() as u8);
+    out.extend_from_slice(&page.segments);
+    out.extend_from_slice(&page.data);
+
+    let crc = crc32(&out[start..]);
+    out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Ogg page checksum (CRC-32, polynomial 0x04C11DB7, no reflection)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn opus_file(comments: &Comments) -> Vec<u8> {
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&comments.to_bytes());
+
+        let mut out = Vec::new();
+        let mut head = paginate(&[b"OpusHead\x01\x02".to_vec()], 7);
+        head[0].header_type = 0x02;
+        let mut pages = head;
+        pages.extend(paginate(&[tags], 7));
+        pages.extend(paginate(&[vec![0xAB; 300]], 7));
+        for (i, page) in pages.iter_mut().enumerate() {
+            page.sequence = i as u32;
+            write_page(&mut out, page);
+        }
+        out
+    }
+
+    fn read_comments(file: &[u8]) -> Comments {
+        let pages = parse_pages(file).unwrap();
+        let mut packet = Vec::new();
+        for page in &pages[1..] {
+            packet.extend_from_slice(&page.data);
+            if page.segments.last() != Some(&255) {
+                break;
+            }
+        }
+        Comments::parse(&packet[8..]).unwrap()
+    }
+
+    #[test]
+    fn test_crc32() {
+        // CRC-32/POSIX check value without the final inversion
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0x765E_7680 ^ 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn test_rewrite_comments_and_renumber_pages() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("song.opus");
+        let comments = Comments {
+            vendor: "test".to_string(),
+            fields: vec![("TITLE".to_string(), "Old".to_string())],
+        };
+        fs::write(&path, opus_file(&comments)).unwrap();
+
+        // A large picture forces the comment header across several pages
+        let picture = super::super::Picture {
+            mime_type: "image/jpeg".to_string(),
+            data: vec![7; 200_000],
+            width: 1,
+            height: 1,
+        };
+        write(&path, &TagEdit::new().set("title", "New").cover(picture)).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let pages = parse_pages(&data).unwrap();
+        assert!(pages.len() > 4);
+        for (i, page) in pages.iter().enumerate() {
+            assert_eq!(page.sequence, i as u32);
+        }
+        assert_eq!(pages.last().unwrap().data, vec![0xAB; 300]);
+
+        let fields = read_comments(&data).fields;
+        assert_eq!(fields[0], ("TITLE".to_string(), "New".to_string()));
+        assert_eq!(fields[1].0, "METADATA_BLOCK_PICTURE");
+
+        // Every page checksum must verify
+        let mut pos = 0;
+        for page in &pages {
+            let len = 27 + page.segments.len() + page.data.len();
+            let mut raw = data[pos..pos + len].to_vec();
+            let stored = u32::from_le_bytes(raw[22..26].try_into().unwrap());
+            raw[22..26].copy_from_slice(&[0; 4]);
+            assert_eq!(crc32(&raw), stored);
+            pos += len;
+        }
+    }
+}
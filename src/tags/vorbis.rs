@@ -0,0 +1,200 @@
+// Vorbis comments, shared by FLAC and Ogg
+// Reference: https://xiph.org/vorbis/doc/v-comment.html
+use super::TagEdit;
+use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
+
+/// Decoded comment block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Comments {
+    pub vendor: String,
+    pub fields: Vec<(String, String)>,
+}
+
+/// Vorbis comment field name for a ferric tag name
+pub fn field_name(key: &str) -> String {
+    match key {
+        "title" => "TITLE".to_string(),
+        "artist" => "ARTIST".to_string(),
+        "album" => "ALBUM".to_string(),
+        "album_artist" => "ALBUMARTIST".to_string(),
+        "date" => "DATE".to_string(),
+        "genre" => "GENRE".to_string(),
+        "track" => "TRACKNUMBER".to_string(),
+        "disc" => "DISCNUMBER".to_string(),
+        "musicbrainz_trackid" => "MUSICBRAINZ_TRACKID".to_string(),
+        "musicbrainz_albumid" => "MUSICBRAINZ_ALBUMID".to_string(),
+        "musicbrainz_releasetrackid" => "MUSICBRAINZ_RELEASETRACKID".to_string(),
+        other => other.to_uppercase(),
+    }
+}
+
+/// Collapse separators so "ALBUM ARTIST", "album_artist" and "ALBUMARTIST" match
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl Comments {
+    /// Parse a comment block (without any packet or block header)
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        let vendor = reader.string()?;
+        let count = reader.u32()?;
+
+        let mut fields = Vec::new();
+        for _ in 0..count {
+            let field = reader.string()?;
+            if let Some((name, value)) = field.split_once('=') {
+                fields.push((name.to_string(), value.to_string()));
+            }
+        }
+
+        Ok(Self { vendor, fields })
+    }
+
+    /// Serialize to a comment block (without framing bit)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.vendor.as_bytes());
+        out.extend_from_slice(&(self.fields.len() as u32).to_le_bytes());
+        for (name, value) in &self.fields {
+            let field = format!("{}={}", name, value);
+            out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+        out
+    }
+
+    /// Apply the text changes of an edit
+    ///
+    /// Existing fields that only differ in case or separators are replaced too. Pictures
+    /// are only touched when `embed_pictures` is set (Ogg keeps them as comments, FLAC
+    /// has separate picture blocks).
+    pub fn apply(&mut self, edit: &TagEdit, embed_pictures: bool) {
+        for (key, value) in &edit.text {
+            // "3/12" goes into TRACKNUMBER and TRACKTOTAL (same for discs)
+            let totals = match key.as_str() {
+                "track" => Some(["TRACKTOTAL", "TOTALTRACKS"]),
+                "disc" => Some(["DISCTOTAL", "TOTALDISCS"]),
+                _ => None,
+            };
+            if let (Some(totals), Some((number, total))) =
+                (totals, value.as_deref().and_then(|v| v.split_once('/')))
+            {
+                self.set(&field_name(key), Some(number.trim()));
+                self.set(totals[1], None);
+                self.set(totals[0], Some(total.trim()));
+                continue;
+            }
+            self.set(&field_name(key), value.as_deref());
+        }
+
+        if embed_pictures {
+            if let Some(cover) = &edit.cover {
+                self.fields.retain(|(n, _)| {
+                    let n = n.to_uppercase();
+                    n != "METADATA_BLOCK_PICTURE" && n != "COVERART" && n != "COVERARTMIME"
+                });
+                if let Some(picture) = cover {
+                    self.fields.push((
+                        "METADATA_BLOCK_PICTURE".to_string(),
+                        general_purpose::STANDARD.encode(picture.to_flac_block()),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Replace every field with this name, however it's spelled
+    ///
+    /// Multiple values become one field each.
+    fn set(&mut self, name: &str, value: Option<&str>) {
+        let normalized = normalize(name);
+        self.fields.retain(|(n, _)| normalize(n) != normalized);
+        for value in value.into_iter().flat_map(|v| v.split(super::SEPARATOR)) {
+            self.fields.push((name.to_string(), value.to_string()));
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| anyhow::anyhow!("Truncated Vorbis comment block"))?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated Vorbis comment block"))?;
+        self.pos += len;
+        Ok(String::from_utf8_lossy(bytes).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_and_apply() {
+        let mut comments = Comments {
+            vendor: "ferric".to_string(),
+            fields: vec![
+                ("Album Artist".to_string(), "Old".to_string()),
+                ("GENRE".to_string(), "Rock".to_string()),
+                ("COMMENT".to_string(), "keep".to_string()),
+            ],
+        };
+        assert_eq!(Comments::parse(&comments.to_bytes()).unwrap(), comments);
+
+        let edit = TagEdit::new().set("album_artist", "New").remove("genre");
+        comments.apply(&edit, true);
+
+        assert_eq!(
+            comments.fields,
+            vec![
+                ("COMMENT".to_string(), "keep".to_string()),
+                ("ALBUMARTIST".to_string(), "New".to_string()),
+            ]
+        );
+
+        comments
+            .fields
+            .push(("TOTALTRACKS".to_string(), "10".to_string()));
+        comments.apply(&TagEdit::new().set("track", "3/12").set("disc", "1"), true);
+        assert_eq!(
+            comments.fields[2..],
+            [
+                ("TRACKNUMBER".to_string(), "3".to_string()),
+                ("TRACKTOTAL".to_string(), "12".to_string()),
+                ("DISCNUMBER".to_string(), "1".to_string()),
+            ]
+        );
+
+        let artists = ["A".to_string(), "B".to_string()];
+        comments.apply(&TagEdit::new().set_values("artists", &artists), true);
+        assert_eq!(
+            comments.fields[5..],
+            [
+                ("ARTISTS".to_string(), "A".to_string()),
+                ("ARTISTS".to_string(), "B".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use crate::metadata::AudioMetadata;
+use anyhow::Result;
+
+/// Fields that can be referenced from a path template
+pub const KNOWN_FIELDS: &[&str] = &[
+    "artist",
+    "albumartist",
+    "album",
+    "title",
+    "track",
+    "tracktotal",
+    "disc",
+    "disctotal",
+    "year",
+    "date",
+    "originalyear",
+    "originaldate",
+    "genre",
+    "composer",
+    "label",
+    "catalognumber",
+    "isrc",
+    "artistsort",
+    "albumartistsort",
+    "albumsort",
+    "titlesort",
+    "codec",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// Literal text copied as-is
+    Literal(String),
+    /// A `/` in the template, starts a new path component
+    Separator,
+    /// `$name` or `${name|other:default}`
+    Field {
+        names: Vec<String>,
+        default: Option<String>,
+    },
+    /// `[...]` - only rendered when every field inside it has a value
+    Optional(Vec<Node>),
+}
+
+/// A parsed `[naming] path_template`
+///
+/// Syntax:
+/// - `$field` or `${field}` inserts a metadata field (see [`KNOWN_FIELDS`])
+/// - `${albumartist|artist}` uses the first field that has a value
+/// - `${genre:Unsorted}` falls back to literal text when the field is missing
+/// - `[...]` is an optional segment, dropped entirely if any field inside it is missing
+/// - `/` separates path components; the last component is the file name (extension is added)
+/// - `\` escapes the next character (e.g. `\[` or `\$`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    nodes: Vec<Node>,
+}
+
+impl PathTemplate {
+    /// Parse a template string, validating field names and brackets
+    pub fn parse(template: &str) -> Result<Self> {
+        if template.trim().is_empty() {
+            anyhow::bail!("Path template is empty");
+        }
+
+        let chars: Vec<char> = template.chars().collect();
+        let mut pos = 0;
+        let nodes = parse_nodes(&chars, &mut pos, false)?;
+
+        Ok(Self { nodes })
+    }
+
+    /// Render the template into path components using a field lookup function
+    ///
+    /// Components are returned raw (not sanitized). Components that render empty are kept,
+    /// callers decide how to handle them.
+    pub fn render_with<F>(&self, lookup: F) -> Vec<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut components = vec![String::new()];
+        render_nodes(&self.nodes, &lookup, &mut components);
+        components
+    }
+
+    /// Whether the template references a field anywhere (including fallbacks)
+    pub fn uses_field(&self, field: &str) -> bool {
+        fn visit(nodes: &[Node], field: &str) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Field { names, .. } => names.iter().any(|n| n == field),
+                Node::Optional(inner) => visit(inner, field),
+                _ => false,
+            })
+        }
+        visit(&self.nodes, field)
+    }
+
+    /// Render the template for a track's metadata
+    pub fn render(&self, metadata: &AudioMetadata) -> Vec<String> {
+        self.render_with(|field| field_value(metadata, field))
+    }
+}
+
+/// Look up a template field from metadata, returning None for missing or blank values
+pub fn field_value(metadata: &AudioMetadata, field: &str) -> Option<String> {
+    let value = match field {
+        "artist" => metadata.artist.clone(),
+        "albumartist" => metadata.album_artist.clone(),
+        "album" => metadata.album.clone(),
+        "title" => metadata.title.clone(),
+        "track" => metadata.track_number.map(|n| format!("{:02}", n)),
+        "tracktotal" => metadata.track_total.map(|n| n.to_string()),
+        "disc" => metadata.disc_number.map(|n| n.to_string()),
+        "disctotal" => metadata.disc_total.map(|n| n.to_string()),
+        "year" => metadata.date.as_deref().and_then(year_of),
+        "date" => metadata.date.clone(),
+        "originalyear" => metadata.original_date.as_deref().and_then(year_of),
+        "originaldate" => metadata.original_date.clone(),
+        "genre" => metadata.genre.clone(),
+        "composer" => metadata.composer.clone(),
+        "label" => metadata.label.clone(),
+        "catalognumber" => metadata.catalog_number.clone(),
+        "isrc" => metadata.isrc.clone(),
+        "artistsort" => metadata.artist_sort.clone(),
+        "albumartistsort" => metadata.album_artist_sort.clone(),
+        "albumsort" => metadata.album_sort.clone(),
+        "titlesort" => metadata.title_sort.clone(),
+        "codec" => Some(metadata.codec.clone()),
+        _ => None,
+    };
+
+    value.filter(|v| !v.trim().is_empty())
+}
+
+/// Leading four-digit year of a date tag
+fn year_of(date: &str) -> Option<String> {
+    let year: String = date.chars().take(4).collect();
+    if year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()) {
+        Some(year)
+    } else {
+        None
+    }
+}
+
+fn parse_nodes(chars: &[char], pos: &mut usize, in_optional: bool) -> Result<Vec<Node>> {
+    let mut nodes = Vec::new();
+    let mut literal = String::new();
+
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        match c {
+            '\\' => {
+                *pos += 1;
+                match chars.get(*pos) {
+                    Some(&next) => literal.push(next),
+                    None => anyhow::bail!("Path template ends with a dangling '\\'"),
+                }
+                *pos += 1;
+            }
+            '/' => {
+                flush_literal(&mut literal, &mut nodes);
+                nodes.push(Node::Separator);
+                *pos += 1;
+            }
+            '[' => {
+                flush_literal(&mut literal, &mut nodes);
+                *pos += 1;
+                let inner = parse_nodes(chars, pos, true)?;
+                nodes.push(Node::Optional(inner));
+            }
+            ']' => {
+                if !in_optional {
+                    anyhow::bail!("Unmatched ']' at position {} in path template", *pos);
+                }
+                flush_literal(&mut literal, &mut nodes);
+                *pos += 1;
+                return Ok(nodes);
+            }
+            '$' => {
+                flush_literal(&mut literal, &mut nodes);
+                *pos += 1;
+                nodes.push(parse_field(chars, pos)?);
+            }
+            _ => {
+                literal.push(c);
+                *pos += 1;
+            }
+        }
+    }
+
+    if in_optional {
+        anyhow::bail!("Unclosed '[' in path template");
+    }
+
+    flush_literal(&mut literal, &mut nodes);
+    Ok(nodes)
+}
+
+fn parse_field(chars: &[char], pos: &mut usize) -> Result<Node> {
+    let (names, default) = if chars.get(*pos) == Some(&'{') {
+        *pos += 1;
+        let start = *pos;
+        while *pos < chars.len() && chars[*pos] != '}' {
+            *pos += 1;
+        }
+        if *pos >= chars.len() {
+            anyhow::bail!("Unclosed '${{' in path template");
+        }
+        let body: String = chars[start..*pos].iter().collect();
+        *pos += 1;
+
+        let (fields, default) = match body.split_once(':') {
+            Some((fields, default)) => (fields.to_string(), Some(default.to_string())),
+            None => (body, None),
+        };
+        let names: Vec<String> = fields.split('|').map(|f| f.trim().to_lowercase()).collect();
+        (names, default)
+    } else {
+        let start = *pos;
+        while *pos < chars.len() && chars[*pos].is_ascii_alphabetic() {
+            *pos += 1;
+        }
+        let name: String = chars[start..*pos].iter().collect();
+        (vec![name.to_lowercase()], None)
+    };
+
+    for name in &names {
+        if name.is_empty() {
+            anyhow::bail!("Empty field name in path template");
+        }
+        if !KNOWN_FIELDS.contains(&name.as_str()) {
+            anyhow::bail!(
+                "Unknown field '{}' in path template (available: {})",
+                name,
+                KNOWN_FIELDS.join(", ")
+            );
+        }
+    }
+
+    Ok(Node::Field { names, default })
+}
+
+fn flush_literal(literal: &mut String, nodes: &mut Vec<Node>) {
+    if !literal.is_empty() {
+        nodes.push(Node::Literal(std::mem::take(literal)));
+    }
+}
+
+fn resolve_field<F>(names: &[String], default: &Option<String>, lookup: &F) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    names
+        .iter()
+        .find_map(|name| lookup(name))
+        .or_else(|| default.clone())
+}
+
+/// True if every field directly inside an optional segment resolves to a value
+fn all_fields_present<F>(nodes: &[Node], lookup: &F) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    nodes.iter().all(|node| match node {
+        Node::Field { names, default } => resolve_field(names, default, lookup).is_some(),
+        _ => true,
+    })
+}
+
+fn render_nodes<F>(nodes: &[Node], lookup: &F, components: &mut Vec<String>)
+where
+    F: Fn(&str) -> Option<String>,
+{
+    for node in nodes {
+        match node {
+            Node::Literal(text) => components.last_mut().unwrap().push_str(text),
+            Node::Separator => components.push(String::new()),
+            Node::Field { names, default } => {
+                if let Some(value) = resolve_field(names, default, lookup) {
+                    components.last_mut().unwrap().push_str(&value);
+                }
+            }
+            Node::Optional(inner) => {
+                if all_fields_present(inner, lookup) {
+                    render_nodes(inner, lookup, components);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> AudioMetadata {
+        AudioMetadata {
+            artist: Some("Guest Artist".to_string()),
+            album_artist: Some("Main Artist".to_string()),
+            album: Some("Record".to_string()),
+            title: Some("Song".to_string()),
+            track_number: Some(3),
+            date: Some("1999-05-01".to_string()),
+            codec: "flac".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_basic() {
+        let template = PathTemplate::parse("$albumartist/$year - $album/$track $title").unwrap();
+        assert_eq!(
+            template.render(&sample()),
+            vec!["Main Artist", "1999 - Record", "03 Song"]
+        );
+    }
+
+    #[test]
+    fn test_fallbacks_and_defaults() {
+        let mut metadata = sample();
+        metadata.album_artist = None;
+
+        let template =
+            PathTemplate::parse("${albumartist|artist}/${genre:Unsorted}/$title").unwrap();
+        assert_eq!(
+            template.render(&metadata),
+            vec!["Guest Artist", "Unsorted", "Song"]
+        );
+    }
+
+    #[test]
+    fn test_optional_segments() {
+        let template = PathTemplate::parse("$album/[$disc-]$track $title[ ($genre)]").unwrap();
+        assert_eq!(template.render(&sample()), vec!["Record", "03 Song"]);
+
+        let mut metadata = sample();
+        metadata.genre = Some("Jazz".to_string());
+        assert_eq!(template.render(&metadata), vec!["Record", "03 Song (Jazz)"]);
+
+        // Separators inside optional segments only appear when the segment renders
+        let template = PathTemplate::parse("$album/[$genre/]$title").unwrap();
+        assert_eq!(template.render(&sample()), vec!["Record", "Song"]);
+        assert_eq!(template.render(&metadata), vec!["Record", "Jazz", "Song"]);
+    }
+
+    #[test]
+    fn test_release_fields() {
+        let mut metadata = sample();
+        metadata.disc_number = Some(2);
+        metadata.disc_total = Some(2);
+        metadata.original_date = Some("1969".to_string());
+        metadata.label = Some("Apple".to_string());
+
+        let template = PathTemplate::parse(
+            "$album[ ($originalyear, $label)]/[Disc $disc of $disctotal/]$track $title",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&metadata),
+            vec!["Record (1969, Apple)", "Disc 2 of 2", "03 Song"]
+        );
+    }
+
+    #[test]
+    fn test_uses_field() {
+        let template = PathTemplate::parse("${albumartist|artist}/$album/[$disc-]$track").unwrap();
+        assert!(template.uses_field("disc"));
+        assert!(template.uses_field("artist"));
+        assert!(!template.uses_field("title"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PathTemplate::parse("").is_err());
+        assert!(PathTemplate::parse("$nope/$title").is_err());
+        assert!(PathTemplate::parse("[$album/$title").is_err());
+        assert!(PathTemplate::parse("$album]/$title").is_err());
+        assert!(PathTemplate::parse("${album/$title").is_err());
+    }
+
+    #[test]
+    fn test_escapes() {
+        let template = PathTemplate::parse("\\[$year\\] $album").unwrap();
+        assert_eq!(template.render(&sample()), vec!["[1999] Record"]);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::config::{Config, TrashConfig};
+use crate::journal;
+use crate::utils;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Trash batches are named after the local time their run started, in this format
+const BATCH_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+
+lazy_static! {
+    /// One trash batch per ferric run, named after the time it started
+    static ref BATCH_NAME: String = chrono::Local::now().format(BATCH_FORMAT).to_string();
+    /// Serializes manifest appends from parallel operations
+    static ref MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+const MANIFEST_FILE: &str = "manifest.jsonl";
+
+/// One file moved into the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    /// Where the file lived before it was trashed
+    pub original: PathBuf,
+    /// Where the file lives inside the trash batch
+    pub trashed: PathBuf,
+    pub size: u64,
+    pub trashed_at: i64,
+}
+
+/// A dated trash directory holding the files removed by one run
+#[derive(Debug, Clone)]
+pub struct TrashBatch {
+    pub name: String,
+    pub path: PathBuf,
+    pub entries: Vec<TrashEntry>,
+    pub size: u64,
+}
+
+/// Result of a purge
+#[derive(Debug, Default)]
+pub struct PurgeStats {
+    pub batches_removed: usize,
+    pub bytes_freed: u64,
+}
+
+/// Remove a losing duplicate: moved to the trash when enabled, deleted otherwise
+///
+/// `root_dir` is the operation's input directory; the file keeps its layout relative
+/// to it inside the trash batch. Symlinks are simply removed since they hold no data.
+pub fn discard(path: &Path, root_dir: &Path, config: &Config) -> io::Result<()> {
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+
+    if !config.trash.enabled || is_symlink {
+        return journal::remove_file(path);
+    }
+
+    let root = config.trash.resolve_path(&config.general.cache_path);
+    quarantine(path, root_dir, &root).map(|_| ())
+}
+
+/// Path of a file relative to the operation root, made safe to join under the trash
+fn relative_layout(path: &Path, root_dir: &Path) -> PathBuf {
+    let relative = path
+        .strip_prefix(root_dir)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf());
+
+    relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Move a file into the current trash batch and record it in the manifest and journal
+pub fn quarantine(path: &Path, root_dir: &Path, trash_root: &Path) -> io::Result<PathBuf> {
+    let batch_dir = trash_root.join(BATCH_NAME.as_str());
+    let dest = utils::unique_path(&batch_dir.join(relative_layout(path, root_dir)));
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let original = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let hash = journal::hash_if_recording(path);
+
+    // Trash may live on another filesystem, fall back to copy + delete
+    if fs::rename(path, &dest).is_err() {
+        fs::copy(path, &dest)?;
+        fs::remove_file(path)?;
+    }
+
+    let entry = TrashEntry {
+        original: original.clone(),
+        trashed: dest.clone(),
+        size,
+        trashed_at: chrono::Utc::now().timestamp(),
+    };
+    append_manifest(&batch_dir, &entry)?;
+    journal::record_trash(&original, &dest, &batch_dir, hash);
+
+    Ok(dest)
+}
+
+fn append_manifest(batch_dir: &Path, entry: &TrashEntry) -> io::Result<()> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(batch_dir.join(MANIFEST_FILE))?;
+    let line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    writeln!(file, "{}", line)
+}
+
+fn read_manifest(batch_dir: &Path) -> Vec<TrashEntry> {
+    fs::read_to_string(batch_dir.join(MANIFEST_FILE))
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn write_manifest(batch_dir: &Path, entries: &[TrashEntry]) -> io::Result<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry).map_err(io::Error::other)?);
+        content.push('\n');
+    }
+    fs::write(batch_dir.join(MANIFEST_FILE), content)
+}
+
+fn dir_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// List trash batches, oldest first
+pub fn list(trash_root: &Path) -> Result<Vec<TrashBatch>> {
+    if !trash_root.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut batches = Vec::new();
+    for entry in fs::read_dir(trash_root).context("Failed to read trash directory")? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        batches.push(TrashBatch {
+            name: entry.file_name().to_string_lossy().to_string(),
+            entries: read_manifest(&path),
+            size: dir_size(&path),
+            path,
+        });
+    }
+
+    // Batch names are timestamps, so name order is age order
+    batches.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(batches)
+}
+
+/// The batch with this name among the ones `list` finds
+///
+/// Names are batch folder names only, so one from the command line can't reach outside the
+/// trash.
+pub fn find(trash_root: &Path, name: &str) -> Result<TrashBatch> {
+    if name.contains(['/', '\\']) || name == "." || name == ".." {
+        anyhow::bail!("Invalid trash batch name '{}'", name);
+    }
+    list(trash_root)?
+        .into_iter()
+        .find(|batch| batch.name == name)
+        .ok_or_else(|| anyhow::anyhow!("No such trash batch '{}'", name))
+}
+
+/// Move trashed files back to their original locations
+///
+/// `only` restricts the restore to entries whose original path starts with the given path.
+/// Returns the restored entries; files whose original location is occupied are left in the trash.
+pub fn restore(trash_root: &Path, batch: &str, only: Option<&Path>) -> Result<Vec<TrashEntry>> {
+    let batch_dir = find(trash_root, batch)?.path;
+
+    let mut restored = Vec::new();
+    let mut remaining = Vec::new();
+
+    for entry in read_manifest(&batch_dir) {
+        let selected = only.is_none_or(|prefix| entry.original.starts_with(prefix));
+
+        if !selected {
+            remaining.push(entry);
+            continue;
+        }
+
+        if !entry.trashed.exists() {
+            // Already restored or removed by hand (e.g. through `undo`)
+            continue;
+        }
+
+        if entry.original.exists() {
+            crate::logger::warning(&format!(
+                "Not restoring {}: original location is occupied",
+                entry.original.display()
+            ));
+            remaining.push(entry);
+            continue;
+        }
+
+        if let Some(parent) = entry.original.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        if fs::rename(&entry.trashed, &entry.original).is_err() {
+            fs::copy(&entry.trashed, &entry.original)
+                .with_context(|| format!("Failed to restore {}", entry.original.display()))?;
+            fs::remove_file(&entry.trashed)?;
+        }
+        // Only empty folders: other files in the batch may still be waiting to be restored
+        utils::remove_empty_dirs(entry.trashed.parent(), &batch_dir);
+        restored.push(entry);
+    }
+
+    if remaining.is_empty() {
+        fs::remove_dir_all(&batch_dir).context("Failed to remove empty trash batch")?;
+    } else {
+        write_manifest(&batch_dir, &remaining)?;
+    }
+
+    Ok(restored)
+}
+
+/// Permanently delete the given batches
+pub fn purge_batches(batches: &[TrashBatch]) -> Result<PurgeStats> {
+    let mut stats = PurgeStats::default();
+    for batch in batches {
+        fs::remove_dir_all(&batch.path)
+            .with_context(|| format!("Failed to purge trash batch {}", batch.name))?;
+        stats.batches_removed += 1;
+        stats.bytes_freed += batch.size;
+    }
+    Ok(stats)
+}
+
+/// Select batches that exceed the configured age or push the trash over its size limit
+///
+/// `current_batch` (the batch this run writes to) is never selected, even when it alone is
+/// over the limit, so a run can't delete what it just quarantined.
+pub fn batches_to_auto_purge(
+    batches: &[TrashBatch],
+    config: &TrashConfig,
+    current_batch: &str,
+) -> Vec<TrashBatch> {
+    let mut purge = Vec::new();
+    let mut kept = Vec::new();
+
+    for batch in batches.iter().filter(|b| b.name != current_batch) {
+        let age_days = batch.age_days().unwrap_or(0);
+
+        if config.max_age_days > 0 && age_days >= config.max_age_days {
+            purge.push(batch.clone());
+        } else {
+            kept.push(batch);
+        }
+    }
+
+    if config.max_size_gb > 0.0 {
+        let limit = (config.max_size_gb * 1024.0 * 1024.0 * 1024.0) as u64;
+        let current_size: u64 = batches
+            .iter()
+            .filter(|b| b.name == current_batch)
+            .map(|b| b.size)
+            .sum();
+        let mut total: u64 = current_size + kept.iter().map(|b| b.size).sum::<u64>();
+
+        // Oldest first
+        for batch in kept {
+            if total <= limit {
+                break;
+            }
+            total -= batch.size;
+            purge.push(batch.clone());
+        }
+    }
+
+    purge
+}
+
+/// Apply the configured age and size limits to the trash
+pub fn auto_purge(config: &Config) -> Result<PurgeStats> {
+    let root = config.trash.resolve_path(&config.general.cache_path);
+    let batches = list(&root)?;
+    purge_batches(&batches_to_auto_purge(
+        &batches,
+        &config.trash,
+        BATCH_NAME.as_str(),
+    ))
+}
+
+impl TrashBatch {
+    /// Whole days since the batch's run started, read from its name; restoring or trashing
+    /// files changes the folder's mtime, so that can't be used. None for folders not named
+    /// like a batch.
+    pub fn age_days(&self) -> Option<u64> {
+        let started = chrono::NaiveDateTime::parse_from_str(&self.name, BATCH_FORMAT).ok()?;
+        let started = started.and_local_timezone(chrono::Local).earliest()?;
+        let age = chrono::Local::now().signed_duration_since(started);
+        Some(age.num_days().max(0) as u64)
+    }
+
+    pub fn print_row(&self) {
+        crate::logger::plain(&format!(
+            "  {}  {} files  {:.1} MB",
+            self.name,
+            self.entries.len(),
+            self.size as f64 / (1024.0 * 1024.0)
+        ));
+    }
+}
+
+impl PurgeStats {
+    pub fn print(&self) {
+        crate::logger::plain(&format!(
+            "Purged {} trash batch(es), freed {:.1} MB",
+            self.batches_removed,
+            self.bytes_freed as f64 / (1024.0 * 1024.0)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_quarantine_and_restore() {
+        let temp = TempDir::new().unwrap();
+        let library = temp.path().join("library");
+        let trash_root = temp.path().join("trash");
+        let file = library.join("Artist/Album/01 - Song.mp3");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, b"audio").unwrap();
+
+        let trashed = quarantine(&file, &library, &trash_root).unwrap();
+        assert!(!file.exists());
+        assert!(trashed.ends_with("Artist/Album/01 - Song.mp3"));
+
+        let batches = list(&trash_root).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].entries.len(), 1);
+
+        let restored = restore(&trash_root, &batches[0].name, None).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert!(file.exists());
+        assert!(list(&trash_root).unwrap().is_empty());
+
+        // Only names of existing batches are accepted
+        fs::create_dir_all(library.join("Other")).unwrap();
+        assert!(restore(&trash_root, "../library", None).is_err());
+        assert!(restore(&trash_root, "..", None).is_err());
+        assert!(restore(&trash_root, "2001-01-01_000000", None).is_err());
+        assert!(library.join("Other").exists());
+    }
+
+    #[test]
+    fn test_relative_layout_outside_root() {
+        let layout = relative_layout(Path::new("/elsewhere/a/b.flac"), Path::new("/library"));
+        assert_eq!(layout, PathBuf::from("elsewhere/a/b.flac"));
+    }
+
+    #[test]
+    fn test_batches_to_auto_purge() {
+        let batch = |age_days: i64, size: u64| {
+            let started = chrono::Local::now() - chrono::Duration::days(age_days);
+            let name = started.format(BATCH_FORMAT).to_string();
+            TrashBatch {
+                path: PathBuf::from(&name),
+                name,
+                entries: Vec::new(),
+                size,
+            }
+        };
+        let names =
+            |purge: Vec<TrashBatch>| -> Vec<String> { purge.into_iter().map(|b| b.name).collect() };
+        let batches = vec![batch(40, 10), batch(5, 600), batch(1, 600)];
+        let (a, b, c) = (&batches[0].name, &batches[1].name, &batches[2].name);
+        assert_eq!(batches[0].age_days(), Some(40));
+
+        let config = TrashConfig {
+            max_age_days: 30,
+            max_size_gb: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(batches_to_auto_purge(&batches, &config, "")),
+            vec![a.clone()]
+        );
+
+        // 1000 byte limit: "a" goes by age, then "b" (oldest) until under the limit
+        let config = TrashConfig {
+            max_age_days: 30,
+            max_size_gb: 1000.0 / (1024.0 * 1024.0 * 1024.0),
+            ..Default::default()
+        };
+        let purged = names(batches_to_auto_purge(&batches, &config, ""));
+        assert_eq!(purged, vec![a.clone(), b.clone()]);
+
+        // The current batch alone is over the limit: older batches go, it stays
+        let purged = names(batches_to_auto_purge(&batches, &config, c));
+        assert_eq!(purged, vec![a.clone(), b.clone()]);
+
+        let batches = vec![batch(0, 5000)];
+        assert!(batches_to_auto_purge(&batches, &config, &batches[0].name).is_empty());
+
+        // Folders not named like a batch have no age to expire by
+        let undated = TrashBatch {
+            name: "kept".to_string(),
+            ..batch(40, 10)
+        };
+        assert_eq!(undated.age_days(), None);
+        assert!(batches_to_auto_purge(&[undated], &config, "").is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::Config;
 use std::path::{Path, PathBuf};
 use std::fs;
 
@@ -55,7 +56,7 @@ pub fn normalize_for_comparison(s: &str) -> String {
     let mut result = s.to_lowercase();
 
     // Replace curly apostrophes with straight ones first
-    result = result.replace('\u{2019}', "'").replace('\u{2018}', "'");
+    result = result.replace(['\u{2019}', '\u{2018}'], "'");
 
     // Common replacements to improve matching
     result = result.replace("&", "and");
@@ -178,22 +179,37 @@ pub fn normalize_name(name: &str, lowercase: bool) -> String {
     result.trim().to_string()
 }
 
+/// Compute a BLAKE3 hash of a file's contents (hex encoded)
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut file = fs::File::open(path)?;
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
 /// Recursively remove empty directories and directories containing only non-audio files
 ///
 /// This function cleans up a directory after files have been moved out of it.
 /// It will:
-/// 1. Remove any leftover non-audio files (like .png, .jpg, .txt)
+/// 1. Move any leftover non-audio files (like .png, .jpg, .txt) to the trash, or delete
+///    them when the trash is disabled, so undo can bring them back
 /// 2. Remove the directory if it's empty or only had non-audio files
 /// 3. Recursively check parent directories up to (but not including) the root
 ///
 /// # Arguments
 /// * `dir_path` - The directory to check and potentially remove
 /// * `root_dir` - The root directory to stop at (will not be removed)
+/// * `config` - Trash settings for leftover files
 /// * `verbose` - Whether to log debug messages
 ///
 /// # Returns
 /// The number of directories removed
-pub fn cleanup_empty_directory(dir_path: &Path, root_dir: &Path, verbose: bool) -> usize {
+pub fn cleanup_empty_directory(
+    dir_path: &Path,
+    root_dir: &Path,
+    config: &Config,
+    verbose: bool,
+) -> usize {
     let mut removed_count = 0;
 
     // Don't remove the root directory itself
@@ -216,7 +232,7 @@ pub fn cleanup_empty_directory(dir_path: &Path, root_dir: &Path, verbose: bool)
             if remaining_files.is_empty() || only_non_audio {
                 // Remove any leftover non-audio files first
                 for file in remaining_files.iter().filter(|p| p.is_file()) {
-                    if let Err(e) = fs::remove_file(file) {
+                    if let Err(e) = crate::trash::discard(file, root_dir, config) {
                         if verbose {
                             crate::logger::debug(
                                 &format!("Failed to remove leftover file {}: {}", file.display(), e),
@@ -244,7 +260,8 @@ pub fn cleanup_empty_directory(dir_path: &Path, root_dir: &Path, verbose: bool)
 
                         // Recursively check parent directory
                         if let Some(parent) = dir_path.parent() {
-                            removed_count += cleanup_empty_directory(parent, root_dir, verbose);
+                            removed_count +=
+                                cleanup_empty_directory(parent, root_dir, config, verbose);
                         }
                     }
                     Err(e) => {
@@ -271,10 +288,29 @@ pub fn cleanup_empty_directory(dir_path: &Path, root_dir: &Path, verbose: bool)
     removed_count
 }
 
+/// Remove `dir` and its parents while they are empty, stopping at `stop` (which is kept)
+pub fn remove_empty_dirs(dir: Option<&Path>, stop: &Path) {
+    let mut current = dir;
+    while let Some(dir) = current.filter(|d| d.starts_with(stop) && *d != stop) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        current = dir.parent();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Config with the trash at `trash`, or disabled
+    fn trash_config(trash: Option<&Path>) -> Config {
+        let mut config = Config::default();
+        config.trash.enabled = trash.is_some();
+        config.trash.path = trash.map(Path::to_path_buf);
+        config
+    }
+
     #[test]
     fn test_sanitize() {
         assert_eq!(sanitize("Artist / Album"), "Artist – Album");
@@ -295,6 +331,21 @@ mod tests {
         assert_eq!(normalize_for_comparison("Can't Stop!!!"), "cant stop");
     }
 
+    #[test]
+    fn test_hash_file() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let a = temp.path().join("a.flac");
+        let b = temp.path().join("b.flac");
+        fs::write(&a, b"same bytes").unwrap();
+        fs::write(&b, b"same bytes").unwrap();
+
+        assert_eq!(hash_file(&a).unwrap(), hash_file(&b).unwrap());
+
+        fs::write(&b, b"other bytes").unwrap();
+        assert_ne!(hash_file(&a).unwrap(), hash_file(&b).unwrap());
+        assert!(hash_file(&temp.path().join("missing.flac")).is_err());
+    }
+
     #[test]
     fn test_is_audio_file() {
         assert!(is_audio_file(Path::new("song.mp3")));
@@ -325,7 +376,7 @@ mod tests {
         fs::create_dir_all(&dir1).unwrap();
 
         // Cleanup should remove all nested directories
-        let removed = cleanup_empty_directory(&dir1, root, false);
+        let removed = cleanup_empty_directory(&dir1, root, &trash_config(None), false);
         assert_eq!(removed, 3); // level3, level2, level1
         assert!(!root.join("level1").exists());
     }
@@ -335,7 +386,8 @@ mod tests {
         use tempfile::TempDir;
 
         let temp = TempDir::new().unwrap();
-        let root = temp.path();
+        let root = temp.path().join("library");
+        let trash = temp.path().join("trash");
 
         let dir1 = root.join("test_dir");
         fs::create_dir_all(&dir1).unwrap();
@@ -344,11 +396,15 @@ mod tests {
         let cover = dir1.join("cover.png");
         fs::write(&cover, "fake image").unwrap();
 
-        // Cleanup should remove the non-audio file and directory
-        let removed = cleanup_empty_directory(&dir1, root, false);
+        // Cleanup should move the non-audio file to the trash and remove the directory
+        let removed = cleanup_empty_directory(&dir1, &root, &trash_config(Some(&trash)), false);
         assert_eq!(removed, 1);
         assert!(!cover.exists());
         assert!(!dir1.exists());
+
+        let batches = crate::trash::list(&trash).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].entries[0].trashed.ends_with("test_dir/cover.png"));
     }
 
     #[test]
@@ -366,7 +422,7 @@ mod tests {
         fs::write(&audio, "fake audio").unwrap();
 
         // Cleanup should NOT remove directory with audio
-        let removed = cleanup_empty_directory(&dir1, root, false);
+        let removed = cleanup_empty_directory(&dir1, root, &trash_config(None), false);
         assert_eq!(removed, 0);
         assert!(dir1.exists());
         assert!(audio.exists());